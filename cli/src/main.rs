@@ -0,0 +1,229 @@
+//! Command-line control of the yowl daemon, for keybindings and scripts.
+//!
+//! Each subcommand sends one command to the daemon and prints its response.
+//! The exit status is nonzero if the daemon answers with an error or can't
+//! be reached.
+
+use std::io::Write;
+use std::process::ExitCode;
+
+use yowl_client::{Client, DiffResult};
+
+const USAGE: &str = "usage: yowl [--instance <name>] \
+                     <start|stop|toggle|status|transcript [--committed|--follow]|shutdown>";
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    /// A daemon command whose response is printed as is
+    Raw(&'static str),
+    /// `START`, retried while the daemon loads its model
+    Start,
+    Toggle,
+    Transcript {
+        committed: bool,
+    },
+    Follow,
+}
+
+fn parse_args(args: &[&str]) -> Option<Command> {
+    Some(match args {
+        ["start"] => Command::Start,
+        ["stop"] => Command::Raw("STOP"),
+        ["status"] => Command::Raw("STATUS"),
+        ["shutdown"] => Command::Raw("SHUTDOWN"),
+        ["toggle"] => Command::Toggle,
+        ["transcript"] => Command::Transcript { committed: false },
+        ["transcript", "--committed"] => Command::Transcript { committed: true },
+        ["transcript", "--follow"] => Command::Follow,
+        _ => return None,
+    })
+}
+
+/// Split off a leading `--instance <name>`, which picks the daemon to talk to.
+fn split_instance<'a>(args: &'a [&'a str]) -> (Option<&'a str>, &'a [&'a str]) {
+    match args {
+        ["--instance", name, rest @ ..] => (Some(name), rest),
+        _ => (None, args),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let (instance, args) = split_instance(&args);
+    let Some(command) = parse_args(args) else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+
+    let path = match instance {
+        Some(name) => yowl_client::validate_instance(name)
+            .map(|()| yowl_client::instance_socket_path(Some(name))),
+        // YOWL_INSTANCE, if set
+        None => yowl_client::checked_socket_path().map_err(|e| e.to_string()),
+    };
+    let path = match path {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("yowl: {e}");
+            return ExitCode::from(2);
+        }
+    };
+    let result = Client::connect_to(&path)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {e}", path.display())))
+        .and_then(|client| run(client, command));
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("yowl: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Run `command`, returning false if the daemon refused it.
+fn run(mut client: Client, command: Command) -> std::io::Result<bool> {
+    let response = match command {
+        Command::Raw(command) => client.request(command)?,
+        Command::Start => start(&mut client)?,
+        Command::Toggle => daemon_error(client.toggle().map(|_| "OK".to_string()))?,
+        Command::Transcript { committed } => daemon_error(client.transcript(committed))?,
+        Command::Follow => return follow(client),
+    };
+
+    println!("{response}");
+    Ok(!response.starts_with("ERROR"))
+}
+
+/// Start recording, waiting out the model load, and return the response to
+/// print.
+fn start(client: &mut Client) -> std::io::Result<String> {
+    daemon_error(client.start().map(|()| "OK".to_string()))
+}
+
+/// Turn an error the daemon answered with back into its response line, as
+/// the other subcommands print it.
+fn daemon_error(result: std::io::Result<String>) -> std::io::Result<String> {
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::Other => Ok(format!("ERROR {e}")),
+        result => result,
+    }
+}
+
+/// Print the live transcript as it changes, until the daemon goes away.
+fn follow(client: Client) -> std::io::Result<bool> {
+    let mut stdout = std::io::stdout().lock();
+    let mut text = String::new();
+    for event in client.subscribe()? {
+        match event? {
+            Some(diff) => render(&diff, &mut text, &mut stdout)?,
+            // the recording is over; the next one starts on a fresh line
+            None if !text.is_empty() => {
+                writeln!(stdout)?;
+                text.clear();
+            }
+            None => {}
+        }
+        stdout.flush()?;
+    }
+    Ok(true)
+}
+
+/// Apply `diff` to the terminal: erase the revised characters of `text`,
+/// then type the new ones.
+fn render(diff: &DiffResult, text: &mut String, out: &mut impl Write) -> std::io::Result<()> {
+    for _ in 0..diff.backspaces {
+        // a line break can't be erased, so the revision goes after it
+        if text.pop().is_some_and(|c| c != '\n') {
+            out.write_all(b"\x08 \x08")?;
+        }
+    }
+    text.push_str(&diff.new_text);
+    out.write_all(diff.new_text.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use yowl_client::testing::fake_daemon;
+
+    use super::*;
+
+    #[test]
+    fn test_start_waits_for_model() {
+        static STARTS: AtomicUsize = AtomicUsize::new(0);
+        let (path, daemon) = fake_daemon("cli-start", 1, usize::MAX, |command| match command {
+            "START" if STARTS.fetch_add(1, Ordering::SeqCst) < 1 => "LOADING".to_string(),
+            "START" => "OK".to_string(),
+            _ => "ERROR unknown command".to_string(),
+        });
+        assert!(run(Client::connect_to(&path).unwrap(), Command::Start).unwrap());
+        let commands = daemon.join().unwrap();
+        assert_eq!(commands[1..], ["START", "START"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_toggle_waits_for_model() {
+        static STARTS: AtomicUsize = AtomicUsize::new(0);
+        let (path, daemon) = fake_daemon("cli-toggle", 1, usize::MAX, |command| match command {
+            "START" if STARTS.fetch_add(1, Ordering::SeqCst) < 1 => "LOADING".to_string(),
+            "START" => "OK".to_string(),
+            // nothing was recording, so toggling starts once the model loads
+            "TOGGLE" => "LOADING".to_string(),
+            _ => "ERROR unknown command".to_string(),
+        });
+        assert!(run(Client::connect_to(&path).unwrap(), Command::Toggle).unwrap());
+        let commands = daemon.join().unwrap();
+        assert_eq!(commands[1..], ["TOGGLE", "START", "START"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse_args(&["toggle"]), Some(Command::Toggle));
+        assert_eq!(parse_args(&["start"]), Some(Command::Start));
+        assert_eq!(parse_args(&["status"]), Some(Command::Raw("STATUS")));
+        assert_eq!(
+            parse_args(&["transcript", "--committed"]),
+            Some(Command::Transcript { committed: true })
+        );
+        assert_eq!(
+            parse_args(&["transcript", "--follow"]),
+            Some(Command::Follow)
+        );
+        assert_eq!(parse_args(&[]), None);
+        assert_eq!(parse_args(&["start", "now"]), None);
+    }
+
+    #[test]
+    fn test_split_instance() {
+        assert_eq!(
+            split_instance(&["--instance", "desk", "toggle"]),
+            (Some("desk"), &["toggle"][..])
+        );
+        assert_eq!(split_instance(&["toggle"]), (None, &["toggle"][..]));
+        assert_eq!(split_instance(&["--instance"]), (None, &["--instance"][..]));
+    }
+
+    #[test]
+    fn test_render_backspaces() {
+        let mut text = String::new();
+        let mut out = Vec::new();
+        for (backspaces, new_text) in [(0, "Hello wor"), (3, "world.")] {
+            let diff = DiffResult {
+                backspaces,
+                new_text: new_text.to_string(),
+            };
+            render(&diff, &mut text, &mut out).unwrap();
+        }
+
+        assert_eq!(text, "Hello world.");
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Hello wor\x08 \x08\x08 \x08\x08 \x08world."
+        );
+    }
+}
@@ -0,0 +1,903 @@
+//! Client for the yowl daemon's socket protocol.
+//!
+//! [`Client`] wraps the line-based text protocol in typed calls, so tools
+//! driving the daemon don't each have to parse its responses:
+//!
+//! ```no_run
+//! let mut client = yowl_client::Client::connect()?;
+//! client.start()?;
+//! let mut text = String::new();
+//! while let Some(diff) = client.poll()? {
+//!     diff.apply(&mut text);
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Errors are `std::io::Error`s. An `ERROR` response from the daemon is
+//! reported with kind `Other` and the daemon's message; a response this
+//! client can't make sense of has kind `InvalidData`.
+
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Protocol spoken by daemons that don't understand HELLO.
+const BASE_PROTOCOL: u32 = 1;
+
+/// Newest protocol this client speaks.
+const PROTOCOL_VERSION: u32 = 2;
+
+/// How long to wait for the daemon to answer a request.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often START is retried while the daemon loads its model.
+const LOADING_RETRY: Duration = Duration::from_millis(100);
+
+/// How long START waits for the daemon to load its model, unless set with
+/// [`Client::set_load_timeout`]. Large models on slow disks take a while.
+const LOAD_TIMEOUT: Duration = Duration::from_secs(180);
+
+/// Commands that only read the daemon's state, so are sent again after a
+/// reconnect without any harm if the daemon saw them the first time.
+const RETRYABLE: &[&str] = &["PING", "STATUS", "POLL", "TRANSCRIPT"];
+
+/// Environment variable naming the daemon instance to talk to, for running
+/// more than one daemon side by side. Unset or empty means the default one.
+pub const INSTANCE_VAR: &str = "YOWL_INSTANCE";
+
+/// Longest instance name accepted.
+const MAX_INSTANCE_LEN: usize = 64;
+
+/// Where the daemon listens, by the same rules it uses to bind.
+///
+/// `YOWL_SOCKET_PATH` wins, then `$XDG_RUNTIME_DIR/yowl/yowl.sock`, then
+/// `yowl-<uid>.sock` in the temp dir, where an instance named in
+/// [`INSTANCE_VAR`] adds `-<name>` before `.sock`. The name is used as
+/// given; [`checked_socket_path`] checks it first.
+pub fn socket_path() -> PathBuf {
+    let instance = std::env::var(INSTANCE_VAR).ok();
+    instance_socket_path(instance.as_deref().filter(|name| !name.is_empty()))
+}
+
+/// Like [`socket_path`], but fails with kind `InvalidInput` if the instance
+/// in [`INSTANCE_VAR`] isn't one [`validate_instance`] accepts.
+pub fn checked_socket_path() -> std::io::Result<PathBuf> {
+    checked_socket_path_in(|var| std::env::var(var).ok())
+}
+
+/// [`checked_socket_path`], reading the environment through `env`.
+fn checked_socket_path_in(env: impl Fn(&str) -> Option<String>) -> std::io::Result<PathBuf> {
+    let instance = env(INSTANCE_VAR).filter(|name| !name.is_empty());
+    if let Some(name) = &instance {
+        validate_instance(name).map_err(|e| {
+            std::io::Error::new(ErrorKind::InvalidInput, format!("{INSTANCE_VAR}: {e}"))
+        })?;
+    }
+    Ok(instance_socket_path_in(env, instance.as_deref()))
+}
+
+/// Like [`socket_path`], for the named `instance` rather than the one in
+/// the environment. Check the name with [`validate_instance`] first.
+pub fn instance_socket_path(instance: Option<&str>) -> PathBuf {
+    instance_socket_path_in(|var| std::env::var(var).ok(), instance)
+}
+
+/// [`instance_socket_path`], reading the environment through `env`, so the
+/// daemon binds where clients look.
+pub fn instance_socket_path_in(
+    env: impl Fn(&str) -> Option<String>,
+    instance: Option<&str>,
+) -> PathBuf {
+    if let Some(path) = env("YOWL_SOCKET_PATH").filter(|path| !path.is_empty()) {
+        return PathBuf::from(path);
+    }
+    let suffix = instance.map_or(String::new(), |name| format!("-{name}"));
+
+    if let Some(runtime_dir) = env("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        return Path::new(&runtime_dir)
+            .join("yowl")
+            .join(format!("yowl{suffix}.sock"));
+    }
+
+    let uid = unsafe { libc::getuid() };
+    std::env::temp_dir().join(format!("yowl-{uid}{suffix}.sock"))
+}
+
+/// Check that `name` can name a daemon instance. It goes into file names,
+/// so it may only hold letters, digits, `-`, `_` and `.`, and can't start
+/// with a `.`.
+pub fn validate_instance(name: &str) -> Result<(), String> {
+    let allowed = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+    if name.is_empty() || name.len() > MAX_INSTANCE_LEN {
+        Err(format!(
+            "instance name must be 1 to {MAX_INSTANCE_LEN} characters"
+        ))
+    } else if name.starts_with('.') || !name.chars().all(allowed) {
+        Err(format!(
+            "invalid instance name {name:?}: use letters, digits, '-', '_' and '.'"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Result of computing a diff between old and new text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffResult {
+    /// Number of characters (or grapheme clusters, per the daemon's
+    /// backspace unit) to backspace/erase
+    pub backspaces: usize,
+    /// New characters to append after backspacing
+    pub new_text: String,
+}
+
+impl DiffResult {
+    /// Apply the diff to `text`, counting backspaces in chars.
+    pub fn apply(&self, text: &mut String) {
+        for _ in 0..self.backspaces {
+            text.pop();
+        }
+        text.push_str(&self.new_text);
+    }
+}
+
+/// What the daemon is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Idle,
+    Recording,
+    /// Recording but paused
+    Paused,
+}
+
+/// The daemon's answer to STATUS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub state: State,
+    /// Time since recording started, or zero when idle.
+    pub elapsed: Duration,
+    /// Samples currently held in the rolling buffer.
+    pub buffered_samples: usize,
+    pub committed_chars: usize,
+    pub provisional_chars: usize,
+    /// Code of the most recent fault this session, if there was one.
+    pub error: Option<String>,
+    /// A transcription pass is taking far longer than it should.
+    pub degraded: bool,
+    /// Language being transcribed, or `auto`. Older daemons don't say.
+    pub language: Option<String>,
+    /// File name of the model, if the daemon knows which it will use.
+    pub model: Option<String>,
+    /// Length of the rolling buffer. Older daemons don't say.
+    pub buffer_secs: Option<u64>,
+}
+
+/// A connection to the daemon. If the daemon restarts, the next request
+/// reconnects, and is sent again if it only reads the daemon's state.
+pub struct Client {
+    path: PathBuf,
+    stream: Option<BufReader<UnixStream>>,
+    /// Protocol settled on with HELLO
+    protocol: u32,
+    /// How long [`Client::start`] waits for the model to load
+    load_timeout: Duration,
+}
+
+impl Client {
+    /// Connect to the daemon at [`checked_socket_path`].
+    pub fn connect() -> std::io::Result<Self> {
+        Self::connect_to(checked_socket_path()?)
+    }
+
+    /// Connect to a daemon listening at `path`.
+    pub fn connect_to(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let mut client = Self {
+            path: path.into(),
+            stream: None,
+            protocol: BASE_PROTOCOL,
+            load_timeout: LOAD_TIMEOUT,
+        };
+        client.reconnect()?;
+        Ok(client)
+    }
+
+    /// The protocol version in use on the current connection.
+    pub fn protocol(&self) -> u32 {
+        self.protocol
+    }
+
+    /// Give up on [`Client::start`] if the daemon is still loading its
+    /// model after `timeout`.
+    pub fn set_load_timeout(&mut self, timeout: Duration) {
+        self.load_timeout = timeout;
+    }
+
+    fn reconnect(&mut self) -> std::io::Result<()> {
+        self.stream = None;
+        let stream = UnixStream::connect(&self.path)?;
+        stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+        let mut stream = BufReader::new(stream);
+
+        let hello = format!(
+            "HELLO yowl-client/{} {PROTOCOL_VERSION}",
+            env!("CARGO_PKG_VERSION")
+        );
+        let response = exchange(&mut stream, &hello)?;
+        // daemons from before HELLO only speak the base protocol
+        self.protocol = if response.starts_with("ERROR") {
+            BASE_PROTOCOL
+        } else {
+            parse_hello(&response)?
+        };
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    /// Send `command` and return the daemon's response line as is.
+    ///
+    /// If the connection has dropped, a command like START or STOP isn't
+    /// sent again, as the daemon may already have acted on it: the error is
+    /// returned, and the next request goes to the new connection.
+    pub fn request(&mut self, command: &str) -> std::io::Result<String> {
+        if let Some(stream) = &mut self.stream {
+            match exchange(stream, command) {
+                Ok(response) => return Ok(response),
+                // the daemon went away; it may be back at the same path
+                Err(e) if is_disconnect(&e) => {
+                    let name = command.split(' ').next().unwrap_or("");
+                    if !RETRYABLE.contains(&name.to_uppercase().as_str()) {
+                        let _ = self.reconnect();
+                        return Err(e);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.reconnect()?;
+        let stream = self.stream.as_mut().expect("just reconnected");
+        exchange(stream, command)
+    }
+
+    /// Start recording, waiting for the daemon to load its model if it
+    /// hasn't yet. Fails with kind `TimedOut` if the load takes longer than
+    /// the load timeout.
+    pub fn start(&mut self) -> std::io::Result<()> {
+        let deadline = std::time::Instant::now() + self.load_timeout;
+        loop {
+            let response = self.request("START")?;
+            // the daemon loads its model when it is first needed
+            if response != "LOADING" {
+                return expect_ok(&response);
+            }
+            if std::time::Instant::now() >= deadline {
+                return Err(std::io::Error::new(
+                    ErrorKind::TimedOut,
+                    "timed out waiting for the daemon to load its model",
+                ));
+            }
+            std::thread::sleep(LOADING_RETRY);
+        }
+    }
+
+    /// Stop recording.
+    pub fn stop(&mut self) -> std::io::Result<()> {
+        expect_ok(&self.request("STOP")?)
+    }
+
+    /// Start recording if idle, or stop if recording. Returns true if the
+    /// daemon is now recording.
+    ///
+    /// The daemon decides which, so two clients toggling at once can't both
+    /// start. While the model loads it waits as [`Client::start`] does, and
+    /// daemons from before TOGGLE are asked for their STATUS first.
+    pub fn toggle(&mut self) -> std::io::Result<bool> {
+        let response = self.request("TOGGLE")?;
+        match response.as_str() {
+            "OK recording" => return Ok(true),
+            "OK idle" => return Ok(false),
+            "LOADING" => return self.start().map(|()| true),
+            response if !response.starts_with("ERROR unknown command") => {
+                check_error(response)?;
+                return Err(invalid(response));
+            }
+            _ => {}
+        }
+        if self.status()?.state == State::Idle {
+            self.start()?;
+            Ok(true)
+        } else {
+            self.stop()?;
+            Ok(false)
+        }
+    }
+
+    /// The terminal edit needed to catch up with the live transcript, or
+    /// `None` once the daemon isn't recording.
+    pub fn poll(&mut self) -> std::io::Result<Option<DiffResult>> {
+        let response = self.request("POLL")?;
+        parse_poll(&response, self.protocol)
+    }
+
+    /// What the daemon is doing.
+    pub fn status(&mut self) -> std::io::Result<Status> {
+        let response = self.request("STATUS")?;
+        parse_status(&response)
+    }
+
+    /// The transcript of the current or most recent recording, or only
+    /// the part that can no longer be revised with `committed`.
+    pub fn transcript(&mut self, committed: bool) -> std::io::Result<String> {
+        let command = if committed {
+            "TRANSCRIPT committed"
+        } else {
+            "TRANSCRIPT"
+        };
+        let response = self.request(command)?;
+        check_error(&response)?
+            .strip_prefix("TRANSCRIPT:")
+            .map(unescape_line)
+            .ok_or_else(|| invalid(&response))
+    }
+
+    /// Have the daemon push each change to the transcript as it happens,
+    /// instead of waiting to be polled. The connection is given over to
+    /// the events from then on.
+    pub fn subscribe(mut self) -> std::io::Result<Events> {
+        expect_ok(&self.request("SUBSCRIBE")?)?;
+        let stream = self.stream.take().expect("connected by the request");
+        // events only come when there is something to say
+        stream.get_ref().set_read_timeout(None)?;
+        Ok(Events {
+            stream,
+            protocol: self.protocol,
+        })
+    }
+}
+
+/// Changes pushed by the daemon after [`Client::subscribe`], as they would
+/// be returned by [`Client::poll`]. Ends when the daemon hangs up.
+pub struct Events {
+    stream: BufReader<UnixStream>,
+    protocol: u32,
+}
+
+impl Iterator for Events {
+    type Item = std::io::Result<Option<DiffResult>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match read_line(&mut self.stream) {
+            Ok(None) => None,
+            Ok(Some(line)) => Some(match line.strip_prefix("EVENT ") {
+                Some(event) => parse_poll(event, self.protocol),
+                None => Err(invalid(&line)),
+            }),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Send `command` as a line and read the response line.
+fn exchange(stream: &mut BufReader<UnixStream>, command: &str) -> std::io::Result<String> {
+    writeln!(stream.get_mut(), "{command}")?;
+    read_line(stream)?.ok_or_else(|| {
+        std::io::Error::new(ErrorKind::UnexpectedEof, "daemon closed the connection")
+    })
+}
+
+/// Read the next line from the daemon, or `None` once it hangs up. A
+/// daemon with keepalives on sends PING to quiet clients; those are
+/// answered here rather than returned.
+fn read_line(stream: &mut BufReader<UnixStream>) -> std::io::Result<Option<String>> {
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line == "PING" {
+            writeln!(stream.get_mut(), "PONG")?;
+            continue;
+        }
+        return Ok(Some(line.to_string()));
+    }
+}
+
+/// Returns true if `error` means the daemon hung up, rather than e.g. a
+/// timeout waiting for it.
+fn is_disconnect(error: &std::io::Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::BrokenPipe
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+fn invalid(response: &str) -> std::io::Error {
+    std::io::Error::new(
+        ErrorKind::InvalidData,
+        format!("unexpected response: {response}"),
+    )
+}
+
+/// Turn an `ERROR <message>` response into an error.
+fn check_error(response: &str) -> std::io::Result<&str> {
+    match response.strip_prefix("ERROR") {
+        Some(message) => Err(std::io::Error::other(message.trim_start().to_string())),
+        None => Ok(response),
+    }
+}
+
+fn expect_ok(response: &str) -> std::io::Result<()> {
+    match check_error(response)? {
+        "OK" => Ok(()),
+        other => Err(invalid(other)),
+    }
+}
+
+/// The protocol from `HELLO version=<v> protocol=<n> ...`.
+fn parse_hello(response: &str) -> std::io::Result<u32> {
+    response
+        .strip_prefix("HELLO ")
+        .and_then(|fields| {
+            fields
+                .split(' ')
+                .find_map(|field| field.strip_prefix("protocol="))
+        })
+        .and_then(|protocol| protocol.parse().ok())
+        .ok_or_else(|| invalid(response))
+}
+
+/// Parse a POLL response: `IDLE:` or `RECORDING:<backspaces>:<text>`, with
+/// the text escaped from protocol 2. `STOPPED:`, for a recording that ended
+/// itself after silence, is idle too.
+fn parse_poll(response: &str, protocol: u32) -> std::io::Result<Option<DiffResult>> {
+    let response = check_error(response)?;
+    if response.starts_with("IDLE:") || response.starts_with("STOPPED:") {
+        return Ok(None);
+    }
+
+    let (backspaces, text) = response
+        .strip_prefix("RECORDING:")
+        .and_then(|rest| rest.split_once(':'))
+        .ok_or_else(|| invalid(response))?;
+    let backspaces = backspaces.parse().map_err(|_| invalid(response))?;
+    let new_text = if protocol >= 2 {
+        unescape_line(text)
+    } else {
+        text.to_string()
+    };
+    Ok(Some(DiffResult {
+        backspaces,
+        new_text,
+    }))
+}
+
+/// Parse the `key=value` fields of a STATUS response. Fields this client
+/// doesn't know are skipped, so newer daemons can add more.
+fn parse_status(response: &str) -> std::io::Result<Status> {
+    let response = check_error(response)?;
+    let mut state = None;
+    let mut status = Status {
+        state: State::Idle,
+        elapsed: Duration::ZERO,
+        buffered_samples: 0,
+        committed_chars: 0,
+        provisional_chars: 0,
+        error: None,
+        degraded: false,
+        language: None,
+        model: None,
+        buffer_secs: None,
+    };
+
+    for field in response.split(' ') {
+        let (key, value) = field.split_once('=').ok_or_else(|| invalid(response))?;
+        let number = || value.parse::<usize>().map_err(|_| invalid(response));
+        match key {
+            "state" => {
+                state = Some(match value {
+                    "idle" => State::Idle,
+                    "recording" => State::Recording,
+                    "paused" => State::Paused,
+                    _ => return Err(invalid(response)),
+                })
+            }
+            "elapsed_ms" => status.elapsed = Duration::from_millis(number()? as u64),
+            "samples" => status.buffered_samples = number()?,
+            "committed" => status.committed_chars = number()?,
+            "provisional" => status.provisional_chars = number()?,
+            "error" => status.error = Some(value.to_string()),
+            "degraded" => status.degraded = value == "yes",
+            "language" => status.language = Some(unescape_line(value)),
+            "model" => status.model = Some(unescape_line(value)),
+            "buffer_secs" => status.buffer_secs = Some(number()? as u64),
+            _ => {}
+        }
+    }
+
+    status.state = state.ok_or_else(|| invalid(response))?;
+    Ok(status)
+}
+
+/// Undo the daemon's escaping of `\`, newlines, carriage returns, tabs and
+/// (in STATUS values) spaces.
+fn unescape_line(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some('s') => unescaped.push(' '),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// A stand-in daemon, for testing code built on this client.
+#[cfg(any(test, feature = "testing"))]
+pub mod testing {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+
+    /// A socket path for the test `name`, unique to this process.
+    pub fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("yowl-test-{}-{name}.sock", std::process::id()))
+    }
+
+    /// Serve `connections` clients in turn at [`socket_path`], answering
+    /// each with `respond` until it has handled `per_connection` commands,
+    /// then hanging up once the client has closed its end. The daemon
+    /// thread returns the commands it answered.
+    pub fn fake_daemon(
+        name: &str,
+        connections: usize,
+        per_connection: usize,
+        respond: fn(&str) -> String,
+    ) -> (PathBuf, std::thread::JoinHandle<Vec<String>>) {
+        let path = socket_path(name);
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let daemon = std::thread::spawn(move || {
+            let mut commands = Vec::new();
+            for _ in 0..connections {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut handled = 0;
+                while handled < per_connection {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() == 0 {
+                        break;
+                    }
+                    // answers to keepalives get no reply, as in the daemon
+                    if line.trim_end() == "PONG" {
+                        continue;
+                    }
+                    let response = respond(line.trim_end());
+                    writeln!(reader.get_mut(), "{response}").unwrap();
+                    commands.push(line.trim_end().to_string());
+                    handled += 1;
+                }
+                // hang up, but let the client finish what it is sending
+                let _ = reader.get_ref().shutdown(std::net::Shutdown::Write);
+                let _ = std::io::copy(&mut reader, &mut std::io::sink());
+            }
+            commands
+        });
+        (path, daemon)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::fake_daemon;
+    use super::*;
+
+    fn daemon_responses(cmd: &str) -> String {
+        match cmd {
+            cmd if cmd.starts_with("HELLO") => {
+                "HELLO version=0.1.0 protocol=2 capabilities=events".to_string()
+            }
+            "START" | "STOP" => "OK".to_string(),
+            "POLL" => "RECORDING:2:one\\ntwo".to_string(),
+            "STATUS" => "state=recording elapsed_ms=1500 samples=24000 committed=3 \
+                         provisional=4 language=en buffer_secs=10 model=my\\sggml-base.en.bin \
+                         error=ERR_CAPTURE degraded=yes future=1"
+                .to_string(),
+            _ => "ERROR unknown command".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_typed_requests() {
+        let (path, daemon) = fake_daemon("typed", 1, 5, daemon_responses);
+        let mut client = Client::connect_to(&path).unwrap();
+        assert_eq!(client.protocol(), 2);
+
+        client.start().unwrap();
+        assert_eq!(
+            client.poll().unwrap(),
+            Some(DiffResult {
+                backspaces: 2,
+                new_text: "one\ntwo".to_string(),
+            })
+        );
+        assert_eq!(
+            client.status().unwrap(),
+            Status {
+                state: State::Recording,
+                elapsed: Duration::from_millis(1500),
+                buffered_samples: 24000,
+                committed_chars: 3,
+                provisional_chars: 4,
+                error: Some("ERR_CAPTURE".to_string()),
+                degraded: true,
+                language: Some("en".to_string()),
+                model: Some("my ggml-base.en.bin".to_string()),
+                buffer_secs: Some(10),
+            }
+        );
+        client.stop().unwrap();
+
+        drop(client);
+        daemon.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reconnects_after_daemon_restart() {
+        // each connection answers HELLO and one command, then hangs up
+        let (path, daemon) = fake_daemon("reconnect", 3, 2, daemon_responses);
+        let mut client = Client::connect_to(&path).unwrap();
+
+        client.start().unwrap();
+        // STATUS is sent again on the new connection
+        assert_eq!(client.status().unwrap().state, State::Recording);
+        // STOP isn't, as the daemon might have stopped already
+        assert!(is_disconnect(&client.stop().unwrap_err()));
+        client.stop().unwrap();
+
+        drop(client);
+        let commands = daemon.join().unwrap();
+        let commands: Vec<&str> = commands
+            .iter()
+            .filter(|cmd| !cmd.starts_with("HELLO"))
+            .map(String::as_str)
+            .collect();
+        assert_eq!(commands, ["START", "STATUS", "STOP"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_toggle() {
+        static RECORDING: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+        let (path, daemon) = fake_daemon("toggle", 1, 3, |cmd| match cmd {
+            "TOGGLE" if RECORDING.fetch_xor(true, std::sync::atomic::Ordering::SeqCst) => {
+                "OK idle".to_string()
+            }
+            "TOGGLE" => "OK recording".to_string(),
+            _ => daemon_responses(cmd),
+        });
+        let mut client = Client::connect_to(&path).unwrap();
+        assert!(client.toggle().unwrap());
+        assert!(!client.toggle().unwrap());
+
+        drop(client);
+        daemon.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_toggle_without_daemon_support() {
+        let (path, daemon) = fake_daemon("old-toggle", 1, 4, daemon_responses);
+        let mut client = Client::connect_to(&path).unwrap();
+        // the daemon says it is recording
+        assert!(!client.toggle().unwrap());
+
+        drop(client);
+        assert_eq!(daemon.join().unwrap()[1..], ["TOGGLE", "STATUS", "STOP"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_start_waits_for_model_load() {
+        static STARTS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let (path, daemon) = fake_daemon("loading", 1, 4, |cmd| match cmd {
+            "START" if STARTS.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < 2 => {
+                "LOADING".to_string()
+            }
+            _ => daemon_responses(cmd),
+        });
+        let mut client = Client::connect_to(&path).unwrap();
+
+        client.start().unwrap();
+        assert_eq!(STARTS.load(std::sync::atomic::Ordering::SeqCst), 3);
+
+        drop(client);
+        daemon.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_start_gives_up_on_slow_load() {
+        let (path, daemon) = fake_daemon("slow-load", 1, usize::MAX, |cmd| match cmd {
+            "START" => "LOADING".to_string(),
+            _ => daemon_responses(cmd),
+        });
+        let mut client = Client::connect_to(&path).unwrap();
+        client.set_load_timeout(Duration::from_millis(300));
+
+        let err = client.start().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+        drop(client);
+        assert!(daemon.join().unwrap().len() > 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_daemon_errors() {
+        let (path, daemon) = fake_daemon("errors", 1, 2, |cmd| {
+            if cmd.starts_with("HELLO") {
+                "ERROR unknown command: HELLO".to_string()
+            } else {
+                "ERROR already recording".to_string()
+            }
+        });
+        let mut client = Client::connect_to(&path).unwrap();
+        assert_eq!(client.protocol(), BASE_PROTOCOL);
+
+        let err = client.start().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Other);
+        assert_eq!(err.to_string(), "already recording");
+
+        drop(client);
+        daemon.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_transcript_and_events() {
+        let (path, daemon) = fake_daemon("events", 1, 3, |cmd| match cmd {
+            // a keepalive can arrive before the response
+            "TRANSCRIPT" => "PING\nTRANSCRIPT:one\\ntwo".to_string(),
+            // the events follow the response to SUBSCRIBE
+            "SUBSCRIBE" => {
+                "OK\nEVENT RECORDING:0:Hi\nPING\nEVENT RECORDING:1:!\nEVENT IDLE:".to_string()
+            }
+            _ => daemon_responses(cmd),
+        });
+        let mut client = Client::connect_to(&path).unwrap();
+        assert_eq!(client.transcript(false).unwrap(), "one\ntwo");
+
+        let events: Vec<Option<DiffResult>> =
+            client.subscribe().unwrap().map(Result::unwrap).collect();
+        assert_eq!(
+            events,
+            [
+                Some(DiffResult {
+                    backspaces: 0,
+                    new_text: "Hi".to_string(),
+                }),
+                Some(DiffResult {
+                    backspaces: 1,
+                    new_text: "!".to_string(),
+                }),
+                None,
+            ]
+        );
+
+        daemon.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_poll() {
+        assert_eq!(parse_poll("IDLE:", 2).unwrap(), None);
+        assert_eq!(parse_poll("STOPPED:", 2).unwrap(), None);
+        // protocol 1 text is sent as is
+        assert_eq!(
+            parse_poll("RECORDING:0:C:\\n", 1)
+                .unwrap()
+                .unwrap()
+                .new_text,
+            "C:\\n"
+        );
+        assert_eq!(
+            parse_poll("RECORDING:0:C:\\\\n", 2)
+                .unwrap()
+                .unwrap()
+                .new_text,
+            "C:\\n"
+        );
+        assert_eq!(
+            parse_poll("RECORDING:x:text", 2).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(
+            parse_poll("ERROR ERR_CAPTURE capture failed", 2)
+                .unwrap_err()
+                .to_string(),
+            "ERR_CAPTURE capture failed"
+        );
+    }
+
+    #[test]
+    fn test_validate_instance() {
+        for name in ["headset", "desk-mic", "user_2", "v1.2"] {
+            assert_eq!(validate_instance(name), Ok(()), "{name}");
+        }
+        for name in [
+            "",
+            "a/b",
+            "..",
+            ".hidden",
+            "desk mic",
+            "a\\b",
+            &"x".repeat(65),
+        ] {
+            assert!(validate_instance(name).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_socket_path_checks_instance() {
+        let env = |instance: &'static str| {
+            move |var: &str| match var {
+                INSTANCE_VAR => Some(instance.to_string()),
+                "XDG_RUNTIME_DIR" => Some("/run/user/1000".to_string()),
+                _ => None,
+            }
+        };
+        assert_eq!(
+            checked_socket_path_in(env("desk")).unwrap(),
+            PathBuf::from("/run/user/1000/yowl/yowl-desk.sock")
+        );
+        assert_eq!(
+            checked_socket_path_in(env("")).unwrap(),
+            PathBuf::from("/run/user/1000/yowl/yowl.sock")
+        );
+        let err = checked_socket_path_in(env("../x")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(err
+            .to_string()
+            .starts_with("YOWL_INSTANCE: invalid instance name"));
+    }
+
+    #[test]
+    fn test_parse_status_idle() {
+        let status =
+            parse_status("state=idle elapsed_ms=0 samples=0 committed=0 provisional=0").unwrap();
+        assert_eq!(status.state, State::Idle);
+        assert_eq!(status.error, None);
+        assert!(!status.degraded);
+        assert_eq!(status.language, None);
+        assert_eq!(status.buffer_secs, None);
+        assert!(parse_status("samples=0").is_err());
+    }
+
+    #[test]
+    fn test_diff_apply() {
+        let mut text = "Hello wor".to_string();
+        DiffResult {
+            backspaces: 3,
+            new_text: "world.".to_string(),
+        }
+        .apply(&mut text);
+        assert_eq!(text, "Hello world.");
+    }
+}
@@ -0,0 +1,132 @@
+//! Drives a real daemon through a recording. It needs a whisper model and a
+//! microphone, so it only runs on request, after building the daemon:
+//!
+//! ```sh
+//! cargo build -p daemon && cargo test -p yowl-client -- --ignored --nocapture
+//! ```
+//!
+//! `YOWL_DAEMON_BIN` overrides where the daemon binary is looked for.
+
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+use yowl_client::{Client, State};
+
+/// Kills the daemon if the test fails before shutting it down.
+struct Daemon(Child);
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn daemon_bin() -> PathBuf {
+    std::env::var_os("YOWL_DAEMON_BIN")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../target/debug/daemon"))
+}
+
+/// Connect once the daemon has loaded its model and started listening.
+fn wait_for_daemon(path: &PathBuf, daemon: &mut Daemon) -> Client {
+    let deadline = Instant::now() + Duration::from_secs(60);
+    loop {
+        if let Ok(client) = Client::connect_to(path) {
+            return client;
+        }
+        if let Some(status) = daemon.0.try_wait().unwrap() {
+            panic!("daemon exited before listening: {status}");
+        }
+        assert!(Instant::now() < deadline, "daemon never started listening");
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+#[ignore] // Run manually, see above
+fn test_start_poll_stop() {
+    let dir = std::env::temp_dir().join(format!("yowl-client-it-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("yowl.sock");
+
+    let mut daemon = Daemon(
+        Command::new(daemon_bin())
+            .env("YOWL_SOCKET_PATH", &path)
+            .spawn()
+            .expect("Failed to spawn daemon; build it first"),
+    );
+    let mut client = wait_for_daemon(&path, &mut daemon);
+    assert_eq!(client.status().unwrap().state, State::Idle);
+
+    client.start().unwrap();
+    assert_eq!(client.status().unwrap().state, State::Recording);
+    println!("Recording for 3 seconds, say something...");
+    let mut text = String::new();
+    let until = Instant::now() + Duration::from_secs(3);
+    while Instant::now() < until {
+        if let Some(diff) = client.poll().unwrap() {
+            diff.apply(&mut text);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    println!("Transcript: {text:?}");
+
+    assert!(client.toggle().is_ok_and(|recording| !recording));
+    assert_eq!(client.poll().unwrap(), None);
+    assert_eq!(client.request("SHUTDOWN").unwrap(), "OK");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+#[ignore] // Run manually, see above
+fn test_sigterm_removes_socket() {
+    let dir = std::env::temp_dir().join(format!("yowl-client-sigterm-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("yowl.sock");
+
+    let mut daemon = Daemon(
+        Command::new(daemon_bin())
+            .env("YOWL_SOCKET_PATH", &path)
+            .spawn()
+            .expect("Failed to spawn daemon; build it first"),
+    );
+    let mut client = wait_for_daemon(&path, &mut daemon);
+    client.start().unwrap();
+
+    unsafe { libc::kill(daemon.0.id() as libc::pid_t, libc::SIGTERM) };
+    let status = daemon.0.wait().unwrap();
+    assert!(status.success(), "daemon exited with {status}");
+    assert!(!path.exists(), "socket left behind");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+#[ignore] // Run manually, see above
+fn test_exits_with_launcher() {
+    let dir = std::env::temp_dir().join(format!("yowl-client-launcher-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("yowl.sock");
+
+    // a launcher that starts the daemon and exits when its stdin closes
+    let mut launcher = Daemon(
+        Command::new("sh")
+            .args(["-c", "\"$0\" & read _"])
+            .arg(daemon_bin())
+            .env("YOWL_SOCKET_PATH", &path)
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn launcher"),
+    );
+    drop(wait_for_daemon(&path, &mut launcher));
+
+    drop(launcher.0.stdin.take());
+    launcher.0.wait().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while path.exists() {
+        assert!(Instant::now() < deadline, "daemon outlived its launcher");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
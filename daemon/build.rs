@@ -0,0 +1,51 @@
+//! Embeds build information reported by VERSION: the git commit and the
+//! whisper-rs version actually compiled in.
+
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").expect("set by cargo");
+    let manifest_dir = Path::new(&manifest_dir);
+
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .current_dir(manifest_dir)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=YOWL_GIT_COMMIT={commit}");
+
+    // the lock file is shared by the whole workspace
+    let lock_file = manifest_dir.join("../Cargo.lock");
+    let whisper_rs = std::fs::read_to_string(&lock_file)
+        .ok()
+        .and_then(|lock| locked_version(&lock, "whisper-rs"))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=YOWL_WHISPER_RS_VERSION={whisper_rs}");
+
+    // only watch files that exist; a missing one would rerun every build
+    let git_dir = manifest_dir.join("../.git");
+    for path in [lock_file, git_dir.join("HEAD"), git_dir.join("refs")] {
+        if path.exists() {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+}
+
+/// The version of `package` recorded in the contents of a Cargo.lock.
+fn locked_version(lock: &str, package: &str) -> Option<String> {
+    let name = format!("name = \"{package}\"");
+    let mut lines = lock.lines().skip_while(|line| line.trim() != name);
+    lines.next()?;
+    lines
+        .next()?
+        .trim()
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')
+        .map(str::to_string)
+}
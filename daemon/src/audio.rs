@@ -1,32 +1,486 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Sample, SampleFormat, Stream, StreamConfig};
+use cpal::{
+    Sample, SampleFormat, SampleRate, Stream, StreamConfig, SupportedStreamConfig,
+    SupportedStreamConfigRange,
+};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
 
 use crate::whisper::SAMPLE_RATE;
 
 const WHISPER_SAMPLE_RATE: u32 = SAMPLE_RATE as u32;
 
+/// Time constant of the input level smoothing.
+const LEVEL_TIME_CONSTANT_SECS: f32 = 0.2;
+
+/// Lobes on each side of the Lanczos kernel.
+const LANCZOS_A: f64 = 3.0;
+
+/// Fraction of the output Nyquist frequency at which the resampling filter
+/// cuts off. A little headroom lets the short kernel's transition band
+/// finish before content can alias.
+const RESAMPLE_ROLLOFF: f64 = 0.9;
+
+/// Cutoff of the anti-aliasing filter run before downsampling, just under
+/// the 8kHz Nyquist frequency of Whisper's 16kHz input.
+const ANTI_ALIAS_CUTOFF_HZ: f32 = 7200.0;
+
+/// How quickly the noise gate opens when speech starts.
+const NOISE_GATE_ATTACK_MS: f32 = 5.0;
+
+/// How slowly the noise gate closes after speech, so word endings survive.
+const NOISE_GATE_RELEASE_MS: f32 = 100.0;
+
+/// Time constant of the envelope the noise gate follows; longer than half a
+/// period of the lowest voice, so the gate stays open through each cycle.
+const NOISE_GATE_ENVELOPE_MS: f32 = 20.0;
+
+/// Time constant of the level the AGC follows: long enough to ride over
+/// single syllables, short enough to catch up as the speaker moves.
+const AGC_WINDOW_MS: f32 = 300.0;
+
+/// Time constant over which the AGC raises its gain. It lowers it at once,
+/// so a sudden loud sound isn't blown up.
+const AGC_RELEASE_MS: f32 = 1000.0;
+
+/// Highest amplitude the AGC lets through, just short of clipping.
+const AGC_CEILING: f32 = 0.99;
+
+/// A source of 16kHz mono f32 audio that can be started and stopped.
+pub trait AudioSource {
+    /// Start capturing audio.
+    fn start(&self) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Stop capturing audio.
+    fn stop(&self) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Receive captured audio samples (16kHz mono f32).
+    /// Returns None if no samples are available (non-blocking).
+    fn try_recv(&self) -> Option<Vec<f32>>;
+
+    /// Live input level, if the source measures one.
+    fn level_meter(&self) -> Option<LevelMeter> {
+        None
+    }
+
+    /// Returns true once the underlying device has failed, e.g. was unplugged.
+    fn device_lost(&self) -> bool {
+        false
+    }
+
+    /// Name of the device being captured, if known.
+    fn device_name(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Smoothed RMS input level, written by the audio callback and readable from
+/// any thread. Clones share the same level.
+#[derive(Debug, Clone, Default)]
+pub struct LevelMeter {
+    /// The level as f32 bits
+    bits: Arc<AtomicU32>,
+}
+
+impl LevelMeter {
+    /// The most recent smoothed level, from 0.0 to 1.0.
+    pub fn level(&self) -> f32 {
+        f32::from_bits(self.bits.load(Ordering::Relaxed))
+    }
+
+    /// Fold a chunk of mono audio at `sample_rate` into the level, smoothing
+    /// exponentially so the meter eases rather than jumps.
+    fn update(&self, samples: &[f32], sample_rate: f32) {
+        if samples.is_empty() {
+            return;
+        }
+
+        let rms = (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt();
+        let elapsed = samples.len() as f32 / sample_rate;
+        let alpha = 1.0 - (-elapsed / LEVEL_TIME_CONSTANT_SECS).exp();
+
+        let level = self.level();
+        let level = (level + alpha * (rms - level)).clamp(0.0, 1.0);
+        self.bits.store(level.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Opens a new audio source, from the named input device if one is given.
+/// Called on the recording worker thread, since platform streams generally
+/// can't be moved between threads.
+pub type AudioOpener = Box<
+    dyn Fn(Option<&str>) -> Result<Box<dyn AudioSource>, Box<dyn std::error::Error>> + Send + Sync,
+>;
+
+/// Names of the available input devices, or an error when devices can't be
+/// enumerated at all (e.g. there is no audio subsystem). Devices whose name
+/// can't be read are skipped.
+pub fn try_list_input_devices() -> Result<Vec<String>, cpal::DevicesError> {
+    Ok(cpal::default_host()
+        .input_devices()?
+        .filter_map(|device| device.name().ok())
+        .collect())
+}
+
+/// An input device and its default format. Details that can't be read are `None`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputDevice {
+    /// Position in the host's device list, usable in place of the name
+    pub index: usize,
+    pub name: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    /// Whether this is the system default input device
+    pub default: bool,
+}
+
+/// Describe every input device, including ones whose name can't be read,
+/// so that indices match the host's device list.
+pub fn describe_input_devices() -> Result<Vec<InputDevice>, cpal::DevicesError> {
+    let default = default_input_device_name();
+    Ok(cpal::default_host()
+        .input_devices()?
+        .enumerate()
+        .map(|(index, device)| {
+            let name = device
+                .name()
+                .inspect_err(|e| log::warn!("Failed to read name of input device {index}: {e}"))
+                .ok();
+            let config = device.default_input_config().ok();
+            InputDevice {
+                index,
+                default: name.is_some() && name == default,
+                sample_rate: config.as_ref().map(|config| config.sample_rate().0),
+                channels: config.map(|config| config.channels()),
+                name,
+            }
+        })
+        .collect())
+}
+
+/// Names of all input devices in host order, with an empty name for any
+/// that can't be read, so positions are device indices.
+fn indexed_input_device_names() -> Vec<String> {
+    match cpal::default_host().input_devices() {
+        Ok(devices) => devices
+            .map(|device| device.name().unwrap_or_default())
+            .collect(),
+        Err(e) => {
+            log::warn!("Failed to enumerate input devices: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Name of the audio backend in use, e.g. `ALSA` or `CoreAudio`.
+pub fn host_name() -> &'static str {
+    cpal::default_host().id().name()
+}
+
+/// Name of the system default input device, if there is one.
+pub fn default_input_device_name() -> Option<String> {
+    cpal::default_host()
+        .default_input_device()
+        .and_then(|device| device.name().ok())
+}
+
+/// Find the device matching `query`: an exact name if there is one, then a
+/// device index, otherwise the first name containing it. Matching ignores case.
+fn match_device_name(names: &[String], query: &str) -> Option<usize> {
+    let query = query.to_lowercase();
+    names
+        .iter()
+        .position(|name| name.to_lowercase() == query)
+        .or_else(|| query.parse().ok().filter(|&index| index < names.len()))
+        .or_else(|| {
+            names
+                .iter()
+                .position(|name| name.to_lowercase().contains(&query))
+        })
+}
+
+/// Check that `query` matches an available input device, returning its full name.
+pub fn resolve_input_device(query: &str) -> Result<String, String> {
+    let names = indexed_input_device_names();
+    match match_device_name(&names, query) {
+        Some(index) if !names[index].is_empty() => Ok(names[index].clone()),
+        Some(index) => Err(format!("Input device {index} has no readable name")),
+        None => Err(no_device_error(query, &names)),
+    }
+}
+
+/// The input device matching `query`, as [`match_device_name`] finds it.
+fn find_input_device(query: &str) -> Result<cpal::Device, Box<dyn std::error::Error>> {
+    let mut devices: Vec<cpal::Device> = cpal::default_host().input_devices()?.collect();
+    let names: Vec<String> = devices
+        .iter()
+        .map(|device| device.name().unwrap_or_default())
+        .collect();
+
+    match match_device_name(&names, query) {
+        Some(index) => Ok(devices.swap_remove(index)),
+        None => Err(no_device_error(query, &names).into()),
+    }
+}
+
+/// The config among `supported` capturing `channels` at `sample_rate`,
+/// in the `preferred` sample format if the device offers it that way, or
+/// else another format the capture can convert from.
+fn match_input_config(
+    supported: &[SupportedStreamConfigRange],
+    sample_rate: u32,
+    channels: u16,
+    preferred: SampleFormat,
+) -> Result<SupportedStreamConfig, String> {
+    let mut matching: Vec<SupportedStreamConfig> = supported
+        .iter()
+        .filter(|range| range.channels() == channels)
+        .filter(|range| {
+            matches!(
+                range.sample_format(),
+                SampleFormat::F32 | SampleFormat::I16 | SampleFormat::U16
+            )
+        })
+        .filter_map(|range| range.try_with_sample_rate(SampleRate(sample_rate)))
+        .collect();
+    // stable, so the device's own order breaks ties
+    matching.sort_by_key(|config| config.sample_format() != preferred);
+    matching.into_iter().next().ok_or_else(|| {
+        let offered: Vec<String> = supported
+            .iter()
+            .map(|range| {
+                let (min, max) = (range.min_sample_rate().0, range.max_sample_rate().0);
+                let rates = if min == max {
+                    format!("{min} Hz")
+                } else {
+                    format!("{min}-{max} Hz")
+                };
+                format!(
+                    "{rates} with {} channel(s) as {:?}",
+                    range.channels(),
+                    range.sample_format()
+                )
+            })
+            .collect();
+        format!(
+            "input doesn't support {sample_rate} Hz with {channels} channel(s) (supported: {})",
+            if offered.is_empty() {
+                "nothing".to_string()
+            } else {
+                offered.join(", ")
+            }
+        )
+    })
+}
+
+fn no_device_error(query: &str, names: &[String]) -> String {
+    let names: Vec<&str> = names
+        .iter()
+        .map(String::as_str)
+        .filter(|name| !name.is_empty())
+        .collect();
+    if names.is_empty() {
+        format!(
+            "No input device matching \"{}\" (no devices available)",
+            query
+        )
+    } else {
+        format!(
+            "No input device matching \"{}\" (available: {})",
+            query,
+            names.join(", ")
+        )
+    }
+}
+
+/// How the channels of a multi-channel input become the mono audio Whisper
+/// takes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChannelMix {
+    /// Average all the channels
+    #[default]
+    Mono,
+    /// Only the first channel
+    Left,
+    /// Only the second channel
+    Right,
+    /// Only the channel at this index, counting from 0
+    Channel(usize),
+}
+
+impl ChannelMix {
+    /// Index of the one channel kept, or None when all are averaged.
+    fn channel(self) -> Option<usize> {
+        match self {
+            Self::Mono => None,
+            Self::Left => Some(0),
+            Self::Right => Some(1),
+            Self::Channel(index) => Some(index),
+        }
+    }
+
+    /// Convert interleaved frames of `channels` samples each to mono f32.
+    fn downmix<T>(self, data: &[T], channels: usize) -> Vec<f32>
+    where
+        T: Sample,
+        f32: cpal::FromSample<T>,
+    {
+        let frames = data.chunks(channels);
+        match self.channel() {
+            Some(index) => frames
+                .map(|frame| frame.get(index).map_or(0.0, |s| f32::from_sample(*s)))
+                .collect(),
+            None => frames
+                .map(|frame| {
+                    let sum: f32 = frame.iter().map(|s| f32::from_sample(*s)).sum();
+                    sum / channels as f32
+                })
+                .collect(),
+        }
+    }
+}
+
+impl std::str::FromStr for ChannelMix {
+    type Err = String;
+
+    /// Parse `mono`, `left`, `right`, or a channel number counting from 1,
+    /// as audio interfaces label their inputs.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            s if s.eq_ignore_ascii_case("mono") => Ok(Self::Mono),
+            s if s.eq_ignore_ascii_case("left") => Ok(Self::Left),
+            s if s.eq_ignore_ascii_case("right") => Ok(Self::Right),
+            s => match s.parse::<usize>() {
+                Ok(number) if number >= 1 => Ok(Self::Channel(number - 1)),
+                _ => Err(format!(
+                    "unknown channel: {s} (use mono, left, right or a channel number)"
+                )),
+            },
+        }
+    }
+}
+
+/// How the captured input is turned into the audio transcribed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct InputProcessing {
+    /// Which channels are kept
+    pub mix: ChannelMix,
+    /// Amplitude below which the input is silenced, if gated
+    pub noise_gate: Option<f32>,
+    /// Levels for automatic gain control, if any
+    pub agc: Option<AgcLevels>,
+}
+
+/// What the AGC aims for, and how far it may go to get there.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AgcLevels {
+    /// RMS level the input is brought toward
+    pub target: f32,
+    /// Most the input is amplified, so silence doesn't become loud noise
+    pub max_gain: f32,
+}
+
 /// Audio capture from the system microphone.
 /// Captures audio and resamples to 16kHz mono f32 for Whisper.
 pub struct AudioCapture {
     stream: Stream,
     receiver: Receiver<Vec<f32>>,
+    meter: LevelMeter,
+    device_name: String,
+    /// Set by the stream's error callback
+    device_lost: Arc<AtomicBool>,
 }
 
 impl AudioCapture {
-    /// Create a new audio capture from the default input device.
-    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+    /// An opener for captures that process their input as `processing`
+    /// says, asking the device for `sample_rate` and `channels` where given
+    /// as [`with_config`](Self::with_config) does.
+    pub fn opener(
+        processing: InputProcessing,
+        sample_rate: Option<u32>,
+        channels: Option<u16>,
+    ) -> AudioOpener {
+        Box::new(move |device| {
+            let capture = match (device, sample_rate, channels) {
+                (device, Some(_), _) | (device, _, Some(_)) => {
+                    Self::with_config(device, sample_rate, channels, processing)?
+                }
+                (Some(name), None, None) => Self::with_device(name, processing)?,
+                (None, None, None) => Self::new(processing)?,
+            };
+            Ok(Box::new(capture) as Box<dyn AudioSource>)
+        })
+    }
+
+    /// Create a new audio capture from the default input device, mixing,
+    /// gating and leveling its input as `processing` says.
+    pub fn new(processing: InputProcessing) -> Result<Self, Box<dyn std::error::Error>> {
         let host = cpal::default_host();
 
         let device = host
             .default_input_device()
             .ok_or("No input device available")?;
 
+        Self::from_device(device, processing)
+    }
+
+    /// Create a new audio capture from the input device whose name matches
+    /// `name` (case-insensitive, substring), or whose index it is.
+    pub fn with_device(
+        name: &str,
+        processing: InputProcessing,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_device(find_input_device(name)?, processing)
+    }
+
+    /// Create a new audio capture from the named input device, or else the
+    /// default one, capturing at `sample_rate` with `channels` where given
+    /// instead of the device's defaults. Fails, listing what the device
+    /// does support, if it can't capture that way.
+    pub fn with_config(
+        device_name: Option<&str>,
+        sample_rate: Option<u32>,
+        channels: Option<u16>,
+        processing: InputProcessing,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let device = match device_name {
+            Some(name) => find_input_device(name)?,
+            None => cpal::default_host()
+                .default_input_device()
+                .ok_or("No input device available")?,
+        };
+
+        let default = device.default_input_config()?;
+        let supported: Vec<SupportedStreamConfigRange> =
+            device.supported_input_configs()?.collect();
+        let config = match_input_config(
+            &supported,
+            sample_rate.unwrap_or(default.sample_rate().0),
+            channels.unwrap_or(default.channels()),
+            default.sample_format(),
+        )?;
+        Self::from_config(device, config, processing)
+    }
+
+    fn from_device(
+        device: cpal::Device,
+        processing: InputProcessing,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let config = device.default_input_config()?;
+        Self::from_config(device, config, processing)
+    }
+
+    fn from_config(
+        device: cpal::Device,
+        config: SupportedStreamConfig,
+        processing: InputProcessing,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         let device_name = device.name().unwrap_or_else(|_| "unknown".to_string());
         log::info!("Using input device: {}", device_name);
 
-        // Get the default config
-        let config = device.default_input_config()?;
         let sample_rate = config.sample_rate().0;
         let channels = config.channels() as usize;
 
@@ -36,77 +490,153 @@ impl AudioCapture {
             channels,
             config.sample_format()
         );
+        if let Some(index) = processing.mix.channel().filter(|&index| index >= channels) {
+            return Err(format!(
+                "input has {channels} channel(s), so there is no channel {}",
+                index + 1
+            )
+            .into());
+        }
 
         let (sender, receiver) = mpsc::channel::<Vec<f32>>();
 
-        // Calculate resampling ratio
-        let resample_ratio = WHISPER_SAMPLE_RATE as f64 / sample_rate as f64;
+        let meter = LevelMeter::default();
+        let device_lost = Arc::new(AtomicBool::new(false));
 
         let stream = match config.sample_format() {
-            SampleFormat::F32 => {
-                build_stream::<f32>(&device, &config.into(), sender, channels, resample_ratio)?
-            }
-            SampleFormat::I16 => {
-                build_stream::<i16>(&device, &config.into(), sender, channels, resample_ratio)?
-            }
-            SampleFormat::U16 => {
-                build_stream::<u16>(&device, &config.into(), sender, channels, resample_ratio)?
-            }
+            SampleFormat::F32 => build_stream::<f32>(
+                &device,
+                &config.into(),
+                processing,
+                sender,
+                meter.clone(),
+                Arc::clone(&device_lost),
+            )?,
+            SampleFormat::I16 => build_stream::<i16>(
+                &device,
+                &config.into(),
+                processing,
+                sender,
+                meter.clone(),
+                Arc::clone(&device_lost),
+            )?,
+            SampleFormat::U16 => build_stream::<u16>(
+                &device,
+                &config.into(),
+                processing,
+                sender,
+                meter.clone(),
+                Arc::clone(&device_lost),
+            )?,
             format => return Err(format!("Unsupported sample format: {:?}", format).into()),
         };
 
-        Ok(Self { stream, receiver })
+        Ok(Self {
+            stream,
+            receiver,
+            meter,
+            device_name,
+            device_lost,
+        })
     }
+}
 
-    /// Start capturing audio.
-    pub fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
+impl AudioSource for AudioCapture {
+    fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
         self.stream.play()?;
         log::info!("Audio capture started");
         Ok(())
     }
 
-    /// Stop capturing audio.
-    pub fn stop(&self) -> Result<(), Box<dyn std::error::Error>> {
+    fn stop(&self) -> Result<(), Box<dyn std::error::Error>> {
         self.stream.pause()?;
         log::info!("Audio capture stopped");
         Ok(())
     }
 
-    /// Receive captured audio samples (16kHz mono f32).
-    /// Returns None if no samples are available (non-blocking).
-    pub fn recv(&self) -> Option<Vec<f32>> {
+    fn try_recv(&self) -> Option<Vec<f32>> {
         self.receiver.try_recv().ok()
     }
+
+    fn level_meter(&self) -> Option<LevelMeter> {
+        Some(self.meter.clone())
+    }
+
+    fn device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::SeqCst)
+    }
+
+    fn device_name(&self) -> Option<String> {
+        Some(self.device_name.clone())
+    }
 }
 
 /// Build an input stream for the given sample type.
 fn build_stream<T>(
     device: &cpal::Device,
     config: &StreamConfig,
+    processing: InputProcessing,
     sender: Sender<Vec<f32>>,
-    channels: usize,
-    resample_ratio: f64,
+    meter: LevelMeter,
+    device_lost: Arc<AtomicBool>,
 ) -> Result<Stream, Box<dyn std::error::Error>>
 where
     T: cpal::Sample + cpal::SizedSample + Send + 'static,
     f32: cpal::FromSample<T>,
 {
-    let err_fn = |err| log::error!("Audio stream error: {}", err);
+    // stream errors mean the device has gone away; the recording worker
+    // notices the flag and reconnects or stops
+    let err_fn = move |err| {
+        log::error!("Audio stream error: {}", err);
+        device_lost.store(true, Ordering::SeqCst);
+    };
+    let channels = config.channels as usize;
+    let sample_rate = config.sample_rate.0 as f32;
+
+    // Calculate resampling ratio
+    let resample_ratio = WHISPER_SAMPLE_RATE as f64 / sample_rate as f64;
+
+    // Filter out content above the new Nyquist frequency before downsampling
+    let mut filter =
+        (resample_ratio < 1.0).then(|| LowPass::new(ANTI_ALIAS_CUTOFF_HZ, sample_rate));
+    let mut resampler = SincResampler::new(resample_ratio);
+
+    let mix = processing.mix;
+    let mut gate = processing.noise_gate.map(|threshold| {
+        NoiseGate::new(
+            threshold,
+            NOISE_GATE_ATTACK_MS,
+            NOISE_GATE_RELEASE_MS,
+            sample_rate,
+        )
+    });
+    let mut agc = processing
+        .agc
+        .map(|levels| Agc::new(levels.target, levels.max_gain, sample_rate));
 
     let stream = device.build_input_stream(
         config,
         move |data: &[T], _: &cpal::InputCallbackInfo| {
-            // Convert to f32 and mix to mono
-            let mono: Vec<f32> = data
-                .chunks(channels)
-                .map(|frame| {
-                    let sum: f32 = frame.iter().map(|s| f32::from_sample(*s)).sum();
-                    sum / channels as f32
-                })
-                .collect();
+            // Convert to f32 and mix to mono, silencing background noise
+            // and then leveling what is left
+            let mono = mix.downmix(data, channels);
+            let mono = match gate.as_mut() {
+                Some(gate) => gate.process(&mono),
+                None => mono,
+            };
+            let mono = match agc.as_mut() {
+                Some(agc) => agc.process(&mono),
+                None => mono,
+            };
 
-            // Resample to 16kHz
-            let resampled = resample(&mono, resample_ratio);
+            meter.update(&mono, sample_rate);
+
+            // Low-pass, then resample to 16kHz
+            let filtered = match filter.as_mut() {
+                Some(filter) => filter.process(&mono),
+                None => mono,
+            };
+            let resampled = resampler.process(&filtered);
 
             if sender.send(resampled).is_err() {
                 log::warn!("Audio receiver dropped");
@@ -119,6 +649,164 @@ where
     Ok(stream)
 }
 
+/// Second-order (biquad) Butterworth low-pass filter.
+/// Keeps its state between calls, so a stream can be filtered in chunks.
+pub struct LowPass {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl LowPass {
+    /// Create a low-pass filter with the given cutoff for audio at `sample_rate`.
+    pub fn new(cutoff_hz: f32, sample_rate: f32) -> Self {
+        let omega = 2.0 * std::f32::consts::PI * cutoff_hz / sample_rate;
+        let alpha = omega.sin() / (2.0 * std::f32::consts::FRAC_1_SQRT_2);
+        let cos = omega.cos();
+        let a0 = 1.0 + alpha;
+
+        Self {
+            b0: (1.0 - cos) / 2.0 / a0,
+            b1: (1.0 - cos) / a0,
+            b2: (1.0 - cos) / 2.0 / a0,
+            a1: -2.0 * cos / a0,
+            a2: (1.0 - alpha) / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    /// Filter a block of samples, continuing from the previous block.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        samples
+            .iter()
+            .map(|&x| {
+                let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+                    - self.a1 * self.y1
+                    - self.a2 * self.y2;
+                self.x2 = self.x1;
+                self.x1 = x;
+                self.y2 = self.y1;
+                self.y1 = y;
+                y
+            })
+            .collect()
+    }
+}
+
+/// Noise gate that silences audio quieter than a threshold, such as a
+/// constant hum, while letting speech through. It opens over `attack_ms`
+/// and closes over `release_ms`, so it doesn't chop the ends off words.
+/// Keeps its state between calls, like [`LowPass`].
+pub struct NoiseGate {
+    threshold: f32,
+    /// Gain added per sample while opening
+    attack_step: f32,
+    /// Gain taken away per sample while closing
+    release_step: f32,
+    /// Per-sample decay of the envelope
+    decay: f32,
+    envelope: f32,
+    gain: f32,
+}
+
+impl NoiseGate {
+    /// Create a closed gate for audio at `sample_rate` that opens for
+    /// samples reaching `threshold` in amplitude.
+    pub fn new(threshold: f32, attack_ms: f32, release_ms: f32, sample_rate: f32) -> Self {
+        let samples = |ms: f32| (ms / 1000.0 * sample_rate).max(1.0);
+        Self {
+            threshold,
+            attack_step: 1.0 / samples(attack_ms),
+            release_step: 1.0 / samples(release_ms),
+            decay: (-1.0 / samples(NOISE_GATE_ENVELOPE_MS)).exp(),
+            envelope: 0.0,
+            gain: 0.0,
+        }
+    }
+
+    /// Gate a block of samples, continuing from the previous block.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        samples
+            .iter()
+            .map(|&x| {
+                self.envelope = x.abs().max(self.envelope * self.decay);
+                self.gain = if self.envelope >= self.threshold {
+                    (self.gain + self.attack_step).min(1.0)
+                } else {
+                    (self.gain - self.release_step).max(0.0)
+                };
+                x * self.gain
+            })
+            .collect()
+    }
+}
+
+/// Automatic gain control, which brings the input toward a target RMS
+/// level so a quiet or distant speaker reaches Whisper at a usable level.
+/// The gain is bounded, and limited so the output never clips. Keeps its
+/// state between calls, like [`LowPass`].
+pub struct Agc {
+    target: f32,
+    max_gain: f32,
+    /// Per-sample decay of the running mean square
+    window: f32,
+    /// Per-sample decay of the distance to a higher gain
+    release: f32,
+    mean_square: f32,
+    gain: f32,
+}
+
+impl Agc {
+    /// Create an AGC for audio at `sample_rate` that aims for an RMS level
+    /// of `target`, amplifying by at most `max_gain`. It starts at unity gain.
+    pub fn new(target: f32, max_gain: f32, sample_rate: f32) -> Self {
+        let decay = |ms: f32| (-1.0 / (ms / 1000.0 * sample_rate).max(1.0)).exp();
+        Self {
+            target,
+            max_gain,
+            window: decay(AGC_WINDOW_MS),
+            release: decay(AGC_RELEASE_MS),
+            mean_square: 0.0,
+            gain: 1.0,
+        }
+    }
+
+    /// Level a block of samples, continuing from the previous block.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        samples
+            .iter()
+            .map(|&x| {
+                self.mean_square = self.window * self.mean_square + (1.0 - self.window) * x * x;
+                let rms = self.mean_square.sqrt();
+                let wanted = if rms > 0.0 {
+                    (self.target / rms).min(self.max_gain)
+                } else {
+                    self.max_gain
+                };
+                self.gain = if wanted < self.gain {
+                    wanted
+                } else {
+                    wanted - (wanted - self.gain) * self.release
+                };
+                // a peak the gain would push past the ceiling lowers it
+                if (x * self.gain).abs() > AGC_CEILING {
+                    self.gain = AGC_CEILING / x.abs();
+                }
+                (x * self.gain).clamp(-1.0, 1.0)
+            })
+            .collect()
+    }
+}
+
 /// Simple linear interpolation resampling.
 /// For ratio < 1.0, this downsamples (e.g., 48kHz -> 16kHz).
 /// For ratio > 1.0, this upsamples.
@@ -148,6 +836,94 @@ fn resample(samples: &[f32], ratio: f64) -> Vec<f32> {
     output
 }
 
+/// Windowed-sinc (Lanczos, a=3) resampling.
+/// When downsampling the kernel is stretched so its cutoff sits just below
+/// the new Nyquist frequency, filtering out content that would otherwise alias.
+/// Keeps its state between calls, like [`LowPass`]: each output sample waits
+/// until the input under its whole kernel has arrived, so chunk edges don't
+/// show. Falls back to `resample` when the rate is unchanged.
+pub struct SincResampler {
+    ratio: f64,
+    cutoff: f64,
+    half_width: f64,
+    /// Input that later output samples still need
+    history: Vec<f32>,
+    /// Stream position of the first sample in `history`
+    start: u64,
+    /// Output samples produced so far
+    produced: u64,
+}
+
+impl SincResampler {
+    /// Create a resampler from one rate to `ratio` times it.
+    pub fn new(ratio: f64) -> Self {
+        let cutoff = ratio.min(1.0) * RESAMPLE_ROLLOFF;
+        Self {
+            ratio,
+            cutoff,
+            half_width: LANCZOS_A / cutoff,
+            history: Vec::new(),
+            start: 0,
+            produced: 0,
+        }
+    }
+
+    /// Resample a block of samples, continuing from the previous block.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        if (self.ratio - 1.0).abs() < 0.001 {
+            return resample(samples, self.ratio);
+        }
+
+        self.history.extend_from_slice(samples);
+        let end = self.start + self.history.len() as u64;
+        let mut output = Vec::new();
+
+        loop {
+            let center = self.produced as f64 / self.ratio;
+            let last = (center + self.half_width).floor() as u64;
+            if last >= end {
+                break;
+            }
+            let first = (center - self.half_width).ceil().max(0.0) as u64;
+
+            let mut sum = 0.0;
+            let mut weight_sum = 0.0;
+            for j in first..=last {
+                let weight = lanczos((center - j as f64) * self.cutoff);
+                sum += self.history[(j - self.start) as usize] as f64 * weight;
+                weight_sum += weight;
+            }
+
+            output.push(if weight_sum > 0.0 {
+                (sum / weight_sum) as f32
+            } else {
+                0.0
+            });
+            self.produced += 1;
+        }
+
+        // drop what the next output sample's kernel no longer reaches
+        let center = self.produced as f64 / self.ratio;
+        let keep = ((center - self.half_width).ceil().max(0.0) as u64).max(self.start);
+        self.history.drain(..(keep - self.start) as usize);
+        self.start = keep;
+
+        output
+    }
+}
+
+/// The Lanczos kernel: sinc(x) windowed by sinc(x / a).
+fn lanczos(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else if x.abs() >= LANCZOS_A {
+        0.0
+    } else {
+        let px = std::f64::consts::PI * x;
+        LANCZOS_A * px.sin() * (px / LANCZOS_A).sin() / (px * px)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -170,10 +946,277 @@ mod tests {
         assert_eq!(output, input);
     }
 
+    /// Peak amplitude of the middle of a signal, away from edge effects.
+    fn peak_amplitude(samples: &[f32]) -> f32 {
+        let margin = samples.len() / 4;
+        samples[margin..samples.len() - margin]
+            .iter()
+            .fold(0.0, |peak, s| peak.max(s.abs()))
+    }
+
+    fn sine(frequency: f32, sample_rate: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_resample_sinc_preserves_passband() {
+        let input = sine(4000.0, 48000.0, 4800);
+        let output = SincResampler::new(16.0 / 48.0).process(&input);
+
+        // the last few wait for the input past the end of their kernels
+        assert_eq!(output.len(), 1597);
+        let amplitude = peak_amplitude(&output);
+        assert!(
+            (amplitude - 1.0).abs() < 0.05,
+            "4kHz amplitude {} not within 5%",
+            amplitude
+        );
+    }
+
+    #[test]
+    fn test_resample_sinc_attenuates_above_nyquist() {
+        let input = sine(10000.0, 48000.0, 4800);
+        let output = SincResampler::new(16.0 / 48.0).process(&input);
+
+        // -20dB
+        let amplitude = peak_amplitude(&output);
+        assert!(
+            amplitude < 0.1,
+            "10kHz amplitude {} not attenuated",
+            amplitude
+        );
+    }
+
+    #[test]
+    fn test_resample_sinc_no_change() {
+        let input: Vec<f32> = vec![1.0, 2.0, 3.0, 4.0];
+        assert_eq!(SincResampler::new(1.0).process(&input), input);
+    }
+
+    #[test]
+    fn test_resample_sinc_chunked_matches_whole() {
+        let input = sine(1000.0, 44100.0, 4410);
+
+        let whole = SincResampler::new(16000.0 / 44100.0).process(&input);
+        let mut chunked_resampler = SincResampler::new(16000.0 / 44100.0);
+        let chunked: Vec<f32> = input
+            .chunks(441)
+            .flat_map(|chunk| chunked_resampler.process(chunk))
+            .collect();
+
+        assert_eq!(whole, chunked);
+    }
+
+    /// Signal energy above and below `split_hz`, from a naive DFT.
+    fn band_energy(samples: &[f32], sample_rate: f32, split_hz: f32) -> (f64, f64) {
+        let n = samples.len();
+        let (mut low, mut high) = (0.0, 0.0);
+        for k in 1..n / 2 {
+            let (mut re, mut im) = (0.0f64, 0.0f64);
+            for (i, &x) in samples.iter().enumerate() {
+                let phase = 2.0 * std::f64::consts::PI * (k * i) as f64 / n as f64;
+                re += x as f64 * phase.cos();
+                im -= x as f64 * phase.sin();
+            }
+            let power = re * re + im * im;
+            if k as f32 * sample_rate / (n as f32) < split_hz {
+                low += power;
+            } else {
+                high += power;
+            }
+        }
+        (low, high)
+    }
+
+    #[test]
+    fn test_low_pass_reduces_high_frequencies() {
+        // deterministic white noise
+        let mut seed: u32 = 12345;
+        let noise: Vec<f32> = (0..2048)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (seed >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+            })
+            .collect();
+
+        let mut filter = LowPass::new(ANTI_ALIAS_CUTOFF_HZ, 48000.0);
+        let filtered = filter.process(&noise);
+
+        let (low_before, high_before) = band_energy(&noise[1024..], 48000.0, 8000.0);
+        let (low_after, high_after) = band_energy(&filtered[1024..], 48000.0, 8000.0);
+
+        let ratio_before = high_before / low_before;
+        let ratio_after = high_after / low_after;
+        assert!(
+            ratio_after < ratio_before / 10.0,
+            "high/low energy {ratio_before:.3} -> {ratio_after:.3}"
+        );
+    }
+
+    #[test]
+    fn test_low_pass_chunked_matches_whole() {
+        let input: Vec<f32> = (0..300).map(|i| ((i * 7) % 13) as f32 / 13.0).collect();
+
+        let whole = LowPass::new(ANTI_ALIAS_CUTOFF_HZ, 48000.0).process(&input);
+        let mut chunked_filter = LowPass::new(ANTI_ALIAS_CUTOFF_HZ, 48000.0);
+        let chunked: Vec<f32> = input
+            .chunks(64)
+            .flat_map(|chunk| chunked_filter.process(chunk))
+            .collect();
+
+        assert_eq!(whole, chunked);
+    }
+
+    #[test]
+    fn test_noise_gate_silences_hum_but_not_speech() {
+        let rate = 16000.0;
+        let peak = |samples: &[f32]| samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        let hum: Vec<f32> = sine(60.0, rate, 8000).iter().map(|s| s * 0.005).collect();
+        let burst: Vec<f32> = sine(440.0, rate, 4000).iter().map(|s| s * 0.5).collect();
+        let mut gate = NoiseGate::new(0.02, 5.0, 50.0, rate);
+
+        assert_eq!(peak(&gate.process(&hum)), 0.0);
+
+        // the burst fades in over the attack, then passes untouched
+        let out = gate.process(&burst);
+        assert!(peak(&out[..40]) < 0.25);
+        let opened = 160;
+        for (out, input) in out[opened..].iter().zip(&burst[opened..]) {
+            assert_eq!(out, input);
+        }
+
+        // the hum after it fades out rather than being cut off
+        let out = gate.process(&hum);
+        assert!(peak(&out[..160]) > 0.0);
+        assert_eq!(peak(&out[4000..]), 0.0);
+    }
+
+    #[test]
+    fn test_agc_raises_quiet_input_to_target() {
+        let rate = 16000.0;
+        let rms = |samples: &[f32]| {
+            (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+        };
+        let peak = |samples: &[f32]| samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        let quiet: Vec<f32> = sine(440.0, rate, 64000).iter().map(|s| s * 0.02).collect();
+        let mut agc = Agc::new(0.1, 20.0, rate);
+
+        // fed in chunks, as the stream would; after a few seconds the level
+        // has settled on the target
+        let out: Vec<f32> = quiet
+            .chunks(480)
+            .flat_map(|chunk| agc.process(chunk))
+            .collect();
+        let settled = rms(&out[48000..]);
+        assert!((settled - 0.1).abs() < 0.01, "settled at {settled}");
+        assert!(peak(&out) <= 1.0);
+
+        // a shout straight after is turned down rather than clipped
+        let loud: Vec<f32> = sine(440.0, rate, 8000).iter().map(|s| s * 0.9).collect();
+        let out = agc.process(&loud);
+        assert!(peak(&out) <= 1.0);
+        assert!(rms(&out[4000..]) < 0.2);
+
+        // and the gain never goes past the limit
+        let mut agc = Agc::new(0.1, 4.0, rate);
+        let out = agc.process(&quiet);
+        assert!(peak(&out[48000..]) <= 0.02 * 4.0 + 1e-6);
+    }
+
+    #[test]
+    fn test_level_meter_converges_smoothly() {
+        let meter = LevelMeter::default();
+        let chunk = vec![0.5; 480]; // 10ms at 48kHz, RMS 0.5
+
+        meter.update(&chunk, 48000.0);
+        let first = meter.level();
+        assert!(first > 0.0 && first < 0.1, "jumped to {first}");
+
+        // a second of audio is five time constants
+        for _ in 0..100 {
+            meter.update(&chunk, 48000.0);
+        }
+        assert!(
+            (meter.level() - 0.5).abs() < 0.01,
+            "level {}",
+            meter.level()
+        );
+
+        // and it decays rather than dropping straight to zero
+        meter.update(&vec![0.0; 480], 48000.0);
+        assert!(meter.level() > 0.4);
+    }
+
+    #[test]
+    fn test_channel_mix_picks_channel() {
+        // interleaved stereo: voice on the left, noise on the right
+        let frames = [0.5, -0.25, 0.1, 0.9, -0.3, 0.0];
+        assert_eq!(ChannelMix::Left.downmix(&frames, 2), [0.5, 0.1, -0.3]);
+        assert_eq!(ChannelMix::Right.downmix(&frames, 2), [-0.25, 0.9, 0.0]);
+        assert_eq!(ChannelMix::Mono.downmix(&frames, 2), [0.125, 0.5, -0.15]);
+        assert_eq!(ChannelMix::Channel(2).downmix(&[1.0, 2.0, 3.0], 3), [3.0]);
+
+        // integer samples are converted too
+        let frames: [i16; 4] = [i16::MIN, 0, 0, i16::MIN];
+        assert_eq!(ChannelMix::Left.downmix(&frames, 2), [-1.0, 0.0]);
+    }
+
+    #[test]
+    fn test_channel_mix_from_str() {
+        assert_eq!("mono".parse(), Ok(ChannelMix::Mono));
+        assert_eq!(" Left ".parse(), Ok(ChannelMix::Left));
+        assert_eq!("RIGHT".parse(), Ok(ChannelMix::Right));
+        assert_eq!("3".parse(), Ok(ChannelMix::Channel(2)));
+        assert!("0".parse::<ChannelMix>().is_err());
+        assert!("centre".parse::<ChannelMix>().is_err());
+    }
+
+    #[test]
+    fn test_match_device_name() {
+        let names: Vec<String> = ["Built-in Microphone", "USB Podcast Mic", "USB"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+
+        assert_eq!(match_device_name(&names, "podcast"), Some(1));
+        assert_eq!(match_device_name(&names, "BUILT-IN"), Some(0));
+        // an exact match beats an earlier substring match
+        assert_eq!(match_device_name(&names, "usb"), Some(2));
+        assert_eq!(match_device_name(&names, "webcam"), None);
+
+        // device indices, as listed by DEVICES
+        assert_eq!(match_device_name(&names, "1"), Some(1));
+        assert_eq!(match_device_name(&names, "3"), None);
+        let numbered = vec!["Input 2".to_string(), "2".to_string()];
+        assert_eq!(match_device_name(&numbered, "2"), Some(1));
+        assert_eq!(match_device_name(&numbered, "0"), Some(0));
+        // a device named like an index is found by name, even when that
+        // index is another device's
+        let numbered: Vec<String> = ["Built-in", "2", "USB", "1"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        assert_eq!(match_device_name(&numbered, "2"), Some(1));
+        assert_eq!(match_device_name(&numbered, "1"), Some(3));
+        assert_eq!(match_device_name(&numbered, "0"), Some(0));
+    }
+
+    #[test]
+    fn test_no_device_error_lists_names() {
+        let names = vec!["Built-in Microphone".to_string(), "USB Mic".to_string()];
+        assert_eq!(
+            no_device_error("webcam", &names),
+            "No input device matching \"webcam\" (available: Built-in Microphone, USB Mic)"
+        );
+    }
+
     #[test]
     #[ignore] // Run manually: cargo test test_capture_audio -- --ignored --nocapture
     fn test_capture_audio() {
-        let capture = AudioCapture::new().expect("Failed to create audio capture");
+        let capture =
+            AudioCapture::new(InputProcessing::default()).expect("Failed to create audio capture");
         capture.start().expect("Failed to start capture");
 
         println!("Recording for 2 seconds...");
@@ -194,6 +1237,68 @@ mod tests {
         assert!(total_samples > 0, "Should have captured some audio");
     }
 
+    #[test]
+    fn test_match_input_config() {
+        let range = |channels, min, max, format| {
+            SupportedStreamConfigRange::new(
+                channels,
+                SampleRate(min),
+                SampleRate(max),
+                cpal::SupportedBufferSize::Unknown,
+                format,
+            )
+        };
+        let supported = [
+            range(2, 44100, 48000, SampleFormat::I16),
+            range(2, 44100, 48000, SampleFormat::F32),
+            range(1, 16000, 16000, SampleFormat::I16),
+            range(4, 96000, 96000, SampleFormat::I32),
+        ];
+
+        let config = match_input_config(&supported, 48000, 2, SampleFormat::F32).unwrap();
+        assert_eq!(config.sample_rate(), SampleRate(48000));
+        assert_eq!(config.channels(), 2);
+        assert_eq!(config.sample_format(), SampleFormat::F32);
+
+        // the device's default format isn't offered here, so the first that is
+        let config = match_input_config(&supported, 44100, 2, SampleFormat::U16).unwrap();
+        assert_eq!(config.sample_format(), SampleFormat::I16);
+
+        let config = match_input_config(&supported, 16000, 1, SampleFormat::F32).unwrap();
+        assert_eq!(config.sample_rate(), SampleRate(16000));
+
+        // a format the capture can't convert is no match
+        assert!(match_input_config(&supported, 96000, 4, SampleFormat::I32).is_err());
+        assert_eq!(
+            match_input_config(&supported[2..3], 22050, 1, SampleFormat::I16),
+            Err("input doesn't support 22050 Hz with 1 channel(s) \
+                 (supported: 16000 Hz with 1 channel(s) as I16)"
+                .to_string())
+        );
+        assert!(match_input_config(&[], 48000, 2, SampleFormat::F32)
+            .unwrap_err()
+            .ends_with("(supported: nothing)"));
+    }
+
+    #[test]
+    #[ignore] // Run manually: cargo test test_capture_with_config -- --ignored --nocapture
+    fn test_capture_with_config() {
+        let capture =
+            AudioCapture::with_config(None, Some(48000), Some(1), InputProcessing::default())
+                .expect("Failed to create audio capture at 48kHz mono");
+        capture.start().expect("Failed to start capture");
+        std::thread::sleep(Duration::from_secs(1));
+
+        let mut total_samples = 0;
+        while let Some(samples) = capture.try_recv() {
+            total_samples += samples.len();
+        }
+        capture.stop().expect("Failed to stop capture");
+
+        // a second, resampled to 16kHz
+        assert!(total_samples > 12000, "got {total_samples} samples");
+    }
+
     #[test]
     #[ignore] // Run manually: cargo test test_live_transcription -- --ignored --nocapture
     fn test_live_transcription() {
@@ -204,7 +1309,8 @@ mod tests {
 
         let transcriber =
             StreamingTranscriber::new(Duration::from_secs(10)).expect("Failed to create transcriber");
-        let capture = AudioCapture::new().expect("Failed to create audio capture");
+        let capture =
+            AudioCapture::new(InputProcessing::default()).expect("Failed to create audio capture");
 
         capture.start().expect("Failed to start capture");
 
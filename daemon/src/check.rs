@@ -0,0 +1,206 @@
+//! `daemon --check`: a one-shot self-test of the whole pipeline.
+//!
+//! It loads the model, records a second from the input device and runs one
+//! transcription pass over it, reporting how each stage went. Nothing is
+//! bound, so it can run beside a daemon that is already serving.
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::audio::AudioOpener;
+use crate::whisper::{Model, ModelLoader, StreamingTranscriber, SAMPLE_RATE};
+
+/// How much audio is recorded.
+const CAPTURE: Duration = Duration::from_secs(1);
+
+/// Longest to wait for that audio before giving up on the device.
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The level reported for digital silence, which would otherwise be -inf
+const SILENCE_DBFS: f32 = -120.0;
+
+/// Run every stage, writing the report to `out`. Returns true if they all passed.
+pub fn run(
+    out: &mut impl Write,
+    model_path: &Path,
+    load_model: ModelLoader,
+    open_audio: AudioOpener,
+) -> io::Result<bool> {
+    writeln!(out, "model: {}", model_path.display())?;
+    let started = Instant::now();
+    let model = match load_model(model_path) {
+        Ok(model) => {
+            writeln!(out, "  ok, loaded in {} ms", started.elapsed().as_millis())?;
+            Some(model)
+        }
+        Err(e) => {
+            writeln!(out, "  FAILED: {e}")?;
+            None
+        }
+    };
+
+    writeln!(out, "audio:")?;
+    let audio = match record(&open_audio) {
+        Ok((device, samples)) => {
+            let rms = rms(&samples);
+            writeln!(
+                out,
+                "  ok, {} ms from {}, RMS {rms:.4} ({:.1} dBFS)",
+                samples.len() * 1000 / SAMPLE_RATE,
+                device.as_deref().unwrap_or("the default input"),
+                dbfs(rms),
+            )?;
+            if rms == 0.0 {
+                writeln!(out, "  warning: the input is silent; is it muted?")?;
+            }
+            Some(samples)
+        }
+        Err(e) => {
+            writeln!(out, "  FAILED: {e}")?;
+            None
+        }
+    };
+
+    writeln!(out, "transcription:")?;
+    let (Some(model), Some(audio)) = (model, audio) else {
+        writeln!(out, "  skipped")?;
+        return Ok(false);
+    };
+    let started = Instant::now();
+    match transcribe(model, &audio) {
+        Ok(text) => {
+            let took = started.elapsed().as_millis();
+            if text.is_empty() {
+                writeln!(out, "  ok in {took} ms, no speech heard")?;
+            } else {
+                writeln!(out, "  ok in {took} ms: {text:?}")?;
+            }
+            Ok(true)
+        }
+        Err(e) => {
+            writeln!(out, "  FAILED: {e}")?;
+            Ok(false)
+        }
+    }
+}
+
+/// Record [`CAPTURE`] of audio from the default input, along with the
+/// device's name if it is known.
+fn record(
+    open_audio: &AudioOpener,
+) -> Result<(Option<String>, Vec<f32>), Box<dyn std::error::Error>> {
+    let source = open_audio(None)?;
+    source.start()?;
+    let wanted = SAMPLE_RATE * CAPTURE.as_millis() as usize / 1000;
+    let deadline = Instant::now() + CAPTURE_TIMEOUT;
+    let mut samples = Vec::with_capacity(wanted);
+    while samples.len() < wanted {
+        if source.device_lost() {
+            break;
+        }
+        match source.try_recv() {
+            Some(chunk) => samples.extend(chunk),
+            None if Instant::now() >= deadline => break,
+            None => std::thread::sleep(Duration::from_millis(10)),
+        }
+    }
+    source.stop()?;
+    if source.device_lost() {
+        return Err("the device was lost while recording".into());
+    }
+    if samples.len() < wanted {
+        return Err(format!(
+            "only {} ms of audio arrived in {} s",
+            samples.len() * 1000 / SAMPLE_RATE,
+            CAPTURE_TIMEOUT.as_secs()
+        )
+        .into());
+    }
+    samples.truncate(wanted);
+    Ok((source.device_name(), samples))
+}
+
+/// One pass over `audio`, as the daemon would make while recording.
+fn transcribe(model: Box<dyn Model>, audio: &[f32]) -> Result<String, Box<dyn std::error::Error>> {
+    let transcriber = StreamingTranscriber::without_model(CAPTURE);
+    transcriber.set_model(model);
+    transcriber.push_audio(audio);
+    let segments = transcriber.transcribe_segments()?;
+    Ok(segments
+        .iter()
+        .map(|segment| segment.text.as_str())
+        .collect::<String>()
+        .trim()
+        .to_string())
+}
+
+fn rms(samples: &[f32]) -> f32 {
+    (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+fn dbfs(rms: f32) -> f32 {
+    (20.0 * rms.log10()).max(SILENCE_DBFS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn check(load_model: ModelLoader, open_audio: AudioOpener) -> (bool, String) {
+        let mut out = Vec::new();
+        let passed = run(&mut out, Path::new("ggml-test.bin"), load_model, open_audio).unwrap();
+        (passed, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_check_passes() {
+        let model = testing::MockModel::default();
+        model.set_text(" hello there");
+        let load_model: ModelLoader =
+            Box::new(move |_| Ok(Box::new(model.clone()) as Box<dyn Model>));
+        let (senders, sources) = std::sync::mpsc::channel();
+        let speaker = std::thread::spawn(move || {
+            let audio: std::sync::mpsc::Sender<Vec<f32>> =
+                sources.recv_timeout(Duration::from_secs(5)).unwrap();
+            let tone: Vec<f32> = (0..1600)
+                .map(|i| 0.5 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 16000.0).sin())
+                .collect();
+            for _ in 0..10 {
+                audio.send(tone.clone()).unwrap();
+            }
+        });
+
+        let (passed, report) = check(load_model, testing::channel_opener(senders));
+        speaker.join().unwrap();
+        assert!(passed, "{report}");
+        assert!(
+            report.contains("ok, 1000 ms from the default input, RMS 0.35"),
+            "{report}"
+        );
+        assert!(report.contains("\"hello there\""), "{report}");
+    }
+
+    #[test]
+    fn test_silence_has_a_level() {
+        assert_eq!(format!("{:.1}", dbfs(rms(&[0.0; 1600]))), "-120.0");
+        assert_eq!(format!("{:.1}", dbfs(0.5)), "-6.0");
+    }
+
+    #[test]
+    fn test_check_reports_failures() {
+        let load_model: ModelLoader = Box::new(|_| Err("Model not found: ggml-test.bin".into()));
+        let (passed, report) = check(
+            load_model,
+            testing::failing_opener("No input device available"),
+        );
+        assert!(!passed);
+        assert_eq!(
+            report,
+            "model: ggml-test.bin\n  FAILED: Model not found: ggml-test.bin\n\
+             audio:\n  FAILED: No input device available\n\
+             transcription:\n  skipped\n"
+        );
+    }
+}
@@ -0,0 +1,665 @@
+//! Daemon settings, from the config file, the environment and clients.
+//!
+//! At startup the settings are read from `yowl/config.toml` in the XDG config
+//! directory (`~/.config/yowl/config.toml`), if it exists, and any of the
+//! variables in [`ENV_OVERRIDES`] then take precedence over the file. Keys
+//! left out keep their defaults.
+//!
+//! Settings named by a key in [`KEYS`] can also be read and changed by
+//! clients with GET and SET. Values are validated when set; when a change
+//! takes effect is up to the code that reads it.
+
+use crate::audio::{AgcLevels, ChannelMix, InputProcessing};
+use crate::{logging, whisper};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Names of the settings, in the order they are listed to clients.
+pub const KEYS: &[&str] = &[
+    "transcribe_interval_ms",
+    "buffer_duration_secs",
+    "language",
+    "silence_timeout_ms",
+    "idle_shutdown_minutes",
+];
+
+/// Accepted range for `transcribe_interval_ms`.
+const TRANSCRIBE_INTERVAL_MS_RANGE: std::ops::RangeInclusive<u64> = 100..=5000;
+/// Accepted range for `buffer_duration_secs`.
+const BUFFER_DURATION_SECS_RANGE: std::ops::RangeInclusive<u64> = 2..=30;
+/// Accepted range for `silence_timeout_ms`, where 0 never stops.
+const SILENCE_TIMEOUT_MS_RANGE: std::ops::RangeInclusive<u64> = 0..=60_000;
+/// Accepted range for `idle_shutdown_minutes`, where 0 never shuts down.
+const IDLE_SHUTDOWN_MINUTES_RANGE: std::ops::RangeInclusive<u64> = 0..=10_080;
+/// Accepted range for `log_file_max_mb`.
+const LOG_FILE_MAX_MB_RANGE: std::ops::RangeInclusive<u64> = 1..=1024;
+/// Accepted range for `log_files_kept`.
+const LOG_FILES_KEPT_RANGE: std::ops::RangeInclusive<usize> = 0..=100;
+/// Accepted range for `sample_rate`, besides 0 for the device's default.
+const SAMPLE_RATE_RANGE: std::ops::RangeInclusive<u32> = 8000..=384_000;
+/// Accepted range for `input_channels`, where 0 is the device's default.
+const INPUT_CHANNELS_RANGE: std::ops::RangeInclusive<u16> = 0..=64;
+/// Accepted range for `agc_max_gain`.
+const AGC_MAX_GAIN_RANGE: std::ops::RangeInclusive<f32> = 1.0..=100.0;
+
+/// Environment variables that override the config file, with the setting
+/// each one replaces.
+const ENV_OVERRIDES: &[(&str, &str)] = &[
+    ("YOWL_MODEL", "model"),
+    ("YOWL_LOG_LEVEL", "log_level"),
+    ("YOWL_LOG_FILE_MAX_MB", "log_file_max_mb"),
+    ("YOWL_LOG_FILES_KEPT", "log_files_kept"),
+    ("YOWL_CHANNEL", "channel"),
+    ("YOWL_SAMPLE_RATE", "sample_rate"),
+    ("YOWL_INPUT_CHANNELS", "input_channels"),
+    ("YOWL_NOISE_GATE", "noise_gate"),
+    ("YOWL_AGC_TARGET", "agc_target"),
+    ("YOWL_AGC_MAX_GAIN", "agc_max_gain"),
+    ("YOWL_TRANSCRIBE_INTERVAL_MS", "transcribe_interval_ms"),
+    ("YOWL_BUFFER_DURATION_SECS", "buffer_duration_secs"),
+    ("YOWL_LANGUAGE", "language"),
+    ("YOWL_SILENCE_TIMEOUT_MS", "silence_timeout_ms"),
+    ("YOWL_IDLE_SHUTDOWN_MINUTES", "idle_shutdown_minutes"),
+    ("YOWL_SESSION_LOG_DIR", "session_log_dir"),
+    ("YOWL_GPU", "gpu"),
+];
+
+/// Daemon settings. Those in [`KEYS`] can change while it runs; the rest
+/// are only read at startup.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Time between transcription passes; applies to a recording in progress
+    pub transcribe_interval_ms: u64,
+    /// Length of the rolling audio buffer; applies from the next START
+    pub buffer_duration_secs: u64,
+    /// Whisper language code (or `auto`); applies from the next START
+    pub language: String,
+    /// Silence after speech that ends a recording, or 0 to record until
+    /// STOP; applies to a recording in progress
+    pub silence_timeout_ms: u64,
+    /// Time with no client connected and no recording before the daemon
+    /// exits, or 0 to keep running; applies at once
+    pub idle_shutdown_minutes: u64,
+    /// Model file to load at startup, instead of the default model
+    pub model: Option<PathBuf>,
+    /// Log level at startup: off, error, warn, info, debug or trace
+    pub log_level: String,
+    /// Size in megabytes at which the log file is rotated
+    pub log_file_max_mb: u64,
+    /// Rotated log files kept beside the current one
+    pub log_files_kept: usize,
+    /// Input channel to transcribe: mono to average them all, left, right,
+    /// or a channel number counting from 1
+    pub channel: String,
+    /// Sample rate to capture at, or 0 for the input device's default
+    pub sample_rate: u32,
+    /// Channels to capture, or 0 for the input device's default
+    pub input_channels: u16,
+    /// Amplitude (0 to 1) below which input is silenced, to keep out a
+    /// steady hum, or 0 for no noise gate
+    pub noise_gate: f32,
+    /// RMS level (0 to 1) automatic gain control brings the input toward,
+    /// e.g. 0.1, or 0 for none
+    pub agc_target: f32,
+    /// Most automatic gain control may amplify the input
+    pub agc_max_gain: f32,
+    /// Directory each recording's transcript is also written to, or None
+    /// to keep no copy; applies from the next START
+    pub session_log_dir: Option<PathBuf>,
+    /// Run inference on the GPU, if yowl was built with a GPU backend
+    pub gpu: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            transcribe_interval_ms: 500,
+            buffer_duration_secs: 10,
+            language: whisper::DEFAULT_LANGUAGE.to_string(),
+            silence_timeout_ms: 2000,
+            idle_shutdown_minutes: 0,
+            model: None,
+            log_level: "warn".to_string(),
+            log_file_max_mb: 10,
+            log_files_kept: 3,
+            channel: "mono".to_string(),
+            sample_rate: 0,
+            input_channels: 0,
+            noise_gate: 0.0,
+            agc_target: 0.0,
+            agc_max_gain: 10.0,
+            session_log_dir: None,
+            gpu: whisper::GPU_BUILD,
+        }
+    }
+}
+
+/// Read the config file, if there is one, and apply the environment's
+/// overrides. A value that SET would refuse is an error here too.
+pub fn load() -> Result<Config, String> {
+    load_from(|var| std::env::var(var).ok())
+}
+
+/// Like [`load`], reading the environment through `env`.
+pub fn load_from(env: impl Fn(&str) -> Option<String>) -> Result<Config, String> {
+    let non_empty = |var: &str| env(var).filter(|value: &String| !value.is_empty());
+
+    let mut config = match path_from(non_empty) {
+        Some(path) => match std::fs::read_to_string(&path) {
+            Ok(text) => Config::parse(&text).map_err(|e| format!("{}: {e}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Config::default(),
+            Err(e) => return Err(format!("{}: {e}", path.display())),
+        },
+        None => Config::default(),
+    };
+
+    for (var, key) in ENV_OVERRIDES {
+        if let Some(value) = non_empty(var) {
+            config
+                .set_at_startup(key, &value)
+                .map_err(|e| format!("{var}: {e}"))?;
+        }
+    }
+    Ok(config)
+}
+
+/// Where the config file is looked for, if there is a home to look in.
+fn path_from(non_empty: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    if let Some(config_home) = non_empty("XDG_CONFIG_HOME") {
+        return Some(Path::new(&config_home).join("yowl/config.toml"));
+    }
+    non_empty("HOME").map(|home| Path::new(&home).join(".config/yowl/config.toml"))
+}
+
+impl Config {
+    /// Parse and validate the contents of a config file.
+    fn parse(text: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(text).map_err(|e| e.message().to_string())?;
+
+        // the checks SET makes, which deserializing doesn't
+        let mut checked = config.clone();
+        for key in KEYS.iter().chain(&[
+            "log_level",
+            "log_file_max_mb",
+            "log_files_kept",
+            "channel",
+            "sample_rate",
+            "input_channels",
+            "noise_gate",
+            "agc_target",
+            "agc_max_gain",
+            "gpu",
+        ]) {
+            checked.set_at_startup(key, &config.get_at_startup(key)?)?;
+        }
+        if let Some(dir) = &config.session_log_dir {
+            checked.set_at_startup("session_log_dir", &dir.to_string_lossy())?;
+        }
+        Ok(config)
+    }
+
+    /// The startup log level, which loading has checked.
+    pub fn log_level(&self) -> log::LevelFilter {
+        logging::parse_level(&self.log_level).unwrap_or(log::LevelFilter::Warn)
+    }
+
+    /// How the log file is rotated.
+    pub fn log_rotation(&self) -> logging::Rotation {
+        logging::Rotation {
+            max_bytes: self.log_file_max_mb * 1024 * 1024,
+            keep: self.log_files_kept,
+        }
+    }
+
+    /// How to mix the input's channels, which loading has checked.
+    pub fn channel_mix(&self) -> ChannelMix {
+        self.channel.parse().unwrap_or_default()
+    }
+
+    /// The sample rate asked of the input device, if not its default.
+    pub fn sample_rate(&self) -> Option<u32> {
+        (self.sample_rate > 0).then_some(self.sample_rate)
+    }
+
+    /// The channel count asked of the input device, if not its default.
+    pub fn input_channels(&self) -> Option<u16> {
+        (self.input_channels > 0).then_some(self.input_channels)
+    }
+
+    /// The noise gate's threshold, if there is one.
+    pub fn noise_gate(&self) -> Option<f32> {
+        (self.noise_gate > 0.0).then_some(self.noise_gate)
+    }
+
+    /// Automatic gain control's levels, if it is on.
+    pub fn agc(&self) -> Option<AgcLevels> {
+        (self.agc_target > 0.0).then_some(AgcLevels {
+            target: self.agc_target,
+            max_gain: self.agc_max_gain,
+        })
+    }
+
+    /// How captured audio is processed, all of which loading has checked.
+    pub fn input_processing(&self) -> InputProcessing {
+        InputProcessing {
+            mix: self.channel_mix(),
+            noise_gate: self.noise_gate(),
+            agc: self.agc(),
+        }
+    }
+
+    fn get_at_startup(&self, key: &str) -> Result<String, String> {
+        match key {
+            "log_level" => Ok(self.log_level.clone()),
+            "log_file_max_mb" => Ok(self.log_file_max_mb.to_string()),
+            "log_files_kept" => Ok(self.log_files_kept.to_string()),
+            "channel" => Ok(self.channel.clone()),
+            "sample_rate" => Ok(self.sample_rate.to_string()),
+            "input_channels" => Ok(self.input_channels.to_string()),
+            "noise_gate" => Ok(self.noise_gate.to_string()),
+            "agc_target" => Ok(self.agc_target.to_string()),
+            "agc_max_gain" => Ok(self.agc_max_gain.to_string()),
+            "gpu" => Ok(self.gpu.to_string()),
+            _ => self.get(key),
+        }
+    }
+
+    /// Like [`set`](Self::set), also accepting the settings only read at startup.
+    fn set_at_startup(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "model" => self.model = Some(PathBuf::from(value)),
+            "session_log_dir" => {
+                let dir = PathBuf::from(value);
+                if !dir.is_absolute() {
+                    return Err("session_log_dir must be an absolute path".to_string());
+                }
+                self.session_log_dir = Some(dir);
+            }
+            "log_level" => {
+                if logging::parse_level(value).is_none() {
+                    return Err(format!("unknown log level: {value}"));
+                }
+                self.log_level = value.to_string();
+            }
+            "log_file_max_mb" => match value.parse::<u64>() {
+                Ok(mb) if LOG_FILE_MAX_MB_RANGE.contains(&mb) => self.log_file_max_mb = mb,
+                _ => {
+                    return Err(format!(
+                        "log_file_max_mb must be between {} and {}",
+                        LOG_FILE_MAX_MB_RANGE.start(),
+                        LOG_FILE_MAX_MB_RANGE.end()
+                    ))
+                }
+            },
+            "log_files_kept" => match value.parse::<usize>() {
+                Ok(kept) if LOG_FILES_KEPT_RANGE.contains(&kept) => self.log_files_kept = kept,
+                _ => {
+                    return Err(format!(
+                        "log_files_kept must be between {} and {}",
+                        LOG_FILES_KEPT_RANGE.start(),
+                        LOG_FILES_KEPT_RANGE.end()
+                    ))
+                }
+            },
+            "channel" => {
+                value.parse::<ChannelMix>()?;
+                self.channel = value.to_string();
+            }
+            "sample_rate" => match value.parse::<u32>() {
+                Ok(rate) if rate == 0 || SAMPLE_RATE_RANGE.contains(&rate) => {
+                    self.sample_rate = rate
+                }
+                _ => {
+                    return Err(format!(
+                        "sample_rate must be 0 or between {} and {}",
+                        SAMPLE_RATE_RANGE.start(),
+                        SAMPLE_RATE_RANGE.end()
+                    ))
+                }
+            },
+            "input_channels" => match value.parse::<u16>() {
+                Ok(channels) if INPUT_CHANNELS_RANGE.contains(&channels) => {
+                    self.input_channels = channels
+                }
+                _ => {
+                    return Err(format!(
+                        "input_channels must be between {} and {}",
+                        INPUT_CHANNELS_RANGE.start(),
+                        INPUT_CHANNELS_RANGE.end()
+                    ))
+                }
+            },
+            "noise_gate" => match value.parse::<f32>() {
+                Ok(threshold) if (0.0..1.0).contains(&threshold) => self.noise_gate = threshold,
+                _ => return Err("noise_gate must be at least 0 and below 1".to_string()),
+            },
+            "agc_target" => match value.parse::<f32>() {
+                Ok(target) if (0.0..1.0).contains(&target) => self.agc_target = target,
+                _ => return Err("agc_target must be at least 0 and below 1".to_string()),
+            },
+            "agc_max_gain" => match value.parse::<f32>() {
+                Ok(gain) if AGC_MAX_GAIN_RANGE.contains(&gain) => self.agc_max_gain = gain,
+                _ => {
+                    return Err(format!(
+                        "agc_max_gain must be between {} and {}",
+                        AGC_MAX_GAIN_RANGE.start(),
+                        AGC_MAX_GAIN_RANGE.end()
+                    ))
+                }
+            },
+            "gpu" => match value.to_lowercase().as_str() {
+                "true" | "on" | "1" => self.gpu = true,
+                "false" | "off" | "0" => self.gpu = false,
+                _ => return Err("gpu must be on or off".to_string()),
+            },
+            _ => return self.set(key, value),
+        }
+        Ok(())
+    }
+
+    /// The value of `key`, formatted as SET accepts it.
+    pub fn get(&self, key: &str) -> Result<String, String> {
+        match key {
+            "transcribe_interval_ms" => Ok(self.transcribe_interval_ms.to_string()),
+            "buffer_duration_secs" => Ok(self.buffer_duration_secs.to_string()),
+            "language" => Ok(self.language.clone()),
+            "silence_timeout_ms" => Ok(self.silence_timeout_ms.to_string()),
+            "idle_shutdown_minutes" => Ok(self.idle_shutdown_minutes.to_string()),
+            _ => Err(unknown_key(key)),
+        }
+    }
+
+    /// Validate `value` and store it under `key`. Nothing changes on error.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "transcribe_interval_ms" => {
+                self.transcribe_interval_ms =
+                    parse_in_range(key, value, TRANSCRIBE_INTERVAL_MS_RANGE)?;
+            }
+            "buffer_duration_secs" => {
+                self.buffer_duration_secs = parse_in_range(key, value, BUFFER_DURATION_SECS_RANGE)?;
+            }
+            "language" => {
+                if !whisper::is_valid_language(value) {
+                    return Err(format!("unknown language: {value}"));
+                }
+                self.language = value.to_string();
+            }
+            "silence_timeout_ms" => {
+                self.silence_timeout_ms = parse_in_range(key, value, SILENCE_TIMEOUT_MS_RANGE)?;
+            }
+            "idle_shutdown_minutes" => {
+                self.idle_shutdown_minutes =
+                    parse_in_range(key, value, IDLE_SHUTDOWN_MINUTES_RANGE)?;
+            }
+            _ => return Err(unknown_key(key)),
+        }
+        Ok(())
+    }
+}
+
+fn unknown_key(key: &str) -> String {
+    format!("unknown key: {key} (valid keys: {})", KEYS.join(", "))
+}
+
+fn parse_in_range(
+    key: &str,
+    value: &str,
+    range: std::ops::RangeInclusive<u64>,
+) -> Result<u64, String> {
+    match value.parse::<u64>() {
+        Ok(n) if range.contains(&n) => Ok(n),
+        _ => Err(format!(
+            "{key} must be between {} and {}",
+            range.start(),
+            range.end()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    fn config_home(contents: &str) -> PathBuf {
+        static COUNT: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "yowl-config-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+        ));
+        std::fs::create_dir_all(dir.join("yowl")).unwrap();
+        std::fs::write(dir.join("yowl/config.toml"), contents).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_parse_config_file() {
+        let config = Config::parse(
+            r#"
+            # settings for the daemon
+            model = "/opt/models/ggml-small.en.bin"
+            buffer_duration_secs = 20
+            transcribe_interval_ms = 250
+            language = "de"
+            idle_shutdown_minutes = 30
+            log_level = "debug"
+            log_file_max_mb = 50
+            log_files_kept = 5
+            channel = "left"
+            sample_rate = 48000
+            input_channels = 2
+            noise_gate = 0.02
+            agc_target = 0.1
+            agc_max_gain = 20
+            session_log_dir = "/home/me/dictation"
+            gpu = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                transcribe_interval_ms: 250,
+                buffer_duration_secs: 20,
+                language: "de".to_string(),
+                silence_timeout_ms: 2000,
+                idle_shutdown_minutes: 30,
+                model: Some(PathBuf::from("/opt/models/ggml-small.en.bin")),
+                log_level: "debug".to_string(),
+                log_file_max_mb: 50,
+                log_files_kept: 5,
+                channel: "left".to_string(),
+                sample_rate: 48000,
+                input_channels: 2,
+                noise_gate: 0.02,
+                agc_target: 0.1,
+                agc_max_gain: 20.0,
+                session_log_dir: Some(PathBuf::from("/home/me/dictation")),
+                gpu: false,
+            }
+        );
+        assert_eq!(config.log_level(), log::LevelFilter::Debug);
+        assert_eq!(
+            config.log_rotation(),
+            logging::Rotation {
+                max_bytes: 50 * 1024 * 1024,
+                keep: 5,
+            }
+        );
+        assert_eq!(config.channel_mix(), ChannelMix::Left);
+        assert_eq!(config.noise_gate(), Some(0.02));
+        assert_eq!(Config::default().noise_gate(), None);
+        assert_eq!(config.sample_rate(), Some(48000));
+        assert_eq!(config.input_channels(), Some(2));
+        assert_eq!(Config::default().sample_rate(), None);
+        assert_eq!(Config::default().input_channels(), None);
+        assert_eq!(
+            config.agc(),
+            Some(AgcLevels {
+                target: 0.1,
+                max_gain: 20.0,
+            })
+        );
+        assert_eq!(Config::default().agc(), None);
+
+        // anything left out keeps its default
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert_eq!(
+            Config::parse("language = \"fr\"").unwrap(),
+            Config {
+                language: "fr".to_string(),
+                ..Config::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_config() {
+        assert_eq!(
+            Config::parse("buffer_duration_secs = 60"),
+            Err("buffer_duration_secs must be between 2 and 30".to_string())
+        );
+        assert_eq!(
+            Config::parse("log_level = \"loud\""),
+            Err("unknown log level: loud".to_string())
+        );
+        assert!(Config::parse("channel = \"centre\"")
+            .unwrap_err()
+            .starts_with("unknown channel: centre"));
+        assert_eq!(
+            Config::parse("noise_gate = 1.5"),
+            Err("noise_gate must be at least 0 and below 1".to_string())
+        );
+        assert_eq!(
+            Config::parse("log_file_max_mb = 0"),
+            Err("log_file_max_mb must be between 1 and 1024".to_string())
+        );
+        assert_eq!(
+            Config::parse("sample_rate = 100"),
+            Err("sample_rate must be 0 or between 8000 and 384000".to_string())
+        );
+        assert_eq!(
+            Config::parse("agc_max_gain = 0.5"),
+            Err("agc_max_gain must be between 1 and 100".to_string())
+        );
+        assert_eq!(
+            Config::parse("session_log_dir = \"dictation\""),
+            Err("session_log_dir must be an absolute path".to_string())
+        );
+        assert!(Config::parse("speed = 2")
+            .unwrap_err()
+            .contains("unknown field `speed`"));
+        assert!(Config::parse("buffer_duration_secs = \"ten\"").is_err());
+    }
+
+    #[test]
+    fn test_config_path() {
+        let path = |vars| path_from(|var| env(vars)(var).filter(|v| !v.is_empty()));
+        assert_eq!(
+            path(&[("XDG_CONFIG_HOME", "/xdg"), ("HOME", "/home/me")]),
+            Some(PathBuf::from("/xdg/yowl/config.toml"))
+        );
+        assert_eq!(
+            path(&[("XDG_CONFIG_HOME", ""), ("HOME", "/home/me")]),
+            Some(PathBuf::from("/home/me/.config/yowl/config.toml"))
+        );
+        assert_eq!(path(&[]), None);
+    }
+
+    #[test]
+    fn test_env_overrides_config_file() {
+        let dir = config_home("language = \"de\"\nbuffer_duration_secs = 20\n");
+        let home = dir.to_str().unwrap();
+
+        let config = load_from(env(&[("XDG_CONFIG_HOME", home)])).unwrap();
+        assert_eq!(config.language, "de");
+        assert_eq!(config.buffer_duration_secs, 20);
+
+        let config = load_from(env(&[
+            ("XDG_CONFIG_HOME", home),
+            ("YOWL_LANGUAGE", "fr"),
+            ("YOWL_MODEL", "/tmp/ggml-tiny.bin"),
+            ("YOWL_LOG_LEVEL", ""),
+            ("YOWL_GPU", "off"),
+        ]))
+        .unwrap();
+        assert_eq!(config.language, "fr");
+        assert_eq!(config.buffer_duration_secs, 20);
+        assert_eq!(config.model, Some(PathBuf::from("/tmp/ggml-tiny.bin")));
+        assert_eq!(config.log_level, "warn");
+        assert!(!config.gpu);
+
+        assert_eq!(
+            load_from(env(&[("XDG_CONFIG_HOME", home), ("YOWL_LANGUAGE", "xx")])),
+            Err("YOWL_LANGUAGE: unknown language: xx".to_string())
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // no file at all is the defaults
+        assert_eq!(
+            load_from(env(&[("XDG_CONFIG_HOME", home)])),
+            Ok(Config::default())
+        );
+    }
+
+    #[test]
+    fn test_get_every_key() {
+        let config = Config::default();
+        for key in KEYS {
+            assert!(config.get(key).is_ok(), "no value for {key}");
+        }
+        assert_eq!(config.get("transcribe_interval_ms"), Ok("500".to_string()));
+        assert_eq!(config.get("language"), Ok("en".to_string()));
+    }
+
+    #[test]
+    fn test_unknown_key_lists_valid_keys() {
+        let mut config = Config::default();
+        let expected = "unknown key: speed (valid keys: transcribe_interval_ms, \
+                        buffer_duration_secs, language, silence_timeout_ms, \
+                        idle_shutdown_minutes)";
+        assert_eq!(config.get("speed"), Err(expected.to_string()));
+        assert_eq!(config.set("speed", "1"), Err(expected.to_string()));
+    }
+
+    #[test]
+    fn test_set_validates() {
+        let mut config = Config::default();
+        assert_eq!(
+            config.set("transcribe_interval_ms", "50"),
+            Err("transcribe_interval_ms must be between 100 and 5000".to_string())
+        );
+        assert!(config.set("transcribe_interval_ms", "fast").is_err());
+        assert!(config.set("buffer_duration_secs", "0").is_err());
+        assert!(config.set("silence_timeout_ms", "90000").is_err());
+        assert_eq!(
+            config.set("idle_shutdown_minutes", "20000"),
+            Err("idle_shutdown_minutes must be between 0 and 10080".to_string())
+        );
+        assert_eq!(
+            config.set("language", "xx"),
+            Err("unknown language: xx".to_string())
+        );
+        assert_eq!(config, Config::default());
+
+        assert_eq!(config.set("transcribe_interval_ms", "100"), Ok(()));
+        assert_eq!(config.set("buffer_duration_secs", "30"), Ok(()));
+        assert_eq!(config.set("language", "auto"), Ok(()));
+        assert_eq!(config.set("silence_timeout_ms", "0"), Ok(()));
+        assert_eq!(config.set("idle_shutdown_minutes", "60"), Ok(()));
+        assert_eq!(config.transcribe_interval_ms, 100);
+        assert_eq!(config.buffer_duration_secs, 30);
+        assert_eq!(config.language, "auto");
+        assert_eq!(config.idle_shutdown_minutes, 60);
+    }
+}
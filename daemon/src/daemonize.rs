@@ -0,0 +1,83 @@
+//! Detaching from the terminal, for `--daemonize`.
+//!
+//! The usual double fork: the first child starts a new session, and its own
+//! child, which can never acquire a controlling terminal, carries on as the
+//! daemon. The process that was started waits only to hear the daemon's
+//! pid, prints it and exits. The daemon's stdout and stderr go to its log
+//! file, so nothing printed is lost, or to `/dev/null` if it logs to syslog
+//! (or os_log) or the file can't be opened.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+
+/// A daemon that has left its session, but still has the terminal for stdio.
+pub struct Detached {
+    /// Where the waiting process hears the daemon's pid
+    writer: File,
+}
+
+/// Detach into the background. Returns only in the daemon, which should
+/// [`finish`](Detached::finish) once it has checked it can run; the calling
+/// process exits once it has. Call this before starting any threads, as fork
+/// copies only the calling one.
+pub fn detach() -> std::io::Result<Detached> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let (mut reader, writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    let child = fork()?;
+    if child != 0 {
+        drop(writer);
+        let mut pid = String::new();
+        reader.read_to_string(&mut pid)?;
+        unsafe { libc::waitpid(child, std::ptr::null_mut(), 0) };
+        // the pipe closes without a pid if the daemon failed to start
+        if pid.is_empty() {
+            return Err(std::io::Error::other("daemon failed to start"));
+        }
+        print!("{pid}");
+        std::process::exit(0);
+    }
+    drop(reader);
+
+    if unsafe { libc::setsid() } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    if fork()? != 0 {
+        // skip our parent's exit handlers, which aren't ours to run
+        unsafe { libc::_exit(0) };
+    }
+
+    std::env::set_current_dir("/")?;
+    Ok(Detached { writer })
+}
+
+impl Detached {
+    /// Let go of the terminal, sending stdout and stderr to `output` if
+    /// given, and report our pid, so the process that started us exits.
+    pub fn finish(mut self, output: Option<File>) -> std::io::Result<()> {
+        let null = File::options().read(true).write(true).open("/dev/null")?;
+        let output = output.as_ref().unwrap_or(&null);
+        for (file, fd) in [
+            (&null, libc::STDIN_FILENO),
+            (output, libc::STDOUT_FILENO),
+            (output, libc::STDERR_FILENO),
+        ] {
+            if unsafe { libc::dup2(file.as_raw_fd(), fd) } < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        writeln!(self.writer, "{}", std::process::id())
+    }
+}
+
+fn fork() -> std::io::Result<libc::pid_t> {
+    match unsafe { libc::fork() } {
+        -1 => Err(std::io::Error::last_os_error()),
+        pid => Ok(pid),
+    }
+}
@@ -11,22 +11,129 @@
 //! - `committed`: Text that has aged out - never revised via backspaces
 //! - `provisional`: Text we've sent but may still revise
 
-/// Result of computing a diff between old and new text.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct DiffResult {
-    /// Number of characters to backspace/erase
-    pub backspaces: usize,
-    /// New characters to append after backspacing
-    pub new_text: String,
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::postprocess::{self, PostProcess, Profile};
+
+/// Shared with clients, which apply it to the text they have typed.
+pub use yowl_client::DiffResult;
+
+/// Granularity at which revisions are backspaced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DiffMode {
+    /// Keep every matching character; fewest backspaces. As a terminal
+    /// can only erase from the end, no finer diff (such as an LCS) could
+    /// keep more: a word inserted mid-sentence still means retyping
+    /// everything after it.
+    #[default]
+    Char,
+    /// Rewrite revised words whole, so a correction doesn't leave a
+    /// half-erased word flickering in the terminal
+    Word,
 }
 
+impl std::str::FromStr for DiffMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            s if s.eq_ignore_ascii_case("char") => Ok(Self::Char),
+            s if s.eq_ignore_ascii_case("word") => Ok(Self::Word),
+            other => Err(format!("unknown diff mode: {other}")),
+        }
+    }
+}
+
+/// What one backspace erases in the client's terminal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BackspaceUnit {
+    /// A single `char`
+    #[default]
+    Char,
+    /// A whole grapheme cluster, such as an emoji with a skin tone or a
+    /// letter followed by combining accents, as most terminals erase them
+    Grapheme,
+}
+
+impl BackspaceUnit {
+    /// Split `text` into the pieces one backspace erases.
+    fn split(self, text: &str) -> Vec<&str> {
+        match self {
+            Self::Char => text
+                .char_indices()
+                .map(|(i, c)| &text[i..i + c.len_utf8()])
+                .collect(),
+            Self::Grapheme => text.graphemes(true).collect(),
+        }
+    }
+
+    /// The first unit boundary in `text` at or after byte index `index`.
+    fn boundary_from(self, text: &str, index: usize) -> usize {
+        match self {
+            Self::Char => index,
+            Self::Grapheme => text
+                .grapheme_indices(true)
+                .map(|(start, _)| start)
+                .find(|&start| start >= index)
+                .unwrap_or(text.len()),
+        }
+    }
+}
+
+impl std::str::FromStr for BackspaceUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            s if s.eq_ignore_ascii_case("char") => Ok(Self::Char),
+            s if s.eq_ignore_ascii_case("grapheme") => Ok(Self::Grapheme),
+            other => Err(format!("unknown backspace unit: {other}")),
+        }
+    }
+}
+
+/// Default number of characters that must match before aging is assumed.
+pub const DEFAULT_MIN_MATCH_LEN: usize = 15;
+
+/// Shortest match accepted as evidence of aging; anything shorter matches
+/// common words by chance.
+pub const MIN_MATCH_LEN_FLOOR: usize = 3;
+
 /// Tracks text state for streaming transcription output.
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct TextTracker {
     /// Text that has aged out of the rolling buffer - locked in, never backspace into this
     committed: String,
     /// Text we've sent but may still revise via backspaces
     provisional: String,
+    /// Post-processing applied to each transcript before diffing
+    profile: Profile,
+    /// Granularity of the common prefix kept on revision
+    mode: DiffMode,
+    /// Characters of the new transcript that must match provisional text
+    /// before it is treated as aging rather than revision
+    min_match_len: usize,
+    /// What `DiffResult::backspaces` counts
+    unit: BackspaceUnit,
+    /// Consecutive updates text must survive unchanged before it is emitted
+    stability: u8,
+    /// The most recent transcripts (after aging), newest last
+    history: std::collections::VecDeque<String>,
+}
+
+impl Default for TextTracker {
+    fn default() -> Self {
+        Self {
+            committed: String::new(),
+            provisional: String::new(),
+            profile: Profile::default(),
+            mode: DiffMode::default(),
+            min_match_len: DEFAULT_MIN_MATCH_LEN,
+            unit: BackspaceUnit::default(),
+            stability: 1,
+            history: std::collections::VecDeque::new(),
+        }
+    }
 }
 
 impl TextTracker {
@@ -34,40 +141,191 @@ impl TextTracker {
         Self::default()
     }
 
+    /// A tracker that needs `min_match_len` matching characters to detect aging.
+    #[cfg(test)]
+    pub fn with_min_match_len(mut self, min_match_len: usize) -> Result<Self, String> {
+        self.set_min_match_len(min_match_len)?;
+        Ok(self)
+    }
+
+    /// Require `min_match_len` matching characters to detect aging. Shorter
+    /// values suit short-phrase dictation; values below
+    /// [`MIN_MATCH_LEN_FLOOR`] are rejected.
+    pub fn set_min_match_len(&mut self, min_match_len: usize) -> Result<(), String> {
+        if min_match_len < MIN_MATCH_LEN_FLOOR {
+            return Err(format!(
+                "min match length must be at least {MIN_MATCH_LEN_FLOOR}"
+            ));
+        }
+        self.min_match_len = min_match_len;
+        Ok(())
+    }
+
+    /// A tracker with a stability window of `updates`.
+    #[cfg(test)]
+    pub fn with_stability(mut self, updates: u8) -> Self {
+        self.set_stability(updates);
+        self
+    }
+
+    /// Only emit text that has stayed the same for `updates` consecutive
+    /// updates, holding back the volatile tail Whisper keeps revising.
+    /// 0 or 1 emits every update as it comes.
+    pub fn set_stability(&mut self, updates: u8) {
+        self.stability = updates.max(1);
+        while self.history.len() > self.stability as usize {
+            self.history.pop_front();
+        }
+    }
+
+    /// Choose whether backspaces count chars or grapheme clusters.
+    pub fn set_backspace_unit(&mut self, unit: BackspaceUnit) {
+        self.unit = unit;
+    }
+
     /// Reset all state (call when starting a new recording).
     pub fn reset(&mut self) {
         self.committed.clear();
         self.provisional.clear();
+        self.history.clear();
+    }
+
+    /// Discard the transcript in progress. Committed text stays; the
+    /// provisional text is taken back by the next update, which diffs it
+    /// against whatever is transcribed from then on.
+    pub fn cancel(&mut self) {
+        self.history.clear();
+    }
+
+    /// Start a fresh dictation mid-recording. Like [`cancel`](Self::cancel),
+    /// the provisional text is taken back by the next update; committed text
+    /// can't be un-typed, so it is only forgotten.
+    pub fn restart(&mut self) {
+        self.committed.clear();
+        self.history.clear();
+    }
+
+    /// Replace the post-processing profile. The next update re-emits the
+    /// provisional text under the new profile; committed text is kept as is.
+    pub fn set_profile(&mut self, profile: Profile) {
+        self.profile = profile;
+    }
+
+    /// Change the clean-up applied by the profile in effect.
+    pub fn set_post_process(&mut self, post_process: PostProcess) {
+        self.profile.set_post_process(post_process);
+    }
+
+    /// The post-processing profile in effect.
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// Choose whether revisions keep matching characters or whole words.
+    pub fn set_diff_mode(&mut self, mode: DiffMode) {
+        self.mode = mode;
     }
 
     /// Update with a new transcript and compute the diff to send.
     ///
     /// Returns `None` if no output is needed (empty transcript, no changes).
     pub fn update(&mut self, new_transcript: &str) -> Option<DiffResult> {
-        if new_transcript.is_empty() && self.provisional.is_empty() {
+        let processed = self.profile.apply(new_transcript);
+
+        if processed.is_empty() && self.provisional.is_empty() {
             return None;
         }
 
         // Step 1: Detect aging - find where new_transcript "picks up" in our provisional text
-        let aging_point = self.find_aging_point(new_transcript);
+        // (before capitalizing, as the transcript may now start mid-sentence)
+        let aging_point = self.find_aging_point(&processed);
 
         if aging_point > 0 {
             // Text before aging_point has aged out - commit it
-            let to_commit: String = self.provisional.chars().take(aging_point).collect();
-            self.committed.push_str(&to_commit);
-            self.provisional = self.provisional.chars().skip(aging_point).collect();
+            let aged: String = self.provisional.drain(..aging_point).collect();
+            self.committed.push_str(&aged);
+            // earlier transcripts lose the same head, or no longer line up
+            self.history = std::mem::take(&mut self.history)
+                .into_iter()
+                .filter_map(|text| text.strip_prefix(&aged).map(String::from))
+                .collect();
+        }
+
+        // aging decides whether the transcript starts a sentence
+        let processed = self.sentence_case(&processed);
+        self.history.push_back(processed);
+        if self.history.len() > self.stability as usize {
+            self.history.pop_front();
         }
 
-        // Step 2: Diff new_transcript against remaining provisional text
-        let common_len = self
-            .provisional
-            .chars()
-            .zip(new_transcript.chars())
+        let target = self.stable_text();
+        self.emit(&target)
+    }
+
+    /// Like [`update`](Self::update), but for a transcript from the same
+    /// transcription pass as the last one (e.g. a repeated POLL), so it
+    /// doesn't count towards the stability window.
+    pub fn revisit(&mut self, new_transcript: &str) -> Option<DiffResult> {
+        self.history.pop_back();
+        self.update(new_transcript)
+    }
+
+    /// Emit everything held back by the stability window, e.g. when
+    /// recording ends.
+    pub fn flush(&mut self) -> Option<DiffResult> {
+        let latest = self.history.back()?.clone();
+        self.emit(&latest)
+    }
+
+    /// The part of the latest transcript that has stayed the same across
+    /// the stability window. Text already emitted is only taken back if the
+    /// latest transcript actually changes it.
+    fn stable_text(&self) -> String {
+        let latest = self.history.back().map_or("", String::as_str);
+        let latest_units = self.unit.split(latest);
+        let common_with_latest = |text: &str| {
+            self.unit
+                .split(text)
+                .iter()
+                .zip(&latest_units)
+                .take_while(|(a, b)| a == b)
+                .count()
+        };
+
+        // until the window fills, nothing new is trusted
+        let stable_len = if self.history.len() < self.stability as usize {
+            0
+        } else {
+            self.history
+                .iter()
+                .map(|text| common_with_latest(text))
+                .min()
+                .unwrap_or(0)
+        };
+        let kept_len = common_with_latest(&self.provisional);
+
+        latest_units[..stable_len.max(kept_len)].concat()
+    }
+
+    /// Diff `target` against the provisional text and make it the new provisional text.
+    fn emit(&mut self, target: &str) -> Option<DiffResult> {
+        let new_transcript = target;
+
+        // Step 2: Diff new_transcript against remaining provisional text,
+        // in whatever a backspace erases
+        let old_units = self.unit.split(&self.provisional);
+        let new_units = self.unit.split(new_transcript);
+        let mut common_len = old_units
+            .iter()
+            .zip(&new_units)
             .take_while(|(a, b)| a == b)
             .count();
+        if self.mode == DiffMode::Word {
+            common_len = word_boundary(&old_units, &new_units, common_len);
+        }
 
-        let backspaces = self.provisional.chars().count() - common_len;
-        let new_text: String = new_transcript.chars().skip(common_len).collect();
+        let backspaces = old_units.len() - common_len;
+        let new_text = new_units[common_len..].concat();
 
         // Step 3: Update provisional to match new transcript
         self.provisional = new_transcript.to_string();
@@ -83,6 +341,18 @@ impl TextTracker {
         }
     }
 
+    /// Capitalize `text` if the profile asks for it and it follows the end
+    /// of a sentence in the committed text.
+    fn sentence_case(&self, text: &str) -> String {
+        if self.profile.post_process() == PostProcess::Capitalize
+            && postprocess::starts_sentence(&self.committed)
+        {
+            postprocess::capitalize_first(text)
+        } else {
+            text.to_string()
+        }
+    }
+
     /// Get the full text that has been output (committed + provisional).
     pub fn full_text(&self) -> String {
         format!("{}{}", self.committed, self.provisional)
@@ -98,7 +368,7 @@ impl TextTracker {
         &self.provisional
     }
 
-    /// Find how many characters from the start of provisional have "aged out".
+    /// Find how many bytes from the start of provisional have "aged out".
     ///
     /// AGING vs REVISION:
     /// - AGING: Audio buffer shifted forward, new transcript starts mid-way in our text
@@ -123,7 +393,7 @@ impl TextTracker {
         // somewhere AFTER the start of provisional. We require a long match
         // to be confident this is aging vs just similar words.
         let new_chars: Vec<char> = new_transcript.chars().collect();
-        let min_match_len = 15; // Require at least 15 chars to match
+        let min_match_len = self.min_match_len;
 
         if new_chars.len() < min_match_len {
             // New transcript too short to confidently detect aging
@@ -131,14 +401,14 @@ impl TextTracker {
         }
 
         // Try different prefix lengths of new_transcript
-        for key_len in (min_match_len..=new_chars.len().min(40)).rev() {
+        for key_len in (min_match_len..=new_chars.len().min(40.max(min_match_len))).rev() {
             let search_key: String = new_chars[..key_len].iter().collect();
 
             if let Some(byte_pos) = self.provisional.find(&search_key) {
                 if byte_pos > 0 {
                     // Found a match after the start - this is aging
                     // Everything before the match point has aged out
-                    return self.provisional[..byte_pos].chars().count();
+                    return byte_pos;
                 }
             }
         }
@@ -149,10 +419,289 @@ impl TextTracker {
     }
 }
 
+/// Move a common prefix of `common_len` units back to the start of the word
+/// it ends in, unless the prefix already ends on a word boundary in both texts:
+/// at either end of the text, or next to whitespace.
+fn word_boundary(old: &[&str], new: &[&str], common_len: usize) -> usize {
+    let is_space = |unit: &&str| unit.chars().all(char::is_whitespace);
+    let ends_word = |text: &[&str]| {
+        common_len == text.len()
+            || common_len == 0
+            || is_space(&text[common_len - 1])
+            || is_space(&text[common_len])
+    };
+    // a pure append (or truncation) just extends the last word
+    if common_len == old.len() || (ends_word(old) && ends_word(new)) {
+        return common_len;
+    }
+    old[..common_len]
+        .iter()
+        .rposition(is_space)
+        .map_or(0, |i| i + 1)
+}
+
+/// Spreads large appends across several emissions so text streams in
+/// smoothly instead of arriving in one burst.
+///
+/// Text that has been diffed but not yet emitted is held back as `pending`.
+/// Later diffs are merged into it, so the emissions still add up to the
+/// tracker's text.
+#[derive(Debug, Default)]
+pub struct OutputPacer {
+    /// Maximum characters appended per emission, or None to emit everything at once
+    max_chars: Option<usize>,
+    /// Text diffed but not yet emitted
+    pending: String,
+    /// What the backspaces of incoming diffs count
+    unit: BackspaceUnit,
+}
+
+impl OutputPacer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum characters appended per emission (None disables pacing).
+    pub fn set_max_chars(&mut self, max_chars: Option<usize>) {
+        self.max_chars = max_chars;
+    }
+
+    /// Count backspaces in the same unit as the tracker feeding this pacer.
+    pub fn set_backspace_unit(&mut self, unit: BackspaceUnit) {
+        self.unit = unit;
+    }
+
+    /// What the backspaces of its emissions count.
+    pub fn backspace_unit(&self) -> BackspaceUnit {
+        self.unit
+    }
+
+    /// Drop any pending text (call when starting a new recording).
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Number of characters diffed but not yet emitted.
+    pub fn pending_chars(&self) -> usize {
+        self.pending.chars().count()
+    }
+
+    /// Merge `diff` (if any) into the pending text and return the next emission.
+    pub fn next(&mut self, diff: Option<DiffResult>) -> Option<DiffResult> {
+        let backspaces = diff.map_or(0, |diff| self.merge(diff));
+
+        let split = match self.max_chars {
+            // never split a cluster across emissions
+            Some(max_chars) => self
+                .unit
+                .boundary_from(&self.pending, chunk_end(&self.pending, max_chars)),
+            None => self.pending.len(),
+        };
+        let new_text: String = self.pending.drain(..split).collect();
+
+        if backspaces > 0 || !new_text.is_empty() {
+            Some(DiffResult {
+                backspaces,
+                new_text,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Merge `diffs` into the pending text and emit all of it at once, as
+    /// when recording ends and there are no more emissions to spread it over.
+    pub fn finish(&mut self, diffs: impl IntoIterator<Item = DiffResult>) -> Option<DiffResult> {
+        let backspaces = diffs.into_iter().map(|diff| self.merge(diff)).sum();
+        let new_text = std::mem::take(&mut self.pending);
+        (backspaces > 0 || !new_text.is_empty()).then_some(DiffResult {
+            backspaces,
+            new_text,
+        })
+    }
+
+    /// Merge `diff` into the pending text. Returns the backspaces that reach
+    /// past it into text the client already has.
+    fn merge(&mut self, diff: DiffResult) -> usize {
+        let pending = DiffResult {
+            backspaces: 0,
+            new_text: std::mem::take(&mut self.pending),
+        };
+        let merged = compose(pending, diff, self.unit);
+        self.pending = merged.new_text;
+        merged.backspaces
+    }
+}
+
+/// The one diff that does what `first` and then `then` do, with backspaces
+/// counted in `unit`.
+pub fn compose(first: DiffResult, then: DiffResult, unit: BackspaceUnit) -> DiffResult {
+    let typed = unit.split(&first.new_text);
+    if then.backspaces >= typed.len() {
+        // the revision reaches past what `first` typed
+        DiffResult {
+            backspaces: first.backspaces + then.backspaces - typed.len(),
+            new_text: then.new_text,
+        }
+    } else {
+        let mut new_text = typed[..typed.len() - then.backspaces].concat();
+        new_text.push_str(&then.new_text);
+        DiffResult {
+            backspaces: first.backspaces,
+            new_text,
+        }
+    }
+}
+
+/// Byte index ending the next chunk of `text`: at most `max_chars` characters,
+/// ending after the last whitespace if there is one.
+fn chunk_end(text: &str, max_chars: usize) -> usize {
+    let max_chars = max_chars.max(1);
+    let hard_end = match text.char_indices().nth(max_chars) {
+        Some((end, _)) => end,
+        None => return text.len(),
+    };
+
+    text[..hard_end]
+        .char_indices()
+        .rev()
+        .find(|(_, c)| c.is_whitespace())
+        .map(|(i, c)| i + c.len_utf8())
+        .filter(|&end| end > 0)
+        .unwrap_or(hard_end)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn apply(terminal: &mut String, diff: &DiffResult) {
+        for _ in 0..diff.backspaces {
+            terminal.pop();
+        }
+        terminal.push_str(&diff.new_text);
+    }
+
+    #[test]
+    fn test_pacer_splits_large_append_at_words() {
+        let mut pacer = OutputPacer::new();
+        pacer.set_max_chars(Some(20));
+
+        let append = "the quick brown fox jumps over the lazy dog and keeps running far away";
+        let mut emissions = vec![pacer
+            .next(Some(DiffResult {
+                backspaces: 0,
+                new_text: append.to_string(),
+            }))
+            .unwrap()];
+        while let Some(diff) = pacer.next(None) {
+            emissions.push(diff);
+        }
+
+        assert!(emissions.len() > 1);
+        for diff in &emissions {
+            assert_eq!(diff.backspaces, 0);
+            assert!(diff.new_text.chars().count() <= 20);
+        }
+        assert_eq!(emissions[0].new_text, "the quick brown fox ");
+
+        let joined: String = emissions.iter().map(|d| d.new_text.as_str()).collect();
+        assert_eq!(joined, append);
+    }
+
+    #[test]
+    fn test_pacer_merges_revisions_of_pending_text() {
+        let mut tracker = TextTracker::new();
+        let mut pacer = OutputPacer::new();
+        pacer.set_max_chars(Some(10));
+
+        let mut terminal = String::new();
+        let transcripts = [
+            "Hello there, how are you doing today",
+            "Hello there, how are you doing today?",
+            "Hello, there. How are you doing today?",
+        ];
+        for transcript in transcripts {
+            if let Some(diff) = pacer.next(tracker.update(transcript)) {
+                apply(&mut terminal, &diff);
+            }
+        }
+        while let Some(diff) = pacer.next(None) {
+            apply(&mut terminal, &diff);
+        }
+
+        assert_eq!(terminal, tracker.full_text());
+    }
+
+    #[test]
+    fn test_compose_diffs() {
+        let diff = |backspaces, new_text: &str| DiffResult {
+            backspaces,
+            new_text: new_text.to_string(),
+        };
+        // the second revises only what the first typed
+        assert_eq!(
+            compose(diff(2, "lo wor"), diff(3, "world"), BackspaceUnit::Char),
+            diff(2, "lo world")
+        );
+        // or reaches past it
+        assert_eq!(
+            compose(diff(0, "ab"), diff(5, "xyz"), BackspaceUnit::Char),
+            diff(3, "xyz")
+        );
+        assert_eq!(
+            compose(diff(1, "e\u{301}"), diff(1, ""), BackspaceUnit::Grapheme),
+            diff(1, "")
+        );
+    }
+
+    #[test]
+    fn test_pacer_finish_releases_everything() {
+        let mut pacer = OutputPacer::new();
+        pacer.set_max_chars(Some(5));
+        let mut terminal = String::new();
+        let first = pacer.next(Some(DiffResult {
+            backspaces: 0,
+            new_text: "one two three".to_string(),
+        }));
+        apply(&mut terminal, &first.unwrap());
+        assert_eq!(terminal, "one ");
+
+        // a revision of held-back text, then one reaching into the terminal
+        let last = pacer.finish([
+            DiffResult {
+                backspaces: 5,
+                new_text: "3".to_string(),
+            },
+            DiffResult {
+                backspaces: 9,
+                new_text: "1 2 3.".to_string(),
+            },
+        ]);
+        apply(&mut terminal, &last.unwrap());
+        assert_eq!(terminal, "1 2 3.");
+        assert_eq!(pacer.pending_chars(), 0);
+        assert_eq!(pacer.finish([]), None);
+    }
+
+    #[test]
+    fn test_pacer_without_limit_passes_through() {
+        let mut pacer = OutputPacer::new();
+        let diff = DiffResult {
+            backspaces: 2,
+            new_text: "a long run of text".to_string(),
+        };
+        assert_eq!(pacer.next(Some(diff.clone())), Some(diff));
+        assert_eq!(pacer.next(None), None);
+    }
+
+    #[test]
+    fn test_chunk_end_without_whitespace() {
+        assert_eq!(chunk_end("abcdefgh", 3), 3);
+        assert_eq!(chunk_end("héllo", 2), "hé".len());
+        assert_eq!(chunk_end("ab cd", 10), 5);
+    }
+
     #[test]
     fn test_initial_text() {
         let mut tracker = TextTracker::new();
@@ -226,6 +775,314 @@ mod tests {
         );
     }
 
+    /// Backspaces needed to revise `from` into `to` in the given mode.
+    fn revision_backspaces(mode: DiffMode, from: &str, to: &str) -> usize {
+        let mut tracker = TextTracker::new();
+        tracker.set_diff_mode(mode);
+        tracker.update(from).unwrap();
+        let result = tracker.update(to).unwrap();
+        assert_eq!(tracker.full_text(), to);
+        result.backspaces
+    }
+
+    /// The fewest backspaces any edit could use, trying every suffix of
+    /// `from` to erase: what's left must be a prefix of `to`.
+    fn fewest_backspaces(from: &str, to: &str) -> usize {
+        let from: Vec<char> = from.chars().collect();
+        let to: Vec<char> = to.chars().collect();
+        (0..=from.len())
+            .find(|&erase| to.starts_with(&from[..from.len() - erase]))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_prefix_diff_is_minimal() {
+        let cases = [
+            // an insertion mid-sentence
+            ("the cat sat", "the big cat sat"),
+            // a deletion mid-sentence
+            ("the big cat sat", "the cat sat"),
+            ("one two three", "one three"),
+            // a reordering
+            ("sat the cat", "the cat sat"),
+            ("Hello word", "Hello world"),
+            ("Hello world", "Hello"),
+        ];
+        for (from, to) in cases {
+            assert_eq!(
+                revision_backspaces(DiffMode::Char, from, to),
+                fewest_backspaces(from, to),
+                "{from:?} -> {to:?}"
+            );
+        }
+        assert_eq!(
+            revision_backspaces(DiffMode::Char, "the cat sat", "the big cat sat"),
+            "cat sat".len()
+        );
+    }
+
+    #[test]
+    fn test_word_level_revision() {
+        assert_eq!(revision_backspaces(DiffMode::Char, "Helo", "Hello"), 1);
+        assert_eq!(revision_backspaces(DiffMode::Word, "Helo", "Hello"), 4);
+
+        assert_eq!(revision_backspaces(DiffMode::Char, "word", "world"), 1);
+        assert_eq!(revision_backspaces(DiffMode::Word, "word", "world"), 4);
+
+        // earlier words are kept; only the revised one is rewritten
+        let mut tracker = TextTracker::new();
+        tracker.set_diff_mode(DiffMode::Word);
+        tracker.update("the three billy").unwrap();
+        let result = tracker.update("the three Billy goats").unwrap();
+        assert_eq!(result.backspaces, "billy".len());
+        assert_eq!(result.new_text, "Billy goats");
+    }
+
+    #[test]
+    fn test_word_level_append() {
+        let mut tracker = TextTracker::new();
+        tracker.set_diff_mode(DiffMode::Word);
+        tracker.update("Hello worl").unwrap();
+        let result = tracker.update("Hello world").unwrap();
+        assert_eq!(result.backspaces, 0);
+        assert_eq!(result.new_text, "d");
+
+        // a revision after a space keeps the whole common prefix
+        let result = tracker.update("Hello there").unwrap();
+        assert_eq!(result.backspaces, "world".len());
+        assert_eq!(result.new_text, "there");
+    }
+
+    #[test]
+    fn test_word_level_shortening() {
+        let mut tracker = TextTracker::new();
+        tracker.set_diff_mode(DiffMode::Word);
+        tracker.update("Hello world foo").unwrap();
+        let result = tracker.update("Hello world").unwrap();
+        assert_eq!(result.backspaces, " foo".len());
+        assert_eq!(result.new_text, "");
+
+        let result = tracker.update("Hello").unwrap();
+        assert_eq!(result.backspaces, " world".len());
+        assert_eq!(result.new_text, "");
+
+        // a word cut short is still rewritten whole
+        tracker.update("Hello there").unwrap();
+        let result = tracker.update("Hello the").unwrap();
+        assert_eq!(result.backspaces, "there".len());
+        assert_eq!(result.new_text, "the");
+    }
+
+    #[test]
+    fn test_word_level_dropped_word() {
+        let mut tracker = TextTracker::new();
+        tracker.set_diff_mode(DiffMode::Word);
+        tracker.update("the big cat sat").unwrap();
+        let result = tracker.update("the big cat").unwrap();
+        assert_eq!(result.backspaces, " sat".len());
+        assert_eq!(result.new_text, "");
+
+        // a word dropped from the middle rewrites only what follows it
+        tracker.update("the big cat sat").unwrap();
+        let result = tracker.update("the cat sat").unwrap();
+        assert_eq!(result.backspaces, "big cat sat".len());
+        assert_eq!(result.new_text, "cat sat");
+    }
+
+    #[test]
+    fn test_diff_mode_from_str() {
+        assert_eq!("word".parse(), Ok(DiffMode::Word));
+        assert_eq!(" CHAR ".parse(), Ok(DiffMode::Char));
+        assert!("line".parse::<DiffMode>().is_err());
+    }
+
+    fn grapheme_tracker() -> TextTracker {
+        let mut tracker = TextTracker::new();
+        tracker.set_backspace_unit(BackspaceUnit::Grapheme);
+        tracker
+    }
+
+    #[test]
+    fn test_grapheme_backspaces_combining_mark() {
+        // "é" as "e" followed by a combining acute accent
+        let decomposed = "cafe\u{301}";
+
+        let mut tracker = TextTracker::new();
+        tracker.update(decomposed).unwrap();
+        let result = tracker.update("cafe").unwrap();
+        // one char backspace would leave the terminal showing "caf"
+        assert_eq!(result.backspaces, 1);
+        assert_eq!(result.new_text, "");
+
+        let mut tracker = grapheme_tracker();
+        tracker.update(decomposed).unwrap();
+        let result = tracker.update("cafe").unwrap();
+        assert_eq!(result.backspaces, 1);
+        assert_eq!(result.new_text, "e");
+
+        // precomposed "é" is one char either way
+        let mut tracker = grapheme_tracker();
+        tracker.update("café").unwrap();
+        let result = tracker.update("cafe").unwrap();
+        assert_eq!(result.backspaces, 1);
+        assert_eq!(result.new_text, "e");
+    }
+
+    #[test]
+    fn test_grapheme_backspaces_emoji() {
+        // thumbs up with a skin tone modifier: two chars, one grapheme
+        let mut tracker = TextTracker::new();
+        tracker.update("nice 👍🏽").unwrap();
+        assert_eq!(tracker.update("nice one").unwrap().backspaces, 2);
+
+        let mut tracker = grapheme_tracker();
+        tracker.update("nice 👍🏽").unwrap();
+        let result = tracker.update("nice one").unwrap();
+        assert_eq!(result.backspaces, 1);
+        assert_eq!(result.new_text, "one");
+
+        // a family joined with zero-width joiners: five chars, one grapheme
+        tracker.update("the 👨‍👩‍👧 waved").unwrap();
+        let result = tracker.update("the 👨‍👩‍👧 waves").unwrap();
+        assert_eq!(result.backspaces, 1);
+        assert_eq!(result.new_text, "s");
+        let result = tracker.update("the family").unwrap();
+        // the family, a space and "waves"
+        assert_eq!(result.backspaces, 7);
+        assert_eq!(result.new_text, "family");
+    }
+
+    #[test]
+    fn test_pacer_keeps_graphemes_whole() {
+        let mut pacer = OutputPacer::new();
+        pacer.set_backspace_unit(BackspaceUnit::Grapheme);
+        pacer.set_max_chars(Some(2));
+
+        let first = pacer
+            .next(Some(DiffResult {
+                backspaces: 0,
+                new_text: "a👍🏽b".to_string(),
+            }))
+            .unwrap();
+        assert_eq!(first.new_text, "a👍🏽");
+
+        // revising the pending "b" counts it as one grapheme
+        let second = pacer
+            .next(Some(DiffResult {
+                backspaces: 1,
+                new_text: "c".to_string(),
+            }))
+            .unwrap();
+        assert_eq!(second.backspaces, 0);
+        assert_eq!(second.new_text, "c");
+    }
+
+    #[test]
+    fn test_backspace_unit_from_str() {
+        assert_eq!("grapheme".parse(), Ok(BackspaceUnit::Grapheme));
+        assert_eq!("CHAR".parse(), Ok(BackspaceUnit::Char));
+        assert!("byte".parse::<BackspaceUnit>().is_err());
+    }
+
+    #[test]
+    fn test_capitalize_post_process() {
+        let mut tracker = TextTracker::new();
+        tracker.set_post_process(PostProcess::Capitalize);
+
+        let mut terminal = String::new();
+        for transcript in [
+            "hello",
+            "hello. world",
+            "hello.  world is  big",
+            "hello. world is big. very",
+            " hello. world is big. very big",
+        ] {
+            if let Some(diff) = tracker.update(transcript) {
+                apply(&mut terminal, &diff);
+            }
+            assert_eq!(terminal, tracker.full_text());
+        }
+        assert_eq!(terminal, "Hello. World is big. Very big");
+    }
+
+    #[test]
+    fn test_capitalize_after_aging() {
+        let mut tracker = TextTracker::new();
+        tracker.set_post_process(PostProcess::Capitalize);
+
+        tracker.update("once upon a time there was").unwrap();
+        tracker.update("a time there was a king").unwrap();
+        // the transcript continues a sentence, so it isn't capitalized
+        assert_eq!(tracker.committed(), "Once upon ");
+        assert_eq!(tracker.full_text(), "Once upon a time there was a king");
+    }
+
+    #[test]
+    fn test_raw_by_default() {
+        let mut tracker = TextTracker::new();
+        tracker.update("hello.  world").unwrap();
+        assert_eq!(tracker.full_text(), "hello.  world");
+    }
+
+    #[test]
+    fn test_stability_holds_back_volatile_tail() {
+        let mut tracker = TextTracker::new().with_stability(2);
+        let mut terminal = String::new();
+        let mut update = |tracker: &mut TextTracker, transcript: &str| {
+            if let Some(diff) = tracker.update(transcript) {
+                apply(&mut terminal, &diff);
+            }
+            assert_eq!(terminal, tracker.full_text());
+            terminal.clone()
+        };
+
+        // nothing has survived two updates yet
+        assert_eq!(update(&mut tracker, "time ther"), "");
+        assert_eq!(update(&mut tracker, "time there."), "time ther");
+        // "." was revised away, so only "time there" has settled
+        assert_eq!(update(&mut tracker, "time there was"), "time there");
+        assert_eq!(update(&mut tracker, "time there was"), "time there was");
+
+        // no backspaces were ever needed
+        let mut tracker = TextTracker::new().with_stability(2);
+        for transcript in ["time ther", "time there.", "time there was"] {
+            if let Some(diff) = tracker.update(transcript) {
+                assert_eq!(diff.backspaces, 0);
+            }
+        }
+
+        // flushing emits the tail that never settled
+        let diff = tracker.flush().unwrap();
+        assert_eq!(diff.backspaces, 0);
+        assert_eq!(diff.new_text, " was");
+        assert_eq!(tracker.full_text(), "time there was");
+    }
+
+    #[test]
+    fn test_revisit_does_not_settle_text() {
+        let mut tracker = TextTracker::new().with_stability(2);
+        tracker.update("time ther");
+        tracker.revisit("time ther");
+        tracker.revisit("time ther");
+        assert_eq!(tracker.full_text(), "");
+
+        tracker.update("time ther");
+        assert_eq!(tracker.full_text(), "time ther");
+    }
+
+    #[test]
+    fn test_stability_keeps_aging() {
+        let mut tracker = TextTracker::new().with_stability(2);
+        tracker.update("Once upon a time there was");
+        tracker.update("Once upon a time there was");
+        assert_eq!(tracker.full_text(), "Once upon a time there was");
+
+        tracker.update("a time there was a king");
+        assert!(tracker.committed().starts_with("Once upon "));
+        tracker.update("a time there was a king");
+        assert_eq!(tracker.full_text(), "Once upon a time there was a king");
+    }
+
     #[test]
     fn test_no_change() {
         let mut tracker = TextTracker::new();
@@ -305,6 +1162,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_short_aging_with_min_match_len() {
+        // too short for the default to be confident: treated as a revision
+        let mut tracker = TextTracker::new();
+        tracker.update("open the door").unwrap();
+        tracker.update("the door and").unwrap();
+        assert_eq!(tracker.committed(), "");
+        assert_eq!(tracker.full_text(), "the door and");
+
+        let mut tracker = TextTracker::new().with_min_match_len(8).unwrap();
+        tracker.update("open the door").unwrap();
+        let result = tracker.update("the door and").unwrap();
+        assert_eq!(tracker.committed(), "open ");
+        assert_eq!(tracker.full_text(), "open the door and");
+        assert_eq!(result.backspaces, 0);
+        assert_eq!(result.new_text, " and");
+    }
+
+    #[test]
+    fn test_min_match_len_floor() {
+        assert!(TextTracker::new().with_min_match_len(2).is_err());
+        assert!(TextTracker::new().with_min_match_len(0).is_err());
+        assert!(TextTracker::new().with_min_match_len(3).is_ok());
+
+        let mut tracker = TextTracker::new();
+        assert!(tracker.set_min_match_len(1).is_err());
+        assert_eq!(tracker.min_match_len, DEFAULT_MIN_MATCH_LEN);
+    }
+
     #[test]
     fn test_gradual_aging() {
         let mut tracker = TextTracker::new();
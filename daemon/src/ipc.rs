@@ -1,97 +1,3101 @@
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use crate::state::DaemonState;
+use serde::{Deserialize, Serialize};
 
-pub fn socket_path() -> PathBuf {
-    std::env::var("YOWL_SOCKET_PATH")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| {
-            let mut path = std::env::temp_dir();
-            let uid = unsafe { libc::getuid() };
-            path.push(format!("yowl-{uid}.sock"));
-            path
-        })
+use crate::audio;
+use crate::pidfile::PidFile;
+use crate::state::{DaemonState, Poll, Reload, StartOptions, Status};
+use crate::whisper;
+
+/// Where the socket lives, as clients find it with
+/// [`yowl_client::instance_socket_path`], plus the yowl-owned directory
+/// holding it, if any.
+fn socket_location(env: impl Fn(&str) -> Option<String>) -> (PathBuf, Option<PathBuf>) {
+    let instance = env(yowl_client::INSTANCE_VAR).filter(|name| !name.is_empty());
+    let path = yowl_client::instance_socket_path_in(&env, instance.as_deref());
+    // only the directory made for us under XDG_RUNTIME_DIR is ours to lock down
+    let owned = env("YOWL_SOCKET_PATH").is_none_or(|path| path.is_empty())
+        && env("XDG_RUNTIME_DIR").is_some_and(|dir| !dir.is_empty());
+    let dir = owned.then(|| path.parent().expect("socket in a directory").to_path_buf());
+    (path, dir)
+}
+
+/// First descriptor passed by systemd socket activation (`SD_LISTEN_FDS_START`).
+const LISTEN_FDS_START: RawFd = 3;
+
+/// The listening socket systemd passed to process `pid`, if it was socket
+/// activated. `LISTEN_PID` is checked because children inherit the variables
+/// without the socket.
+fn activation_fd(env: impl Fn(&str) -> Option<String>, pid: u32) -> Option<RawFd> {
+    if env("LISTEN_PID")?.parse::<u32>().ok()? != pid {
+        return None;
+    }
+    match env("LISTEN_FDS")?.parse::<u32>().ok()? {
+        0 => None,
+        1 => Some(LISTEN_FDS_START),
+        count => {
+            log::warn!("using the first of {count} activation sockets");
+            Some(LISTEN_FDS_START)
+        }
+    }
+}
+
+/// The keepalive interval set by `YOWL_KEEPALIVE_SECS`; `0` leaves it off.
+fn parse_keepalive(value: &str) -> Option<std::time::Duration> {
+    match value.trim().parse::<u64>() {
+        Ok(0) => None,
+        Ok(secs) => Some(std::time::Duration::from_secs(secs)),
+        Err(_) => {
+            log::warn!("ignoring YOWL_KEEPALIVE_SECS={value}: not a number of seconds");
+            None
+        }
+    }
+}
+
+/// Lock the pid file beside the socket (`yowl.pid` for `yowl.sock`, or
+/// `yowl-<name>.pid` for an instance), so that only one daemon runs per
+/// socket.
+pub fn lock_pid_file() -> std::io::Result<PidFile> {
+    let (socket, dir) = socket_location(|var| std::env::var(var).ok());
+    if let Some(dir) = &dir {
+        create_private_dir(dir)?;
+    }
+    PidFile::acquire(socket.with_extension("pid"), dir)
+}
+
+/// Create `dir` (and any parents) readable only by us.
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+}
+
+/// Protocol spoken by clients that never send HELLO.
+pub const BASE_PROTOCOL: u32 = 1;
+
+/// Newest protocol this daemon speaks. Version 2 escapes transcript text in
+/// RECORDING responses and events, as TRANSCRIPT does.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Most output a connection may have waiting for its client to read before
+/// the client is dropped, so one stuck client can't hold up the others.
+const MAX_QUEUED_BYTES: usize = 64 * 1024;
+
+/// Longest command line accepted; a client exceeding it is dropped.
+const MAX_COMMAND_LEN: usize = 64 * 1024;
+
+/// Most input thrown away in one turn from a client being closed, so one
+/// that keeps sending can't hold up the others.
+const MAX_DISCARD_PER_TURN: usize = 64 * 1024;
+
+/// Most input thrown away from a client being closed before it is dropped
+/// without waiting for it to hang up.
+const MAX_DISCARD: usize = 1 << 20;
+
+/// Largest message accepted in the framed protocol.
+const MAX_FRAME_LEN: usize = 1 << 20;
+
+/// Declare the `Capability` enum, with `ALL` listing every variant and
+/// `name` giving its protocol name, from one list so they can't disagree.
+macro_rules! capabilities {
+    ($($variant:ident => $name:literal,)*) => {
+        /// Optional features a client can discover with CAPABILITIES or HELLO.
+        /// The names are part of the protocol, so existing ones must not change.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        enum Capability {
+            $($variant,)*
+        }
+
+        impl Capability {
+            /// Every capability, in the order they are listed to clients.
+            const ALL: &[Capability] = &[$(Self::$variant,)*];
+
+            fn name(self) -> &'static str {
+                match self {
+                    $(Self::$variant => $name,)*
+                }
+            }
+        }
+    };
+}
+
+capabilities! {
+    Events => "events",
+    Json => "json",
+    Transcript => "transcript",
+    Waveform => "waveform",
+    Models => "models",
+    Chunk => "chunk",
+    Graphemes => "graphemes",
+    Config => "config",
+    DiffMode => "diff-mode",
+    Warmup => "warmup",
+    Vad => "vad",
+    Profiles => "profiles",
+    DeviceSelect => "device-select",
+    Devices => "devices",
+    Binary => "binary",
+    Peek => "peek",
+    Metrics => "metrics",
+    Cuda => "cuda",
+    Metal => "metal",
+    Vulkan => "vulkan",
+    Toggle => "toggle",
+}
+
+impl Capability {
+    /// Whether this build has it. GPU inference depends on cargo features.
+    // the cfg! arms look constant to clippy, but differ between builds
+    #[allow(clippy::match_like_matches_macro)]
+    fn supported(self) -> bool {
+        match self {
+            Self::Cuda => cfg!(feature = "cuda"),
+            Self::Metal => cfg!(feature = "metal"),
+            Self::Vulkan => cfg!(feature = "vulkan"),
+            _ => true,
+        }
+    }
+}
+
+/// Names of the capabilities this build supports.
+fn capabilities() -> Vec<&'static str> {
+    Capability::ALL
+        .iter()
+        .copied()
+        .filter(|capability| capability.supported())
+        .map(Capability::name)
+        .collect()
+}
+
+/// Per-connection protocol state.
+#[derive(Debug)]
+pub struct Session {
+    /// Protocol version negotiated by HELLO
+    pub protocol: u32,
+    /// Whether pushed events are sent to this connection
+    pub subscribed: bool,
+    /// Messages are length-prefixed frames rather than lines (see `HELLO ... BINARY`)
+    pub framed: bool,
+    /// Only HELLO and PING are answered until a HELLO succeeds
+    pub handshake_pending: bool,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            protocol: BASE_PROTOCOL,
+            subscribed: false,
+            framed: false,
+            handshake_pending: false,
+        }
+    }
 }
 
 pub struct Server {
     listener: UnixListener,
     path: PathBuf,
+    /// Directory created for the socket, removed on drop if empty
+    dir: Option<PathBuf>,
+    connections: Vec<Connection>,
+    /// Looks up the uid of a connecting client
+    peer_uid: fn(&UnixStream) -> std::io::Result<u32>,
+    /// New clients must send HELLO before anything but PING
+    strict_handshake: bool,
+    /// We created the socket file, so it is ours to remove on drop
+    owns_socket: bool,
+    /// Ping clients that are quiet for this long, and drop them if they stay
+    /// quiet as long again. Off by default, as older clients don't expect it.
+    keepalive: Option<std::time::Duration>,
+    /// When a client was last connected, for shutting down when idle
+    last_active: std::time::Instant,
 }
 
 impl Server {
+    /// Listen on the socket systemd activated us with, or else bind our own.
     pub fn bind() -> std::io::Result<Self> {
-        let path = socket_path();
+        let mut server = match activation_fd(|var| std::env::var(var).ok(), std::process::id()) {
+            Some(fd) => {
+                // inherited descriptors aren't close-on-exec like ours
+                unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+                // systemd passed the descriptor to us alone, and nothing else
+                // in the process takes ownership of it
+                Self::adopt(unsafe { UnixListener::from_raw_fd(fd) })?
+            }
+            None => {
+                let (path, dir) = socket_location(|var| std::env::var(var).ok());
+                if let Some(dir) = &dir {
+                    create_private_dir(dir)?;
+                }
+
+                let mut server = Self::bind_at(path)?;
+                server.dir = dir;
+                server
+            }
+        };
+        server.strict_handshake = std::env::var("YOWL_STRICT_HANDSHAKE")
+            .is_ok_and(|value| !value.is_empty() && value != "0");
+        server.keepalive = std::env::var("YOWL_KEEPALIVE_SECS")
+            .ok()
+            .and_then(|value| parse_keepalive(&value));
+        Ok(server)
+    }
 
-        // remove stale socket if it exists
-        if path.exists() {
-            std::fs::remove_file(&path)?;
+    /// Bind at `path`, replacing a stale socket left by a daemon that exited
+    /// uncleanly. Fails with `AddrInUse` if a daemon is still listening there.
+    pub fn bind_at(path: PathBuf) -> std::io::Result<Self> {
+        match UnixStream::connect(&path) {
+            Ok(stream) => {
+                let message = if answers_ping(stream) {
+                    format!(
+                        "another yowl daemon is already running at {}",
+                        path.display()
+                    )
+                } else {
+                    format!("socket {} is in use by another process", path.display())
+                };
+                return Err(std::io::Error::new(ErrorKind::AddrInUse, message));
+            }
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+                log::info!("removing stale socket {}", path.display());
+                std::fs::remove_file(&path)?;
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
         }
 
-        let listener = UnixListener::bind(&path)?;
+        // only our own user may connect. The umask keeps others out from the
+        // moment the socket exists, before its mode is tightened to 0600.
+        let old_umask = unsafe { libc::umask(0o077) };
+        let bound = UnixListener::bind(&path);
+        unsafe { libc::umask(old_umask) };
+        let listener = bound?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
         log::info!("IPC server listening on {}", path.display());
 
-        Ok(Self { listener, path })
+        Ok(Self {
+            listener,
+            path,
+            dir: None,
+            connections: Vec::new(),
+            peer_uid,
+            strict_handshake: false,
+            owns_socket: true,
+            keepalive: None,
+            last_active: std::time::Instant::now(),
+        })
+    }
+
+    /// Serve on a listener bound by someone else, as with socket activation.
+    /// The socket file is theirs, so it is left in place on drop.
+    pub fn adopt(listener: UnixListener) -> std::io::Result<Self> {
+        let path = listener
+            .local_addr()?
+            .as_pathname()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        log::info!(
+            "IPC server listening on {} (socket activated)",
+            path.display()
+        );
+
+        Ok(Self {
+            listener,
+            path,
+            dir: None,
+            connections: Vec::new(),
+            peer_uid,
+            strict_handshake: false,
+            owns_socket: false,
+            keepalive: None,
+            last_active: std::time::Instant::now(),
+        })
     }
 
     pub fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
         self.listener.set_nonblocking(nonblocking)
     }
 
+    /// Accept a pending client. Clients running as another user are
+    /// rejected with `PermissionDenied`.
     pub fn accept(&self) -> std::io::Result<Connection> {
         let (stream, _) = self.listener.accept()?;
+
+        let uid = unsafe { libc::getuid() };
+        let peer = (self.peer_uid)(&stream)?;
+        if peer != uid {
+            log::warn!("rejected connection from uid {peer}");
+            return Err(std::io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!("peer uid {peer} does not match {uid}"),
+            ));
+        }
+
         stream.set_nonblocking(true)?;
         log::debug!("client connected");
-        Ok(Connection::new(stream))
+        let mut conn = Connection::new(stream);
+        conn.session.handshake_pending = self.strict_handshake;
+        Ok(conn)
+    }
+
+    /// Accept any pending clients, then read at most one command from each
+    /// connection and reply to it with `handler`'s response. Disconnected
+    /// clients are pruned without affecting the others.
+    ///
+    /// Each connection has its own [`Session`], which `handler` may update.
+    ///
+    /// Returns true if a client requested SHUTDOWN.
+    pub fn service<F>(&mut self, mut handler: F) -> bool
+    where
+        F: FnMut(&mut Session, &str) -> String,
+    {
+        loop {
+            match self.accept() {
+                Ok(conn) => self.connections.push(conn),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::PermissionDenied => continue,
+                Err(e) => {
+                    log::warn!("accept error: {e}");
+                    break;
+                }
+            }
+        }
+
+        let mut shutdown = false;
+        let keepalive = self.keepalive;
+        self.connections.retain_mut(|conn| {
+            // catch up on output the client wasn't ready for last time
+            if let Err(e) = conn.flush() {
+                log::warn!("dropping client: {e}");
+                return false;
+            }
+
+            if conn.closing {
+                return conn.discard_input();
+            }
+
+            match conn.receive() {
+                // the answer to a keepalive PING needs no reply
+                Ok(Some(cmd)) if cmd.eq_ignore_ascii_case("PONG") => true,
+                Ok(Some(cmd)) => {
+                    log::debug!("received command: {cmd}");
+                    // a switch of framing applies after the response
+                    let framed = conn.session.framed;
+                    let response = handler(&mut conn.session, &cmd);
+                    let sent = if framed {
+                        conn.send_frame(&response)
+                    } else {
+                        conn.send(&response)
+                    };
+                    if let Err(e) = sent {
+                        log::warn!("dropping client: {e}");
+                        return false;
+                    }
+                    if is_shutdown(&cmd) {
+                        shutdown = true;
+                    }
+                    true
+                }
+                Ok(None) => {
+                    log::debug!("client disconnected");
+                    false
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => match keepalive {
+                    Some(idle) => conn.keep_alive(idle, std::time::Instant::now()),
+                    None => true,
+                },
+                // a bad command, but the stream is still in step
+                Err(e) if e.kind() == ErrorKind::InvalidInput => {
+                    conn.deliver(&format!("ERROR {e}")).is_ok()
+                }
+                // the stream can't be followed any further
+                Err(e) if e.kind() == ErrorKind::InvalidData => {
+                    log::warn!("closing client: {e}");
+                    conn.close_with(&format!("ERROR {e}"));
+                    true
+                }
+                Err(e) => {
+                    log::warn!("read error: {e}");
+                    false
+                }
+            }
+        });
+
+        // any command comes from a client, so this covers commands too
+        if !self.connections.is_empty() {
+            self.last_active = std::time::Instant::now();
+        }
+
+        shutdown
     }
+
+    /// How long there has been no client connected, as of the last
+    /// [`service`](Self::service).
+    pub fn idle_for(&self) -> std::time::Duration {
+        self.last_active.elapsed()
+    }
+
+    /// One turn of the daemon's main loop: answer clients with
+    /// [`handle_command`], then push any new events to subscribers.
+    /// Returns true once a client has asked to shut down.
+    pub fn run_once(&mut self, state: &Arc<DaemonState>) -> bool {
+        let shutdown = self.service(|session, cmd| handle_command(state, session, cmd));
+
+        state.waker().clear();
+        self.broadcast(&state.take_events());
+        state.set_subscribed(self.has_subscribers());
+
+        shutdown
+    }
+
+    /// Sleep until a client connects, sends something or can take queued
+    /// output, one of `wakeups` (such as a
+    /// [`Waker`](crate::waker::Waker)) becomes readable, or
+    /// `timeout` passes. Returns at once if a client already has another
+    /// command buffered, as `service` reads only one per turn.
+    pub fn wait(&self, wakeups: &[RawFd], timeout: std::time::Duration) -> std::io::Result<()> {
+        if self
+            .connections
+            .iter()
+            .any(Connection::has_buffered_message)
+        {
+            return Ok(());
+        }
+
+        let watch = |fd: RawFd, events| libc::pollfd {
+            fd,
+            events,
+            revents: 0,
+        };
+        let mut fds = vec![watch(self.listener.as_raw_fd(), libc::POLLIN)];
+        fds.extend(wakeups.iter().map(|&fd| watch(fd, libc::POLLIN)));
+        fds.extend(self.connections.iter().map(|conn| {
+            let events = if conn.outgoing.is_empty() {
+                libc::POLLIN
+            } else {
+                libc::POLLIN | libc::POLLOUT
+            };
+            watch(conn.reader.as_raw_fd(), events)
+        }));
+
+        let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } < 0 {
+            let e = std::io::Error::last_os_error();
+            // a signal only cuts the wait short
+            if e.kind() != ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if any connected client has subscribed to events.
+    pub fn has_subscribers(&self) -> bool {
+        self.connections.iter().any(|conn| conn.session.subscribed)
+    }
+
+    /// Send `events` to every subscribed client in one write each, dropping
+    /// any that fail.
+    pub fn broadcast(&mut self, events: &[Poll]) {
+        if events.is_empty() {
+            return;
+        }
+        self.connections.retain_mut(|conn| {
+            if !conn.session.subscribed {
+                return true;
+            }
+            let lines: Vec<String> = events
+                .iter()
+                .map(|event| format_event(event, conn.session.protocol))
+                .collect();
+            match conn.send_all(&lines) {
+                Ok(()) => true,
+                Err(e) => {
+                    log::warn!("dropping subscriber: {e}");
+                    false
+                }
+            }
+        });
+    }
+}
+
+/// Returns true if whatever is listening on the other end of `stream`
+/// answers PING like a yowl daemon.
+fn answers_ping(stream: UnixStream) -> bool {
+    let _ = stream.set_read_timeout(Some(std::time::Duration::from_secs(1)));
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return false,
+    };
+    if writeln!(writer, "PING").is_err() {
+        return false;
+    }
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).is_ok() && line.trim() == "PONG"
+}
+
+/// The uid of the process on the other end of `stream`.
+#[cfg(target_os = "linux")]
+fn peer_uid(stream: &UnixStream) -> std::io::Result<u32> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+/// The uid of the process on the other end of `stream`.
+#[cfg(not(target_os = "linux"))]
+fn peer_uid(stream: &UnixStream) -> std::io::Result<u32> {
+    let mut uid = 0;
+    let mut gid = 0;
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(uid)
 }
 
 impl Drop for Server {
     fn drop(&mut self) {
-        if self.path.exists() {
+        if self.owns_socket && self.path.exists() {
             let _ = std::fs::remove_file(&self.path);
         }
+        // only succeeds if nothing else is in there
+        if let Some(dir) = &self.dir {
+            let _ = std::fs::remove_dir(dir);
+        }
     }
 }
 
 pub struct Connection {
-    reader: BufReader<UnixStream>,
+    reader: UnixStream,
+    /// Bytes received that don't yet make up a whole line
+    pending: Vec<u8>,
     writer: UnixStream,
+    /// Bytes sent but not yet taken by the client
+    outgoing: Vec<u8>,
+    /// Sent a final error; input is discarded until the client hangs up
+    closing: bool,
+    /// Bytes thrown away since `closing` was set
+    discarded: usize,
+    session: Session,
+    /// When the client last sent anything
+    last_heard: std::time::Instant,
+    /// When a keepalive PING went unanswered since
+    pinged: Option<std::time::Instant>,
 }
 
 impl Connection {
     fn new(stream: UnixStream) -> Self {
         let writer = stream.try_clone().expect("failed to clone stream");
         Self {
-            reader: BufReader::new(stream),
+            reader: stream,
+            pending: Vec::new(),
             writer,
+            outgoing: Vec::new(),
+            closing: false,
+            discarded: 0,
+            session: Session::default(),
+            last_heard: std::time::Instant::now(),
+            pinged: None,
+        }
+    }
+
+    /// Note that the client has sent something, so it is still there.
+    fn heard(&mut self) {
+        self.last_heard = std::time::Instant::now();
+        self.pinged = None;
+    }
+
+    /// PING a client that has been quiet for `idle`, in its framing, and
+    /// give up on it if it is still quiet `idle` after that. Returns false
+    /// once the client should be dropped.
+    fn keep_alive(&mut self, idle: std::time::Duration, now: std::time::Instant) -> bool {
+        match self.pinged {
+            Some(pinged) if now.duration_since(pinged) >= idle => {
+                log::warn!("dropping client that didn't answer a keepalive PING");
+                false
+            }
+            Some(_) => true,
+            None if now.duration_since(self.last_heard) >= idle => {
+                self.pinged = Some(now);
+                match self.deliver("PING") {
+                    Ok(()) => true,
+                    Err(e) => {
+                        log::warn!("dropping client: {e}");
+                        false
+                    }
+                }
+            }
+            None => true,
         }
     }
 
+    /// Read the next whole command line. On a nonblocking stream this fails
+    /// with `WouldBlock` until the line is complete; the bytes received so
+    /// far are kept for the next call.
+    ///
+    /// A line containing control characters fails with `InvalidInput`. One
+    /// longer than [`MAX_COMMAND_LEN`] fails with `InvalidData`, after which
+    /// the connection can't be read any further.
     pub fn read_command(&mut self) -> std::io::Result<Option<String>> {
-        let mut line = String::new();
-        let bytes = self.reader.read_line(&mut line)?;
-        if bytes == 0 {
-            return Ok(None); // EOF - client disconnected
+        let mut chunk = [0; 1024];
+        loop {
+            let end = self.pending.iter().position(|&b| b == b'\n');
+            if end.unwrap_or(self.pending.len()) > MAX_COMMAND_LEN {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("command too long (limit {MAX_COMMAND_LEN} bytes)"),
+                ));
+            }
+            if let Some(end) = end {
+                let line: Vec<u8> = self.pending.drain(..=end).collect();
+                return parse_command(&line).map(Some);
+            }
+
+            match self.reader.read(&mut chunk)? {
+                // EOF - deliver an unterminated last line, then report the disconnect
+                0 if self.pending.is_empty() => return Ok(None),
+                0 => {
+                    let line = std::mem::take(&mut self.pending);
+                    return parse_command(&line).map(Some);
+                }
+                n => {
+                    self.heard();
+                    self.pending.extend_from_slice(&chunk[..n]);
+                }
+            }
         }
-        Ok(Some(line.trim().to_string()))
     }
 
+    /// Queue `response` as a line and write as much of the queue as the
+    /// client will take. Fails once the client falls too far behind.
     pub fn send(&mut self, response: &str) -> std::io::Result<()> {
-        writeln!(self.writer, "{}", response)?;
-        self.writer.flush()
+        self.queue_line(response);
+        self.flush()
+    }
+
+    /// Queue all of `messages` in the client's framing and flush once, so a
+    /// burst of events goes out as one write rather than one per message.
+    pub fn send_all(&mut self, messages: &[impl AsRef<str>]) -> std::io::Result<()> {
+        for message in messages {
+            if self.session.framed {
+                self.queue_frame(message.as_ref())?;
+            } else {
+                self.queue_line(message.as_ref());
+            }
+        }
+        self.flush()
+    }
+
+    fn queue_line(&mut self, line: &str) {
+        self.outgoing.extend_from_slice(line.as_bytes());
+        self.outgoing.push(b'\n');
+    }
+
+    /// Write queued output without blocking, keeping whatever the client
+    /// isn't ready for. Fails if more than [`MAX_QUEUED_BYTES`] are left.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.writer.write(&self.outgoing) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.outgoing.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        if self.outgoing.len() > MAX_QUEUED_BYTES {
+            return Err(std::io::Error::other(format!(
+                "client isn't reading ({} bytes queued)",
+                self.outgoing.len()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Read the next frame: a 4-byte big-endian length, then that many bytes
+    /// of UTF-8. Like `read_command`, this fails with `WouldBlock` until the
+    /// whole frame has arrived.
+    pub fn read_frame(&mut self) -> std::io::Result<Option<String>> {
+        let mut chunk = [0; 1024];
+        loop {
+            if let Some(header) = self.pending.first_chunk::<4>() {
+                let len = u32::from_be_bytes(*header) as usize;
+                if len > MAX_FRAME_LEN {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("frame of {len} bytes is too large"),
+                    ));
+                }
+                if self.pending.len() >= 4 + len {
+                    let payload: Vec<u8> = self.pending.drain(..4 + len).skip(4).collect();
+                    return String::from_utf8(payload)
+                        .map(Some)
+                        .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e));
+                }
+            }
+
+            match self.reader.read(&mut chunk)? {
+                0 if self.pending.is_empty() => return Ok(None),
+                0 => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "connection closed mid-frame",
+                    ))
+                }
+                n => {
+                    self.heard();
+                    self.pending.extend_from_slice(&chunk[..n]);
+                }
+            }
+        }
+    }
+
+    /// Queue `message` as a length-prefixed frame, as `send` does for lines.
+    pub fn send_frame(&mut self, message: &str) -> std::io::Result<()> {
+        self.queue_frame(message)?;
+        self.flush()
+    }
+
+    fn queue_frame(&mut self, message: &str) -> std::io::Result<()> {
+        let len = u32::try_from(message.len())
+            .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, "message too large"))?;
+        self.outgoing.extend_from_slice(&len.to_be_bytes());
+        self.outgoing.extend_from_slice(message.as_bytes());
+        Ok(())
+    }
+
+    /// Send `message` and stop reading commands. Closing the socket outright
+    /// while the client is still writing would reset the connection and lose
+    /// the message, so input is discarded until the client hangs up instead.
+    fn close_with(&mut self, message: &str) {
+        let _ = self.deliver(message);
+        let _ = self.writer.shutdown(Shutdown::Write);
+        self.pending = Vec::new();
+        self.closing = true;
+    }
+
+    /// Throw away what the client has sent, up to [`MAX_DISCARD_PER_TURN`]
+    /// bytes. Returns false once it has hung up, or has sent more than
+    /// [`MAX_DISCARD`] since being closed.
+    fn discard_input(&mut self) -> bool {
+        let mut chunk = [0; 1024];
+        let mut this_turn = 0;
+        while this_turn < MAX_DISCARD_PER_TURN {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => return false,
+                Ok(n) => this_turn += n,
+                Err(e) => return e.kind() == ErrorKind::WouldBlock,
+            }
+        }
+        self.discarded += this_turn;
+        if self.discarded > MAX_DISCARD {
+            log::warn!("dropping client that kept sending after being closed");
+            return false;
+        }
+        true
+    }
+
+    /// Whether a whole message has already been read off the socket, so poll
+    /// won't report it.
+    fn has_buffered_message(&self) -> bool {
+        if self.session.framed {
+            self.pending.first_chunk::<4>().is_some_and(|header| {
+                self.pending.len() >= 4 + u32::from_be_bytes(*header) as usize
+            })
+        } else {
+            self.pending.contains(&b'\n')
+        }
+    }
+
+    /// Read the next message in the framing this connection uses.
+    fn receive(&mut self) -> std::io::Result<Option<String>> {
+        if self.session.framed {
+            self.read_frame()
+        } else {
+            self.read_command()
+        }
+    }
+
+    /// Send a message in the framing this connection uses.
+    fn deliver(&mut self, message: &str) -> std::io::Result<()> {
+        if self.session.framed {
+            self.send_frame(message)
+        } else {
+            self.send(message)
+        }
     }
 }
 
-pub fn handle_command(cmd: &str, state: &Arc<DaemonState>) -> String {
+/// Returns true if `cmd` asks the daemon to shut down, in either protocol.
+pub fn is_shutdown(cmd: &str) -> bool {
+    if cmd.starts_with('{') {
+        matches!(
+            serde_json::from_str::<JsonRequest>(cmd),
+            Ok(JsonRequest::Shutdown)
+        )
+    } else {
+        cmd.to_uppercase() == "SHUTDOWN"
+    }
+}
+
+pub fn handle_command(state: &Arc<DaemonState>, session: &mut Session, cmd: &str) -> String {
+    if session.handshake_pending && !is_handshake(cmd) {
+        return if cmd.starts_with('{') {
+            serde_json::to_string(&JsonResponse::Error {
+                message: "handshake required".to_string(),
+            })
+            .expect("responses always serialize")
+        } else {
+            "ERROR handshake required".to_string()
+        };
+    }
+
+    if cmd.starts_with('{') {
+        return handle_json(state, cmd);
+    }
+
     let parts: Vec<&str> = cmd.splitn(2, ' ').collect();
     match parts[0].to_uppercase().as_str() {
         "PING" => "PONG".to_string(),
-        "START" => state.start_recording().to_string(),
+        "HELLO" => hello(session, parts.get(1).copied().unwrap_or("")),
+        "CAPABILITIES" => format_capabilities(state),
+        "VERSION" => format!("VERSION:{}", crate::version::describe()),
+        // SHUTDOWN mid-recording answers with the final transcript, so it isn't lost
+        "SHUTDOWN" => match state.finish_recording() {
+            Some(text) => format!("TRANSCRIPT:{}", escape_line(&text)),
+            None => "OK".to_string(),
+        },
+        "START" => match parse_start_options(parts.get(1).copied().unwrap_or("")) {
+            Ok(options) => state.start_recording(options),
+            Err(e) => format!("ERROR {e}"),
+        },
+        "PRELOAD" => state.preload(),
         "STOP" => state.stop_recording().to_string(),
-        "POLL" => state.poll(),
+        // TOGGLE is STOP if recording, or else START with no options, answering
+        // `OK recording` or `OK idle` for the state it leaves. Commands are
+        // handled one at a time, so unlike STATUS then START or STOP, no other
+        // client can get in between.
+        "TOGGLE" if state.is_recording() => match state.stop_recording() {
+            "OK" => "OK idle".to_string(),
+            error => error.to_string(),
+        },
+        "TOGGLE" => match state.start_recording(StartOptions::default()) {
+            ok if ok == "OK" => "OK recording".to_string(),
+            other => other,
+        },
+        "CANCEL" => state.cancel().to_string(),
+        "RESET" => state.reset_session().to_string(),
+        "PAUSE" => state.pause_recording().to_string(),
+        "RESUME" => state.resume_recording().to_string(),
+        // POLL reports the terminal edit needed to catch up with the live transcript:
+        //   IDLE:                         not recording
+        //   STOPPED:                      recording stopped itself after silence,
+        //                                 reported once; IDLE: after that
+        //   RECORDING:<backspaces>:<text> erase <backspaces> chars, then type <text>;
+        //                                 once more after recording ends if
+        //                                 there are last words to deliver
+        // Applying every response in order reconstructs the transcript. From
+        // protocol 2, <text> is escaped as for TRANSCRIPT.
+        "POLL" => format_poll(&state.poll(), session.protocol),
+        // After SUBSCRIBE the daemon also pushes `EVENT <poll response>` lines to
+        // the connection whenever the worker produces a change, until UNSUBSCRIBE.
+        // Events don't take anything from POLL, which other clients still see.
+        "SUBSCRIBE" => {
+            session.subscribed = true;
+            "OK".to_string()
+        }
+        "UNSUBSCRIBE" => {
+            session.subscribed = false;
+            "OK".to_string()
+        }
+        "STATUS" => format_status(&state.status()),
+        "METRICS" => format_metrics(&state.metrics()),
+        // STATS: recent inference times against the interval; behind=yes
+        // means passes take longer than the interval, so try a smaller model
+        "STATS" => format_metrics(&state.stats()),
+        "PEEK" => {
+            let (committed, provisional) = state.peek();
+            format_peek(&committed, &provisional)
+        }
+        "LEVEL" => format!("LEVEL:{:.3}", state.input_level()),
+        // GET_TRANSCRIPT is the same text unescaped, so it needs binary framing
+        "GET_TRANSCRIPT" if session.framed => format!("TRANSCRIPT:{}", state.transcript(false)),
+        "GET_TRANSCRIPT" => {
+            "ERROR GET_TRANSCRIPT needs binary framing (HELLO ... BINARY)".to_string()
+        }
+        // TRANSCRIPT [committed] returns the text delivered so far, escaped so
+        // it stays on one line: `\\` for a backslash, `\n` and `\r` for newlines.
+        "TRANSCRIPT" => {
+            let args: Vec<String> = parts
+                .get(1)
+                .map_or("", |arg| arg.trim())
+                .split_whitespace()
+                .map(str::to_lowercase)
+                .collect();
+            let committed = args.iter().any(|arg| arg == "committed");
+            let segments = args.iter().any(|arg| arg == "--segments");
+            if args
+                .iter()
+                .any(|arg| arg != "committed" && arg != "--segments")
+            {
+                "ERROR usage: TRANSCRIPT [committed] [--segments]".to_string()
+            } else if segments {
+                format_segments(&state.segments(committed))
+            } else {
+                format!("TRANSCRIPT:{}", escape_line(&state.transcript(committed)))
+            }
+        }
+        // The segments TRANSCRIPT --segments lists, as a JSON array of
+        // {"text","start_ms","end_ms"} objects on one line
+        "SEGMENTS" => match parts.get(1).map(|arg| arg.trim().to_lowercase()).as_deref() {
+            None | Some("") => {
+                serde_json::to_string(&state.segments(false)).expect("segments always serialize")
+            }
+            Some("committed") => {
+                serde_json::to_string(&state.segments(true)).expect("segments always serialize")
+            }
+            _ => "ERROR usage: SEGMENTS [committed]".to_string(),
+        },
+        "WAVEFORM" => match parts.get(1).map(|arg| arg.trim().parse::<usize>()) {
+            Some(Ok(bins)) => match state.waveform(bins) {
+                Ok(envelope) => {
+                    let values: Vec<String> = envelope.iter().map(|v| format!("{v:.3}")).collect();
+                    format!("WAVEFORM:{}", values.join(","))
+                }
+                Err(e) => format!("ERROR {e}"),
+            },
+            _ => "ERROR usage: WAVEFORM <bins>".to_string(),
+        },
+        // MODEL and SETMODEL load in the background too, reported by RELOAD
+        "MODEL" => match parts.get(1) {
+            Some(path) => state.load_model(path.trim()),
+            None => "ERROR usage: MODEL <path>".to_string(),
+        },
+        // RELOAD <path> loads a model in the background; RELOAD alone reports how it went
+        "RELOAD" => match parts.get(1).map(|arg| arg.trim()) {
+            Some(path) if !path.is_empty() => state.reload_model(path),
+            _ => match state.reload_status() {
+                Reload::Idle => "RELOAD:idle".to_string(),
+                Reload::Loading(name) => format!("RELOAD:loading {name}"),
+                Reload::Done(name) => format!("RELOAD:done {name}"),
+                Reload::Failed(message) => format!("ERROR {message}"),
+            },
+        },
+        "SETMODEL" => match parts.get(1) {
+            Some(name) => state.load_named_model(name.trim()),
+            None => "ERROR usage: SETMODEL <name>".to_string(),
+        },
+        "SETMODELDIR" => match parts.get(1) {
+            Some(dir) => state.set_models_dir(dir.trim()),
+            None => "ERROR usage: SETMODELDIR <path>".to_string(),
+        },
+        "INFERENCE_ERRORS" => {
+            let args: Vec<&str> = parts
+                .get(1)
+                .map_or(vec![], |a| a.split_whitespace().collect());
+            let stop = match args.get(1).map(|a| a.to_lowercase()) {
+                None => Some(false),
+                Some(a) if a == "continue" => Some(false),
+                Some(a) if a == "stop" => Some(true),
+                Some(_) => None,
+            };
+            match (args.first().map(|a| a.parse::<u32>()), stop) {
+                (Some(Ok(threshold)), Some(stop)) => state
+                    .set_inference_error_policy(threshold, stop)
+                    .to_string(),
+                _ => "ERROR usage: INFERENCE_ERRORS <threshold> [continue|stop]".to_string(),
+            }
+        }
+        // WATCHDOG: a pass running longer than <intervals> transcribe
+        // intervals is logged and shows as degraded=yes in STATUS; with
+        // abort, it is also cut short
+        "WATCHDOG" => {
+            let args: Vec<&str> = parts
+                .get(1)
+                .map_or(vec![], |a| a.split_whitespace().collect());
+            let abort = match args.get(1).map(|a| a.to_lowercase()) {
+                None => Some(false),
+                Some(a) if a == "warn" => Some(false),
+                Some(a) if a == "abort" => Some(true),
+                Some(_) => None,
+            };
+            match (args.first().map(|a| a.parse::<u32>()), abort) {
+                (Some(Ok(intervals)), Some(abort)) => {
+                    state.set_watchdog(intervals, abort);
+                    "OK".to_string()
+                }
+                _ => "ERROR usage: WATCHDOG <intervals> [warn|abort]".to_string(),
+            }
+        }
+        "CHUNK" => match parts.get(1).map(|arg| arg.trim()) {
+            Some(arg) if arg.eq_ignore_ascii_case("off") => {
+                state.set_chunk_size(None);
+                "OK".to_string()
+            }
+            Some(arg) => match arg.parse::<usize>() {
+                Ok(max_chars) if max_chars > 0 => {
+                    state.set_chunk_size(Some(max_chars));
+                    "OK".to_string()
+                }
+                _ => "ERROR usage: CHUNK <chars|off>".to_string(),
+            },
+            None => "ERROR usage: CHUNK <chars|off>".to_string(),
+        },
+        "GET" => match parts.get(1).map(|arg| tokenize(arg)) {
+            Some(Ok(args)) if args.len() == 1 => state.get_config(&args[0]),
+            Some(Err(e)) => format!("ERROR {e}"),
+            _ => "ERROR usage: GET <key>".to_string(),
+        },
+        "SET" => match parts.get(1).map(|arg| tokenize(arg)) {
+            Some(Ok(args)) if args.len() == 2 => state.set_config(&args[0], &args[1]),
+            Some(Err(e)) => format!("ERROR {e}"),
+            _ => "ERROR usage: SET <key> <value>".to_string(),
+        },
+        "BUFFER" => match parts.get(1) {
+            Some(secs) => state.set_buffer_duration(secs.trim()),
+            None => "ERROR usage: BUFFER <secs>".to_string(),
+        },
+        "LANG" => match parts.get(1) {
+            Some(language) => state.set_language(language.trim()),
+            None => "ERROR usage: LANG <code>".to_string(),
+        },
+        "TRANSLATE" => match parts.get(1).map(|arg| arg.trim().to_lowercase()).as_deref() {
+            Some("on") => state.set_translate(true),
+            Some("off") => state.set_translate(false),
+            _ => "ERROR usage: TRANSLATE <on|off>".to_string(),
+        },
+        // answers with the level before, so a client can put it back
+        "LOGLEVEL" => match parts.get(1).map(|level| level.trim()) {
+            Some(level) => match crate::logging::set_level(level) {
+                Ok(previous) => {
+                    log::info!("log level set to {level}");
+                    format!("OK {}", previous.as_str().to_lowercase())
+                }
+                Err(e) => format!("ERROR {e}"),
+            },
+            None => "ERROR usage: LOGLEVEL <trace|debug|info|warn|error|off>".to_string(),
+        },
+        "PROMPT" => state.set_initial_prompt(parts.get(1).map_or("", |prompt| prompt.trim())),
+        "AGING_MATCH" => match parts.get(1).map(|arg| arg.trim().parse::<usize>()) {
+            Some(Ok(min_match_len)) => state.set_min_match_len(min_match_len),
+            _ => "ERROR usage: AGING_MATCH <chars>".to_string(),
+        },
+        "BACKSPACE" => match parts.get(1) {
+            Some(unit) => state.set_backspace_unit(unit),
+            None => "ERROR usage: BACKSPACE <char|grapheme>".to_string(),
+        },
+        "STABILITY" => match parts.get(1).map(|arg| arg.trim().parse::<u8>()) {
+            Some(Ok(updates)) => {
+                state.set_stability(updates);
+                "OK".to_string()
+            }
+            _ => "ERROR usage: STABILITY <updates>".to_string(),
+        },
+        "DIFF" => match parts.get(1) {
+            Some(mode) => state.set_diff_mode(mode),
+            None => "ERROR usage: DIFF <char|word>".to_string(),
+        },
+        // SUBSTITUTE <from>=<to> replaces the whole words <from> with <to>
+        "SUBSTITUTE" => match parts.get(1).and_then(|arg| arg.split_once('=')) {
+            Some((from, to)) => state.add_substitution(from, to),
+            None => "ERROR usage: SUBSTITUTE <from>=<to>".to_string(),
+        },
+        "POSTPROCESS" => match parts.get(1) {
+            Some(post_process) => state.set_post_process(post_process),
+            None => "ERROR usage: POSTPROCESS <raw|capitalize>".to_string(),
+        },
+        // REPEATS <n> collapses runs of n or more of the same word to one
+        "REPEATS" => match parts.get(1) {
+            Some(min_run) => state.set_collapse_repeats(min_run),
+            None => "ERROR usage: REPEATS <words of 2 or more|off>".to_string(),
+        },
+        "SAVEPROFILE" => match parts.get(1) {
+            Some(name) => state.save_profile(name.trim()),
+            None => "ERROR usage: SAVEPROFILE <name>".to_string(),
+        },
+        "SETPROFILE" => match parts.get(1) {
+            Some(name) => state.set_profile(name.trim()),
+            None => "ERROR usage: SETPROFILE <name>".to_string(),
+        },
+        "LISTPROFILES" => {
+            let (active, names) = state.profiles();
+            format!("active={active} profiles={}", names.join(","))
+        }
+        // SESSIONS:<file>|... lists the transcripts kept in session_log_dir
+        "SESSIONS" => match state.session_files() {
+            Ok(names) => format!("SESSIONS:{}", names.join("|")),
+            Err(e) => format!("ERROR {e}"),
+        },
+        "LIST_DEVICES" => match audio::try_list_input_devices() {
+            Ok(names) => format_devices(&names, audio::default_input_device_name().as_deref()),
+            Err(e) => {
+                log::warn!("Failed to enumerate input devices: {e}");
+                "ERROR no audio devices".to_string()
+            }
+        },
+        // DEVICES:<index>:<sample rate>:<channels>:<name>|... with the default
+        // device's name marked by a leading `*` and unreadable details left empty
+        "DEVICES" => match audio::describe_input_devices() {
+            Ok(devices) => format_device_details(&devices),
+            Err(e) => {
+                log::warn!("Failed to enumerate input devices: {e}");
+                "ERROR no audio devices".to_string()
+            }
+        },
+        "DEVICE" => match parts.get(1) {
+            Some(name) => state.set_device(name.trim()),
+            None => "ERROR usage: DEVICE <name|default>".to_string(),
+        },
+        "SILENCE_TIMEOUT" => match parts.get(1).map(|arg| arg.trim()) {
+            Some(ms) if ms.parse::<u64>().is_ok() => state.set_config("silence_timeout_ms", ms),
+            _ => "ERROR usage: SILENCE_TIMEOUT <ms>".to_string(),
+        },
+        "RECORD_WAV" => match parts.get(1) {
+            Some(path) => state.set_record_wav(path.trim()),
+            None => "ERROR usage: RECORD_WAV <path|off>".to_string(),
+        },
+        "VAD" => state.set_vad_threshold(parts.get(1).map_or("", |arg| arg.trim())),
+        "NO_SPEECH" => state.set_no_speech_threshold(parts.get(1).map_or("", |arg| arg.trim())),
+        "WARMUP" => state.set_warmup(parts.get(1).copied().unwrap_or("")),
+        "OVERLAP" => match parts.get(1) {
+            Some(policy) => state.set_overlap_policy(policy.trim()),
+            None => "ERROR usage: OVERLAP <clamp|drop>".to_string(),
+        },
         _ => format!("ERROR unknown command: {}", parts[0]),
     }
 }
+
+/// Decode a command line, without its terminator. Control characters
+/// (including NUL) are rejected; they have no place in a command. Tabs are
+/// whitespace, as between arguments.
+fn parse_command(line: &[u8]) -> std::io::Result<String> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    if line.iter().any(|&b| b.is_ascii_control() && b != b'\t') {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            "command contains control characters",
+        ));
+    }
+    Ok(String::from_utf8_lossy(line).trim().to_string())
+}
+
+/// Returns true for the commands allowed before a strict handshake.
+fn is_handshake(cmd: &str) -> bool {
+    let name = cmd.split_whitespace().next().unwrap_or("");
+    name.eq_ignore_ascii_case("HELLO") || name.eq_ignore_ascii_case("PING")
+}
+
+/// Handle `HELLO <client-version> [<max-protocol>]`, settling on the newest
+/// protocol both sides speak.
+/// The short form `HELLO <max-protocol>` answers `YOWL <protocol> <daemon-version>`.
+/// A trailing `BINARY` (or a bare `HELLO BINARY`) switches the connection to
+/// length-prefixed frames once this response has been sent as a line.
+fn hello(session: &mut Session, args: &str) -> String {
+    let mut args: Vec<&str> = args.split_whitespace().collect();
+    let binary = args
+        .last()
+        .is_some_and(|arg| arg.eq_ignore_ascii_case("binary"));
+    if binary {
+        args.pop();
+    }
+
+    let short = matches!(args.as_slice(), [version] if version.parse::<u32>().is_ok());
+    let max_protocol = match args.as_slice() {
+        [] if binary => PROTOCOL_VERSION,
+        [version] if short => match version.parse::<u32>() {
+            Ok(max) if max >= BASE_PROTOCOL => max,
+            _ => return format!("ERROR unsupported protocol: {version}"),
+        },
+        [_] => PROTOCOL_VERSION,
+        [_, max] => match max.parse::<u32>() {
+            Ok(max) if max >= BASE_PROTOCOL => max,
+            _ => return format!("ERROR unsupported protocol: {max}"),
+        },
+        _ => return "ERROR usage: HELLO <client-version> [<max-protocol>] [BINARY]".to_string(),
+    };
+
+    session.protocol = max_protocol.min(PROTOCOL_VERSION);
+    session.framed = binary;
+    session.handshake_pending = false;
+    log::info!(
+        "client {} negotiated protocol {}{}",
+        args.first().filter(|_| !short).unwrap_or(&"unknown"),
+        session.protocol,
+        if binary { " with binary framing" } else { "" }
+    );
+
+    let framing = if binary { " framing=binary" } else { "" };
+    if short {
+        return format!(
+            "YOWL {} {}{framing}",
+            session.protocol,
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+    format!(
+        "HELLO version={} protocol={} capabilities={}{framing}",
+        env!("CARGO_PKG_VERSION"),
+        session.protocol,
+        capabilities().join(","),
+    )
+}
+
+/// The build's capabilities followed by runtime facts, e.g.
+/// `CAPABILITIES:events json ... model=ggml-base.en.bin audio-host=ALSA`.
+fn format_capabilities(state: &DaemonState) -> String {
+    let mut list = capabilities().join(" ");
+    if let Some(model) = state.model_name() {
+        list.push_str(&format!(" model={model}"));
+    }
+    list.push_str(&format!(" audio-host={}", audio::host_name()));
+    format!("CAPABILITIES:{list}")
+}
+
+fn format_poll(poll: &Poll, protocol: u32) -> String {
+    match poll {
+        Poll::Idle => "IDLE:".to_string(),
+        Poll::Stopped => "STOPPED:".to_string(),
+        Poll::Recording(diff) if protocol >= 2 => {
+            format!(
+                "RECORDING:{}:{}",
+                diff.backspaces,
+                escape_line(&diff.new_text)
+            )
+        }
+        Poll::Recording(diff) => format!("RECORDING:{}:{}", diff.backspaces, diff.new_text),
+        Poll::Fault(fault) => format!("ERROR {} {}", fault.code, fault.message),
+    }
+}
+
+/// Format a pushed event for a subscriber speaking `protocol`.
+fn format_event(event: &Poll, protocol: u32) -> String {
+    format!("EVENT {}", format_poll(event, protocol))
+}
+
+/// Split a command line into arguments. Whitespace separates arguments,
+/// double quotes group them, and a backslash escapes the next character.
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut quoted = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped) => current.get_or_insert_with(String::new).push(escaped),
+                None => return Err("trailing backslash".to_string()),
+            },
+            '"' => {
+                quoted = !quoted;
+                current.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if let Some(arg) = current.take() {
+                    args.push(arg);
+                }
+            }
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if quoted {
+        return Err("unterminated quote".to_string());
+    }
+    args.extend(current);
+    Ok(args)
+}
+
+/// Parse `--key value` pairs, allowing only the given keys.
+fn parse_options(args: &[String], allowed: &[&str]) -> Result<Vec<(String, String)>, String> {
+    let mut options = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let key = arg
+            .strip_prefix("--")
+            .ok_or_else(|| format!("unexpected argument: {arg}"))?;
+        if !allowed.contains(&key) {
+            return Err(format!("unknown option: --{key}"));
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for --{key}"))?;
+        options.push((key.to_string(), value.clone()));
+    }
+
+    Ok(options)
+}
+
+/// Parse `START [--device <name>] [--language <code>]`.
+fn parse_start_options(args: &str) -> Result<StartOptions, String> {
+    let mut options = StartOptions::default();
+    for (key, value) in parse_options(&tokenize(args)?, &["device", "language"])? {
+        match key.as_str() {
+            "device" => options.device = Some(value),
+            "language" => options.language = Some(value),
+            _ => unreachable!("option was allowed"),
+        }
+    }
+    Ok(options)
+}
+
+/// Format input device names as `LIST_DEVICES:<name>|<name>|...`, marking
+/// the default device with a leading `*`.
+fn format_devices(names: &[String], default: Option<&str>) -> String {
+    let names: Vec<String> = names
+        .iter()
+        .map(|name| {
+            if Some(name.as_str()) == default {
+                format!("*{name}")
+            } else {
+                name.clone()
+            }
+        })
+        .collect();
+    format!("LIST_DEVICES:{}", names.join("|"))
+}
+
+/// Format input devices for the DEVICES response.
+fn format_device_details(devices: &[audio::InputDevice]) -> String {
+    let optional = |value: Option<String>| value.unwrap_or_default();
+    let entries: Vec<String> = devices
+        .iter()
+        .map(|device| {
+            format!(
+                "{}:{}:{}:{}{}",
+                device.index,
+                optional(device.sample_rate.map(|rate| rate.to_string())),
+                optional(device.channels.map(|channels| channels.to_string())),
+                if device.default { "*" } else { "" },
+                optional(device.name.clone()),
+            )
+        })
+        .collect();
+    format!("DEVICES:{}", entries.join("|"))
+}
+
+/// Escape text so it can be sent as a single protocol line.
+fn escape_line(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Format segments as `SEGMENTS:<t0>:<t1>:<text>` entries separated by tabs,
+/// with times in centiseconds from the start of the recording and text
+/// escaped as in PEEK.
+fn format_segments(segments: &[whisper::Segment]) -> String {
+    let entries: Vec<String> = segments
+        .iter()
+        .map(|segment| {
+            format!(
+                "{}:{}:{}",
+                segment.start_ms / 10,
+                segment.end_ms / 10,
+                escape_field(&segment.text)
+            )
+        })
+        .collect();
+    format!("SEGMENTS:{}", entries.join("\t"))
+}
+
+/// Format metrics as space-separated `key=value` pairs, like STATUS.
+fn format_metrics(metrics: &[(&str, String)]) -> String {
+    metrics
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Format committed and provisional text as `PEEK:<committed>\t<provisional>`,
+/// each escaped for the line protocol with tabs escaped as `\t`.
+fn format_peek(committed: &str, provisional: &str) -> String {
+    format!(
+        "PEEK:{}\t{}",
+        escape_field(committed),
+        escape_field(provisional)
+    )
+}
+
+/// Escape text for a tab-separated field of a protocol line.
+fn escape_field(text: &str) -> String {
+    escape_line(text).replace('\t', "\\t")
+}
+
+/// Escape text for a `key=value` pair of a space-separated line, with
+/// spaces escaped as `\s`.
+fn escape_value(text: &str) -> String {
+    escape_line(text).replace(' ', "\\s")
+}
+
+/// Format a status snapshot as space-separated `key=value` pairs.
+fn format_status(status: &Status) -> String {
+    format!(
+        "state={} elapsed_ms={} samples={} committed={} provisional={} \
+         language={} buffer_secs={}{}{}{}",
+        if status.paused {
+            "paused"
+        } else if status.recording {
+            "recording"
+        } else {
+            "idle"
+        },
+        status.elapsed.as_millis(),
+        status.buffered_samples,
+        status.committed_chars,
+        status.provisional_chars,
+        escape_value(&status.language),
+        status.buffer_secs,
+        status.model.as_ref().map_or(String::new(), |model| format!(
+            " model={}",
+            escape_value(model)
+        )),
+        status
+            .error
+            .map_or(String::new(), |code| format!(" error={code}")),
+        if status.degraded { " degraded=yes" } else { "" },
+    )
+}
+
+/// A request in the JSON protocol, e.g. `{"cmd":"waveform","bins":64}`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "lowercase")]
+enum JsonRequest {
+    Ping,
+    Shutdown,
+    Start,
+    Stop,
+    Pause,
+    Resume,
+    Poll,
+    Waveform {
+        bins: usize,
+    },
+    Overlap {
+        policy: String,
+    },
+    Transcript {
+        #[serde(default)]
+        committed: bool,
+    },
+    Devices,
+    Peek,
+}
+
+/// A response in the JSON protocol, e.g. `{"status":"recording","backspaces":3,"text":"ld"}`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum JsonResponse {
+    Ok,
+    Loading,
+    Pong,
+    Idle,
+    Stopped,
+    Recording {
+        backspaces: usize,
+        text: String,
+    },
+    Waveform {
+        values: Vec<f32>,
+    },
+    Transcript {
+        text: String,
+    },
+    Peek {
+        committed: String,
+        provisional: String,
+    },
+    Devices {
+        devices: Vec<audio::InputDevice>,
+    },
+    Fault {
+        code: String,
+        message: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl JsonResponse {
+    /// Convert a plain "OK" / "LOADING" / "ERROR <message>" text response.
+    fn from_text(response: &str) -> Self {
+        match response.strip_prefix("ERROR") {
+            Some(message) => Self::Error {
+                message: message.trim().to_string(),
+            },
+            None if response == "LOADING" => Self::Loading,
+            None => Self::Ok,
+        }
+    }
+}
+
+fn handle_json(state: &Arc<DaemonState>, cmd: &str) -> String {
+    let response = match serde_json::from_str::<JsonRequest>(cmd) {
+        Ok(JsonRequest::Ping) => JsonResponse::Pong,
+        Ok(JsonRequest::Shutdown) => match state.finish_recording() {
+            Some(text) => JsonResponse::Transcript { text },
+            None => JsonResponse::Ok,
+        },
+        Ok(JsonRequest::Start) => {
+            JsonResponse::from_text(&state.start_recording(StartOptions::default()))
+        }
+        Ok(JsonRequest::Stop) => JsonResponse::from_text(state.stop_recording()),
+        Ok(JsonRequest::Pause) => JsonResponse::from_text(state.pause_recording()),
+        Ok(JsonRequest::Resume) => JsonResponse::from_text(state.resume_recording()),
+        Ok(JsonRequest::Poll) => match state.poll() {
+            Poll::Idle => JsonResponse::Idle,
+            Poll::Stopped => JsonResponse::Stopped,
+            Poll::Recording(diff) => JsonResponse::Recording {
+                backspaces: diff.backspaces,
+                text: diff.new_text,
+            },
+            Poll::Fault(fault) => JsonResponse::Fault {
+                code: fault.code.to_string(),
+                message: fault.message,
+            },
+        },
+        Ok(JsonRequest::Waveform { bins }) => match state.waveform(bins) {
+            Ok(values) => JsonResponse::Waveform { values },
+            Err(message) => JsonResponse::Error { message },
+        },
+        Ok(JsonRequest::Overlap { policy }) => {
+            JsonResponse::from_text(&state.set_overlap_policy(&policy))
+        }
+        Ok(JsonRequest::Transcript { committed }) => JsonResponse::Transcript {
+            text: state.transcript(committed),
+        },
+        Ok(JsonRequest::Peek) => {
+            let (committed, provisional) = state.peek();
+            JsonResponse::Peek {
+                committed,
+                provisional,
+            }
+        }
+        Ok(JsonRequest::Devices) => match audio::describe_input_devices() {
+            Ok(devices) => JsonResponse::Devices { devices },
+            Err(e) => JsonResponse::Error {
+                message: format!("no audio devices: {e}"),
+            },
+        },
+        Err(e) => JsonResponse::Error {
+            message: format!("invalid request: {e}"),
+        },
+    };
+
+    serde_json::to_string(&response).expect("responses always serialize")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::waker::Waker;
+    use std::time::Duration;
+
+    fn test_socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("yowl-test-{}-{name}.sock", std::process::id()))
+    }
+
+    fn test_server(name: &str) -> Server {
+        let server = Server::bind_at(test_socket_path(name)).expect("Failed to bind server");
+        server.set_nonblocking(true).unwrap();
+        server
+    }
+
+    fn connect(server: &Server) -> BufReader<UnixStream> {
+        let stream = UnixStream::connect(&server.path).expect("Failed to connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        BufReader::new(stream)
+    }
+
+    fn ping_handler(session: &mut Session, cmd: &str) -> String {
+        match cmd {
+            "PING" => "PONG".to_string(),
+            "SHUTDOWN" => "OK".to_string(),
+            "SUBSCRIBE" => {
+                session.subscribed = true;
+                "OK".to_string()
+            }
+            other => format!("ERROR unknown command: {other}"),
+        }
+    }
+
+    fn request(server: &mut Server, client: &mut BufReader<UnixStream>, cmd: &str) -> String {
+        writeln!(client.get_mut(), "{cmd}").unwrap();
+        server.service(ping_handler);
+        let mut line = String::new();
+        client.read_line(&mut line).unwrap();
+        line.trim().to_string()
+    }
+
+    #[test]
+    fn test_handle_start_twice() {
+        let (state, _) = crate::testing::state();
+
+        assert_eq!(crate::testing::command(&state, "START"), "OK");
+        assert_eq!(
+            crate::testing::command(&state, "START"),
+            "ERROR already recording"
+        );
+        assert_eq!(crate::testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_toggle() {
+        let (state, _) = crate::testing::state();
+
+        assert_eq!(crate::testing::command(&state, "TOGGLE"), "OK recording");
+        assert!(state.is_recording());
+        assert_eq!(crate::testing::command(&state, "TOGGLE"), "OK idle");
+        assert!(!state.is_recording());
+    }
+
+    #[test]
+    fn test_json_ping_and_errors() {
+        let (state, _) = crate::testing::state();
+
+        assert_eq!(
+            crate::testing::command(&state, r#"{"cmd":"ping"}"#),
+            r#"{"status":"pong"}"#
+        );
+        assert!(crate::testing::command(&state, r#"{"cmd":"bogus"}"#)
+            .starts_with(r#"{"status":"error""#));
+        assert!(crate::testing::command(&state, "{not json").starts_with(r#"{"status":"error""#));
+        assert!(is_shutdown(r#"{"cmd":"shutdown"}"#));
+        assert!(is_shutdown("shutdown"));
+        assert!(!is_shutdown(r#"{"cmd":"stop"}"#));
+    }
+
+    #[test]
+    fn test_json_poll_round_trip() {
+        let (state, model) = crate::testing::state();
+        let response = |cmd: &str| -> JsonResponse {
+            serde_json::from_str(&crate::testing::command(&state, cmd))
+                .expect("invalid JSON response")
+        };
+
+        assert_eq!(response(r#"{"cmd":"poll"}"#), JsonResponse::Idle);
+        assert_eq!(response(r#"{"cmd":"start"}"#), JsonResponse::Ok);
+
+        let transcripts = [
+            "Note: this has a colon",
+            "Note: this has \"quotes\" and: colons",
+            "Note: this has \"quotes\" and: colons\nand a newline",
+        ];
+
+        let mut terminal = String::new();
+        for transcript in transcripts {
+            model.set_text(transcript);
+            crate::testing::transcribe(&state);
+
+            let JsonResponse::Recording { backspaces, text } = response(r#"{"cmd":"poll"}"#) else {
+                panic!("expected a recording response");
+            };
+            for _ in 0..backspaces {
+                terminal.pop();
+            }
+            terminal.push_str(&text);
+            assert_eq!(terminal, transcript);
+        }
+
+        assert_eq!(response(r#"{"cmd":"pause"}"#), JsonResponse::Ok);
+        assert_eq!(
+            response(r#"{"cmd":"pause"}"#),
+            JsonResponse::Error {
+                message: "already paused".to_string()
+            }
+        );
+        assert_eq!(response(r#"{"cmd":"resume"}"#), JsonResponse::Ok);
+
+        assert_eq!(response(r#"{"cmd":"stop"}"#), JsonResponse::Ok);
+        assert_eq!(
+            response(r#"{"cmd":"stop"}"#),
+            JsonResponse::Error {
+                message: "not recording".to_string()
+            }
+        );
+    }
+
+    fn tokens(line: &str) -> Vec<String> {
+        tokenize(line).expect("tokenize failed")
+    }
+
+    #[test]
+    fn test_tokenize_quotes() {
+        assert_eq!(
+            tokens(r#"--device "USB Microphone" --language en"#),
+            ["--device", "USB Microphone", "--language", "en"]
+        );
+        assert_eq!(tokens(r#"a"b c"d"#), ["ab cd"]);
+        assert_eq!(tokens(r#""""#), [""]);
+        assert_eq!(
+            tokenize(r#""unterminated"#),
+            Err("unterminated quote".to_string())
+        );
+    }
+
+    #[test]
+    fn test_tokenize_escapes() {
+        assert_eq!(tokens(r#"say \"hi\""#), ["say", "\"hi\""]);
+        assert_eq!(tokens(r"two\ words"), ["two words"]);
+        assert_eq!(tokens(r#""back\\slash""#), ["back\\slash"]);
+        assert_eq!(tokenize("oops\\"), Err("trailing backslash".to_string()));
+    }
+
+    #[test]
+    fn test_tokenize_whitespace() {
+        assert_eq!(tokens("  a \t b  "), ["a", "b"]);
+        assert!(tokens("   ").is_empty());
+        assert!(tokens("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_start_options() {
+        assert_eq!(parse_start_options(""), Ok(StartOptions::default()));
+        assert_eq!(
+            parse_start_options(r#"--device "USB Microphone" --language de"#),
+            Ok(StartOptions {
+                device: Some("USB Microphone".to_string()),
+                language: Some("de".to_string()),
+            })
+        );
+        assert_eq!(
+            parse_start_options("--speed fast"),
+            Err("unknown option: --speed".to_string())
+        );
+        assert_eq!(
+            parse_start_options("--device"),
+            Err("missing value for --device".to_string())
+        );
+        assert_eq!(
+            parse_start_options("now"),
+            Err("unexpected argument: now".to_string())
+        );
+    }
+
+    #[test]
+    fn test_start_language_option() {
+        let (state, model) = crate::testing::state();
+        assert_eq!(
+            crate::testing::command(&state, "START --language xx"),
+            "ERROR unknown language: xx"
+        );
+        assert_eq!(
+            crate::testing::command(&state, "START --colour red"),
+            "ERROR unknown option: --colour"
+        );
+
+        assert_eq!(crate::testing::command(&state, "START --language de"), "OK");
+        // already recording matters more than the language
+        assert_eq!(
+            crate::testing::command(&state, "START --language xx"),
+            "ERROR already recording"
+        );
+        crate::testing::transcribe(&state);
+        assert_eq!(model.last_language().as_deref(), Some("de"));
+        assert_eq!(crate::testing::command(&state, "STOP"), "OK");
+
+        // the override only lasts for that session
+        assert_eq!(crate::testing::command(&state, "START"), "OK");
+        crate::testing::transcribe(&state);
+        assert_eq!(model.last_language().as_deref(), Some("en"));
+        assert_eq!(crate::testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_escape_line() {
+        assert_eq!(escape_line("plain text"), "plain text");
+        assert_eq!(escape_line("one\ntwo\r\n"), "one\\ntwo\\r\\n");
+        assert_eq!(escape_line("C:\\n"), "C:\\\\n");
+    }
+
+    #[test]
+    fn test_hello_negotiates_protocol() {
+        let (state, model) = crate::testing::state();
+        let mut session = Session::default();
+
+        let response = handle_command(&state, &mut session, "HELLO kitten-1.0");
+        assert!(response.starts_with("HELLO version="));
+        assert!(response.contains(" protocol=2 "));
+        assert!(response.contains("events"));
+        assert_eq!(session.protocol, 2);
+
+        // newer framing: transcript text is escaped
+        model.set_text("one\ntwo");
+        assert_eq!(handle_command(&state, &mut session, "START"), "OK");
+        crate::testing::transcribe(&state);
+        assert_eq!(
+            handle_command(&state, &mut session, "POLL"),
+            "RECORDING:0:one\\ntwo"
+        );
+        assert_eq!(handle_command(&state, &mut session, "STOP"), "OK");
+
+        // a client capped at the base protocol keeps plain responses
+        let mut old = Session::default();
+        assert!(handle_command(&state, &mut old, "HELLO legacy 1").contains(" protocol=1 "));
+        assert_eq!(old.protocol, BASE_PROTOCOL);
+        assert!(handle_command(&state, &mut old, "HELLO legacy 0").starts_with("ERROR"));
+        assert!(handle_command(&state, &mut old, "HELLO").starts_with("ERROR"));
+    }
+
+    #[test]
+    fn test_shutdown_sends_final_transcript() {
+        let (state, model) = crate::testing::state();
+        let mut server = test_server("shutdown");
+        let mut client = connect(&server);
+
+        assert_eq!(crate::testing::command(&state, "START"), "OK");
+        state.transcriber().push_audio(&[0.0; 160]);
+        model.set_text("the last words");
+
+        writeln!(client.get_mut(), "SHUTDOWN").unwrap();
+        assert!(server.service(|session, cmd| handle_command(&state, session, cmd)));
+        drop(server);
+
+        let mut line = String::new();
+        client.read_line(&mut line).unwrap();
+        assert_eq!(line.trim(), "TRANSCRIPT:the last words");
+        assert!(!state.is_recording());
+
+        // nothing to flush when idle
+        assert_eq!(crate::testing::command(&state, "SHUTDOWN"), "OK");
+        assert_eq!(
+            crate::testing::command(&state, r#"{"cmd":"shutdown"}"#),
+            r#"{"status":"ok"}"#
+        );
+    }
+
+    fn connection_pair() -> (Connection, UnixStream) {
+        let (server, client) = UnixStream::pair().unwrap();
+        server.set_nonblocking(true).unwrap();
+        (Connection::new(server), client)
+    }
+
+    fn would_block(result: std::io::Result<Option<String>>) -> bool {
+        matches!(result, Err(e) if e.kind() == ErrorKind::WouldBlock)
+    }
+
+    #[test]
+    fn test_read_command_byte_by_byte() {
+        let (mut conn, mut client) = connection_pair();
+
+        for byte in b"START --language de" {
+            client.write_all(&[*byte]).unwrap();
+            assert!(would_block(conn.read_command()));
+        }
+        client.write_all(b"\n").unwrap();
+
+        assert_eq!(
+            conn.read_command().unwrap(),
+            Some("START --language de".to_string())
+        );
+        assert!(would_block(conn.read_command()));
+    }
+
+    #[test]
+    fn test_read_command_split_and_batched_writes() {
+        let (mut conn, mut client) = connection_pair();
+
+        client.write_all(b"PING\nSTA").unwrap();
+        assert_eq!(conn.read_command().unwrap(), Some("PING".to_string()));
+        assert!(would_block(conn.read_command()));
+
+        client.write_all(b"TUS\nPOLL\n").unwrap();
+        assert_eq!(conn.read_command().unwrap(), Some("STATUS".to_string()));
+        assert_eq!(conn.read_command().unwrap(), Some("POLL".to_string()));
+        assert!(would_block(conn.read_command()));
+
+        // a last line without a newline still arrives before the disconnect
+        client.write_all(b"STOP").unwrap();
+        drop(client);
+        assert_eq!(conn.read_command().unwrap(), Some("STOP".to_string()));
+        assert_eq!(conn.read_command().unwrap(), None);
+    }
+
+    fn write_frame(client: &mut UnixStream, payload: &str) {
+        client
+            .write_all(&(payload.len() as u32).to_be_bytes())
+            .unwrap();
+        client.write_all(payload.as_bytes()).unwrap();
+    }
+
+    fn read_frame_from(client: &mut UnixStream) -> String {
+        let mut header = [0; 4];
+        client.read_exact(&mut header).unwrap();
+        let mut payload = vec![0; u32::from_be_bytes(header) as usize];
+        client.read_exact(&mut payload).unwrap();
+        String::from_utf8(payload).unwrap()
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        let (mut conn, mut client) = connection_pair();
+        let payloads = ["PING", "first line\nsecond line\n", "naïve café 👍🏽", ""];
+
+        for payload in payloads {
+            write_frame(&mut client, payload);
+            assert_eq!(conn.read_frame().unwrap(), Some(payload.to_string()));
+
+            conn.send_frame(payload).unwrap();
+            assert_eq!(read_frame_from(&mut client), payload);
+        }
+        assert!(would_block(conn.read_frame()));
+
+        drop(client);
+        assert_eq!(conn.read_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_frame_in_pieces() {
+        let (mut conn, mut client) = connection_pair();
+        let payload = "über\nline";
+        let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(payload.as_bytes());
+
+        for byte in &bytes[..bytes.len() - 1] {
+            client.write_all(&[*byte]).unwrap();
+            assert!(would_block(conn.read_frame()));
+        }
+        client.write_all(&bytes[bytes.len() - 1..]).unwrap();
+        assert_eq!(conn.read_frame().unwrap(), Some(payload.to_string()));
+    }
+
+    #[test]
+    fn test_read_frame_rejects_bad_frames() {
+        let (mut conn, mut client) = connection_pair();
+        client
+            .write_all(&(MAX_FRAME_LEN as u32 + 1).to_be_bytes())
+            .unwrap();
+        let e = conn.read_frame().unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+        let (mut conn, mut client) = connection_pair();
+        client.write_all(&2u32.to_be_bytes()).unwrap();
+        client.write_all(&[0xc3, 0x28]).unwrap();
+        let e = conn.read_frame().unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_hello_binary_switches_to_frames() {
+        let (state, _) = crate::testing::state();
+        let mut server = test_server("binary");
+        let mut client = connect(&server);
+        let handler = |session: &mut Session, cmd: &str| handle_command(&state, session, cmd);
+
+        // the handshake and its response are still lines
+        writeln!(client.get_mut(), "HELLO kitten-1.0 BINARY").unwrap();
+        server.service(handler);
+        let mut line = String::new();
+        client.read_line(&mut line).unwrap();
+        assert!(line.contains("capabilities="));
+        assert!(line.trim_end().ends_with(" framing=binary"));
+
+        write_frame(client.get_mut(), "PING");
+        server.service(handler);
+        assert_eq!(read_frame_from(client.get_mut()), "PONG");
+
+        write_frame(client.get_mut(), "SUBSTITUTE new\nline=x");
+        server.service(handler);
+        assert_eq!(read_frame_from(client.get_mut()), "OK");
+    }
+
+    #[test]
+    fn test_hello_binary_forms() {
+        let mut session = Session::default();
+        assert!(hello(&mut session, "BINARY").starts_with("HELLO "));
+        assert!(session.framed);
+        assert_eq!(session.protocol, PROTOCOL_VERSION);
+
+        let mut session = Session::default();
+        hello(&mut session, "kitten-1.0 1 binary");
+        assert!(session.framed);
+        assert_eq!(session.protocol, 1);
+
+        let mut session = Session::default();
+        assert!(hello(&mut session, "kitten-1.0").starts_with("HELLO "));
+        assert!(!session.framed);
+        assert!(hello(&mut session, "").starts_with("ERROR usage"));
+    }
+
+    #[test]
+    fn test_hello_short_form() {
+        let (state, _) = crate::testing::state();
+        let mut session = Session::default();
+
+        assert_eq!(
+            handle_command(&state, &mut session, "HELLO 1"),
+            format!("YOWL 1 {}", env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(session.protocol, 1);
+
+        // a newer client settles on the newest protocol we speak
+        assert_eq!(
+            handle_command(&state, &mut session, "HELLO 99"),
+            format!("YOWL {PROTOCOL_VERSION} {}", env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(session.protocol, PROTOCOL_VERSION);
+
+        assert_eq!(
+            handle_command(&state, &mut session, "HELLO 0"),
+            "ERROR unsupported protocol: 0"
+        );
+    }
+
+    #[test]
+    fn test_strict_handshake() {
+        let (state, _) = crate::testing::state();
+        let mut session = Session {
+            handshake_pending: true,
+            ..Session::default()
+        };
+
+        assert_eq!(
+            handle_command(&state, &mut session, "STATUS"),
+            "ERROR handshake required"
+        );
+        let response = handle_command(&state, &mut session, r#"{"cmd":"poll"}"#);
+        assert_eq!(
+            serde_json::from_str::<JsonResponse>(&response).unwrap(),
+            JsonResponse::Error {
+                message: "handshake required".to_string()
+            }
+        );
+        assert_eq!(handle_command(&state, &mut session, "ping"), "PONG");
+
+        // a failed HELLO doesn't count
+        assert!(handle_command(&state, &mut session, "HELLO 0").starts_with("ERROR"));
+        assert!(session.handshake_pending);
+
+        assert!(handle_command(&state, &mut session, "HELLO 2").starts_with("YOWL 2 "));
+        assert!(!session.handshake_pending);
+        assert_eq!(handle_command(&state, &mut session, "POLL"), "IDLE:");
+    }
+
+    #[test]
+    fn test_strict_server_requires_handshake() {
+        let (state, _) = crate::testing::state();
+        let mut server = test_server("strict");
+        server.strict_handshake = true;
+        let mut client = connect(&server);
+
+        let mut exchange = |cmd: &str| {
+            writeln!(client.get_mut(), "{cmd}").unwrap();
+            server.service(|session, cmd| handle_command(&state, session, cmd));
+            let mut line = String::new();
+            client.read_line(&mut line).unwrap();
+            line.trim().to_string()
+        };
+        assert_eq!(exchange("POLL"), "ERROR handshake required");
+        assert!(exchange("HELLO 2").starts_with("YOWL 2 "));
+        assert_eq!(exchange("POLL"), "IDLE:");
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let (state, _) = crate::testing::state();
+        let mut session = Session::default();
+
+        let response = handle_command(&state, &mut session, "CAPABILITIES");
+        let list: Vec<&str> = response
+            .strip_prefix("CAPABILITIES:")
+            .unwrap()
+            .split(' ')
+            .collect();
+        assert_eq!(list[..3], ["events", "json", "transcript"]);
+        assert_eq!(list.contains(&"cuda"), cfg!(feature = "cuda"));
+        assert!(list.last().unwrap().starts_with("audio-host="));
+        // the test state isn't running a model from a file
+        assert!(!list.iter().any(|item| item.starts_with("model=")));
+
+        // HELLO lists the same build capabilities
+        let hello = handle_command(&state, &mut session, "HELLO kitten-1.0");
+        assert!(hello.ends_with(&format!("capabilities={}", capabilities().join(","))));
+    }
+
+    #[test]
+    fn test_peek() {
+        let (state, model) = crate::testing::state();
+        let mut session = Session::default();
+        assert_eq!(handle_command(&state, &mut session, "PEEK"), "PEEK:\t");
+
+        assert_eq!(handle_command(&state, &mut session, "START"), "OK");
+        for text in ["once upon a time there was", "a time there was a\tking\n"] {
+            model.set_text(text);
+            crate::testing::transcribe(&state);
+            handle_command(&state, &mut session, "POLL");
+        }
+        let (committed, provisional) = state.peek();
+        assert_eq!(
+            handle_command(&state, &mut session, "PEEK"),
+            format_peek(&committed, &provisional)
+        );
+
+        assert_eq!(
+            format_peek("one ", "two\tthree\n"),
+            "PEEK:one \ttwo\\tthree\\n"
+        );
+
+        let response = handle_command(&state, &mut session, r#"{"cmd":"peek"}"#);
+        assert_eq!(
+            serde_json::from_str::<JsonResponse>(&response).unwrap(),
+            JsonResponse::Peek {
+                committed,
+                provisional
+            }
+        );
+    }
+
+    #[test]
+    fn test_get_transcript() {
+        let (state, model) = crate::testing::state();
+        let mut session = Session::default();
+        assert!(handle_command(&state, &mut session, "GET_TRANSCRIPT").starts_with("ERROR"));
+
+        session.framed = true;
+        assert_eq!(handle_command(&state, &mut session, "START"), "OK");
+        let mut terminal = String::new();
+        for text in ["Hello wor", "Hello world.", "Hello, world.\nNext line"] {
+            model.set_text(text);
+            crate::testing::transcribe(&state);
+            let response = handle_command(&state, &mut session, "POLL");
+            crate::testing::apply_poll(&mut terminal, &response);
+        }
+
+        assert_eq!(
+            handle_command(&state, &mut session, "GET_TRANSCRIPT"),
+            format!("TRANSCRIPT:{terminal}")
+        );
+        assert_eq!(terminal, "Hello, world.\nNext line");
+    }
+
+    #[test]
+    fn test_transcript_segments() {
+        let (state, model) = crate::testing::state();
+        let mut session = Session::default();
+        assert_eq!(
+            handle_command(&state, &mut session, "TRANSCRIPT --segments"),
+            "SEGMENTS:"
+        );
+
+        assert_eq!(handle_command(&state, &mut session, "START"), "OK");
+        model.set_segments(vec![
+            whisper::Segment {
+                text: " Hello,\tworld.".to_string(),
+                start_ms: 0,
+                end_ms: 1200,
+                ..whisper::Segment::default()
+            },
+            whisper::Segment {
+                text: " Bye.".to_string(),
+                start_ms: 1200,
+                end_ms: 2050,
+                ..whisper::Segment::default()
+            },
+        ]);
+        crate::testing::transcribe(&state);
+        assert_eq!(handle_command(&state, &mut session, "STOP"), "OK");
+
+        assert_eq!(
+            handle_command(&state, &mut session, "TRANSCRIPT --segments"),
+            "SEGMENTS:0:120: Hello,\\tworld.\t120:205: Bye."
+        );
+        // nothing has aged out of the buffer yet
+        assert_eq!(
+            handle_command(&state, &mut session, "TRANSCRIPT committed --segments"),
+            "SEGMENTS:"
+        );
+        assert!(handle_command(&state, &mut session, "TRANSCRIPT --srt").starts_with("ERROR usage"));
+    }
+
+    #[test]
+    fn test_segments_json() {
+        let (state, model) = crate::testing::state();
+        assert_eq!(crate::testing::command(&state, "SEGMENTS"), "[]");
+
+        assert_eq!(crate::testing::command(&state, "START"), "OK");
+        // whisper's overlapping timestamps come out ordered and disjoint
+        model.set_segments(vec![
+            whisper::Segment {
+                text: " One.".to_string(),
+                start_ms: 0,
+                end_ms: 1500,
+                ..whisper::Segment::default()
+            },
+            whisper::Segment {
+                text: " Two\nlines.".to_string(),
+                start_ms: 1200,
+                end_ms: 2400,
+                ..whisper::Segment::default()
+            },
+            whisper::Segment {
+                text: " Three.".to_string(),
+                start_ms: 2400,
+                end_ms: 3000,
+                ..whisper::Segment::default()
+            },
+        ]);
+        crate::testing::transcribe(&state);
+
+        let response = crate::testing::command(&state, "SEGMENTS");
+        assert!(!response.contains('\n'));
+        let segments: Vec<serde_json::Value> = serde_json::from_str(&response).unwrap();
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[1]["text"], " Two\nlines.");
+        let times: Vec<(i64, i64)> = segments
+            .iter()
+            .map(|s| {
+                (
+                    s["start_ms"].as_i64().unwrap(),
+                    s["end_ms"].as_i64().unwrap(),
+                )
+            })
+            .collect();
+        for (start, end) in &times {
+            assert!(start <= end);
+        }
+        for pair in times.windows(2) {
+            assert!(pair[0].1 <= pair[1].0, "{times:?} overlap");
+        }
+
+        assert_eq!(crate::testing::command(&state, "SEGMENTS committed"), "[]");
+        assert!(crate::testing::command(&state, "SEGMENTS all").starts_with("ERROR usage"));
+        assert_eq!(crate::testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_loglevel() {
+        let _level = crate::testing::LOG_LEVEL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let logs = crate::testing::captured_logs();
+        let (state, _) = crate::testing::state();
+        // another thread, as the recording worker logs from
+        let log_elsewhere = |message: &'static str| {
+            std::thread::spawn(move || log::debug!("{message}"))
+                .join()
+                .unwrap()
+        };
+        let logged = |message: &str| {
+            logs.lock()
+                .unwrap()
+                .iter()
+                .any(|line| line == &format!("DEBUG {message}"))
+        };
+
+        assert!(crate::testing::command(&state, "LOGLEVEL warn").starts_with("OK "));
+        log_elsewhere("loglevel test: before");
+        assert!(!logged("loglevel test: before"));
+
+        assert_eq!(crate::testing::command(&state, "LOGLEVEL debug"), "OK warn");
+        // what the log macros check before handing a record to the logger
+        assert!(log::Level::Debug <= log::max_level());
+        assert!(log::Level::Trace > log::max_level());
+        log_elsewhere("loglevel test: after");
+        assert!(logged("loglevel test: after"));
+
+        let options = "(use trace, debug, info, warn, error or off)";
+        assert_eq!(
+            crate::testing::command(&state, "LOGLEVEL loud"),
+            format!("ERROR unknown log level: loud {options}")
+        );
+        // the previous level can always be restored, even off
+        assert_eq!(crate::testing::command(&state, "LOGLEVEL off"), "OK debug");
+        assert_eq!(crate::testing::command(&state, "LOGLEVEL debug"), "OK off");
+        assert_eq!(
+            crate::testing::command(&state, "LOGLEVEL"),
+            "ERROR usage: LOGLEVEL <trace|debug|info|warn|error|off>"
+        );
+        assert_eq!(log::max_level(), log::LevelFilter::Debug);
+    }
+
+    #[test]
+    fn test_version() {
+        let (state, _) = crate::testing::state();
+        let response = crate::testing::command(&state, "VERSION");
+        let expected = format!("VERSION:yowl {} (commit ", env!("CARGO_PKG_VERSION"));
+        assert!(response.starts_with(&expected), "{response}");
+        assert!(response.contains(", whisper-rs "), "{response}");
+        assert!(response.ends_with(')'), "{response}");
+    }
+
+    #[test]
+    fn test_capability_names_are_unique() {
+        let mut names: Vec<&str> = Capability::ALL.iter().map(|c| c.name()).collect();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), Capability::ALL.len());
+    }
+
+    #[test]
+    fn test_control_characters_rejected() {
+        let mut server = test_server("control");
+        let mut client = connect(&server);
+
+        assert_eq!(
+            request(&mut server, &mut client, "PI\0NG"),
+            "ERROR command contains control characters"
+        );
+        assert_eq!(
+            request(&mut server, &mut client, "PING\x1b"),
+            "ERROR command contains control characters"
+        );
+        // the connection survives, and CRLF still ends a line
+        assert_eq!(request(&mut server, &mut client, "PING\r"), "PONG");
+        // tabs separate words like spaces
+        assert_eq!(request(&mut server, &mut client, "\tPING\t"), "PONG");
+    }
+
+    #[test]
+    fn test_endless_line_is_bounded() {
+        let mut server = test_server("endless");
+        let garbage = connect(&server);
+        let mut other = connect(&server);
+        assert_eq!(request(&mut server, &mut other, "PING"), "PONG");
+
+        // 10 MB with no newline, from a thread as it'll stop being read
+        let mut stream = garbage.into_inner();
+        let writer = std::thread::spawn(move || {
+            let chunk = [b'x'; 64 * 1024];
+            for _ in 0..160 {
+                if stream.write_all(&chunk).is_err() {
+                    break;
+                }
+            }
+        });
+
+        while !writer.is_finished() {
+            server.service(ping_handler);
+            for conn in &server.connections {
+                assert!(conn.pending.len() <= MAX_COMMAND_LEN + 1024);
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(request(&mut server, &mut other, "PING"), "PONG");
+
+        // the garbage client is gone once it hangs up
+        server.service(ping_handler);
+        assert_eq!(server.connections.len(), 1);
+    }
+
+    #[test]
+    fn test_closed_client_that_keeps_sending_is_dropped() {
+        let mut server = test_server("keeps-sending");
+        let garbage = connect(&server);
+        let mut other = connect(&server);
+        assert_eq!(request(&mut server, &mut other, "PING"), "PONG");
+
+        // never hanging up, so only the daemon can end it
+        let mut stream = garbage.into_inner();
+        let writer = std::thread::spawn(move || {
+            let chunk = [b'x'; 64 * 1024];
+            while stream.write_all(&chunk).is_ok() {}
+        });
+
+        // the other client is answered every turn until the garbage is gone
+        while server.connections.len() > 1 {
+            assert_eq!(request(&mut server, &mut other, "PING"), "PONG");
+        }
+        writer.join().unwrap();
+        assert_eq!(request(&mut server, &mut other, "PING"), "PONG");
+    }
+
+    #[test]
+    fn test_overlong_line_rejected_cleanly() {
+        let mut server = test_server("overlong");
+        let mut client = connect(&server);
+
+        let mut stream = client.get_ref().try_clone().unwrap();
+        let writer = std::thread::spawn(move || {
+            let _ = stream.write_all(&[b'x'; 100 * 1024]);
+        });
+        while !writer.is_finished() {
+            server.service(ping_handler);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        server.service(ping_handler);
+
+        let mut line = String::new();
+        client.read_line(&mut line).unwrap();
+        assert_eq!(line.trim(), "ERROR command too long (limit 65536 bytes)");
+        // then the daemon hangs up
+        line.clear();
+        assert_eq!(client.read_line(&mut line).unwrap(), 0);
+
+        drop(client);
+        server.service(ping_handler);
+        assert!(server.connections.is_empty());
+    }
+
+    #[test]
+    fn test_keepalive_drops_silent_client() {
+        let (state, _) = crate::testing::state();
+        let mut server = test_server("keepalive");
+        server.keepalive = Some(Duration::from_millis(50));
+        let mut alive = connect(&server);
+        let mut silent = connect(&server);
+
+        writeln!(silent.get_mut(), "START").unwrap();
+        writeln!(alive.get_mut(), "PING").unwrap();
+        server.run_once(&state);
+        let mut line = String::new();
+        silent.read_line(&mut line).unwrap();
+        alive.read_line(&mut line).unwrap();
+        assert_eq!(line, "OK\nPONG\n");
+
+        // both go quiet, so both are pinged, but only one answers
+        std::thread::sleep(Duration::from_millis(60));
+        server.run_once(&state);
+        for client in [&mut alive, &mut silent] {
+            line.clear();
+            client.read_line(&mut line).unwrap();
+            assert_eq!(line.trim(), "PING");
+        }
+        writeln!(alive.get_mut(), "PONG").unwrap();
+        server.run_once(&state);
+        assert_eq!(server.connections.len(), 2);
+
+        std::thread::sleep(Duration::from_millis(60));
+        server.run_once(&state);
+        assert_eq!(server.connections.len(), 1, "silent client was kept");
+        line.clear();
+        assert_eq!(silent.read_line(&mut line).unwrap(), 0);
+
+        // the recording it started carries on, and the other client is
+        // unaffected, though pinged again for going quiet
+        assert!(state.is_recording());
+        line.clear();
+        alive.read_line(&mut line).unwrap();
+        assert_eq!(line.trim(), "PING");
+        assert_eq!(request(&mut server, &mut alive, "PING"), "PONG");
+        assert_eq!(state.stop_recording(), "OK");
+    }
+
+    #[test]
+    fn test_wait_sleeps_until_needed() {
+        let mut server = test_server("wait");
+        let waker = Waker::new().unwrap();
+        let mut client = connect(&server);
+        server.service(ping_handler);
+
+        let started = std::time::Instant::now();
+        server
+            .wait(&[waker.as_raw_fd()], Duration::from_millis(50))
+            .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        // a command wakes it long before the timeout
+        let writer = std::thread::spawn({
+            let mut stream = client.get_ref().try_clone().unwrap();
+            move || {
+                std::thread::sleep(Duration::from_millis(20));
+                stream.write_all(b"PING\nPING\n").unwrap();
+            }
+        });
+        let started = std::time::Instant::now();
+        server
+            .wait(&[waker.as_raw_fd()], Duration::from_secs(10))
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        writer.join().unwrap();
+
+        // the second command is already off the socket, so poll wouldn't
+        // report it
+        server.service(ping_handler);
+        let started = std::time::Instant::now();
+        server
+            .wait(&[waker.as_raw_fd()], Duration::from_secs(10))
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        server.service(ping_handler);
+        let mut lines = String::new();
+        client.read_line(&mut lines).unwrap();
+        client.read_line(&mut lines).unwrap();
+        assert_eq!(lines, "PONG\nPONG\n");
+    }
+
+    #[test]
+    fn test_waker_interrupts_wait() {
+        let server = test_server("waker");
+        let waker = std::sync::Arc::new(Waker::new().unwrap());
+
+        let wakes = std::thread::spawn({
+            let waker = std::sync::Arc::clone(&waker);
+            move || {
+                std::thread::sleep(Duration::from_millis(20));
+                waker.wake();
+                waker.wake();
+            }
+        });
+        let started = std::time::Instant::now();
+        server
+            .wait(&[waker.as_raw_fd()], Duration::from_secs(10))
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        wakes.join().unwrap();
+
+        // once cleared, both wakes are spent
+        waker.clear();
+        let started = std::time::Instant::now();
+        server
+            .wait(&[waker.as_raw_fd()], Duration::from_millis(50))
+            .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_parse_keepalive() {
+        assert_eq!(parse_keepalive("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_keepalive("0"), None);
+        assert_eq!(parse_keepalive("soon"), None);
+    }
+
+    #[test]
+    fn test_stuck_client_is_dropped() {
+        let mut server = test_server("stuck");
+        let mut stuck = connect(&server);
+        let mut other = connect(&server);
+
+        // subscribes, then never reads again
+        assert_eq!(request(&mut server, &mut stuck, "SUBSCRIBE"), "OK");
+        assert_eq!(request(&mut server, &mut other, "PING"), "PONG");
+
+        let event = Poll::Recording(crate::diff::DiffResult {
+            backspaces: 0,
+            new_text: "x".repeat(1024),
+        });
+        for _ in 0..1024 {
+            server.broadcast(std::slice::from_ref(&event));
+            if server.connections.len() < 2 {
+                break;
+            }
+        }
+        assert_eq!(server.connections.len(), 1, "stuck client was kept");
+
+        // everyone else carries on as normal
+        assert_eq!(request(&mut server, &mut other, "PING"), "PONG");
+    }
+
+    #[test]
+    fn test_send_all_keeps_order() {
+        let (mut conn, client) = connection_pair();
+        let events: Vec<String> = (0..50).map(|i| format!("EVENT RECORDING:0:{i}")).collect();
+
+        conn.send_all(&events).unwrap();
+        assert!(conn.outgoing.is_empty());
+
+        let mut reader = BufReader::new(client);
+        for event in &events {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line.trim_end(), event);
+        }
+
+        // framed clients get one frame per message, in the same order
+        let mut client = reader.into_inner();
+        conn.session.framed = true;
+        conn.send_all(&["first", "second\nline", ""]).unwrap();
+        assert_eq!(read_frame_from(&mut client), "first");
+        assert_eq!(read_frame_from(&mut client), "second\nline");
+        assert_eq!(read_frame_from(&mut client), "");
+    }
+
+    #[test]
+    fn test_output_queued_until_client_reads() {
+        let (mut conn, client) = connection_pair();
+
+        // more than the socket buffer takes, but within the queue limit
+        let line = "y".repeat(1024);
+        let mut sent = 0;
+        while conn.outgoing.is_empty() {
+            conn.send(&line).unwrap();
+            sent += 1;
+        }
+
+        let mut reader = BufReader::new(client);
+        for _ in 0..sent {
+            conn.flush().unwrap();
+            let mut received = String::new();
+            reader.read_line(&mut received).unwrap();
+            assert_eq!(received.trim_end(), line);
+        }
+        assert!(conn.outgoing.is_empty());
+    }
+
+    #[test]
+    fn test_subscriber_receives_diffs_without_polling() {
+        let (state, model) = crate::testing::state();
+        state.set_config("transcribe_interval_ms", "100");
+        let mut server = test_server("push");
+        let mut client = connect(&server);
+
+        writeln!(client.get_mut(), "SUBSCRIBE").unwrap();
+        writeln!(client.get_mut(), "START").unwrap();
+        let mut next_line = || {
+            client.get_ref().set_nonblocking(true).unwrap();
+            for _ in 0..200 {
+                server.run_once(&state);
+                let mut line = String::new();
+                match client.read_line(&mut line) {
+                    Ok(_) => return line.trim_end().to_string(),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(e) => panic!("read failed: {e}"),
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            panic!("nothing received");
+        };
+        assert_eq!(next_line(), "OK");
+        assert_eq!(next_line(), "OK");
+
+        model.set_text("hello");
+        crate::testing::transcribe(&state);
+        assert_eq!(next_line(), "EVENT RECORDING:0:hello");
+
+        model.set_text("help");
+        crate::testing::transcribe(&state);
+        assert_eq!(next_line(), "EVENT RECORDING:2:p");
+
+        state.stop_recording();
+        assert_eq!(next_line(), "EVENT IDLE:");
+    }
+
+    #[test]
+    fn test_capture_failure_answers_start() {
+        let transcriber = crate::whisper::StreamingTranscriber::with_model(
+            Box::new(crate::testing::MockModel::default()),
+            Duration::from_secs(10),
+        );
+        let state = DaemonState::with_parts(transcriber, crate::testing::failing_opener("busy"));
+        let mut server = test_server("capture-error");
+        let mut client = connect(&server);
+
+        writeln!(client.get_mut(), "SUBSCRIBE").unwrap();
+        server.run_once(&state);
+        let mut line = String::new();
+        client.read_line(&mut line).unwrap();
+        assert_eq!(line.trim(), "OK");
+
+        // the caller hears of it in the reply, and no event follows
+        writeln!(client.get_mut(), "START").unwrap();
+        client.get_ref().set_nonblocking(true).unwrap();
+        let mut received = Vec::new();
+        for _ in 0..20 {
+            server.run_once(&state);
+            let mut line = String::new();
+            if client.read_line(&mut line).is_ok() {
+                received.push(line.trim_end().to_string());
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            received,
+            ["ERROR ERR_AUDIO_DEVICE_UNAVAILABLE audio device unavailable: busy"]
+        );
+        assert!(state.status().error.is_some());
+    }
+
+    #[test]
+    fn test_broadcast_reaches_only_subscribers() {
+        let mut server = test_server("subscribe");
+        let mut subscriber = connect(&server);
+        let mut other = connect(&server);
+
+        assert_eq!(request(&mut server, &mut subscriber, "SUBSCRIBE"), "OK");
+        assert_eq!(request(&mut server, &mut other, "PING"), "PONG");
+        assert!(server.has_subscribers());
+
+        server.broadcast(&[Poll::Recording(crate::diff::DiffResult {
+            backspaces: 0,
+            new_text: "hello".to_string(),
+        })]);
+
+        let mut line = String::new();
+        subscriber.read_line(&mut line).unwrap();
+        assert_eq!(line.trim(), "EVENT RECORDING:0:hello");
+
+        // the unsubscribed client only sees its own responses
+        assert_eq!(request(&mut server, &mut other, "PING"), "PONG");
+    }
+
+    fn env<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[test]
+    fn test_socket_location_override() {
+        let vars = [
+            ("YOWL_SOCKET_PATH", "/custom/yowl.sock"),
+            ("XDG_RUNTIME_DIR", "/run/user/1000"),
+        ];
+        assert_eq!(
+            socket_location(env(&vars)),
+            (PathBuf::from("/custom/yowl.sock"), None)
+        );
+
+        // set but empty is the same as unset
+        let vars = [
+            ("YOWL_SOCKET_PATH", ""),
+            ("XDG_RUNTIME_DIR", "/run/user/1000"),
+        ];
+        assert_eq!(
+            socket_location(env(&vars)),
+            (
+                PathBuf::from("/run/user/1000/yowl/yowl.sock"),
+                Some(PathBuf::from("/run/user/1000/yowl"))
+            )
+        );
+    }
+
+    #[test]
+    fn test_socket_location_runtime_dir() {
+        let vars = [("XDG_RUNTIME_DIR", "/run/user/1000")];
+        assert_eq!(
+            socket_location(env(&vars)),
+            (
+                PathBuf::from("/run/user/1000/yowl/yowl.sock"),
+                Some(PathBuf::from("/run/user/1000/yowl"))
+            )
+        );
+    }
+
+    #[test]
+    fn test_socket_location_instance() {
+        let vars = [
+            ("XDG_RUNTIME_DIR", "/run/user/1000"),
+            ("YOWL_INSTANCE", "headset"),
+        ];
+        assert_eq!(
+            socket_location(env(&vars)),
+            (
+                PathBuf::from("/run/user/1000/yowl/yowl-headset.sock"),
+                Some(PathBuf::from("/run/user/1000/yowl"))
+            )
+        );
+
+        let uid = unsafe { libc::getuid() };
+        assert_eq!(
+            socket_location(env(&[("YOWL_INSTANCE", "desk")])),
+            (
+                std::env::temp_dir().join(format!("yowl-{uid}-desk.sock")),
+                None
+            )
+        );
+        // an empty name is the default instance
+        assert_eq!(
+            socket_location(env(&[("YOWL_INSTANCE", "")])),
+            (std::env::temp_dir().join(format!("yowl-{uid}.sock")), None)
+        );
+    }
+
+    #[test]
+    fn test_socket_location_temp_dir() {
+        let uid = unsafe { libc::getuid() };
+        let expected = std::env::temp_dir().join(format!("yowl-{uid}.sock"));
+        assert_eq!(socket_location(env(&[])), (expected.clone(), None));
+        assert_eq!(
+            socket_location(env(&[("XDG_RUNTIME_DIR", "")])),
+            (expected, None)
+        );
+    }
+
+    #[test]
+    fn test_runtime_dir_created_and_removed() {
+        let dir = std::env::temp_dir().join(format!("yowl-test-{}-runtime", std::process::id()));
+        create_private_dir(&dir).unwrap();
+        let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        let mut server = Server::bind_at(dir.join("yowl.sock")).unwrap();
+        server.dir = Some(dir.clone());
+        drop(server);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_bind_replaces_stale_socket() {
+        let path = test_socket_path("stale");
+        let _ = std::fs::remove_file(&path);
+
+        // a socket file with nobody listening, as left by a crashed daemon
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let server = Server::bind_at(path.clone()).expect("stale socket should be replaced");
+        assert!(UnixStream::connect(&server.path).is_ok());
+    }
+
+    #[test]
+    fn test_bind_refuses_live_daemon() {
+        let mut server = test_server("live");
+        let path = server.path.clone();
+
+        let running = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let keep_running = std::sync::Arc::clone(&running);
+        let daemon = std::thread::spawn(move || {
+            while keep_running.load(std::sync::atomic::Ordering::SeqCst) {
+                server.service(ping_handler);
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        });
+
+        let err = Server::bind_at(path.clone())
+            .err()
+            .expect("second daemon should not bind");
+        assert_eq!(err.kind(), ErrorKind::AddrInUse);
+        assert!(err.to_string().contains("already running"), "{err}");
+
+        // the first daemon's socket is untouched
+        assert!(path.exists());
+        assert!(UnixStream::connect(&path).is_ok());
+
+        running.store(false, std::sync::atomic::Ordering::SeqCst);
+        daemon.join().unwrap();
+    }
+
+    #[test]
+    fn test_activation_fd() {
+        let pid = std::process::id();
+        let ours = pid.to_string();
+        assert_eq!(
+            activation_fd(env(&[("LISTEN_PID", &ours), ("LISTEN_FDS", "1")]), pid),
+            Some(LISTEN_FDS_START)
+        );
+        // meant for some other process
+        assert_eq!(
+            activation_fd(env(&[("LISTEN_PID", "1"), ("LISTEN_FDS", "1")]), pid),
+            None
+        );
+        assert_eq!(
+            activation_fd(env(&[("LISTEN_PID", &ours), ("LISTEN_FDS", "0")]), pid),
+            None
+        );
+        assert_eq!(activation_fd(env(&[("LISTEN_FDS", "1")]), pid), None);
+        assert_eq!(activation_fd(env(&[]), pid), None);
+    }
+
+    #[test]
+    fn test_adopted_socket_is_left_in_place() {
+        let path = test_socket_path("adopted");
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let mut server = Server::adopt(listener).unwrap();
+        server.set_nonblocking(true).unwrap();
+        assert_eq!(server.path, path);
+        let mut client = connect(&server);
+        assert_eq!(request(&mut server, &mut client, "PING"), "PONG");
+
+        // the socket belongs to whoever bound it, e.g. systemd
+        drop(server);
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_socket_is_private() {
+        let server = test_server("private");
+        let mode = std::fs::metadata(&server.path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_peer_uid_is_ours() {
+        let server = test_server("peer-uid");
+        let client = UnixStream::connect(&server.path).unwrap();
+        assert_eq!(peer_uid(&client).unwrap(), unsafe { libc::getuid() });
+    }
+
+    #[test]
+    fn test_other_users_rejected() {
+        let mut server = test_server("reject");
+        server.peer_uid = |_| Ok(unsafe { libc::getuid() } + 1);
+
+        let mut client = connect(&server);
+        writeln!(client.get_mut(), "PING").unwrap();
+        server.service(ping_handler);
+
+        // the connection is closed (or reset) without a response
+        let mut line = String::new();
+        assert!(matches!(client.read_line(&mut line), Ok(0) | Err(_)));
+        assert!(line.is_empty());
+        assert!(server.connections.is_empty());
+    }
+
+    #[test]
+    fn test_format_devices() {
+        let names = vec!["Built-in Microphone".to_string(), "USB Mic".to_string()];
+        assert_eq!(
+            format_devices(&names, Some("USB Mic")),
+            "LIST_DEVICES:Built-in Microphone|*USB Mic"
+        );
+        assert_eq!(
+            format_devices(&names, None),
+            "LIST_DEVICES:Built-in Microphone|USB Mic"
+        );
+        assert_eq!(format_devices(&[], None), "LIST_DEVICES:");
+    }
+
+    fn input_device(index: usize, name: Option<&str>, default: bool) -> audio::InputDevice {
+        audio::InputDevice {
+            index,
+            name: name.map(String::from),
+            sample_rate: name.map(|_| 48000),
+            channels: name.map(|_| 2),
+            default,
+        }
+    }
+
+    #[test]
+    fn test_format_device_details() {
+        let devices = [
+            input_device(0, Some("Built-in Microphone"), false),
+            input_device(1, None, false),
+            input_device(2, Some("USB Mic"), true),
+        ];
+        assert_eq!(
+            format_device_details(&devices),
+            "DEVICES:0:48000:2:Built-in Microphone|1:::|2:48000:2:*USB Mic"
+        );
+        assert_eq!(format_device_details(&[]), "DEVICES:");
+    }
+
+    #[test]
+    fn test_json_devices_response() {
+        let response = JsonResponse::Devices {
+            devices: vec![input_device(1, None, false)],
+        };
+        assert_eq!(
+            serde_json::to_string(&response).unwrap(),
+            r#"{"status":"devices","devices":[{"index":1,"name":null,"sample_rate":null,"channels":null,"default":false}]}"#
+        );
+    }
+
+    #[test]
+    fn test_list_devices_well_formed() {
+        // whatever audio this machine has (possibly none), the reply is one valid line
+        let (state, _) = crate::testing::state();
+        let response = crate::testing::command(&state, "LIST_DEVICES");
+        assert!(
+            response.starts_with("LIST_DEVICES:") || response == "ERROR no audio devices",
+            "unexpected response: {response}"
+        );
+        assert!(!response.contains('\n'));
+    }
+
+    #[test]
+    fn test_multiple_clients_ping() {
+        let mut server = test_server("multi-ping");
+        let mut client1 = connect(&server);
+        let mut client2 = connect(&server);
+
+        // both clients send before the server gets a chance to service either
+        writeln!(client1.get_mut(), "PING").unwrap();
+        writeln!(client2.get_mut(), "PING").unwrap();
+        assert!(!server.service(ping_handler));
+        assert_eq!(server.connections.len(), 2);
+
+        let mut line1 = String::new();
+        let mut line2 = String::new();
+        client1.read_line(&mut line1).unwrap();
+        client2.read_line(&mut line2).unwrap();
+        assert_eq!(line1.trim(), "PONG");
+        assert_eq!(line2.trim(), "PONG");
+    }
+
+    #[test]
+    fn test_idle_only_without_clients() {
+        let mut server = test_server("idle");
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(server.idle_for() >= Duration::from_millis(30));
+
+        // a connected client keeps the daemon busy even while it's quiet
+        let mut client = connect(&server);
+        assert_eq!(request(&mut server, &mut client, "PING"), "PONG");
+        std::thread::sleep(Duration::from_millis(30));
+        server.service(ping_handler);
+        assert!(server.idle_for() < Duration::from_millis(30));
+
+        drop(client);
+        server.service(ping_handler);
+        std::thread::sleep(Duration::from_millis(30));
+        server.service(ping_handler);
+        assert!(server.idle_for() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_client_disconnect_keeps_others() {
+        let mut server = test_server("disconnect");
+        let mut client1 = connect(&server);
+        let mut client2 = connect(&server);
+
+        assert_eq!(request(&mut server, &mut client1, "PING"), "PONG");
+        assert_eq!(request(&mut server, &mut client2, "PING"), "PONG");
+
+        drop(client1);
+        server.service(ping_handler);
+        assert_eq!(server.connections.len(), 1);
+
+        assert_eq!(request(&mut server, &mut client2, "PING"), "PONG");
+    }
+
+    #[test]
+    fn test_shutdown_from_one_client() {
+        let mut server = test_server("shutdown");
+        let mut client1 = connect(&server);
+        let _client2 = connect(&server);
+
+        writeln!(client1.get_mut(), "SHUTDOWN").unwrap();
+        assert!(server.service(ping_handler));
+
+        let mut line = String::new();
+        client1.read_line(&mut line).unwrap();
+        assert_eq!(line.trim(), "OK");
+    }
+}
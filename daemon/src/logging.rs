@@ -1,37 +1,529 @@
+//! Logging setup. Logs go to `yowl/yowl.log` in the XDG state directory
+//! (`~/.local/state/yowl/yowl.log`) by default, or to the file named by
+//! `YOWL_LOG_FILE`, rotated by size. In the foreground, warnings and errors
+//! are mirrored to stderr too. If the file can't be opened, logs go to
+//! stderr instead.
+//!
+//! `YOWL_LOG_FILE=syslog`, or having no home to find the state directory
+//! in, sends them to syslog (os_log on macOS). `YOWL_LOG_FORMAT=json` writes
+//! one JSON object per line, for log aggregators, to the file or else to
+//! stderr.
+//!
+//! A named instance logs as `yowl-<name>`, and to `<file>-<name>.<ext>`
+//! rather than the file itself, so instances sharing the environment keep
+//! separate logs.
+
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// `YOWL_LOG_FILE` value that logs to syslog rather than a file.
+const SYSLOG: &str = "syslog";
+
+/// How the log file is rotated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    /// Size at which the file is rotated
+    pub max_bytes: u64,
+    /// Rotated files kept, as `<file>.1` (the newest) to `<file>.<keep>`
+    pub keep: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    Json,
+}
+
+/// Start logging at `level`, rotating the log file as `rotation` says.
+/// Running in the `foreground`, warnings and errors also go to stderr.
+pub fn init(
+    level: log::LevelFilter,
+    rotation: Rotation,
+    foreground: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let format = std::env::var("YOWL_LOG_FORMAT");
+    let (format, unknown) = match format.as_deref() {
+        Ok("json") => (Format::Json, None),
+        Ok("text") | Err(_) => (Format::Text, None),
+        Ok(other) => (Format::Text, Some(other)),
+    };
+
+    if let Some(path) = file_path() {
+        let logger = match RotatingFile::open(path.clone(), rotation.max_bytes, rotation.keep) {
+            Ok(file) => {
+                let file: Box<dyn Write + Send> = Box::new(file);
+                // detached, stderr is the log file already
+                let mirror = foreground.then(|| Box::new(std::io::stderr()) as _);
+                LineLogger::new(file, format).mirrored(mirror)
+            }
+            Err(e) => {
+                eprintln!("can't log to {}: {e}; logging to stderr", path.display());
+                LineLogger::new(Box::new(std::io::stderr()) as _, format)
+            }
+        };
+        set_logger(logger, level)?;
+    } else if format == Format::Json {
+        set_logger(LineLogger::new(std::io::stderr(), format), level)?;
+    } else {
+        init_system(level)?;
+    }
+
+    if let Some(unknown) = unknown {
+        log::warn!("ignoring unknown YOWL_LOG_FORMAT: {unknown}");
+    }
+    Ok(())
+}
+
+/// The file logs go to, unless they go to syslog.
+pub fn file_path() -> Option<PathBuf> {
+    let path = match std::env::var_os("YOWL_LOG_FILE").filter(|path| !path.is_empty()) {
+        Some(path) if path == SYSLOG => None,
+        Some(path) => Some(PathBuf::from(path)),
+        None => default_path(|var| std::env::var(var).ok()),
+    };
+    path.map(|path| instance_path(path, instance().as_deref()))
+}
+
+/// Open the log file at `path` for appending, creating it and its directory
+/// if need be.
+pub fn open_file(path: &Path) -> std::io::Result<File> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    // logs can quote what was dictated
+    File::options()
+        .create(true)
+        .read(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)
+}
+
+/// The default log file, in the XDG state directory, if there is a home to
+/// find that in.
+fn default_path(env: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    let non_empty = |var: &str| env(var).filter(|value| !value.is_empty());
+    if let Some(state_home) = non_empty("XDG_STATE_HOME") {
+        return Some(Path::new(&state_home).join("yowl/yowl.log"));
+    }
+    non_empty("HOME").map(|home| Path::new(&home).join(".local/state/yowl/yowl.log"))
+}
+
+/// The daemon instance's name, if it has one.
+fn instance() -> Option<String> {
+    std::env::var(yowl_client::INSTANCE_VAR)
+        .ok()
+        .filter(|name| !name.is_empty())
+}
+
+/// Where `instance` logs when told to log to `path`: `yowl.log` becomes
+/// `yowl-<name>.log`.
+fn instance_path(path: PathBuf, instance: Option<&str>) -> PathBuf {
+    let Some(name) = instance else {
+        return path;
+    };
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(format!("-{name}"));
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
+
+fn set_logger<W: Write + Send + 'static>(
+    logger: LineLogger<W>,
+    level: log::LevelFilter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    log::set_boxed_logger(Box::new(logger)).map(|()| log::set_max_level(level))?;
+    Ok(())
+}
+
 #[cfg(target_os = "macos")]
-pub fn init() -> Result<(), Box<dyn std::error::Error>> {
+fn init_system(level: log::LevelFilter) -> Result<(), Box<dyn std::error::Error>> {
+    // no per-category filters, so the logger defers to `log::max_level()`
+    // and LOGLEVEL can change it later
     oslog::OsLogger::new("com.benleadbetter.yowl")
-        .level_filter(level())
-        .init()?;
+        .init()
+        .map(|()| log::set_max_level(level))?;
     Ok(())
 }
 
 #[cfg(target_os = "linux")]
-pub fn init() -> Result<(), Box<dyn std::error::Error>> {
+fn init_system(level: log::LevelFilter) -> Result<(), Box<dyn std::error::Error>> {
     let formatter = syslog::Formatter3164 {
         facility: syslog::Facility::LOG_USER,
         hostname: None,
-        process: "yowl".into(),
+        process: instance().map_or("yowl".into(), |name| format!("yowl-{name}")),
         pid: 0,
     };
 
     let logger = syslog::unix(formatter)?;
     log::set_boxed_logger(Box::new(syslog::BasicLogger::new(logger)))
-        .map(|()| log::set_max_level(level()))?;
+        .map(|()| log::set_max_level(level))?;
     Ok(())
 }
 
-fn level() -> log::LevelFilter {
-    match std::env::var("YOWL_LOG_LEVEL") {
-        Ok(value) => match &*value.to_lowercase() {
-            "off" => log::LevelFilter::Off,
-            "error" => log::LevelFilter::Error,
-            "warn" => log::LevelFilter::Warn,
-            "info" => log::LevelFilter::Info,
-            "debug" => log::LevelFilter::Debug,
-            "trace" => log::LevelFilter::Trace,
-            _ => log::LevelFilter::Warn,
-        },
-        Err(_) => log::LevelFilter::Warn,
+pub fn parse_level(value: &str) -> Option<log::LevelFilter> {
+    Some(match &*value.to_lowercase() {
+        "off" => log::LevelFilter::Off,
+        "error" => log::LevelFilter::Error,
+        "warn" => log::LevelFilter::Warn,
+        "info" => log::LevelFilter::Info,
+        "debug" => log::LevelFilter::Debug,
+        "trace" => log::LevelFilter::Trace,
+        _ => return None,
+    })
+}
+
+/// Levels [`set_level`] accepts, for error messages.
+const LEVELS: &str = "trace, debug, info, warn, error or off";
+
+/// Log at `level` from now on, whichever logger is in use, as `log_level`
+/// in the config sets it at startup. None of the loggers filters by target
+/// or keeps a level of its own, so this applies to every thread at once.
+/// Returns the level before, which can be passed back to restore it, or an
+/// error unless `level` is one of trace, debug, info, warn, error or off.
+pub fn set_level(level: &str) -> Result<log::LevelFilter, String> {
+    match parse_level(level) {
+        None => Err(format!("unknown log level: {level} (use {LEVELS})")),
+        Some(level) => {
+            let previous = log::max_level();
+            log::set_max_level(level);
+            Ok(previous)
+        }
+    }
+}
+
+/// Writes each record on a line of its own. As JSON, a record is an object
+/// with `ts` (seconds since the epoch), `level`, `target` and `msg` fields.
+struct LineLogger<W> {
+    out: Mutex<W>,
+    /// Also gets warnings and errors
+    mirror: Option<Mutex<Box<dyn Write + Send>>>,
+    format: Format,
+}
+
+impl<W: Write> LineLogger<W> {
+    fn new(out: W, format: Format) -> Self {
+        Self {
+            out: Mutex::new(out),
+            mirror: None,
+            format,
+        }
+    }
+
+    /// Copy warnings and errors to `mirror`, if given.
+    fn mirrored(mut self, mirror: Option<Box<dyn Write + Send>>) -> Self {
+        self.mirror = mirror.map(Mutex::new);
+        self
+    }
+}
+
+fn json_line(record: &log::Record, ts: std::time::SystemTime) -> String {
+    let ts = ts
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    serde_json::json!({
+        "ts": (ts * 1000.0).round() / 1000.0,
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "msg": record.args().to_string(),
+    })
+    .to_string()
+}
+
+fn text_line(record: &log::Record, ts: std::time::SystemTime) -> String {
+    format!(
+        "{} {:<5} {}: {}",
+        local_time(ts),
+        record.level(),
+        record.target(),
+        record.args()
+    )
+}
+
+/// `ts` in local time, as `YYYY-MM-DD HH:MM:SS`.
+fn local_time(ts: std::time::SystemTime) -> String {
+    let tm = local_tm(ts);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+/// `ts` broken down in local time.
+pub fn local_tm(ts: std::time::SystemTime) -> libc::tm {
+    let secs = ts
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&secs, &mut tm) };
+    tm
+}
+
+impl<W: Write + Send> log::Log for LineLogger<W> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    // the log macros have already checked the level, as `set_level` left it
+    fn log(&self, record: &log::Record) {
+        let now = std::time::SystemTime::now();
+        let mut line = match self.format {
+            Format::Text => text_line(record, now),
+            Format::Json => json_line(record, now),
+        };
+        line.push('\n');
+        // one write per line, so lines from different threads don't interleave
+        let _ = self.out.lock().unwrap().write_all(line.as_bytes());
+        if let Some(mirror) = &self.mirror {
+            if record.level() <= log::Level::Warn {
+                let _ = mirror.lock().unwrap().write_all(line.as_bytes());
+            }
+        }
+    }
+
+    fn flush(&self) {
+        let _ = self.out.lock().unwrap().flush();
+        if let Some(mirror) = &self.mirror {
+            let _ = mirror.lock().unwrap().flush();
+        }
+    }
+}
+
+/// A log file that is moved aside to `<path>.1` before it grows past
+/// `max_bytes`, shifting older ones along and dropping any past
+/// `<path>.<keep>`. Every step of a rotation is a rename, so a daemon killed
+/// part way through loses no lines, and a line torn by a kill mid-write is
+/// ended before the next is appended.
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    len: u64,
+}
+
+impl RotatingFile {
+    /// Open `path` for appending, creating it and its directory if needed.
+    fn open(path: PathBuf, max_bytes: u64, keep: usize) -> std::io::Result<Self> {
+        let file = open_file(&path)?;
+        let mut len = file.metadata()?.len();
+        let mut last = [0];
+        if len > 0 && file.read_at(&mut last, len - 1)? == 1 && last[0] != b'\n' {
+            (&file).write_all(b"\n")?;
+            len += 1;
+        }
+        Ok(Self {
+            path,
+            max_bytes,
+            keep,
+            file,
+            len,
+        })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        for n in (1..self.keep).rev() {
+            let _ = std::fs::rename(self.rotated(n), self.rotated(n + 1));
+        }
+        if self.keep > 0 {
+            std::fs::rename(&self.path, self.rotated(1))?;
+        } else {
+            std::fs::remove_file(&self.path)?;
+        }
+        self.file = open_file(&self.path)?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.len > 0 && self.len + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Log;
+
+    #[test]
+    fn test_json_log_line() {
+        let logger = LineLogger::new(Vec::new(), Format::Json);
+        logger.log(
+            &log::Record::builder()
+                .level(log::Level::Error)
+                .target("daemon::ipc")
+                .args(format_args!("bind failed: \"{}\"", "in use"))
+                .build(),
+        );
+
+        let out = logger.out.into_inner().unwrap();
+        let line = String::from_utf8(out).unwrap();
+        assert_eq!(line.matches('\n').count(), 1);
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["level"], "ERROR");
+        assert_eq!(value["target"], "daemon::ipc");
+        assert_eq!(value["msg"], "bind failed: \"in use\"");
+        assert!(value["ts"].as_f64().unwrap() > 1.6e9);
+    }
+
+    #[test]
+    fn test_instance_log_path() {
+        let path = PathBuf::from("/var/log/yowl.log");
+        assert_eq!(instance_path(path.clone(), None), path);
+        assert_eq!(
+            instance_path(path, Some("headset")),
+            PathBuf::from("/var/log/yowl-headset.log")
+        );
+        assert_eq!(
+            instance_path(PathBuf::from("/tmp/yowl"), Some("desk")),
+            PathBuf::from("/tmp/yowl-desk")
+        );
+    }
+
+    #[test]
+    fn test_default_log_path() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |var: &str| {
+                vars.iter()
+                    .find(|(name, _)| *name == var)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert_eq!(
+            default_path(env(&[("XDG_STATE_HOME", "/state"), ("HOME", "/home/me")])),
+            Some(PathBuf::from("/state/yowl/yowl.log"))
+        );
+        assert_eq!(
+            default_path(env(&[("XDG_STATE_HOME", ""), ("HOME", "/home/me")])),
+            Some(PathBuf::from("/home/me/.local/state/yowl/yowl.log"))
+        );
+        assert_eq!(default_path(env(&[])), None);
+    }
+
+    /// A writer whose output the test can still read once it is handed over.
+    #[derive(Clone, Default)]
+    struct Shared(std::sync::Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mirror_gets_warnings_only() {
+        let mirror = Shared::default();
+        let logger =
+            LineLogger::new(Vec::new(), Format::Text).mirrored(Some(Box::new(mirror.clone())));
+        for level in [log::Level::Info, log::Level::Warn, log::Level::Error] {
+            logger.log(
+                &log::Record::builder()
+                    .level(level)
+                    .target("daemon")
+                    .args(format_args!("at {level}"))
+                    .build(),
+            );
+        }
+
+        let out = String::from_utf8(logger.out.into_inner().unwrap()).unwrap();
+        assert_eq!(out.lines().count(), 3);
+        let mirrored = String::from_utf8(mirror.0.lock().unwrap().clone()).unwrap();
+        let mirrored: Vec<&str> = mirrored.lines().collect();
+        assert_eq!(mirrored.len(), 2);
+        assert!(mirrored[0].ends_with("WARN  daemon: at WARN"));
+        assert!(mirrored[1].ends_with("ERROR daemon: at ERROR"));
+    }
+
+    #[test]
+    fn test_log_file_ends_torn_line() {
+        let dir = std::env::temp_dir().join(format!("yowl-test-{}-torn-log", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("yowl.log");
+        // as a daemon killed mid-write leaves it
+        std::fs::write(&path, "whole line\ntorn li").unwrap();
+
+        let mut file = RotatingFile::open(path.clone(), 1000, 2).unwrap();
+        file.write_all(b"next line\n").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "whole line\ntorn li\nnext line\n"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_log_file_rotates() {
+        let dir = std::env::temp_dir().join(format!("yowl-test-{}-logs", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        // the directory is created as needed
+        let path = dir.join("nested").join("yowl.log");
+        let logger = LineLogger::new(
+            RotatingFile::open(path.clone(), 1000, 2).unwrap(),
+            Format::Text,
+        );
+
+        let line = |n| {
+            logger.log(
+                &log::Record::builder()
+                    .level(log::Level::Info)
+                    .target("daemon")
+                    .args(format_args!("line {n:04} {}", "x".repeat(80)))
+                    .build(),
+            )
+        };
+        for n in 0..15 {
+            line(n);
+        }
+
+        let rotated = std::fs::read_to_string(dir.join("nested/yowl.log.1")).unwrap();
+        assert!(rotated.contains("INFO  daemon: line 0000"));
+        let current = std::fs::read_to_string(&path).unwrap();
+        assert!(current.len() <= 1000);
+        assert!(current.ends_with(&format!("line 0014 {}\n", "x".repeat(80))));
+
+        // only `keep` old files are kept
+        for n in 15..60 {
+            line(n);
+        }
+        assert!(dir.join("nested/yowl.log.2").exists());
+        assert!(!dir.join("nested/yowl.log.3").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
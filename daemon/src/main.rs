@@ -1,69 +1,299 @@
 mod audio;
+mod check;
+mod config;
+mod daemonize;
 mod diff;
 mod ipc;
 mod logging;
+mod metrics;
+mod models;
+mod notify;
+mod parent;
+mod pidfile;
+mod postprocess;
+mod sessionlog;
+mod signals;
 mod state;
+#[cfg(test)]
+mod testing;
+mod version;
+mod waker;
+mod wav;
 mod whisper;
 
-use std::io::ErrorKind;
+use std::os::fd::AsRawFd;
 use std::time::Duration;
 
+/// Longest the main loop sleeps while nothing happens, so keepalives and,
+/// where there is no better way, the parent pid check still run
+const TICK: Duration = Duration::from_secs(1);
+
+const USAGE: &str = "usage: daemon [--daemonize] [--check] [--instance <name>]";
+
+/// What the command line asks for.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Args {
+    detach: bool,
+    /// Run the self-test and exit instead of serving
+    check: bool,
+    /// Run as this named instance, beside the default one
+    instance: Option<String>,
+}
+
+fn parse_args(args: &[&str]) -> Option<Args> {
+    let mut parsed = Args::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--daemonize" => parsed.detach = true,
+            "--check" => parsed.check = true,
+            "--instance" => parsed.instance = Some(args.next()?.to_string()),
+            _ => return None,
+        }
+    }
+    Some(parsed)
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    crate::logging::init()?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let Some(Args {
+        detach,
+        check,
+        instance,
+    }) = parse_args(&args)
+    else {
+        eprintln!("{USAGE}");
+        std::process::exit(2);
+    };
+    let instance = instance.or_else(|| {
+        std::env::var(yowl_client::INSTANCE_VAR)
+            .ok()
+            .filter(|name| !name.is_empty())
+    });
+    if let Some(name) = instance {
+        if let Err(e) = yowl_client::validate_instance(&name) {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+        // the socket, pid file and logs follow the environment; no threads
+        // have started to read it yet
+        std::env::set_var(yowl_client::INSTANCE_VAR, name);
+    }
+    let config = match config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let use_gpu = whisper::use_gpu(config.gpu);
+    if check {
+        let model_path = config
+            .model
+            .clone()
+            .unwrap_or_else(models::default_model_path);
+        let passed = check::run(
+            &mut std::io::stdout(),
+            &model_path,
+            whisper::WhisperModel::loader(use_gpu),
+            audio::AudioCapture::opener(
+                config.input_processing(),
+                config.sample_rate(),
+                config.input_channels(),
+            ),
+        )?;
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+    let detached = if detach {
+        Some(daemonize::detach()?)
+    } else {
+        None
+    };
+    // before the slow model load, so a second daemon gives up at once
+    let _pid_file = match ipc::lock_pid_file() {
+        Ok(pid_file) => pid_file,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    if let Some(detached) = detached {
+        let output = logging::file_path().and_then(|path| logging::open_file(&path).ok());
+        detached.finish(output)?;
+    }
+    crate::logging::init(config.log_level(), config.log_rotation(), !detach)?;
 
     let parent_pid = std::os::unix::process::parent_id();
     log::info!("yowl daemon started (parent_pid={parent_pid})");
+    log::info!("{}", version::describe());
+    // detaching is meant to outlive the parent
+    let parent = if detach {
+        None
+    } else {
+        Some(parent::ParentWatch::start(parent_pid)?)
+    };
 
-    log::info!("loading whisper model...");
-    let state = state::DaemonState::new()?;
-    log::info!("whisper model loaded");
+    let state = state::DaemonState::new(config, use_gpu);
+    signals::install(state.waker())?;
 
-    let server = ipc::Server::bind()?;
+    let mut server = ipc::Server::bind().inspect_err(|e| log::error!("{e}"))?;
     server.set_nonblocking(true)?;
-    let mut connection: Option<ipc::Connection> = None;
+    notify::ready();
+
+    let mut recording = false;
+    let mut last_recording = std::time::Instant::now();
+    let mut wakeups = vec![state.waker().as_raw_fd()];
+    wakeups.extend(parent.as_ref().and_then(parent::ParentWatch::fd));
 
     loop {
-        if std::os::unix::process::parent_id() != parent_pid {
+        if signals::shutdown_requested() {
+            log::info!("terminated by signal, shutting down");
+            break;
+        }
+
+        if signals::reload_requested() {
+            reload_config(&state, config::load());
+        }
+
+        if parent.as_ref().is_some_and(parent::ParentWatch::exited) {
             log::info!("parent process exited, shutting down");
             break;
         }
 
-        match server.accept() {
-            Ok(conn) => {
-                connection = Some(conn);
-            }
-            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
-            Err(e) => log::warn!("accept error: {e}"),
+        state.check_watchdog();
+
+        if server.run_once(&state) {
+            log::info!("shutdown command received");
+            break;
         }
 
-        if let Some(ref mut conn) = connection {
-            match conn.read_command() {
-                Ok(Some(cmd)) => {
-                    log::debug!("received command: {cmd}");
-                    let response = ipc::handle_command(&cmd, &state);
-                    if let Err(e) = conn.send(&response) {
-                        log::warn!("send error: {e}");
-                        connection = None;
-                    }
-                    if cmd.to_uppercase() == "SHUTDOWN" {
-                        log::info!("shutdown command received");
-                        break;
-                    }
-                }
-                Ok(None) => {
-                    log::debug!("client disconnected");
-                    connection = None;
-                }
-                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
-                Err(e) => {
-                    log::warn!("read error: {e}");
-                    connection = None;
-                }
-            }
+        if state.is_recording() != recording {
+            recording = !recording;
+            notify::status(if recording { "recording" } else { "idle" });
         }
+        if recording {
+            last_recording = std::time::Instant::now();
+        }
+
+        let idle = server.idle_for().min(last_recording.elapsed());
+        if state.idle_shutdown().is_some_and(|limit| idle >= limit) {
+            log::info!("idle for {}s, shutting down", idle.as_secs());
+            break;
+        }
+
+        server.wait(&wakeups, TICK)?;
+    }
 
-        std::thread::sleep(Duration::from_millis(100));
+    notify::stopping();
+    // release the microphone before the socket goes away
+    if state.is_recording() {
+        state.stop_recording();
     }
 
     Ok(())
 }
+
+/// Apply the config file as re-read on SIGHUP, or keep the settings as they
+/// are if it can't be used.
+fn reload_config(state: &state::DaemonState, loaded: Result<config::Config, String>) {
+    match loaded {
+        Ok(config) => {
+            log::set_max_level(config.log_level());
+            state.apply_config(config);
+            log::info!("config reloaded");
+        }
+        Err(e) => log::error!("config not reloaded: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse_args(&[]), Some(Args::default()));
+        assert_eq!(
+            parse_args(&["--instance", "headset", "--daemonize"]),
+            Some(Args {
+                detach: true,
+                instance: Some("headset".to_string()),
+                ..Args::default()
+            })
+        );
+        assert_eq!(
+            parse_args(&["--check"]),
+            Some(Args {
+                check: true,
+                ..Args::default()
+            })
+        );
+        assert_eq!(parse_args(&["--instance"]), None);
+        assert_eq!(parse_args(&["--verbose"]), None);
+    }
+
+    #[test]
+    fn test_sighup_reloads_interval() {
+        // reloading sets the log level too
+        let _level = testing::LOG_LEVEL.lock().unwrap_or_else(|e| e.into_inner());
+        let _signals = testing::SIGNALS.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = std::env::temp_dir().join(format!("yowl-reload-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("yowl")).unwrap();
+        let write_config = |text: &str| std::fs::write(dir.join("yowl/config.toml"), text).unwrap();
+        let home = dir.to_str().unwrap().to_string();
+        let load = || config::load_from(|var| (var == "XDG_CONFIG_HOME").then(|| home.clone()));
+
+        let model = testing::MockModel::default();
+        let transcriber = whisper::StreamingTranscriber::with_model(
+            Box::new(model.clone()),
+            Duration::from_secs(10),
+        );
+        let (senders, sources) = std::sync::mpsc::channel();
+        let state = state::DaemonState::with_parts(transcriber, testing::channel_opener(senders));
+        write_config("transcribe_interval_ms = 5000\n");
+        reload_config(&state, load());
+        assert_eq!(testing::command(&state, "START"), "OK");
+        let audio = sources.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let tone: Vec<f32> = (0..1600)
+            .map(|i| 0.3 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 16000.0).sin())
+            .collect();
+        let speak_until = |done: &dyn Fn() -> bool| {
+            let deadline = std::time::Instant::now() + Duration::from_secs(5);
+            while !done() && std::time::Instant::now() < deadline {
+                audio.send(tone.clone()).unwrap();
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        };
+        for _ in 0..3 {
+            audio.send(tone.clone()).unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(model.calls(), 0, "passed before the interval was up");
+
+        // a config that can't be used leaves the old one in place
+        write_config("transcribe_interval_ms = 1\n");
+        signals::install(state.waker()).unwrap();
+        unsafe { libc::raise(libc::SIGHUP) };
+        assert!(signals::reload_requested());
+        assert!(!signals::reload_requested());
+        reload_config(&state, load());
+        assert_eq!(
+            testing::command(&state, "GET transcribe_interval_ms"),
+            "transcribe_interval_ms=5000"
+        );
+
+        write_config("transcribe_interval_ms = 100\n");
+        unsafe { libc::raise(libc::SIGHUP) };
+        assert!(signals::reload_requested());
+        reload_config(&state, load());
+        speak_until(&|| model.calls() > 1);
+        assert!(model.calls() > 1, "worker still on the old interval");
+
+        // the handlers mustn't outlive the waker they write to
+        signals::uninstall();
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,230 @@
+//! Statistics for tuning a recording session, reported by METRICS.
+//!
+//! Counters cover the whole session; rolling averages cover the most recent
+//! passes or seconds of audio, so they show how things are going now. Both
+//! are reset at START and kept after STOP.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Passes (and tracker updates) covered by the rolling averages.
+const RECENT_PASSES: usize = 20;
+/// Span of audio covered by the rolling sample rate.
+const RECENT_AUDIO: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    started: Instant,
+    /// Transcription passes run, including failed ones
+    passes: u64,
+    /// Passes skipped because the audio was silent
+    skipped: u64,
+    inference_time: Duration,
+    recent_inference: VecDeque<Duration>,
+    samples: u64,
+    /// When each batch of samples arrived, and its size
+    recent_samples: VecDeque<(Instant, usize)>,
+    /// Tracker updates that changed the output
+    updates: u64,
+    backspaces: u64,
+    recent_backspaces: VecDeque<usize>,
+}
+
+impl Metrics {
+    /// Empty metrics for a session starting at `started`.
+    pub fn new(started: Instant) -> Self {
+        Self {
+            started,
+            passes: 0,
+            skipped: 0,
+            inference_time: Duration::ZERO,
+            recent_inference: VecDeque::new(),
+            samples: 0,
+            recent_samples: VecDeque::new(),
+            updates: 0,
+            backspaces: 0,
+            recent_backspaces: VecDeque::new(),
+        }
+    }
+
+    /// Record a transcription pass that took `took`.
+    pub fn record_pass(&mut self, took: Duration) {
+        self.passes += 1;
+        self.inference_time += took;
+        push_recent(&mut self.recent_inference, took);
+    }
+
+    /// Record a pass skipped for silence.
+    pub fn record_skip(&mut self) {
+        self.skipped += 1;
+    }
+
+    /// Record `count` samples of audio arriving at `at`.
+    pub fn record_samples(&mut self, count: usize, at: Instant) {
+        self.samples += count as u64;
+        self.recent_samples.push_back((at, count));
+        while self
+            .recent_samples
+            .front()
+            .is_some_and(|&(arrived, _)| at.duration_since(arrived) >= RECENT_AUDIO)
+        {
+            self.recent_samples.pop_front();
+        }
+    }
+
+    /// Record a tracker update that erased `backspaces` characters.
+    pub fn record_update(&mut self, backspaces: usize) {
+        self.updates += 1;
+        self.backspaces += backspaces as u64;
+        push_recent(&mut self.recent_backspaces, backspaces);
+    }
+
+    /// The metrics as of `now`, as `key=value` pairs in a fixed order.
+    pub fn report(&self, now: Instant) -> Vec<(&'static str, String)> {
+        let elapsed = now.saturating_duration_since(self.started);
+        let recent_span = elapsed.min(RECENT_AUDIO);
+        let recent_samples: usize = self
+            .recent_samples
+            .iter()
+            .filter(|&&(arrived, _)| now.saturating_duration_since(arrived) < RECENT_AUDIO)
+            .map(|&(_, count)| count)
+            .sum();
+        let recent_inference: Duration = self.recent_inference.iter().sum();
+        let recent_backspaces: usize = self.recent_backspaces.iter().sum();
+
+        vec![
+            ("passes", self.passes.to_string()),
+            ("skipped", self.skipped.to_string()),
+            (
+                "inference_ms_avg",
+                format_ms(average(
+                    self.inference_time.as_secs_f64(),
+                    self.passes as usize,
+                )),
+            ),
+            (
+                "inference_ms_recent",
+                format_ms(average(
+                    recent_inference.as_secs_f64(),
+                    self.recent_inference.len(),
+                )),
+            ),
+            ("samples", self.samples.to_string()),
+            (
+                "samples_per_sec_avg",
+                format!("{:.0}", rate(self.samples as f64, elapsed)),
+            ),
+            (
+                "samples_per_sec_recent",
+                format!("{:.0}", rate(recent_samples as f64, recent_span)),
+            ),
+            ("updates", self.updates.to_string()),
+            ("backspaces", self.backspaces.to_string()),
+            (
+                "backspaces_per_update_recent",
+                format!(
+                    "{:.2}",
+                    average(recent_backspaces as f64, self.recent_backspaces.len())
+                ),
+            ),
+        ]
+    }
+}
+
+fn push_recent<T>(recent: &mut VecDeque<T>, value: T) {
+    if recent.len() == RECENT_PASSES {
+        recent.pop_front();
+    }
+    recent.push_back(value);
+}
+
+fn average(total: f64, count: usize) -> f64 {
+    if count == 0 {
+        0.0
+    } else {
+        total / count as f64
+    }
+}
+
+fn rate(total: f64, span: Duration) -> f64 {
+    if span.is_zero() {
+        0.0
+    } else {
+        total / span.as_secs_f64()
+    }
+}
+
+fn format_ms(secs: f64) -> String {
+    format!("{:.1}", secs * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(report: &[(&str, String)], key: &str) -> String {
+        report
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.clone())
+            .unwrap_or_else(|| panic!("no {key} in report"))
+    }
+
+    #[test]
+    fn test_empty_report() {
+        let start = Instant::now();
+        let report = Metrics::new(start).report(start);
+        assert_eq!(value(&report, "passes"), "0");
+        assert_eq!(value(&report, "inference_ms_avg"), "0.0");
+        assert_eq!(value(&report, "samples_per_sec_recent"), "0");
+    }
+
+    #[test]
+    fn test_inference_averages() {
+        let start = Instant::now();
+        let mut metrics = Metrics::new(start);
+        for _ in 0..RECENT_PASSES {
+            metrics.record_pass(Duration::from_millis(100));
+        }
+        // the slow early passes drop out of the rolling average
+        for _ in 0..RECENT_PASSES {
+            metrics.record_pass(Duration::from_millis(300));
+        }
+        metrics.record_skip();
+
+        let report = metrics.report(start);
+        assert_eq!(value(&report, "passes"), "40");
+        assert_eq!(value(&report, "skipped"), "1");
+        assert_eq!(value(&report, "inference_ms_avg"), "200.0");
+        assert_eq!(value(&report, "inference_ms_recent"), "300.0");
+    }
+
+    #[test]
+    fn test_sample_rates() {
+        let start = Instant::now();
+        let mut metrics = Metrics::new(start);
+        for second in 1..=10 {
+            let count = if second <= 5 { 16000 } else { 8000 };
+            metrics.record_samples(count, start + Duration::from_secs(second));
+        }
+
+        let report = metrics.report(start + Duration::from_secs(10));
+        assert_eq!(value(&report, "samples"), "120000");
+        assert_eq!(value(&report, "samples_per_sec_avg"), "12000");
+        assert_eq!(value(&report, "samples_per_sec_recent"), "8000");
+    }
+
+    #[test]
+    fn test_backspace_churn() {
+        let start = Instant::now();
+        let mut metrics = Metrics::new(start);
+        metrics.record_update(0);
+        metrics.record_update(3);
+        metrics.record_update(6);
+
+        let report = metrics.report(start);
+        assert_eq!(value(&report, "updates"), "3");
+        assert_eq!(value(&report, "backspaces"), "9");
+        assert_eq!(value(&report, "backspaces_per_update_recent"), "3.00");
+    }
+}
@@ -0,0 +1,141 @@
+//! Locating whisper model files on disk.
+//!
+//! Models live in a models directory as `ggml-<name>.bin` and are referred to
+//! by name, e.g. `base.en`. The directory comes from `YOWL_MODELS_DIR`, then
+//! the XDG data directory (`~/.local/share/yowl/models`). Development builds
+//! also find models in the source tree's `models/` directory.
+
+use std::path::{Path, PathBuf};
+
+// TODO: allow model download at runtime
+pub const DEFAULT_MODEL: &str = "base.en";
+
+/// Models directory in the source tree, used by development builds.
+const BUILD_MODELS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/models");
+
+/// The models directory configured by the environment.
+pub fn default_dir() -> PathBuf {
+    default_dir_from(|var| std::env::var(var).ok())
+}
+
+fn default_dir_from(env: impl Fn(&str) -> Option<String>) -> PathBuf {
+    let non_empty = |var| env(var).filter(|value: &String| !value.is_empty());
+
+    if let Some(dir) = non_empty("YOWL_MODELS_DIR") {
+        return PathBuf::from(dir);
+    }
+    if let Some(data_home) = non_empty("XDG_DATA_HOME") {
+        return Path::new(&data_home).join("yowl").join("models");
+    }
+    if let Some(home) = non_empty("HOME") {
+        return Path::new(&home).join(".local/share/yowl/models");
+    }
+    PathBuf::from(BUILD_MODELS_DIR)
+}
+
+/// Path of the default model in the configured models directory.
+pub fn default_model_path() -> PathBuf {
+    resolve(DEFAULT_MODEL, &default_dir())
+}
+
+/// Returns true if `name` can safely be used as a model name.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(['/', '\\']) && name != "." && name != ".."
+}
+
+/// Resolve a model name to its file in `dir`. If the model isn't there but
+/// exists in the source tree's models directory, that copy is used instead.
+pub fn resolve(name: &str, dir: &Path) -> PathBuf {
+    resolve_with_fallback(name, dir, Path::new(BUILD_MODELS_DIR))
+}
+
+fn resolve_with_fallback(name: &str, dir: &Path, fallback_dir: &Path) -> PathBuf {
+    let file_name = format!("ggml-{name}.bin");
+
+    let path = dir.join(&file_name);
+    if path.exists() {
+        return path;
+    }
+
+    let fallback = fallback_dir.join(&file_name);
+    if fallback.exists() {
+        return fallback;
+    }
+
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[test]
+    fn test_default_dir_precedence() {
+        let all = [
+            ("YOWL_MODELS_DIR", "/opt/yowl"),
+            ("XDG_DATA_HOME", "/data"),
+            ("HOME", "/home/me"),
+        ];
+        assert_eq!(default_dir_from(env(&all)), PathBuf::from("/opt/yowl"));
+        assert_eq!(
+            default_dir_from(env(&all[1..])),
+            PathBuf::from("/data/yowl/models")
+        );
+        assert_eq!(
+            default_dir_from(env(&all[2..])),
+            PathBuf::from("/home/me/.local/share/yowl/models")
+        );
+        assert_eq!(
+            default_dir_from(env(&[("YOWL_MODELS_DIR", ""), ("HOME", "/home/me")])),
+            PathBuf::from("/home/me/.local/share/yowl/models")
+        );
+        assert_eq!(default_dir_from(env(&[])), PathBuf::from(BUILD_MODELS_DIR));
+    }
+
+    #[test]
+    fn test_resolve_uses_configured_dir() {
+        let root = std::env::temp_dir().join(format!("yowl-test-{}-models", std::process::id()));
+        let dir = root.join("configured");
+        let fallback = root.join("fallback");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::create_dir_all(&fallback).unwrap();
+        std::fs::write(dir.join("ggml-tiny.en.bin"), b"").unwrap();
+        std::fs::write(fallback.join("ggml-tiny.en.bin"), b"").unwrap();
+        std::fs::write(fallback.join("ggml-small.en.bin"), b"").unwrap();
+
+        // present in the configured dir
+        assert_eq!(
+            resolve_with_fallback("tiny.en", &dir, &fallback),
+            dir.join("ggml-tiny.en.bin")
+        );
+        // only in the fallback dir
+        assert_eq!(
+            resolve_with_fallback("small.en", &dir, &fallback),
+            fallback.join("ggml-small.en.bin")
+        );
+        // nowhere: report where it was expected
+        assert_eq!(
+            resolve_with_fallback("large", &dir, &fallback),
+            dir.join("ggml-large.bin")
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_model_names() {
+        assert!(is_valid_name("base.en"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name(".."));
+        assert!(!is_valid_name("../base.en"));
+        assert!(!is_valid_name("models/base.en"));
+    }
+}
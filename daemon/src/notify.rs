@@ -0,0 +1,142 @@
+//! Readiness notification for launchers.
+//!
+//! A launcher that needs to know when the daemon can accept connections
+//! passes the write end of a pipe in `YOWL_READY_FD`. Once the IPC socket is
+//! bound, the daemon writes `READY` to it and closes it, so the launcher can
+//! simply block reading the other end. The model loads later, when it is
+//! first needed.
+//!
+//! Under a systemd service with `Type=notify`, the daemon also tells systemd
+//! it is ready over `NOTIFY_SOCKET`, then keeps its status up to date and
+//! says when it is stopping.
+
+use std::ffi::OsStr;
+use std::io::Write;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixDatagram;
+
+const READY_FD_VAR: &str = "YOWL_READY_FD";
+
+/// Signal readiness to whoever launched the daemon, if they asked for it.
+pub fn ready() {
+    sd_notify("READY=1\nSTATUS=idle");
+
+    let Ok(value) = std::env::var(READY_FD_VAR) else {
+        return;
+    };
+    // the fd is closed below, so processes we spawn mustn't be told of it
+    std::env::remove_var(READY_FD_VAR);
+
+    match value.trim().parse::<RawFd>() {
+        Ok(fd) if fd > 2 => match notify_fd(fd) {
+            Ok(()) => log::debug!("readiness signalled on fd {fd}"),
+            Err(e) => log::warn!("failed to signal readiness on fd {fd}: {e}"),
+        },
+        _ => log::warn!("ignoring invalid {READY_FD_VAR}: {value}"),
+    }
+}
+
+/// Show `status`, such as `recording`, in `systemctl status`.
+pub fn status(status: &str) {
+    sd_notify(&format!("STATUS={status}"));
+}
+
+/// Tell systemd the daemon is shutting down.
+pub fn stopping() {
+    sd_notify("STOPPING=1");
+}
+
+/// Send `state` to systemd, if it started us with a notification socket.
+fn sd_notify(state: &str) {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = send_notification(&socket, state) {
+        log::warn!("failed to notify systemd: {e}");
+    }
+}
+
+/// Send `state` as one datagram to the socket at `path`, where a leading
+/// `@` names an abstract socket.
+fn send_notification(path: &OsStr, state: &str) -> std::io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write `READY` to `fd` and close it.
+fn notify_fd(fd: RawFd) -> std::io::Result<()> {
+    // take ownership so the fd is closed on drop, giving the reader EOF
+    let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
+    file.write_all(b"READY\n")?;
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_ready_fd_fires_after_bind() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut reader = unsafe { std::fs::File::from_raw_fd(fds[0]) };
+
+        let path =
+            std::env::temp_dir().join(format!("yowl-test-{}-ready.sock", std::process::id()));
+        let server = crate::ipc::Server::bind_at(path.clone()).expect("Failed to bind server");
+        notify_fd(fds[1]).expect("Failed to signal readiness");
+
+        // the writer is closed, so this reads to EOF rather than blocking
+        let mut message = String::new();
+        reader.read_to_string(&mut message).unwrap();
+        assert_eq!(message, "READY\n");
+        assert!(
+            path.exists(),
+            "socket should be bound before readiness fires"
+        );
+
+        drop(server);
+    }
+
+    #[test]
+    fn test_notification_datagram() {
+        let path =
+            std::env::temp_dir().join(format!("yowl-test-{}-notify.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let systemd = UnixDatagram::bind(&path).unwrap();
+
+        send_notification(path.as_os_str(), "READY=1\nSTATUS=idle").unwrap();
+        let mut buf = [0; 64];
+        let n = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1\nSTATUS=idle");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_notification_abstract_socket() {
+        use std::os::linux::net::SocketAddrExt;
+        let name = format!("yowl-test-{}-notify", std::process::id());
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(&name).unwrap();
+        let systemd = UnixDatagram::bind_addr(&addr).unwrap();
+
+        send_notification(OsStr::new(&format!("@{name}")), "STOPPING=1").unwrap();
+        let mut buf = [0; 64];
+        let n = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"STOPPING=1");
+    }
+}
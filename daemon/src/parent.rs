@@ -0,0 +1,141 @@
+//! Shutting down along with whoever launched the daemon.
+//!
+//! A launcher can pass the read end of a pipe in `YOWL_PARENT_FD` and hold
+//! the write end for as long as the daemon should run. However the launcher
+//! ends, the pipe then closes, and the main loop sees that in poll(2) along
+//! with its clients. This works through any amount of reparenting.
+//!
+//! Without a pipe the daemon follows its parent process: the main loop
+//! compares the parent pid on each tick. On Linux the kernel also sends
+//! SIGTERM when the parent dies, so shutdown doesn't wait for a tick. That
+//! signal follows the *thread* that spawned the daemon rather than the
+//! process, so it can come early from a launcher that spawns from a worker
+//! thread; the pid check covers launchers where it doesn't come at all.
+
+use std::io::{ErrorKind, Read};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+
+const PARENT_FD_VAR: &str = "YOWL_PARENT_FD";
+
+pub enum ParentWatch {
+    /// The read end of the launcher's pipe
+    Pipe(std::fs::File),
+    /// The parent's pid when the daemon started
+    Pid(u32),
+}
+
+impl ParentWatch {
+    /// Watch the pipe in `YOWL_PARENT_FD` if the launcher passed one, or else
+    /// the process `parent`, the daemon's parent when it started.
+    pub fn start(parent: u32) -> std::io::Result<Self> {
+        if let Ok(value) = std::env::var(PARENT_FD_VAR) {
+            match value.trim().parse::<RawFd>() {
+                // the launcher passed the descriptor to us alone
+                Ok(fd) if fd > 2 => return Self::pipe(unsafe { std::fs::File::from_raw_fd(fd) }),
+                _ => log::warn!("ignoring invalid {PARENT_FD_VAR}: {value}"),
+            }
+        }
+        Self::process(parent)
+    }
+
+    fn pipe(file: std::fs::File) -> std::io::Result<Self> {
+        let fd = file.as_raw_fd();
+        // inherited descriptors aren't close-on-exec like ours
+        let cloexec = unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if cloexec < 0
+            || flags < 0
+            || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+        log::info!("following the launcher's pipe on fd {fd}");
+        Ok(Self::Pipe(file))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn process(parent: u32) -> std::io::Result<Self> {
+        if unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // a parent that died before we asked can't signal us any more
+        if std::os::unix::process::parent_id() != parent {
+            log::info!("parent process exited, shutting down");
+            unsafe { libc::raise(libc::SIGTERM) };
+        }
+        Ok(Self::Pid(parent))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn process(parent: u32) -> std::io::Result<Self> {
+        Ok(Self::Pid(parent))
+    }
+
+    /// A descriptor for the main loop to poll, which wakes it when the
+    /// launcher goes away.
+    pub fn fd(&self) -> Option<RawFd> {
+        match self {
+            Self::Pipe(file) => Some(file.as_raw_fd()),
+            Self::Pid(_) => None,
+        }
+    }
+
+    /// Whether the launcher has gone away.
+    pub fn exited(&self) -> bool {
+        match self {
+            Self::Pipe(file) => {
+                let mut chunk = [0; 64];
+                loop {
+                    match (&*file).read(&mut chunk) {
+                        Ok(0) => return true,
+                        // nothing is meant to be written, but it isn't a goodbye
+                        Ok(_) => {}
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => return false,
+                        Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                        Err(e) => {
+                            log::warn!("launcher pipe failed: {e}");
+                            return true;
+                        }
+                    }
+                }
+            }
+            Self::Pid(parent) => std::os::unix::process::parent_id() != *parent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_pipe_closes_when_launcher_exits() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut launcher = unsafe { std::fs::File::from_raw_fd(fds[1]) };
+        let watch = ParentWatch::pipe(unsafe { std::fs::File::from_raw_fd(fds[0]) }).unwrap();
+
+        assert!(!watch.exited());
+        launcher.write_all(b"hi").unwrap();
+        assert!(!watch.exited());
+
+        // the launcher's end closes with it, however it exits
+        drop(launcher);
+        let mut fd = libc::pollfd {
+            fd: watch.fd().unwrap(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        assert_eq!(unsafe { libc::poll(&mut fd, 1, 1000) }, 1);
+        assert!(watch.exited());
+    }
+
+    #[test]
+    fn test_pid_changes_when_parent_exits() {
+        let parent = std::os::unix::process::parent_id();
+        assert!(!ParentWatch::Pid(parent).exited());
+        // once the parent is gone we are reparented, and the pid differs
+        assert!(ParentWatch::Pid(parent + 1).exited());
+    }
+}
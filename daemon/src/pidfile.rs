@@ -0,0 +1,157 @@
+//! A pid file, locked for as long as the daemon runs.
+//!
+//! The lock rather than the file says whether a daemon is running: a file
+//! left behind by one that crashed is unlocked, and is simply taken over.
+
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::PathBuf;
+
+pub struct PidFile {
+    path: PathBuf,
+    /// Directory created for the file, removed on drop if empty
+    dir: Option<PathBuf>,
+    /// Holds the lock until the daemon exits
+    _file: File,
+}
+
+impl PidFile {
+    /// Lock the pid file at `path` and write our pid to it. Fails with
+    /// `AddrInUse` if another daemon holds it.
+    pub fn acquire(path: PathBuf, dir: Option<PathBuf>) -> std::io::Result<Self> {
+        let mut file = loop {
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .mode(0o600)
+                .open(&path)?;
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+                let e = std::io::Error::last_os_error();
+                if e.kind() != ErrorKind::WouldBlock {
+                    return Err(e);
+                }
+                return Err(std::io::Error::new(
+                    ErrorKind::AddrInUse,
+                    match read_pid(&file) {
+                        Some(pid) => format!("another yowl daemon is already running (pid {pid})"),
+                        None => "another yowl daemon is already running".to_string(),
+                    },
+                ));
+            }
+
+            // a daemon on its way out may have removed the file we locked
+            let locked = file.metadata()?;
+            match std::fs::metadata(&path) {
+                Ok(current) if current.dev() == locked.dev() && current.ino() == locked.ino() => {
+                    break file;
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        };
+
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        log::debug!("locked pid file {}", path.display());
+        Ok(Self {
+            path,
+            dir,
+            _file: file,
+        })
+    }
+}
+
+/// The pid written by the daemon holding `file`, unless it is still
+/// writing it.
+fn read_pid(mut file: &File) -> Option<u32> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // removed while still locked, so no other daemon can be using it
+        let _ = std::fs::remove_file(&self.path);
+        // only succeeds if nothing else is in there
+        if let Some(dir) = &self.dir {
+            let _ = std::fs::remove_dir(dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("yowl-test-{}-{name}.pid", std::process::id()))
+    }
+
+    #[test]
+    fn test_takes_over_after_crash() {
+        // a daemon that crashed left its pid behind, but no lock
+        let path = test_path("crashed");
+        std::fs::write(&path, "999999999\n").unwrap();
+
+        let pid_file = PidFile::acquire(path.clone(), None).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_second_daemon_refused() {
+        let path = test_path("concurrent");
+        let first = PidFile::acquire(path.clone(), None).unwrap();
+
+        // each open file has its own lock, so this contends as another
+        // process would
+        let err = PidFile::acquire(path.clone(), None).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::AddrInUse);
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "another yowl daemon is already running (pid {})",
+                std::process::id()
+            )
+        );
+
+        drop(first);
+        let second = PidFile::acquire(path.clone(), None).unwrap();
+        drop(second);
+    }
+
+    #[test]
+    fn test_simultaneous_starts() {
+        let path = test_path("race");
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(8));
+        let starts: Vec<_> = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                let barrier = std::sync::Arc::clone(&barrier);
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    PidFile::acquire(path, None)
+                })
+            })
+            .collect();
+        let winners: Vec<PidFile> = starts
+            .into_iter()
+            .filter_map(|start| start.join().unwrap().ok())
+            .collect();
+
+        assert_eq!(winners.len(), 1);
+        drop(winners);
+        assert!(!path.exists());
+    }
+}
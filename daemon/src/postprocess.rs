@@ -0,0 +1,276 @@
+//! Post-processing applied to transcripts before they are diffed and typed.
+//!
+//! The settings in effect form a [`Profile`]. Profiles can be saved under a
+//! name and switched as a whole, e.g. between "email" and "code" dictation.
+
+/// Clean-up applied after substitutions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PostProcess {
+    /// Type the text as whisper produced it
+    #[default]
+    Raw,
+    /// Capitalize sentence starts and collapse repeated spaces
+    Capitalize,
+}
+
+impl std::str::FromStr for PostProcess {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            s if s.eq_ignore_ascii_case("raw") => Ok(Self::Raw),
+            s if s.eq_ignore_ascii_case("capitalize") => Ok(Self::Capitalize),
+            other => Err(format!("unknown post-processing: {other}")),
+        }
+    }
+}
+
+/// A complete post-processing configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Whole-word replacements, applied in order
+    substitutions: Vec<(String, String)>,
+    /// Clean-up applied after the substitutions
+    post_process: PostProcess,
+    /// Runs of at least this many of the same word, as whisper produces when
+    /// it gets stuck in a loop, are collapsed to one; None leaves them be
+    collapse_repeats: Option<usize>,
+}
+
+impl Profile {
+    /// Replace whole-word occurrences of `from` with `to`. A later substitution
+    /// for the same `from` replaces the earlier one.
+    pub fn add_substitution(&mut self, from: &str, to: &str) {
+        self.substitutions.retain(|(existing, _)| existing != from);
+        self.substitutions.push((from.to_string(), to.to_string()));
+    }
+
+    pub fn set_post_process(&mut self, post_process: PostProcess) {
+        self.post_process = post_process;
+    }
+
+    pub fn post_process(&self) -> PostProcess {
+        self.post_process
+    }
+
+    /// Collapse runs of `min_run` or more of the same word to one, or with
+    /// `None`, leave repeats alone.
+    pub fn set_collapse_repeats(&mut self, min_run: Option<usize>) {
+        self.collapse_repeats = min_run;
+    }
+
+    /// Apply the profile to a transcript. The first letter is never
+    /// capitalized here, as only the caller knows if it starts a sentence.
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = match self.collapse_repeats {
+            Some(min_run) => collapse_repeats(text, min_run),
+            None => text.to_string(),
+        };
+        for (from, to) in &self.substitutions {
+            text = replace_words(&text, from, to);
+        }
+        match self.post_process {
+            PostProcess::Raw => text,
+            PostProcess::Capitalize => tidy_sentences(&text),
+        }
+    }
+}
+
+/// Drop leading spaces, collapse runs of spaces, and capitalize the first
+/// letter after sentence-ending punctuation.
+fn tidy_sentences(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut sentence_ended = false;
+    let mut capitalize_next = false;
+
+    for c in text.trim_start_matches(' ').chars() {
+        if c == ' ' && result.ends_with(' ') {
+            continue;
+        }
+        if c.is_whitespace() {
+            capitalize_next |= sentence_ended;
+        } else if capitalize_next && c.is_alphabetic() {
+            result.extend(c.to_uppercase());
+            capitalize_next = false;
+            sentence_ended = false;
+            continue;
+        } else {
+            capitalize_next = false;
+        }
+        sentence_ended = matches!(c, '.' | '!' | '?') || (sentence_ended && c.is_whitespace());
+        result.push(c);
+    }
+    result
+}
+
+/// Collapse each run of `min_run` or more of the same word, ignoring case
+/// and punctuation, to its last word, which carries any punctuation ending
+/// the run. Shorter runs, like "very very good", are kept.
+fn collapse_repeats(text: &str, min_run: usize) -> String {
+    // split_whitespace yields slices of `text`, so each knows its offset
+    let words: Vec<(usize, &str)> = text
+        .split_whitespace()
+        .map(|word| (word.as_ptr() as usize - text.as_ptr() as usize, word))
+        .collect();
+    let key = |word: &str| {
+        word.trim_matches(|c: char| !c.is_alphanumeric())
+            .to_lowercase()
+    };
+
+    let mut result = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut start = 0;
+    while start < words.len() {
+        let word = key(words[start].1);
+        let mut end = start + 1;
+        while !word.is_empty() && end < words.len() && key(words[end].1) == word {
+            end += 1;
+        }
+        if end - start >= min_run {
+            result.push_str(&text[copied..words[start].0]);
+            copied = words[end - 1].0;
+        }
+        start = end;
+    }
+
+    result.push_str(&text[copied..]);
+    result
+}
+
+/// Returns true if text following `text` starts a new sentence.
+pub fn starts_sentence(text: &str) -> bool {
+    text.trim_end()
+        .chars()
+        .next_back()
+        .is_none_or(|c| matches!(c, '.' | '!' | '?'))
+}
+
+/// Uppercase the first letter of `text`.
+pub fn capitalize_first(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Replace occurrences of `from` that aren't part of a longer word.
+fn replace_words(text: &str, from: &str, to: &str) -> String {
+    if from.is_empty() {
+        return text.to_string();
+    }
+
+    let is_word_char = |c: Option<char>| c.is_some_and(char::is_alphanumeric);
+    let mut result = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut search = 0;
+
+    while let Some(found) = text[search..].find(from) {
+        let start = search + found;
+        let end = start + from.len();
+
+        if is_word_char(text[..start].chars().next_back())
+            || is_word_char(text[end..].chars().next())
+        {
+            // part of a longer word
+            search = start + text[start..].chars().next().map_or(1, char::len_utf8);
+            continue;
+        }
+
+        result.push_str(&text[copied..start]);
+        result.push_str(to);
+        copied = end;
+        search = end;
+    }
+
+    result.push_str(&text[copied..]);
+    result
+}
+
+/// Returns true if `name` can be used as a profile name.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || c == ',')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitutions_match_whole_words() {
+        let mut profile = Profile::default();
+        profile.add_substitution("at", "@");
+        profile.add_substitution("dot com", ".com");
+
+        assert_eq!(
+            profile.apply("mail me at example dot com, that's all"),
+            "mail me @ example .com, that's all"
+        );
+        assert_eq!(profile.apply("attic cat at"), "attic cat @");
+    }
+
+    #[test]
+    fn test_later_substitution_replaces_earlier() {
+        let mut profile = Profile::default();
+        profile.add_substitution("period", ".");
+        profile.add_substitution("period", "!");
+        assert_eq!(profile.apply("stop period"), "stop !");
+    }
+
+    #[test]
+    fn test_capitalize_tidies_sentences() {
+        let mut profile = Profile::default();
+        assert_eq!(profile.apply(" hello.  world"), " hello.  world");
+
+        profile.set_post_process(PostProcess::Capitalize);
+        assert_eq!(profile.apply("hello. world"), "hello. World");
+        assert_eq!(
+            profile.apply("  is it?   yes!  it is 3.5 metres"),
+            "is it? Yes! It is 3.5 metres"
+        );
+        assert_eq!(
+            profile.apply("done. \"quoted\" text"),
+            "done. \"quoted\" text"
+        );
+        assert_eq!(
+            capitalize_first(&profile.apply("hello. world")),
+            "Hello. World"
+        );
+    }
+
+    #[test]
+    fn test_collapse_repeats() {
+        let mut profile = Profile::default();
+        let looped = " and the the the the the the dog";
+        assert_eq!(profile.apply(looped), looped);
+
+        profile.set_collapse_repeats(Some(4));
+        assert_eq!(profile.apply(looped), " and the dog");
+        assert_eq!(profile.apply(" very very good"), " very very good");
+        assert_eq!(profile.apply("The the, the the. Next"), "the. Next");
+        assert_eq!(profile.apply("... ... ... ..."), "... ... ... ...");
+    }
+
+    #[test]
+    fn test_starts_sentence() {
+        assert!(starts_sentence(""));
+        assert!(starts_sentence("Done. "));
+        assert!(starts_sentence("Really?"));
+        assert!(!starts_sentence("Once upon "));
+    }
+
+    #[test]
+    fn test_post_process_from_str() {
+        assert_eq!("capitalize".parse(), Ok(PostProcess::Capitalize));
+        assert_eq!("Raw".parse(), Ok(PostProcess::Raw));
+        assert!("shout".parse::<PostProcess>().is_err());
+    }
+
+    #[test]
+    fn test_profile_names() {
+        assert!(is_valid_name("email"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("my profile"));
+        assert!(!is_valid_name("a,b"));
+    }
+}
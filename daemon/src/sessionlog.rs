@@ -0,0 +1,229 @@
+//! The daemon's own copy of each dictation, in case the window it was typed
+//! into goes away.
+//!
+//! Each session writes `YYYYMMDD-HHMMSS.txt`, holding its text as it stands,
+//! and `YYYYMMDD-HHMMSS.jsonl`, with a line for each update, for debugging
+//! the text tracker. Both are written as updates arrive, so a daemon crash
+//! loses at most the last transcription interval, and flushed to disk when
+//! the session ends. Only the user can read them, as they hold what was
+//! dictated.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::diff::DiffResult;
+use crate::logging;
+
+pub struct SessionLog {
+    text_path: PathBuf,
+    /// Unbuffered, so each line reaches the file as it is written
+    updates: File,
+}
+
+impl SessionLog {
+    /// Start a session's files in `dir`, creating it if need be, named for
+    /// `now` in local time. A name already taken gets a `-2`, `-3`, ...
+    /// suffix.
+    pub fn create(dir: &Path, now: SystemTime) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let stem = file_stem(now);
+        for n in 1.. {
+            let stem = match n {
+                1 => stem.clone(),
+                n => format!("{stem}-{n}"),
+            };
+            let updates = match File::options()
+                .append(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(dir.join(format!("{stem}.jsonl")))
+            {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            };
+            let log = Self {
+                text_path: dir.join(format!("{stem}.txt")),
+                updates,
+            };
+            log.write_text("", false)?;
+            return Ok(log);
+        }
+        unreachable!("ran out of file names")
+    }
+
+    /// Where the session's text is kept.
+    pub fn text_path(&self) -> &Path {
+        &self.text_path
+    }
+
+    /// Record `diff`, which left the session's text as `text`.
+    pub fn record(&mut self, diff: &DiffResult, text: &str) -> io::Result<()> {
+        let ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let line = serde_json::json!({
+            "ts": (ts * 1000.0).round() / 1000.0,
+            "backspaces": diff.backspaces,
+            "new_text": diff.new_text,
+        });
+        writeln!(self.updates, "{line}")?;
+        self.write_text(text, false)
+    }
+
+    /// Replace the session's text file with `text`, and flush both files to
+    /// disk. Meant for the end of a session, not every update.
+    pub fn save_text(&self, text: &str) -> io::Result<()> {
+        self.write_text(text, true)?;
+        self.updates.sync_data()
+    }
+
+    /// Replace the session's text file with `text`. It is written to a
+    /// temporary file beside it that is then renamed over it, so a crash or
+    /// full disk partway through leaves the previous text intact. With
+    /// `sync`, it reaches the disk before the rename.
+    fn write_text(&self, text: &str, sync: bool) -> io::Result<()> {
+        let mut tmp = self.text_path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let written = File::options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)
+            .and_then(|mut file| {
+                file.write_all(text.as_bytes())?;
+                if sync {
+                    file.sync_data()?;
+                }
+                Ok(())
+            });
+        match written.and_then(|()| fs::rename(&tmp, &self.text_path)) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&tmp);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// The session files in `dir`, oldest first. A directory that doesn't exist
+/// yet has none.
+pub fn list(dir: &Path) -> io::Result<Vec<String>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if name.ends_with(".txt") || name.ends_with(".jsonl") {
+            names.push(name);
+        }
+    }
+    // the names start with the time, so this is the order they were made,
+    // as long as `x-2` comes after `x`
+    names.sort_by(|a, b| {
+        let stem = |name: &str| {
+            name.split_once('.')
+                .map_or("", |(stem, _)| stem)
+                .to_string()
+        };
+        (stem(a), a).cmp(&(stem(b), b))
+    });
+    Ok(names)
+}
+
+/// `now` in local time, as `YYYYMMDD-HHMMSS`.
+fn file_stem(now: SystemTime) -> String {
+    let tm = logging::local_tm(now);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_files_follow_updates() {
+        let dir = std::env::temp_dir().join(format!("yowl-sessionlog-test-{}", std::process::id()));
+        let now = SystemTime::now();
+        let stem = file_stem(now);
+        assert_eq!(stem.len(), "YYYYMMDD-HHMMSS".len());
+
+        let mut log = SessionLog::create(&dir, now).unwrap();
+        assert_eq!(log.text_path(), dir.join(format!("{stem}.txt")));
+        log.record(
+            &DiffResult {
+                backspaces: 0,
+                new_text: "Hello wor".to_string(),
+            },
+            "Hello wor",
+        )
+        .unwrap();
+        log.record(
+            &DiffResult {
+                backspaces: 3,
+                new_text: "world".to_string(),
+            },
+            "Hello world",
+        )
+        .unwrap();
+        // written as it goes, without waiting for the log to be dropped
+        assert_eq!(fs::read_to_string(log.text_path()).unwrap(), "Hello world");
+        assert!(!dir.join(format!("{stem}.txt.tmp")).exists());
+        log.save_text("Hello world!").unwrap();
+        assert_eq!(fs::read_to_string(log.text_path()).unwrap(), "Hello world!");
+        // private to the user, whatever the umask
+        for ext in ["txt", "jsonl"] {
+            let mode = fs::metadata(dir.join(format!("{stem}.{ext}")))
+                .unwrap()
+                .permissions();
+            assert_eq!(
+                std::os::unix::fs::PermissionsExt::mode(&mode) & 0o777,
+                0o600
+            );
+        }
+        let updates = fs::read_to_string(dir.join(format!("{stem}.jsonl"))).unwrap();
+        let lines: Vec<serde_json::Value> = updates
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["backspaces"], 3);
+        assert_eq!(lines[1]["new_text"], "world");
+        assert!(lines[1]["ts"].as_f64().unwrap() > 0.0);
+
+        // a second session in the same second doesn't overwrite the first
+        let second = SessionLog::create(&dir, now).unwrap();
+        assert_eq!(second.text_path(), dir.join(format!("{stem}-2.txt")));
+        assert_eq!(
+            list(&dir).unwrap(),
+            [
+                format!("{stem}.jsonl"),
+                format!("{stem}.txt"),
+                format!("{stem}-2.jsonl"),
+                format!("{stem}-2.txt"),
+            ]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(list(&dir).unwrap(), Vec::<String>::new());
+    }
+}
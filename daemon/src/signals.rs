@@ -0,0 +1,105 @@
+//! Clean shutdown on SIGTERM and SIGINT, and config reloads on SIGHUP.
+//!
+//! The handlers only note the signal and wake the main loop. On SIGTERM or
+//! SIGINT it stops any recording and returns, so the socket is removed just
+//! as for SHUTDOWN; on SIGHUP it re-reads the config file. The handlers are
+//! installed just before the socket is bound; until then a signal ends the
+//! daemon straight away, with nothing to clean up.
+
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+use crate::waker::Waker;
+
+static RECEIVED: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
+/// The main loop's wakeup descriptor, or -1
+static WAKE_FD: AtomicI32 = AtomicI32::new(-1);
+
+#[cfg(target_os = "linux")]
+unsafe fn errno() -> *mut libc::c_int {
+    libc::__errno_location()
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn errno() -> *mut libc::c_int {
+    libc::__error()
+}
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    if signal == libc::SIGHUP {
+        RELOAD.store(true, Ordering::SeqCst);
+    } else {
+        RECEIVED.store(true, Ordering::SeqCst);
+    }
+    let fd = WAKE_FD.load(Ordering::SeqCst);
+    if fd >= 0 {
+        // whatever was interrupted may be about to look at errno
+        unsafe {
+            let saved = *errno();
+            libc::write(fd, [1u8].as_ptr().cast(), 1);
+            *errno() = saved;
+        }
+    }
+}
+
+/// Catch SIGTERM, SIGINT and SIGHUP from now on, waking `waker` when one
+/// arrives.
+pub fn install(waker: &Waker) -> std::io::Result<()> {
+    WAKE_FD.store(waker.wake_fd(), Ordering::SeqCst);
+    for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        if unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Put the default handlers back and forget the waker, so a test doesn't
+/// leave signals writing to a descriptor it has closed.
+#[cfg(test)]
+pub fn uninstall() {
+    for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+    }
+    WAKE_FD.store(-1, Ordering::SeqCst);
+}
+
+/// Whether SIGTERM or SIGINT has arrived since `install`.
+pub fn shutdown_requested() -> bool {
+    RECEIVED.load(Ordering::SeqCst)
+}
+
+/// Whether SIGHUP has arrived since the last call.
+pub fn reload_requested() -> bool {
+    RELOAD.swap(false, Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::AsRawFd;
+
+    #[test]
+    fn test_sigterm_requests_shutdown() {
+        let _signals = crate::testing::SIGNALS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let waker = Waker::new().unwrap();
+        install(&waker).unwrap();
+        unsafe { libc::raise(libc::SIGTERM) };
+
+        assert!(shutdown_requested());
+        let mut fd = libc::pollfd {
+            fd: waker.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        assert_eq!(unsafe { libc::poll(&mut fd, 1, 0) }, 1, "waker not woken");
+
+        // leave Ctrl-C able to stop the test run
+        uninstall();
+    }
+}
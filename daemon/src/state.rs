@@ -1,117 +1,3402 @@
-use crate::audio::AudioCapture;
-use crate::diff::TextTracker;
-use crate::whisper::StreamingTranscriber;
+use crate::audio::{self, AudioCapture, AudioOpener, AudioSource, LevelMeter};
+use crate::config::Config;
+use crate::diff::{self, BackspaceUnit, DiffMode, DiffResult, OutputPacer, TextTracker};
+use crate::metrics::Metrics;
+use crate::models;
+use crate::postprocess::{self, PostProcess, Profile};
+use crate::sessionlog::{self, SessionLog};
+use crate::waker::Waker;
+use crate::wav::WavRecorder;
+use crate::whisper::{self, Model, ModelLoader, StreamingTranscriber, WhisperModel};
 
-const TRANSCRIBE_INTERVAL_MS: u64 = 500;
-const BUFFER_DURATION_SECS: u64 = 10;
+const MAX_WAVEFORM_BINS: usize = 4096;
+const INFERENCE_ERROR_THRESHOLD: u32 = 3;
+/// Transcribe intervals a single pass may take before the watchdog warns.
+const WATCHDOG_INTERVALS: u32 = 10;
+const DEFAULT_PROFILE: &str = "default";
+/// Longest START waits for the worker to open the input device. A device
+/// slower than this is reported on a later POLL instead. Kept well short of
+/// the client's response timeout, as every client waits while START does.
+const CAPTURE_START_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Error code reported once inference has failed too many times in a row.
+pub const ERR_INFERENCE: &str = "ERR_INFERENCE";
+/// Error code reported when the input device fails and can't be reopened.
+pub const ERR_DEVICE_LOST: &str = "ERR_DEVICE_LOST";
+/// Error code reported when audio capture can't be started at all, after
+/// START has stopped waiting for it.
+pub const ERR_CAPTURE: &str = "ERR_CAPTURE";
+/// Error code START answers with when the input device can't be opened,
+/// e.g. because it isn't plugged in.
+pub const ERR_AUDIO_DEVICE_UNAVAILABLE: &str = "ERR_AUDIO_DEVICE_UNAVAILABLE";
+/// Error code reported when the recording worker panicked.
+pub const ERR_WORKER_PANICKED: &str = "ERR_WORKER_PANICKED";
+
+/// A failure reported to the client on its next POLL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fault {
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Outcome of polling for transcript changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Poll {
+    /// Not recording.
+    Idle,
+    /// Recording ended itself after the speaker fell silent; reported once.
+    Stopped,
+    /// Recording; apply the diff to catch up (it is empty when nothing changed).
+    Recording(DiffResult),
+    /// Something went wrong since the last poll.
+    Fault(Fault),
+}
+
+/// What one reader of the transcript hasn't been told yet. POLL and the
+/// subscribers' events each have their own, so neither takes anything from
+/// the other.
+#[derive(Debug, Default)]
+struct Outbox {
+    /// The latest failure since the reader last heard
+    fault: Option<Fault>,
+    /// Text released since the reader last caught up
+    diff: Option<DiffResult>,
+    /// Recording ended itself after the speaker fell silent
+    stopped: bool,
+}
+
+impl Outbox {
+    /// Add `diff`, counting backspaces in `unit`, to what the reader has
+    /// yet to apply.
+    fn push(&mut self, diff: DiffResult, unit: BackspaceUnit) {
+        self.diff = Some(match self.diff.take() {
+            Some(pending) => diff::compose(pending, diff, unit),
+            None => diff,
+        });
+    }
+
+    /// What to tell the reader next, whether or not `recording`.
+    fn take(&mut self, recording: bool) -> Poll {
+        if let Some(fault) = self.fault.take() {
+            return Poll::Fault(fault);
+        }
+        match self.diff.take() {
+            Some(diff) => Poll::Recording(diff),
+            None if recording => Poll::Recording(DiffResult {
+                backspaces: 0,
+                new_text: String::new(),
+            }),
+            None if std::mem::take(&mut self.stopped) => Poll::Stopped,
+            None => Poll::Idle,
+        }
+    }
+}
+
+/// How long output is held back at the start of a session, while the first
+/// transcripts are still being heavily revised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Warmup {
+    #[default]
+    Off,
+    /// Suppress output for this many transcription passes.
+    Ticks(u32),
+    /// Suppress output for this long after recording starts.
+    Duration(std::time::Duration),
+}
+
+impl std::str::FromStr for Warmup {
+    type Err = String;
+
+    /// Parses `off`, `<n> ticks` or `<n> ms`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let args: Vec<&str> = s.split_whitespace().collect();
+        match args.as_slice() {
+            [off] if off.eq_ignore_ascii_case("off") => Ok(Warmup::Off),
+            [n, unit] => {
+                let n = n
+                    .parse::<u32>()
+                    .map_err(|e| format!("invalid warmup: {e}"))?;
+                match unit.to_lowercase().as_str() {
+                    "ticks" => Ok(Warmup::Ticks(n)),
+                    "ms" => Ok(Warmup::Duration(std::time::Duration::from_millis(n.into()))),
+                    _ => Err(format!("unknown warmup unit: {unit}")),
+                }
+            }
+            _ => Err("usage: WARMUP <n> ticks|<n> ms|off".to_string()),
+        }
+    }
+}
+
+/// Filename of the model at `path`, as reported to clients.
+fn model_name(path: &std::path::Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Progress of the most recent RELOAD.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reload {
+    /// No reload has been requested
+    Idle,
+    /// The model with this filename is loading in the background
+    Loading(String),
+    /// The model with this filename has replaced the previous one
+    Done(String),
+    /// Loading failed and the previous model is still in use
+    Failed(String),
+}
+
+/// Per-session overrides given to START.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StartOptions {
+    /// Input device, instead of the one selected with DEVICE
+    pub device: Option<String>,
+    /// Whisper language code (or `auto`), instead of the default
+    pub language: Option<String>,
+}
+
+/// Snapshot of what the daemon is doing, for status displays.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub recording: bool,
+    /// Recording but paused; implies `recording`.
+    pub paused: bool,
+    /// Time since recording started, or zero when idle.
+    pub elapsed: std::time::Duration,
+    /// Samples currently held in the rolling buffer.
+    pub buffered_samples: usize,
+    pub committed_chars: usize,
+    pub provisional_chars: usize,
+    /// Code of the most recent fault this session, if there was one.
+    pub error: Option<&'static str>,
+    /// A transcription pass has been running longer than the watchdog allows.
+    pub degraded: bool,
+    /// Language being transcribed, or when idle, the one START will use.
+    pub language: String,
+    /// File name of the model in use or to be loaded, if one is known.
+    pub model: Option<String>,
+    /// Length of the rolling buffer, or when idle, the one START will use.
+    pub buffer_secs: u64,
+}
 
 pub struct DaemonState {
     transcriber: StreamingTranscriber,
+    model_path: std::sync::Mutex<Option<std::path::PathBuf>>,
+    models_dir: std::sync::Mutex<std::path::PathBuf>,
+    load_model_file: ModelLoader,
+    reload: std::sync::Mutex<Reload>,
+    open_audio: AudioOpener,
+    /// Input device for future recordings, or None for the system default
+    device: std::sync::Mutex<Option<String>>,
+    /// WAV file that future recordings' audio is also written to
+    record_wav: std::sync::Mutex<Option<std::path::PathBuf>>,
+    /// Copy of the current (or last) session's transcript, if one is kept
+    session_log: std::sync::Mutex<Option<SessionLog>>,
+    /// Settings changed with SET
+    config: std::sync::Mutex<Config>,
+    /// Model the reloaded config asks for, loaded at the next START
+    pending_model: std::sync::Mutex<Option<std::path::PathBuf>>,
+    /// How long the most recent model load took
+    model_load_time: std::sync::Mutex<Option<std::time::Duration>>,
+    /// Input level of the current recording's audio source
+    input_meter: std::sync::Mutex<Option<LevelMeter>>,
     recording: std::sync::atomic::AtomicBool,
+    /// Capture and inference are suspended, but the session is kept
+    paused: std::sync::atomic::AtomicBool,
+    started_at: std::sync::Mutex<Option<std::time::Instant>>,
     worker_thread: std::sync::Mutex<Option<std::thread::JoinHandle<()>>>,
     text_tracker: std::sync::Mutex<TextTracker>,
+    pacer: std::sync::Mutex<OutputPacer>,
+    /// What POLL has yet to report
+    poll_outbox: std::sync::Mutex<Outbox>,
+    /// What subscribers have yet to be sent, while there are any
+    event_outbox: std::sync::Mutex<Outbox>,
+    /// Saved post-processing profiles; the one in effect lives in the tracker
+    profiles: std::sync::Mutex<std::collections::BTreeMap<String, Profile>>,
+    active_profile: std::sync::Mutex<String>,
+    warmup: std::sync::Mutex<Warmup>,
+    /// Transcription passes completed this session
+    ticks: std::sync::atomic::AtomicU32,
+    /// The transcriber pass the tracker was last updated from
+    polled_pass: std::sync::Mutex<Option<u64>>,
+    /// Statistics for the current (or last) session
+    metrics: std::sync::Mutex<Metrics>,
+    inference_errors: std::sync::atomic::AtomicU32,
+    inference_error_threshold: std::sync::atomic::AtomicU32,
+    stop_on_inference_error: std::sync::atomic::AtomicBool,
+    /// The worker's transcription pass in progress, numbered as in `ticks`,
+    /// and when it started
+    inference_started: std::sync::Mutex<Option<(u32, std::time::Instant)>>,
+    /// Transcribe intervals a pass may take before it counts as stuck, or 0
+    /// for no watchdog
+    watchdog_intervals: std::sync::atomic::AtomicU32,
+    /// Abort a stuck pass rather than only warning about it
+    watchdog_abort: std::sync::atomic::AtomicBool,
+    /// The watchdog has already acted on the pass in progress
+    watchdog_fired: std::sync::atomic::AtomicBool,
+    /// Code of the most recent fault, kept for STATUS after POLL reports it
+    last_error: std::sync::Mutex<Option<&'static str>>,
+    /// The worker panicked, and no STOP has said so yet
+    unreported_panic: std::sync::atomic::AtomicBool,
+    /// Whether any client has subscribed to pushed events
+    subscribed: std::sync::atomic::AtomicBool,
+    /// Events produced by the worker, waiting to be pushed to subscribers
+    events: std::sync::Mutex<Vec<Poll>>,
+    /// Rouses the main loop when there are events for it
+    waker: Waker,
 }
 
 impl DaemonState {
-    pub fn new() -> Result<std::sync::Arc<Self>, Box<dyn std::error::Error>> {
-        let transcriber = StreamingTranscriber::new(std::time::Duration::from_secs(BUFFER_DURATION_SECS))?;
+    /// Daemon state for the model `config` names, or else the default model.
+    /// It isn't loaded until the first START (or PRELOAD), so the daemon
+    /// starts at once; it and models loaded later by MODEL and RELOAD run on
+    /// the GPU if `use_gpu`.
+    pub fn new(config: Config, use_gpu: bool) -> std::sync::Arc<Self> {
+        let model_path = config
+            .model
+            .clone()
+            .unwrap_or_else(models::default_model_path);
+        let transcriber = StreamingTranscriber::without_model(std::time::Duration::from_secs(
+            config.buffer_duration_secs,
+        ));
 
-        Ok(std::sync::Arc::new(Self {
+        // input processing is read once, like the model: a reload doesn't
+        // change it
+        let state = Self::with_model_loader(
             transcriber,
+            AudioCapture::opener(
+                config.input_processing(),
+                config.sample_rate(),
+                config.input_channels(),
+            ),
+            WhisperModel::loader(use_gpu),
+        );
+        *state.model_path.lock().unwrap() = Some(model_path);
+        *state.config.lock().unwrap() = config;
+        state
+    }
+
+    /// Build daemon state from an existing transcriber and audio source.
+    #[cfg(test)]
+    pub fn with_parts(
+        transcriber: StreamingTranscriber,
+        open_audio: AudioOpener,
+    ) -> std::sync::Arc<Self> {
+        Self::with_model_loader(transcriber, open_audio, WhisperModel::loader(false))
+    }
+
+    /// Like `with_parts`, loading models for MODEL and RELOAD with `load_model_file`.
+    pub fn with_model_loader(
+        transcriber: StreamingTranscriber,
+        open_audio: AudioOpener,
+        load_model_file: ModelLoader,
+    ) -> std::sync::Arc<Self> {
+        std::sync::Arc::new(Self {
+            transcriber,
+            model_path: std::sync::Mutex::new(None),
+            models_dir: std::sync::Mutex::new(models::default_dir()),
+            load_model_file,
+            reload: std::sync::Mutex::new(Reload::Idle),
+            open_audio,
+            device: std::sync::Mutex::new(None),
+            record_wav: std::sync::Mutex::new(None),
+            session_log: std::sync::Mutex::new(None),
+            config: std::sync::Mutex::new(Config::default()),
+            pending_model: std::sync::Mutex::new(None),
+            model_load_time: std::sync::Mutex::new(None),
+            input_meter: std::sync::Mutex::new(None),
             recording: std::sync::atomic::AtomicBool::new(false),
+            paused: std::sync::atomic::AtomicBool::new(false),
+            started_at: std::sync::Mutex::new(None),
             worker_thread: std::sync::Mutex::new(None),
             text_tracker: std::sync::Mutex::new(TextTracker::new()),
-        }))
+            pacer: std::sync::Mutex::new(OutputPacer::new()),
+            poll_outbox: std::sync::Mutex::new(Outbox::default()),
+            event_outbox: std::sync::Mutex::new(Outbox::default()),
+            profiles: std::sync::Mutex::new(std::collections::BTreeMap::from([(
+                DEFAULT_PROFILE.to_string(),
+                Profile::default(),
+            )])),
+            active_profile: std::sync::Mutex::new(DEFAULT_PROFILE.to_string()),
+            warmup: std::sync::Mutex::new(Warmup::Off),
+            ticks: std::sync::atomic::AtomicU32::new(0),
+            polled_pass: std::sync::Mutex::new(None),
+            metrics: std::sync::Mutex::new(Metrics::new(std::time::Instant::now())),
+            inference_errors: std::sync::atomic::AtomicU32::new(0),
+            inference_error_threshold: std::sync::atomic::AtomicU32::new(INFERENCE_ERROR_THRESHOLD),
+            stop_on_inference_error: std::sync::atomic::AtomicBool::new(false),
+            inference_started: std::sync::Mutex::new(None),
+            watchdog_intervals: std::sync::atomic::AtomicU32::new(WATCHDOG_INTERVALS),
+            watchdog_abort: std::sync::atomic::AtomicBool::new(false),
+            watchdog_fired: std::sync::atomic::AtomicBool::new(false),
+            last_error: std::sync::Mutex::new(None),
+            unreported_panic: std::sync::atomic::AtomicBool::new(false),
+            subscribed: std::sync::atomic::AtomicBool::new(false),
+            events: std::sync::Mutex::new(Vec::new()),
+            waker: Waker::new().expect("failed to create wakeup socket"),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn transcriber(&self) -> &StreamingTranscriber {
+        &self.transcriber
     }
 
-    pub fn start_recording(self: &std::sync::Arc<Self>) -> &'static str {
+    /// Start a recording session, or answer `LOADING` while the model it
+    /// needs loads, after which the client should START again.
+    pub fn start_recording(self: &std::sync::Arc<Self>, options: StartOptions) -> String {
+        // whatever else is wrong with the request, that comes first
+        if self.is_recording() {
+            return "ERROR already recording".to_string();
+        }
+
+        let config = self.config.lock().unwrap().clone();
+        let language = options.language.unwrap_or(config.language);
+        if !whisper::is_valid_language(&language) {
+            return format!("ERROR unknown language: {language}");
+        }
+
+        if let Some(response) = self.prepare_model() {
+            return response;
+        }
+
+        // a worker that stopped itself, say after silence, may still be
+        // settling its text; let it finish before a new session resets it
+        if let Some(handle) = self.worker_thread.lock().unwrap().take() {
+            if handle.join().is_err() {
+                log::error!("recording worker exited with a panic");
+            }
+        }
         if self.recording.swap(true, std::sync::atomic::Ordering::SeqCst) {
-            return "ERROR already recording";
+            return "ERROR already recording".to_string();
         }
 
         // reset any previous recording session
+        self.paused
+            .store(false, std::sync::atomic::Ordering::SeqCst);
         self.transcriber.reset();
+        self.transcriber.set_language(&language);
+        self.transcriber
+            .set_buffer_duration(std::time::Duration::from_secs(config.buffer_duration_secs));
         self.text_tracker.lock().unwrap().reset();
+        self.pacer.lock().unwrap().reset();
+        *self.poll_outbox.lock().unwrap() = Outbox::default();
+        *self.event_outbox.lock().unwrap() = Outbox::default();
+        self.ticks.store(0, std::sync::atomic::Ordering::SeqCst);
+        *self.polled_pass.lock().unwrap() = None;
+        self.inference_errors
+            .store(0, std::sync::atomic::Ordering::SeqCst);
+        *self.last_error.lock().unwrap() = None;
+        self.unreported_panic
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+        self.open_session_log(config.session_log_dir.as_deref());
+        let now = std::time::Instant::now();
+        *self.started_at.lock().unwrap() = Some(now);
+        *self.metrics.lock().unwrap() = Metrics::new(now);
 
+        let device = options
+            .device
+            .or_else(|| self.device.lock().unwrap().clone());
+
+        // the device is opened on the worker, which says here how that went
+        let (opened, capture_result) = std::sync::mpsc::sync_channel(1);
         let state = std::sync::Arc::clone(self);
         let handle = std::thread::spawn(move || {
-            let capture = match AudioCapture::new() {
-                Ok(c) => c,
-                Err(e) => {
-                    log::error!("Failed to create audio capture: {}", e);
-                    state.recording.store(false, std::sync::atomic::Ordering::SeqCst);
-                    return;
-                }
-            };
+            // a panic would otherwise leave the session recording forever
+            let run = std::panic::AssertUnwindSafe(|| Self::run_worker(&state, device, opened));
+            if let Err(panic) = std::panic::catch_unwind(run) {
+                state.worker_panicked(panic.as_ref());
+            }
+            log::debug!("worker thread exiting");
+        });
+
+        match capture_result.recv_timeout(CAPTURE_START_TIMEOUT) {
+            Ok(Err(e)) => {
+                let _ = handle.join();
+                *self.last_error.lock().unwrap() = Some(ERR_AUDIO_DEVICE_UNAVAILABLE);
+                return format!(
+                    "ERROR {ERR_AUDIO_DEVICE_UNAVAILABLE} audio device unavailable: {e}"
+                );
+            }
+            Ok(Ok(())) => {}
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                log::warn!("input device still opening, not waiting for it");
+            }
+            // the worker panicked, which it reports itself
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {}
+        }
+        drop(capture_result);
+
+        *self.worker_thread.lock().unwrap() = Some(handle);
+        log::info!("recording started");
+        "OK".to_string()
+    }
 
-            if let Err(e) = capture.start() {
+    /// Capture and transcribe until recording stops: the body of the worker
+    /// thread. Whether the capture could be opened is sent on `opened`.
+    fn run_worker(
+        state: &std::sync::Arc<Self>,
+        device: Option<String>,
+        opened: std::sync::mpsc::SyncSender<Result<(), String>>,
+    ) {
+        let mut capture = match state.open_capture(device.as_deref()) {
+            Ok(c) => {
+                let _ = opened.send(Ok(()));
+                c
+            }
+            Err(e) => {
                 log::error!("Failed to start audio capture: {}", e);
                 state.recording.store(false, std::sync::atomic::Ordering::SeqCst);
+                // START answers with the error unless it gave up waiting
+                if opened.send(Err(e.to_string())).is_err() {
+                    state.raise(Fault {
+                        code: ERR_CAPTURE,
+                        message: format!("capture failed: {e}"),
+                    });
+                    state.publish();
+                }
                 return;
             }
+        };
+
+        let wav_path = state.record_wav.lock().unwrap().clone();
+        // a bad path loses the copy, not the recording
+        let mut wav = wav_path.as_ref().and_then(|path| {
+            WavRecorder::create(path)
+                .inspect(|_| log::info!("recording audio to {}", path.display()))
+                .inspect_err(|e| log::warn!("can't record audio to {}: {e}", path.display()))
+                .ok()
+        });
 
-            let mut last_transcribe = std::time::Instant::now();
-            let transcribe_interval = std::time::Duration::from_millis(TRANSCRIBE_INTERVAL_MS);
+        let mut last_transcribe = std::time::Instant::now();
+        let mut last_speech = std::time::Instant::now();
+        let mut capture_paused = false;
 
-            while state.recording.load(std::sync::atomic::Ordering::SeqCst) {
-                while let Some(samples) = capture.recv() {
-                    state.transcriber.push_audio(&samples);
+        while state.recording.load(std::sync::atomic::Ordering::SeqCst) {
+            let paused = state.paused.load(std::sync::atomic::Ordering::SeqCst);
+            if paused != capture_paused {
+                let result = if paused {
+                    capture.stop()
+                } else {
+                    capture.start()
+                };
+                if let Err(e) = result {
+                    log::warn!("Error pausing or resuming capture: {}", e);
                 }
+                capture_paused = paused;
+                // a pause isn't the speaker falling silent
+                last_speech = std::time::Instant::now();
+            }
+            if paused {
+                // keep the buffer and text as they are until RESUME
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                continue;
+            }
+
+            if capture.device_lost() {
+                // try the same device once more before giving up
+                let name = capture.device_name().or_else(|| device.clone());
+                log::warn!("audio device lost, reconnecting");
+                let _ = capture.stop();
 
-                if last_transcribe.elapsed() >= transcribe_interval {
-                    match state.transcriber.transcribe() {
-                        Ok(Some(text)) => {
-                            log::debug!("transcribed: {}", text);
-                        }
-                        Ok(None) => {
-                            // no change
-                        }
-                        Err(e) => {
-                            log::error!("Transcription error: {}", e);
-                        }
+                match state.open_capture(name.as_deref()) {
+                    Ok(c) => {
+                        log::info!("audio device reconnected");
+                        capture = c;
+                    }
+                    Err(e) => {
+                        log::error!("Failed to reconnect audio device: {}", e);
+                        state.raise(Fault {
+                            code: ERR_DEVICE_LOST,
+                            message: format!("device lost: {e}"),
+                        });
+                        state
+                            .recording
+                            .store(false, std::sync::atomic::Ordering::SeqCst);
+                        break;
                     }
-                    last_transcribe = std::time::Instant::now();
                 }
+            }
 
-                std::thread::sleep(std::time::Duration::from_millis(10));
+            while let Some(samples) = capture.try_recv() {
+                state.transcriber.push_audio(&samples);
+                if let Some(Err(e)) = wav.as_mut().map(|wav| wav.write(&samples)) {
+                    log::warn!("stopped recording audio to WAV: {e}");
+                    wav = None;
+                }
+                state
+                    .metrics
+                    .lock()
+                    .unwrap()
+                    .record_samples(samples.len(), std::time::Instant::now());
             }
 
-            if let Err(e) = capture.stop() {
-                log::warn!("Error stopping capture: {}", e);
+            // read each time round, so SET applies to this recording
+            let (transcribe_interval, silence_timeout) = {
+                let config = state.config.lock().unwrap();
+                (
+                    std::time::Duration::from_millis(config.transcribe_interval_ms),
+                    std::time::Duration::from_millis(config.silence_timeout_ms),
+                )
+            };
+            if last_transcribe.elapsed() >= transcribe_interval {
+                if !state.transcriber.has_new_audio() {
+                    // a pass would only repeat the last one
+                } else if state.transcriber.tail_has_speech() {
+                    last_speech = std::time::Instant::now();
+                    state.transcribe_tick();
+                } else {
+                    // skip inference while the speaker is pausing
+                    state.metrics.lock().unwrap().record_skip();
+                    if !silence_timeout.is_zero() && last_speech.elapsed() >= silence_timeout {
+                        log::info!("stopping recording after {silence_timeout:?} of silence");
+                        state.deliver(|outbox| outbox.stopped = true);
+                        state
+                            .recording
+                            .store(false, std::sync::atomic::Ordering::SeqCst);
+                    }
+                }
+                state.publish();
+                last_transcribe = std::time::Instant::now();
             }
 
-            log::debug!("worker thread exiting");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        if let Err(e) = capture.stop() {
+            log::warn!("Error stopping capture: {}", e);
+        }
+        *state.input_meter.lock().unwrap() = None;
+        if let Some(Err(e)) = wav.map(WavRecorder::finish) {
+            log::warn!("failed to finish WAV recording: {e}");
+        }
+        state.settle_text();
+        state.save_session_text(&state.text_tracker.lock().unwrap().full_text());
+
+        // let subscribers have the last words, and know recording has ended
+        state.publish();
+    }
+
+    /// Clean up after the worker panicked, so the next START works, and
+    /// report it like any other fault.
+    fn worker_panicked(&self, panic: &(dyn std::any::Any + Send)) {
+        let message = panic
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        log::error!("recording worker panicked: {message}");
+        *self.input_meter.lock().unwrap() = None;
+        // the pass died with the worker, so the watchdog has nothing to time
+        *self.inference_started.lock().unwrap() = None;
+        self.watchdog_fired
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+        self.raise(Fault {
+            code: ERR_WORKER_PANICKED,
+            message: format!("recording worker panicked: {message}"),
         });
+        self.unreported_panic
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+        self.recording
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+        self.publish();
+    }
 
-        *self.worker_thread.lock().unwrap() = Some(handle);
-        log::info!("recording started");
+    /// Open and start an audio source on the worker thread, and follow its level.
+    fn open_capture(
+        &self,
+        device: Option<&str>,
+    ) -> Result<Box<dyn AudioSource>, Box<dyn std::error::Error>> {
+        let capture = (self.open_audio)(device)?;
+        capture.start()?;
+        *self.input_meter.lock().unwrap() = capture.level_meter();
+        Ok(capture)
+    }
+
+    /// Run one transcription pass. A single failure is simply retried on the
+    /// next tick, but once `inference_error_threshold` passes fail in a row the
+    /// failure is reported to the client and, if configured, recording stops.
+    fn transcribe_tick(&self) {
+        let pass = self.ticks.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+
+        let started = std::time::Instant::now();
+        *self.inference_started.lock().unwrap() = Some((pass, started));
+        let result = self.transcriber.transcribe();
+        *self.inference_started.lock().unwrap() = None;
+        self.metrics.lock().unwrap().record_pass(started.elapsed());
+        if self
+            .watchdog_fired
+            .swap(false, std::sync::atomic::Ordering::SeqCst)
+        {
+            log::info!("transcription recovered after {:?}", started.elapsed());
+        }
+
+        match result {
+            Ok(Some(text)) => {
+                log::debug!("transcribed: {}", text);
+                self.inference_errors
+                    .store(0, std::sync::atomic::Ordering::SeqCst);
+            }
+            Ok(None) => {
+                // no change
+                self.inference_errors
+                    .store(0, std::sync::atomic::Ordering::SeqCst);
+            }
+            Err(e) => {
+                log::error!("Transcription error: {}", e);
+                let errors = self
+                    .inference_errors
+                    .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+                    + 1;
+                let threshold = self
+                    .inference_error_threshold
+                    .load(std::sync::atomic::Ordering::SeqCst);
+                if errors != threshold {
+                    return;
+                }
+
+                self.raise(Fault {
+                    code: ERR_INFERENCE,
+                    message: format!("{errors} consecutive inference failures: {e}"),
+                });
+
+                if self
+                    .stop_on_inference_error
+                    .load(std::sync::atomic::Ordering::SeqCst)
+                {
+                    log::error!("stopping recording after {errors} inference failures");
+                    self.recording
+                        .store(false, std::sync::atomic::Ordering::SeqCst);
+                }
+            }
+        }
+    }
+
+    /// Report `fault` on the next POLL (or event), and in STATUS until the
+    /// next START.
+    fn raise(&self, fault: Fault) {
+        *self.last_error.lock().unwrap() = Some(fault.code);
+        self.deliver(|outbox| outbox.fault = Some(fault.clone()));
+    }
+
+    /// Configure how many consecutive inference failures are tolerated before
+    /// the client is told, and whether recording stops at that point.
+    pub fn set_inference_error_policy(&self, threshold: u32, stop: bool) -> &'static str {
+        if threshold == 0 {
+            return "ERROR threshold must be at least 1";
+        }
+
+        self.inference_error_threshold
+            .store(threshold, std::sync::atomic::Ordering::SeqCst);
+        self.stop_on_inference_error
+            .store(stop, std::sync::atomic::Ordering::SeqCst);
+        "OK"
+    }
+
+    /// Configure how many transcribe intervals a pass may run before the
+    /// watchdog calls it stuck (0 turns the watchdog off), and whether it
+    /// then aborts the pass.
+    pub fn set_watchdog(&self, intervals: u32, abort: bool) {
+        self.watchdog_intervals
+            .store(intervals, std::sync::atomic::Ordering::SeqCst);
+        self.watchdog_abort
+            .store(abort, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    /// The worker's pass in progress, and how long it has been running, if
+    /// that is longer than the watchdog allows.
+    fn overdue_inference(&self) -> Option<(u32, std::time::Duration)> {
+        let intervals = self
+            .watchdog_intervals
+            .load(std::sync::atomic::Ordering::SeqCst);
+        let (pass, started) = (*self.inference_started.lock().unwrap())?;
+        if intervals == 0 {
+            return None;
+        }
+        let interval_ms = self.config.lock().unwrap().transcribe_interval_ms;
+        let limit = std::time::Duration::from_millis(interval_ms * u64::from(intervals));
+        let running = started.elapsed();
+        (running > limit).then_some((pass, running))
+    }
+
+    /// Warn about a transcription pass that has been running too long, and
+    /// abort it if configured to. Called from the main loop, as the worker
+    /// is the thread that is stuck; acts once per pass.
+    pub fn check_watchdog(&self) {
+        let Some((pass, running)) = self.overdue_inference() else {
+            return;
+        };
+        // holding this keeps the worker from starting another pass, which
+        // would take an abort meant for this one as its own
+        let in_progress = self.inference_started.lock().unwrap();
+        if !matches!(*in_progress, Some((current, _)) if current == pass) {
+            return;
+        }
+        if self
+            .watchdog_fired
+            .swap(true, std::sync::atomic::Ordering::SeqCst)
+        {
+            return;
+        }
+        log::warn!("transcription pass stuck for {running:?}");
+        if self
+            .watchdog_abort
+            .load(std::sync::atomic::Ordering::SeqCst)
+        {
+            log::warn!("aborting stuck transcription pass");
+            self.transcriber.abort();
+        }
+    }
+
+    /// Make sure the model recording needs is loaded: the configured one on
+    /// first use, or the one a reloaded config asked for. Returns the
+    /// response for a START that has to wait (or can't go ahead), or None
+    /// once it can.
+    fn prepare_model(self: &std::sync::Arc<Self>) -> Option<String> {
+        {
+            // the model being loaded would otherwise be swapped in mid-recording
+            let mut reload = self.reload.lock().unwrap();
+            match &*reload {
+                Reload::Loading(_) => return Some("LOADING".to_string()),
+                // say why once, then try again
+                Reload::Failed(e) if !self.transcriber.has_model() => {
+                    let response = format!("ERROR model failed to load: {e}");
+                    *reload = Reload::Idle;
+                    return Some(response);
+                }
+                _ => {}
+            }
+        }
+
+        let pending = self.pending_model.lock().unwrap().take();
+        let path = match pending {
+            Some(path) => path,
+            None if self.transcriber.has_model() => return None,
+            None => self.model_path.lock().unwrap().clone()?,
+        };
+        let response = self.reload_model(&path.to_string_lossy());
+        if !response.starts_with("ERROR") {
+            Some("LOADING".to_string())
+        } else if self.transcriber.has_model() {
+            log::error!("keeping the current model: {response}");
+            None
+        } else {
+            Some(response)
+        }
+    }
+
+    /// Start loading the model, if it isn't loaded, so the first START
+    /// needn't wait. Answers as START would, or `OK` if the model is ready.
+    pub fn preload(self: &std::sync::Arc<Self>) -> String {
+        if self.is_recording() {
+            return "OK".to_string();
+        }
+        self.prepare_model().unwrap_or_else(|| "OK".to_string())
+    }
+
+    /// Load the whisper model at `path` in place of the current one. It
+    /// loads in the background as for `reload_model`, so the response
+    /// carries the model's filename before it has loaded.
+    pub fn load_model(self: &std::sync::Arc<Self>, path: &str) -> String {
+        if self.recording.load(std::sync::atomic::Ordering::SeqCst) {
+            return "ERROR cannot change model while recording".to_string();
+        }
+        self.reload_model(path)
+    }
+
+    /// Load the whisper model at `path` in the background, swapping it in
+    /// once it has loaded so clients are served meanwhile. Recording can't
+    /// start until the load finishes, and a failed load keeps the old model.
+    /// Poll the outcome with `reload_status`.
+    pub fn reload_model(self: &std::sync::Arc<Self>, path: &str) -> String {
+        if self.recording.load(std::sync::atomic::Ordering::SeqCst) {
+            return "ERROR cannot reload model while recording".to_string();
+        }
+
+        let path = std::path::PathBuf::from(path);
+        if !path.is_file() {
+            return format!("ERROR model not found: {}", path.display());
+        }
+
+        let name = model_name(&path);
+        {
+            let mut reload = self.reload.lock().unwrap();
+            if matches!(*reload, Reload::Loading(_)) {
+                return "ERROR a reload is already in progress".to_string();
+            }
+            *reload = Reload::Loading(name.clone());
+        }
+
+        let state = std::sync::Arc::clone(self);
+        let loaded = name.clone();
+        std::thread::spawn(move || {
+            let outcome = match state.load_model_file_timed(&path) {
+                Ok(model) => {
+                    state.switch_model(model, path);
+                    Reload::Done(loaded)
+                }
+                Err(e) => {
+                    log::error!("reloading {} failed: {e}", path.display());
+                    Reload::Failed(e.to_string())
+                }
+            };
+            *state.reload.lock().unwrap() = outcome;
+        });
+
+        format!("OK loading {name}")
+    }
+
+    /// File name of the model in use, if it was loaded from a file.
+    pub fn model_name(&self) -> Option<String> {
+        self.model_path.lock().unwrap().as_deref().map(model_name)
+    }
+
+    /// Progress of the most recent RELOAD.
+    pub fn reload_status(&self) -> Reload {
+        self.reload.lock().unwrap().clone()
+    }
+
+    /// Load the model file at `path`, noting how long it took for METRICS.
+    fn load_model_file_timed(
+        &self,
+        path: &std::path::Path,
+    ) -> Result<Box<dyn Model>, Box<dyn std::error::Error>> {
+        let started = std::time::Instant::now();
+        let model = (self.load_model_file)(path)?;
+        *self.model_load_time.lock().unwrap() = Some(started.elapsed());
+        Ok(model)
+    }
+
+    fn switch_model(&self, model: Box<dyn Model>, path: std::path::PathBuf) {
+        self.transcriber.set_model(model);
+        log::info!("switched to model {}", path.display());
+        *self.model_path.lock().unwrap() = Some(path);
+    }
+
+    /// Load a model by name (e.g. `base.en`) from the models directory.
+    pub fn load_named_model(self: &std::sync::Arc<Self>, name: &str) -> String {
+        if !models::is_valid_name(name) {
+            return format!("ERROR invalid model name: {name}");
+        }
+
+        let path = models::resolve(name, &self.models_dir.lock().unwrap());
+        self.load_model(&path.to_string_lossy())
+    }
+
+    /// Set the directory used to look up models by name.
+    pub fn set_models_dir(&self, dir: &str) -> String {
+        let dir = std::path::PathBuf::from(dir);
+        if !dir.is_dir() {
+            return format!("ERROR not a directory: {}", dir.display());
+        }
+
+        log::info!("models directory set to {}", dir.display());
+        *self.models_dir.lock().unwrap() = dir;
+        "OK".to_string()
+    }
+
+    /// Record whether any client is subscribed to pushed events. While
+    /// nobody is, nothing is kept for them.
+    pub fn set_subscribed(&self, subscribed: bool) {
+        self.subscribed
+            .store(subscribed, std::sync::atomic::Ordering::SeqCst);
+        if !subscribed {
+            *self.event_outbox.lock().unwrap() = Outbox::default();
+        }
+    }
+
+    /// Woken whenever an event is queued for subscribers.
+    pub fn waker(&self) -> &Waker {
+        &self.waker
+    }
+
+    /// Take the events produced since the last call, oldest first.
+    pub fn take_events(&self) -> Vec<Poll> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+
+    /// Queue the latest changes for subscribers, if there are any, from
+    /// their own outbox, leaving POLL's alone. Once recording has ended,
+    /// that ends with the state it is left in. Only takes the events lock,
+    /// so a slow subscriber never holds up the worker.
+    fn publish(&self) {
+        if !self.subscribed.load(std::sync::atomic::Ordering::SeqCst) {
+            return;
+        }
+
+        let mut events = Vec::new();
+        let recording = self.is_recording();
+        if recording {
+            self.advance();
+        }
+        let mut outbox = self.event_outbox.lock().unwrap();
+        loop {
+            match outbox.take(recording) {
+                Poll::Recording(diff) if diff.backspaces == 0 && diff.new_text.is_empty() => break,
+                event @ (Poll::Recording(_) | Poll::Fault(_)) => events.push(event),
+                event => {
+                    events.push(event);
+                    break;
+                }
+            }
+        }
+        drop(outbox);
+
+        if !events.is_empty() {
+            self.events.lock().unwrap().extend(events);
+            self.waker.wake();
+        }
+    }
+
+    /// Hand `update` to every reader of the transcript: POLL, and the
+    /// subscribers if there are any.
+    fn deliver(&self, update: impl Fn(&mut Outbox)) {
+        update(&mut self.poll_outbox.lock().unwrap());
+        if self.subscribed.load(std::sync::atomic::Ordering::SeqCst) {
+            update(&mut self.event_outbox.lock().unwrap());
+        }
+    }
+
+    /// Hand `diff`, a change released by the pacer, to every reader.
+    fn deliver_diff(&self, diff: Option<DiffResult>, unit: BackspaceUnit) {
+        if let Some(diff) = diff {
+            self.deliver(|outbox| outbox.push(diff.clone(), unit));
+        }
+    }
+
+    /// Suspend capture and inference, keeping the transcript and buffered
+    /// audio so RESUME can carry on where it left off.
+    pub fn pause_recording(&self) -> &'static str {
+        if !self.recording.load(std::sync::atomic::Ordering::SeqCst) {
+            return "ERROR not recording";
+        }
+        if self.paused.swap(true, std::sync::atomic::Ordering::SeqCst) {
+            return "ERROR already paused";
+        }
+
+        log::info!("recording paused");
+        "OK"
+    }
+
+    /// Throw away the utterance in progress without ending the session: the
+    /// buffered audio is dropped and the next POLL erases the provisional text.
+    pub fn cancel(&self) -> &'static str {
+        if !self.recording.load(std::sync::atomic::Ordering::SeqCst) {
+            return "ERROR not recording";
+        }
+
+        let mut tracker = self.text_tracker.lock().unwrap();
+        self.transcriber.cancel();
+        tracker.cancel();
+        log::info!("utterance cancelled");
+        "OK"
+    }
+
+    /// Start a fresh dictation without ending the recording: the committed
+    /// text and buffered audio are forgotten, and the next POLL erases the
+    /// provisional text.
+    pub fn reset_session(&self) -> &'static str {
+        if !self.recording.load(std::sync::atomic::Ordering::SeqCst) {
+            return "ERROR not recording";
+        }
+
+        let mut tracker = self.text_tracker.lock().unwrap();
+        self.save_session_text(&tracker.full_text());
+        self.transcriber.reset();
+        tracker.restart();
+        // a fresh dictation gets fresh files, keeping the text so far
+        let dir = self
+            .session_log
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|log| log.text_path().parent().map(std::path::Path::to_path_buf));
+        self.open_session_log(dir.as_deref());
+        log::info!("session reset");
+        "OK"
+    }
+
+    pub fn resume_recording(&self) -> &'static str {
+        if !self.recording.load(std::sync::atomic::Ordering::SeqCst) {
+            return "ERROR not recording";
+        }
+        if !self.paused.swap(false, std::sync::atomic::Ordering::SeqCst) {
+            return "ERROR not paused";
+        }
+
+        log::info!("recording resumed");
         "OK"
     }
 
     pub fn stop_recording(&self) -> &'static str {
         if !self.recording.swap(false, std::sync::atomic::Ordering::SeqCst) {
+            // the worker has already stopped, but the client should hear why, once
+            if self
+                .unreported_panic
+                .swap(false, std::sync::atomic::Ordering::SeqCst)
+            {
+                return "ERROR ERR_WORKER_PANICKED recording worker panicked";
+            }
             return "ERROR not recording";
         }
+        self.paused
+            .store(false, std::sync::atomic::Ordering::SeqCst);
 
         if let Some(handle) = self.worker_thread.lock().unwrap().take() {
-            let _ = handle.join();
+            if handle.join().is_err() {
+                log::error!("recording worker exited with a panic");
+            }
         }
 
         log::info!("recording stopped");
         "OK"
     }
 
-    pub fn poll(&self) -> String {
-        if !self.recording.load(std::sync::atomic::Ordering::SeqCst) {
-            return "IDLE:".to_string();
+    pub fn is_recording(&self) -> bool {
+        self.recording.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Stop recording for shutdown and transcribe whatever audio is still
+    /// buffered. Returns the full transcript, or None if not recording.
+    pub fn finish_recording(&self) -> Option<String> {
+        if !self.is_recording() || self.stop_recording() != "OK" {
+            return None;
+        }
+
+        let mut tracker = self.text_tracker.lock().unwrap();
+        match self.transcriber.transcribe() {
+            Ok(Some(transcript)) => {
+                let diff = tracker.update(&transcript);
+                self.tracked(diff, &tracker);
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!("final transcription failed: {e}"),
+        }
+        let diff = tracker.flush();
+        self.tracked(diff, &tracker);
+        Some(tracker.full_text())
+    }
+
+    pub fn poll(&self) -> Poll {
+        let recording = self.is_recording();
+        if recording {
+            self.advance();
+        }
+        self.poll_outbox.lock().unwrap().take(recording)
+    }
+
+    /// Catch the text up with the latest pass, and hand what the pacer
+    /// releases to every reader.
+    fn advance(&self) {
+        // hold everything back until warmup is over, then send it as one append
+        if self.warming_up() {
+            return;
         }
 
         let new_transcript = self.transcriber.current_transcript();
         let mut tracker = self.text_tracker.lock().unwrap();
 
-        match tracker.update(&new_transcript) {
-            Some(result) => format!("RECORDING:{}:{}", result.backspaces, result.new_text),
-            None => "RECORDING:0:".to_string(),
+        // only a new transcription pass counts towards the stability window
+        let pass = self.transcriber.passes();
+        let diff = if self.polled_pass.lock().unwrap().replace(pass) == Some(pass) {
+            tracker.revisit(&new_transcript)
+        } else {
+            tracker.update(&new_transcript)
+        };
+        let diff = self.tracked(diff, &tracker);
+
+        let mut pacer = self.pacer.lock().unwrap();
+        let released = pacer.next(diff);
+        self.deliver_diff(released, pacer.backspace_unit());
+    }
+
+    /// Note an update of `tracker`, which produced `diff`, in the session's
+    /// metrics and log.
+    fn tracked(&self, diff: Option<DiffResult>, tracker: &TextTracker) -> Option<DiffResult> {
+        if let Some(diff) = &diff {
+            self.metrics.lock().unwrap().record_update(diff.backspaces);
+            self.log_update(diff, &tracker.full_text());
+        }
+        diff
+    }
+
+    /// Once recording has ended, catch the text up with the last pass and
+    /// release what the stability window and pacer are holding back, for
+    /// POLL and subscribers to deliver. Otherwise the last words would never
+    /// be typed.
+    fn settle_text(&self) {
+        let mut tracker = self.text_tracker.lock().unwrap();
+        let mut diffs = Vec::new();
+        let pass = self.transcriber.passes();
+        if self.polled_pass.lock().unwrap().replace(pass) != Some(pass) {
+            let diff = tracker.update(&self.transcriber.current_transcript());
+            diffs.extend(self.tracked(diff, &tracker));
+        }
+        let diff = tracker.flush();
+        diffs.extend(self.tracked(diff, &tracker));
+        let mut pacer = self.pacer.lock().unwrap();
+        let released = pacer.finish(diffs);
+        self.deliver_diff(released, pacer.backspace_unit());
+    }
+
+    /// Select the input device for future recordings by (partial) name, or
+    /// `default` for the system default.
+    pub fn set_device(&self, name: &str) -> String {
+        if name.eq_ignore_ascii_case("default") {
+            *self.device.lock().unwrap() = None;
+            return "OK".to_string();
+        }
+
+        match audio::resolve_input_device(name) {
+            Ok(device) => {
+                log::info!("input device set to {device}");
+                *self.device.lock().unwrap() = Some(device.clone());
+                format!("OK {device}")
+            }
+            Err(e) => format!("ERROR {e}"),
+        }
+    }
+
+    /// Also write the audio of recordings from the next START on to a WAV
+    /// file at `path`, an absolute path, or stop with `off`.
+    pub fn set_record_wav(&self, path: &str) -> String {
+        if path.eq_ignore_ascii_case("off") {
+            *self.record_wav.lock().unwrap() = None;
+            return "OK".to_string();
         }
+
+        let path = std::path::Path::new(path);
+        if !path.is_absolute() {
+            return "ERROR path must be absolute".to_string();
+        }
+        if !path.parent().is_some_and(std::path::Path::is_dir) {
+            return format!("ERROR no such directory: {}", path.display());
+        }
+        *self.record_wav.lock().unwrap() = Some(path.to_path_buf());
+        "OK".to_string()
+    }
+
+    /// Start keeping the session's transcript in `dir`, or with `None`,
+    /// stop. A directory that can't be written to loses the copy, not the
+    /// recording.
+    fn open_session_log(&self, dir: Option<&std::path::Path>) {
+        let log = dir.and_then(|dir| {
+            SessionLog::create(dir, std::time::SystemTime::now())
+                .inspect(|log| log::info!("saving transcript to {}", log.text_path().display()))
+                .inspect_err(|e| log::warn!("can't save transcript in {}: {e}", dir.display()))
+                .ok()
+        });
+        *self.session_log.lock().unwrap() = log;
+    }
+
+    /// Add a tracker update, which left the text as `text`, to the session log.
+    fn log_update(&self, diff: &DiffResult, text: &str) {
+        let mut session_log = self.session_log.lock().unwrap();
+        if let Some(Err(e)) = session_log.as_mut().map(|log| log.record(diff, text)) {
+            log::warn!("stopped saving the transcript: {e}");
+            *session_log = None;
+        }
+    }
+
+    /// Write the session's final text to its log, and flush it to disk.
+    fn save_session_text(&self, text: &str) {
+        if let Some(Err(e)) = self
+            .session_log
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|log| log.save_text(text))
+        {
+            log::warn!("failed to save the transcript: {e}");
+        }
+    }
+
+    /// The files in `session_log_dir`, oldest first.
+    pub fn session_files(&self) -> Result<Vec<String>, String> {
+        let dir = self.config.lock().unwrap().session_log_dir.clone();
+        let dir = dir.ok_or("session_log_dir is not set")?;
+        sessionlog::list(&dir).map_err(|e| format!("can't list {}: {e}", dir.display()))
+    }
+
+    /// Set the RMS level below which audio is treated as silence and not
+    /// transcribed. Zero transcribes everything.
+    pub fn set_vad_threshold(&self, threshold: &str) -> String {
+        match threshold.parse::<f32>() {
+            Ok(threshold) if (0.0..=1.0).contains(&threshold) => {
+                self.transcriber.set_vad_threshold(threshold);
+                "OK".to_string()
+            }
+            _ => "ERROR usage: VAD <rms threshold between 0 and 1>".to_string(),
+        }
+    }
+
+    /// Set the no-speech probability above which a transcribed segment
+    /// whisper was unsure of is dropped as a likely hallucination. One keeps
+    /// every segment.
+    pub fn set_no_speech_threshold(&self, threshold: &str) -> String {
+        match threshold.parse::<f32>() {
+            Ok(threshold) if (0.0..=1.0).contains(&threshold) => {
+                self.transcriber.set_no_speech_threshold(threshold);
+                "OK".to_string()
+            }
+            _ => "ERROR usage: NO_SPEECH <probability between 0 and 1>".to_string(),
+        }
+    }
+
+    /// Add a whole-word substitution to the post-processing in effect.
+    pub fn add_substitution(&self, from: &str, to: &str) -> String {
+        if from.trim().is_empty() {
+            return "ERROR substitution needs a word to replace".to_string();
+        }
+
+        let mut tracker = self.text_tracker.lock().unwrap();
+        let mut profile = tracker.profile().clone();
+        profile.add_substitution(from.trim(), to.trim());
+        tracker.set_profile(profile);
+        "OK".to_string()
+    }
+
+    /// Choose the clean-up applied after substitutions, e.g. capitalizing
+    /// sentence starts. Like substitutions, it is saved with the profile.
+    pub fn set_post_process(&self, post_process: &str) -> String {
+        match post_process.parse::<PostProcess>() {
+            Ok(post_process) => {
+                self.text_tracker
+                    .lock()
+                    .unwrap()
+                    .set_post_process(post_process);
+                "OK".to_string()
+            }
+            Err(e) => format!("ERROR {e}"),
+        }
+    }
+
+    /// Collapse runs of at least `min_run` of the same word, as whisper
+    /// produces when it loops, or with `off`, leave them be. Like
+    /// substitutions, it is saved with the profile.
+    pub fn set_collapse_repeats(&self, min_run: &str) -> String {
+        let min_run = match min_run.trim() {
+            "off" => None,
+            n => match n.parse::<usize>() {
+                Ok(n) if n >= 2 => Some(n),
+                _ => return "ERROR usage: REPEATS <words of 2 or more|off>".to_string(),
+            },
+        };
+
+        let mut tracker = self.text_tracker.lock().unwrap();
+        let mut profile = tracker.profile().clone();
+        profile.set_collapse_repeats(min_run);
+        tracker.set_profile(profile);
+        "OK".to_string()
+    }
+
+    /// Save the post-processing in effect under `name`.
+    pub fn save_profile(&self, name: &str) -> String {
+        if !postprocess::is_valid_name(name) {
+            return format!("ERROR invalid profile name: {name}");
+        }
+
+        let profile = self.text_tracker.lock().unwrap().profile().clone();
+        self.profiles
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), profile);
+        *self.active_profile.lock().unwrap() = name.to_string();
+        "OK".to_string()
+    }
+
+    /// Switch the whole post-processing configuration to a saved profile.
+    /// While recording, the next POLL rewrites the provisional text to match.
+    pub fn set_profile(&self, name: &str) -> String {
+        let profile = match self.profiles.lock().unwrap().get(name) {
+            Some(profile) => profile.clone(),
+            None => return format!("ERROR unknown profile: {name}"),
+        };
+
+        self.text_tracker.lock().unwrap().set_profile(profile);
+        *self.active_profile.lock().unwrap() = name.to_string();
+        log::info!("post-processing profile set to {name}");
+        "OK".to_string()
+    }
+
+    /// The active profile name and all saved profile names, sorted.
+    pub fn profiles(&self) -> (String, Vec<String>) {
+        let active = self.active_profile.lock().unwrap().clone();
+        let names = self.profiles.lock().unwrap().keys().cloned().collect();
+        (active, names)
+    }
+
+    /// Set how long output is suppressed at the start of each session.
+    pub fn set_warmup(&self, warmup: &str) -> String {
+        match warmup.parse() {
+            Ok(warmup) => {
+                *self.warmup.lock().unwrap() = warmup;
+                "OK".to_string()
+            }
+            Err(e) => format!("ERROR {e}"),
+        }
+    }
+
+    fn warming_up(&self) -> bool {
+        match *self.warmup.lock().unwrap() {
+            Warmup::Off => false,
+            Warmup::Ticks(ticks) => self.ticks.load(std::sync::atomic::Ordering::SeqCst) <= ticks,
+            Warmup::Duration(duration) => self
+                .started_at
+                .lock()
+                .unwrap()
+                .is_some_and(|started_at| started_at.elapsed() < duration),
+        }
+    }
+
+    /// The text delivered to clients so far in the current (or last) session,
+    /// i.e. what applying every POLL response in order produces. With
+    /// `committed_only`, text that may still be revised is left out.
+    pub fn transcript(&self, committed_only: bool) -> String {
+        let tracker = self.text_tracker.lock().unwrap();
+        let text = if committed_only {
+            tracker.committed().to_string()
+        } else {
+            tracker.full_text()
+        };
+
+        // leave out anything the pacer is still holding back
+        let delivered =
+            tracker.full_text().chars().count() - self.pacer.lock().unwrap().pending_chars();
+        text.chars().take(delivered).collect()
+    }
+
+    /// The committed and provisional text, read together so they agree.
+    pub fn peek(&self) -> (String, String) {
+        let tracker = self.text_tracker.lock().unwrap();
+        (
+            tracker.committed().to_string(),
+            tracker.provisional().to_string(),
+        )
+    }
+
+    /// The transcribed segments, timed from the start of the recording.
+    /// With `final_only`, segments that may still be revised are left out.
+    pub fn segments(&self, final_only: bool) -> Vec<whisper::Segment> {
+        self.transcriber.segments(final_only)
+    }
+
+    /// Limit how many characters each POLL appends; larger appends are
+    /// spread over successive polls. `None` delivers them all at once.
+    pub fn set_chunk_size(&self, max_chars: Option<usize>) {
+        self.pacer.lock().unwrap().set_max_chars(max_chars);
+    }
+
+    /// Set how many characters must match before text is treated as aged
+    /// out of the buffer rather than revised.
+    pub fn set_min_match_len(&self, min_match_len: usize) -> String {
+        match self
+            .text_tracker
+            .lock()
+            .unwrap()
+            .set_min_match_len(min_match_len)
+        {
+            Ok(()) => "OK".to_string(),
+            Err(e) => format!("ERROR {e}"),
+        }
+    }
+
+    /// The value of a runtime setting.
+    pub fn get_config(&self, key: &str) -> String {
+        match self.config.lock().unwrap().get(key) {
+            Ok(value) => format!("{key}={value}"),
+            Err(e) => format!("ERROR {e}"),
+        }
+    }
+
+    /// Change a runtime setting. The transcription interval, silence timeout
+    /// and idle shutdown apply immediately; the buffer duration and language
+    /// from the next START.
+    pub fn set_config(&self, key: &str, value: &str) -> String {
+        match self.config.lock().unwrap().set(key, value) {
+            Ok(()) => {
+                log::info!("{key} set to {value}");
+                "OK".to_string()
+            }
+            Err(e) => format!("ERROR {e}"),
+        }
+    }
+
+    /// How long the daemon may sit idle before exiting, if it should at all.
+    pub fn idle_shutdown(&self) -> Option<std::time::Duration> {
+        match self.config.lock().unwrap().idle_shutdown_minutes {
+            0 => None,
+            minutes => Some(std::time::Duration::from_secs(minutes * 60)),
+        }
+    }
+
+    /// Take on settings re-read from the config file. They apply as SET
+    /// would, the language to a recording in progress too, except that a
+    /// different model waits for the next START.
+    pub fn apply_config(&self, config: Config) {
+        let mut current = self.config.lock().unwrap();
+        if config.language != current.language {
+            self.transcriber.set_language(&config.language);
+        }
+        if config.model != current.model {
+            let path = config
+                .model
+                .clone()
+                .unwrap_or_else(models::default_model_path);
+            log::info!("switching to {} at the next START", path.display());
+            *self.pending_model.lock().unwrap() = Some(path);
+        }
+        *current = config;
+    }
+
+    /// Resize the rolling buffer to `secs` seconds of audio right away. Only
+    /// allowed between recordings, as resizing drops audio mid-utterance.
+    pub fn set_buffer_duration(&self, secs: &str) -> String {
+        if self.recording.load(std::sync::atomic::Ordering::SeqCst) {
+            return "ERROR cannot change buffer while recording".to_string();
+        }
+
+        let mut config = self.config.lock().unwrap();
+        if let Err(e) = config.set("buffer_duration_secs", secs) {
+            return format!("ERROR {e}");
+        }
+        self.transcriber
+            .set_buffer_duration(std::time::Duration::from_secs(config.buffer_duration_secs));
+        log::info!("buffer duration set to {}s", config.buffer_duration_secs);
+        "OK".to_string()
+    }
+
+    /// Switch the transcription language, from the next pass of a recording
+    /// in progress as well as for later ones.
+    pub fn set_language(&self, language: &str) -> String {
+        let mut config = self.config.lock().unwrap();
+        if let Err(e) = config.set("language", language) {
+            return format!("ERROR {e}");
+        }
+        self.transcriber.set_language(language);
+        log::info!("language set to {language}");
+        "OK".to_string()
+    }
+
+    /// Turn translation to English on or off, from the next pass.
+    pub fn set_translate(&self, translate: bool) -> String {
+        self.transcriber.set_translate(translate);
+        log::info!("translation {}", if translate { "on" } else { "off" });
+        "OK".to_string()
+    }
+
+    /// Prime whisper with names and jargon to expect, from the next pass.
+    pub fn set_initial_prompt(&self, prompt: &str) -> String {
+        if self.transcriber.set_initial_prompt(prompt) {
+            return format!("OK prompt cut to {} characters", whisper::MAX_PROMPT_CHARS);
+        }
+        log::info!(
+            "initial prompt {}",
+            if prompt.is_empty() { "cleared" } else { "set" }
+        );
+        "OK".to_string()
+    }
+
+    /// Set whether a backspace counts as one char or one grapheme cluster,
+    /// to match how the client's terminal erases emoji and accents.
+    pub fn set_backspace_unit(&self, unit: &str) -> String {
+        match unit.parse::<BackspaceUnit>() {
+            Ok(unit) => {
+                self.text_tracker.lock().unwrap().set_backspace_unit(unit);
+                self.pacer.lock().unwrap().set_backspace_unit(unit);
+                "OK".to_string()
+            }
+            Err(e) => format!("ERROR {e}"),
+        }
+    }
+
+    /// Hold back text until it has stayed the same for `updates` transcription
+    /// passes, so a tail Whisper keeps revising isn't typed and erased.
+    /// Repeated POLLs within a pass don't count.
+    pub fn set_stability(&self, updates: u8) {
+        self.text_tracker.lock().unwrap().set_stability(updates);
+    }
+
+    /// Set whether revisions are backspaced by character or by whole word.
+    pub fn set_diff_mode(&self, mode: &str) -> String {
+        match mode.parse::<DiffMode>() {
+            Ok(mode) => {
+                self.text_tracker.lock().unwrap().set_diff_mode(mode);
+                "OK".to_string()
+            }
+            Err(e) => format!("ERROR {e}"),
+        }
+    }
+
+    /// The smoothed microphone level from 0.0 to 1.0, or zero when not recording.
+    pub fn input_level(&self) -> f32 {
+        if !self.recording.load(std::sync::atomic::Ordering::SeqCst) {
+            return 0.0;
+        }
+        self.input_meter
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0.0, LevelMeter::level)
+    }
+
+    pub fn status(&self) -> Status {
+        let recording = self.recording.load(std::sync::atomic::Ordering::SeqCst);
+        let elapsed = match *self.started_at.lock().unwrap() {
+            Some(started_at) if recording => started_at.elapsed(),
+            _ => std::time::Duration::ZERO,
+        };
+        // a recording keeps what START set up, whatever has changed since
+        let (language, buffer_secs) = if recording {
+            (
+                self.transcriber.language(),
+                self.transcriber.buffer_duration().as_secs(),
+            )
+        } else {
+            let config = self.config.lock().unwrap();
+            (config.language.clone(), config.buffer_duration_secs)
+        };
+        let (committed_chars, provisional_chars) = {
+            let tracker = self.text_tracker.lock().unwrap();
+            (
+                tracker.committed().chars().count(),
+                tracker.provisional().chars().count(),
+            )
+        };
+
+        Status {
+            recording,
+            paused: recording && self.paused.load(std::sync::atomic::Ordering::SeqCst),
+            elapsed,
+            buffered_samples: self.transcriber.buffered_samples(),
+            committed_chars,
+            provisional_chars,
+            error: *self.last_error.lock().unwrap(),
+            degraded: self.overdue_inference().is_some(),
+            language,
+            model: self.model_name(),
+            buffer_secs,
+        }
+    }
+
+    /// Recent inference times with the current model, as `key=value`
+    /// pairs for STATS, and whether they are too slow for the transcription
+    /// interval, in which case a smaller model would keep up better.
+    pub fn stats(&self) -> Vec<(&'static str, String)> {
+        let stats = self.transcriber.inference_stats();
+        let interval_ms = self.config.lock().unwrap().transcribe_interval_ms;
+        let ms = |took: std::time::Duration| format!("{:.1}", took.as_secs_f64() * 1000.0);
+        let behind = stats.avg > std::time::Duration::from_millis(interval_ms);
+        vec![
+            ("calls", stats.calls.to_string()),
+            ("min_ms", ms(stats.min)),
+            ("avg_ms", ms(stats.avg)),
+            ("max_ms", ms(stats.max)),
+            ("interval_ms", interval_ms.to_string()),
+            ("behind", if behind { "yes" } else { "no" }.to_string()),
+        ]
+    }
+
+    /// Statistics for the current session, or the last one once stopped.
+    pub fn metrics(&self) -> Vec<(&'static str, String)> {
+        let mut report = self
+            .metrics
+            .lock()
+            .unwrap()
+            .report(std::time::Instant::now());
+        // what loading the model when it was first needed cost, if it has loaded
+        let load_time = self.model_load_time.lock().unwrap().unwrap_or_default();
+        report.push((
+            "model_load_ms",
+            format!("{:.1}", load_time.as_secs_f64() * 1000.0),
+        ));
+        report
+    }
+
+    pub fn waveform(&self, bins: usize) -> Result<Vec<f32>, String> {
+        if bins == 0 || bins > MAX_WAVEFORM_BINS {
+            return Err(format!("bins must be between 1 and {MAX_WAVEFORM_BINS}"));
+        }
+
+        Ok(self.transcriber.waveform(bins))
+    }
+
+    pub fn set_overlap_policy(&self, policy: &str) -> String {
+        match policy.parse() {
+            Ok(policy) => {
+                self.transcriber.set_overlap_policy(policy);
+                "OK".to_string()
+            }
+            Err(e) => format!("ERROR {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_start_twice_is_rejected() {
+        let (state, _) = testing::state();
+
+        assert_eq!(state.start_recording(StartOptions::default()), "OK");
+        assert_eq!(
+            state.start_recording(StartOptions::default()),
+            "ERROR already recording"
+        );
+        assert_eq!(state.stop_recording(), "OK");
+        assert_eq!(state.stop_recording(), "ERROR not recording");
+    }
+
+    #[test]
+    fn test_peek_splits_committed_and_provisional() {
+        let (state, _) = testing::state();
+        assert_eq!(state.peek(), (String::new(), String::new()));
+
+        for transcript in [
+            "once upon a time there was",
+            "a time there was a king",
+            "a time there was a king who",
+        ] {
+            state.text_tracker.lock().unwrap().update(transcript);
+            let tracker = state.text_tracker.lock().unwrap();
+            let expected = (
+                tracker.committed().to_string(),
+                tracker.provisional().to_string(),
+            );
+            drop(tracker);
+            assert_eq!(state.peek(), expected);
+        }
+        assert_eq!(
+            state.peek(),
+            (
+                "once upon ".to_string(),
+                "a time there was a king who".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_metrics_kept_after_stop_and_reset_on_start() {
+        let (state, model) = testing::state();
+        let passes = |state: &DaemonState| {
+            state
+                .metrics()
+                .into_iter()
+                .find(|(key, _)| *key == "passes")
+                .unwrap()
+                .1
+        };
+
+        assert_eq!(state.start_recording(StartOptions::default()), "OK");
+        for text in ["hello", "help"] {
+            model.set_text(text);
+            state.transcriber.push_audio(&[0.0; 160]);
+            state.transcribe_tick();
+            state.poll();
+        }
+        assert_eq!(state.stop_recording(), "OK");
+
+        let metrics = testing::command(&state, "METRICS");
+        assert!(metrics.starts_with("passes=2 skipped="), "{metrics}");
+        assert!(metrics.contains(" updates=2 backspaces=2 "), "{metrics}");
+
+        assert_eq!(state.start_recording(StartOptions::default()), "OK");
+        assert_eq!(passes(&state), "0");
+        assert_eq!(state.stop_recording(), "OK");
+    }
+
+    #[test]
+    fn test_cancel_erases_provisional_text() {
+        let (state, model) = testing::state();
+        assert_eq!(testing::command(&state, "CANCEL"), "ERROR not recording");
+        assert_eq!(testing::command(&state, "START"), "OK");
+
+        let mut terminal = String::new();
+        for transcript in ["once upon a time there was", "a time there was a king"] {
+            model.set_text(transcript);
+            testing::transcribe(&state);
+            testing::apply_poll(&mut terminal, &testing::command(&state, "POLL"));
+        }
+        let (committed, provisional) = state.peek();
+        assert_eq!(committed, "once upon ");
+
+        assert_eq!(testing::command(&state, "CANCEL"), "OK");
+        assert_eq!(state.transcriber.buffered_samples(), 0);
+        let response = testing::command(&state, "POLL");
+        assert_eq!(
+            response,
+            format!("RECORDING:{}:", provisional.chars().count())
+        );
+        testing::apply_poll(&mut terminal, &response);
+        assert_eq!(terminal, "once upon ");
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:");
+
+        // dictation carries on after the committed text
+        model.set_text("the end");
+        testing::transcribe(&state);
+        let response = testing::command(&state, "POLL");
+        testing::apply_poll(&mut terminal, &response);
+        assert_eq!(terminal, "once upon the end");
+        assert_eq!(state.stop_recording(), "OK");
+    }
+
+    #[test]
+    fn test_reset_starts_fresh_dictation() {
+        let (state, model) = testing::state();
+        assert_eq!(testing::command(&state, "RESET"), "ERROR not recording");
+        assert_eq!(testing::command(&state, "START"), "OK");
+
+        let mut terminal = String::new();
+        for transcript in ["once upon a time there was", "a time there was a king"] {
+            model.set_text(transcript);
+            testing::transcribe(&state);
+            testing::apply_poll(&mut terminal, &testing::command(&state, "POLL"));
+        }
+        let (committed, provisional) = state.peek();
+        assert_eq!(committed, "once upon ");
+
+        assert_eq!(testing::command(&state, "RESET"), "OK");
+        assert!(state.is_recording());
+        assert_eq!(state.transcriber.buffered_samples(), 0);
+        model.set_text("");
+        let response = testing::command(&state, "POLL");
+        assert_eq!(
+            response,
+            format!("RECORDING:{}:", provisional.chars().count())
+        );
+        testing::apply_poll(&mut terminal, &response);
+        assert_eq!(terminal, "once upon ");
+        assert_eq!(state.text_tracker.lock().unwrap().full_text(), "");
+
+        // what follows is all the new dictation holds
+        model.set_text("the end");
+        testing::transcribe(&state);
+        testing::apply_poll(&mut terminal, &testing::command(&state, "POLL"));
+        assert_eq!(terminal, "once upon the end");
+        assert_eq!(state.text_tracker.lock().unwrap().full_text(), "the end");
+        assert_eq!(state.stop_recording(), "OK");
+    }
+
+    #[test]
+    fn test_reset_discards_pass_in_flight() {
+        let (state, model) = testing::state();
+        assert_eq!(testing::command(&state, "START"), "OK");
+
+        model.set_text("the old dictation");
+        model.set_delay(std::time::Duration::from_millis(300));
+        let pass = {
+            let state = std::sync::Arc::clone(&state);
+            std::thread::spawn(move || testing::transcribe(&state))
+        };
+        wait_until(|| model.calls() > 0);
+        assert_eq!(testing::command(&state, "RESET"), "OK");
+        pass.join().unwrap();
+
+        // the pass finished after the reset, so its text is gone with it
+        assert_eq!(state.transcriber.current_transcript(), "");
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:");
+
+        model.set_delay(std::time::Duration::ZERO);
+        model.set_text("the new one");
+        testing::transcribe(&state);
+        let mut terminal = String::new();
+        testing::apply_poll(&mut terminal, &testing::command(&state, "POLL"));
+        assert_eq!(terminal, "the new one");
+        assert_eq!(state.stop_recording(), "OK");
+    }
+
+    #[test]
+    fn test_buffer_resized_only_when_idle() {
+        let (state, _) = testing::state();
+
+        assert_eq!(
+            testing::command(&state, "BUFFER 1"),
+            "ERROR buffer_duration_secs must be between 2 and 30"
+        );
+        assert_eq!(testing::command(&state, "BUFFER 2"), "OK");
+        assert_eq!(
+            state.get_config("buffer_duration_secs"),
+            "buffer_duration_secs=2"
+        );
+        state
+            .transcriber
+            .push_audio(&vec![0.0; 3 * whisper::SAMPLE_RATE]);
+        assert_eq!(
+            state.transcriber.buffered_samples(),
+            2 * whisper::SAMPLE_RATE
+        );
+
+        assert_eq!(testing::command(&state, "START"), "OK");
+        assert_eq!(
+            testing::command(&state, "BUFFER 20"),
+            "ERROR cannot change buffer while recording"
+        );
+        assert_eq!(
+            state.get_config("buffer_duration_secs"),
+            "buffer_duration_secs=2"
+        );
+        assert_eq!(state.stop_recording(), "OK");
+    }
+
+    #[test]
+    fn test_language_switched_mid_recording() {
+        let (state, model) = testing::state();
+        assert_eq!(testing::command(&state, "START"), "OK");
+        testing::transcribe(&state);
+        assert_eq!(model.last_language().as_deref(), Some("en"));
+
+        assert_eq!(testing::command(&state, "LANG de"), "OK");
+        testing::transcribe(&state);
+        assert_eq!(model.last_language().as_deref(), Some("de"));
+        assert_eq!(state.get_config("language"), "language=de");
+
+        assert_eq!(
+            testing::command(&state, "LANG xx"),
+            "ERROR unknown language: xx"
+        );
+        assert_eq!(testing::command(&state, "LANG auto"), "OK");
+        testing::transcribe(&state);
+        assert_eq!(model.last_language().as_deref(), Some("auto"));
+        assert_eq!(testing::command(&state, "LANG"), "ERROR usage: LANG <code>");
+        assert_eq!(state.stop_recording(), "OK");
+    }
+
+    #[test]
+    fn test_translate_composes_with_language() {
+        let (state, model) = testing::state();
+        assert_eq!(testing::command(&state, "LANG es"), "OK");
+        assert_eq!(testing::command(&state, "TRANSLATE on"), "OK");
+        assert_eq!(testing::command(&state, "START"), "OK");
+        testing::transcribe(&state);
+        assert_eq!(
+            model.last_decode(),
+            Some(whisper::Decode {
+                language: "es".to_string(),
+                translate: true,
+                initial_prompt: String::new(),
+            })
+        );
+
+        assert_eq!(testing::command(&state, "TRANSLATE OFF"), "OK");
+        testing::transcribe(&state);
+        assert!(!model.last_decode().unwrap().translate);
+        assert_eq!(
+            testing::command(&state, "TRANSLATE maybe"),
+            "ERROR usage: TRANSLATE <on|off>"
+        );
+        assert_eq!(state.stop_recording(), "OK");
+    }
+
+    #[test]
+    fn test_prompt_applies_to_next_pass() {
+        let (state, model) = testing::state();
+        assert_eq!(testing::command(&state, "START"), "OK");
+        assert_eq!(
+            testing::command(&state, "PROMPT Yowl, kitty, whisper.cpp"),
+            "OK"
+        );
+        testing::transcribe(&state);
+        assert_eq!(
+            model.last_decode().unwrap().initial_prompt,
+            "Yowl, kitty, whisper.cpp"
+        );
+
+        let long = format!("PROMPT Early {}Latest", "glossary ".repeat(100));
+        assert_eq!(
+            testing::command(&state, &long),
+            format!("OK prompt cut to {} characters", whisper::MAX_PROMPT_CHARS)
+        );
+        testing::transcribe(&state);
+        let prompt = model.last_decode().unwrap().initial_prompt;
+        // whisper reads the end of a prompt, so that is what is kept
+        assert!(prompt.starts_with("glossary glossary"));
+        assert!(prompt.ends_with("glossary Latest"));
+        assert!(prompt.chars().count() <= whisper::MAX_PROMPT_CHARS);
+
+        assert_eq!(testing::command(&state, "PROMPT"), "OK");
+        testing::transcribe(&state);
+        assert_eq!(model.last_decode().unwrap().initial_prompt, "");
+        assert_eq!(state.stop_recording(), "OK");
+    }
+
+    #[test]
+    fn test_poll_diffs_reconstruct_transcript() {
+        let (state, model) = testing::state();
+        assert_eq!(testing::command(&state, "POLL"), "IDLE:");
+        assert_eq!(testing::command(&state, "START"), "OK");
+
+        let transcripts = [
+            "Hello wor",
+            "Hello world.",
+            "Hello, world.",
+            "Hello, world. How are you",
+        ];
+
+        let mut terminal = String::new();
+        for transcript in transcripts {
+            model.set_text(transcript);
+            state.transcriber.push_audio(&[0.0; 160]);
+            state
+                .transcriber
+                .transcribe()
+                .expect("Transcription failed");
+
+            let response = testing::command(&state, "POLL");
+            testing::apply_poll(&mut terminal, &response);
+            assert_eq!(terminal, transcript);
+        }
+
+        // nothing changed since the last poll
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:");
+
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+        assert_eq!(testing::command(&state, "POLL"), "IDLE:");
+    }
+
+    #[test]
+    fn test_single_inference_error_is_retried() {
+        let (state, model) = testing::state();
+        assert_eq!(testing::command(&state, "START"), "OK");
+        state.transcriber.push_audio(&[0.0; 160]);
+
+        model.set_error(Some("ggml hiccup"));
+        state.transcribe_tick();
+        state.transcribe_tick();
+        model.set_error(None);
+        model.set_text("Hello");
+        state.transcribe_tick();
+        model.set_error(Some("ggml hiccup"));
+        state.transcribe_tick();
+        state.transcribe_tick();
+
+        // never three in a row, so nothing is surfaced
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:Hello");
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_watchdog_flags_stuck_pass() {
+        let (state, model) = testing::state();
+        assert_eq!(
+            testing::command(&state, "SET transcribe_interval_ms 100"),
+            "OK"
+        );
+        assert_eq!(testing::command(&state, "WATCHDOG 2"), "OK");
+        model.set_delay(std::time::Duration::from_millis(600));
+        let stuck_pass = |state: &std::sync::Arc<DaemonState>| {
+            state.transcriber.push_audio(&[0.0; 160]);
+            let state = std::sync::Arc::clone(state);
+            std::thread::spawn(move || state.transcribe_tick())
+        };
+
+        // a slow pass is flagged, but left to finish
+        let pass = stuck_pass(&state);
+        wait_until(|| state.status().degraded);
+        state.check_watchdog();
+        assert!(testing::command(&state, "STATUS").ends_with(" degraded=yes"));
+        pass.join().unwrap();
+        assert!(
+            !state.status().degraded,
+            "should recover once the pass ends"
+        );
+        assert!(!testing::command(&state, "STATUS").contains("degraded"));
+
+        // with abort, the watchdog cuts it short
+        assert_eq!(testing::command(&state, "WATCHDOG 2 abort"), "OK");
+        model.set_delay(std::time::Duration::from_secs(10));
+        let started = std::time::Instant::now();
+        let pass = stuck_pass(&state);
+        wait_until(|| state.status().degraded);
+        state.check_watchdog();
+        pass.join().unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        assert!(!state.status().degraded);
+
+        // 0 turns it off
+        assert_eq!(testing::command(&state, "WATCHDOG 0"), "OK");
+        model.set_delay(std::time::Duration::from_millis(300));
+        let pass = stuck_pass(&state);
+        std::thread::sleep(std::time::Duration::from_millis(250));
+        assert!(!state.status().degraded);
+        pass.join().unwrap();
+
+        assert_eq!(
+            testing::command(&state, "WATCHDOG soon"),
+            "ERROR usage: WATCHDOG <intervals> [warn|abort]"
+        );
+    }
+
+    #[test]
+    fn test_repeated_inference_errors_are_surfaced() {
+        let (state, model) = testing::state();
+        assert_eq!(testing::command(&state, "START"), "OK");
+        state.transcriber.push_audio(&[0.0; 160]);
+
+        model.set_error(Some("ggml hiccup"));
+        for _ in 0..INFERENCE_ERROR_THRESHOLD - 1 {
+            state.transcribe_tick();
+            assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:");
+        }
+
+        state.transcribe_tick();
+        let response = testing::command(&state, "POLL");
+        assert!(
+            response.starts_with("ERROR ERR_INFERENCE "),
+            "expected inference error, got: {response}"
+        );
+        assert!(response.contains("ggml hiccup"));
+
+        // reported once, and recording carries on by default
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:");
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_inference_errors_can_stop_recording() {
+        let (state, model) = testing::state();
+        assert_eq!(testing::command(&state, "INFERENCE_ERRORS 2 stop"), "OK");
+        assert_eq!(testing::command(&state, "START"), "OK");
+        state.transcriber.push_audio(&[0.0; 160]);
+
+        model.set_error(Some("ggml hiccup"));
+        state.transcribe_tick();
+        state.transcribe_tick();
+
+        assert!(testing::command(&state, "POLL").starts_with("ERROR ERR_INFERENCE "));
+        assert_eq!(testing::command(&state, "POLL"), "IDLE:");
+        assert_eq!(testing::command(&state, "STOP"), "ERROR not recording");
+    }
+
+    #[test]
+    fn test_load_model_rejected_while_recording() {
+        let (state, _) = testing::state();
+        assert_eq!(testing::command(&state, "START"), "OK");
+
+        let path = std::env::current_exe().unwrap();
+        let response = testing::command(&state, &format!("MODEL {}", path.display()));
+        assert_eq!(response, "ERROR cannot change model while recording");
+
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_reloaded_model_waits_for_start() {
+        let loaded = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = std::sync::Arc::clone(&loaded);
+        let state = DaemonState::with_model_loader(
+            StreamingTranscriber::with_model(
+                Box::new(testing::MockModel::default()),
+                std::time::Duration::from_secs(10),
+            ),
+            testing::silent_opener(),
+            Box::new(move |path| {
+                seen.lock().unwrap().push(path.to_path_buf());
+                Ok(Box::new(testing::MockModel::default()) as Box<dyn Model>)
+            }),
+        );
+        let model = std::env::current_exe().unwrap();
+
+        state.apply_config(Config {
+            language: "de".to_string(),
+            model: Some(model.clone()),
+            ..Config::default()
+        });
+        assert_eq!(state.get_config("language"), "language=de");
+        assert!(loaded.lock().unwrap().is_empty());
+
+        assert_eq!(testing::command(&state, "START"), "LOADING");
+        assert_eq!(wait_for_reload(&state), Reload::Done(model_name(&model)));
+        assert_eq!(*loaded.lock().unwrap(), vec![model.clone()]);
+        assert_eq!(state.model_name(), Some(model_name(&model)));
+        assert_eq!(testing::command(&state, "START"), "OK");
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+
+        // only once
+        assert_eq!(testing::command(&state, "START"), "OK");
+        assert_eq!(loaded.lock().unwrap().len(), 1);
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+    }
+
+    /// State whose model loads wait for a go-ahead on the returned sender,
+    /// then fail for empty files as whisper would for a corrupt model.
+    fn gated_reload_state() -> (
+        std::sync::Arc<DaemonState>,
+        std::sync::mpsc::Sender<()>,
+        testing::MockModel,
+    ) {
+        gated_state(StreamingTranscriber::with_model(
+            Box::new(testing::MockModel::default()),
+            std::time::Duration::from_secs(10),
+        ))
+    }
+
+    /// Like `gated_reload_state`, with `transcriber`.
+    fn gated_state(
+        transcriber: StreamingTranscriber,
+    ) -> (
+        std::sync::Arc<DaemonState>,
+        std::sync::mpsc::Sender<()>,
+        testing::MockModel,
+    ) {
+        let (go, gate) = std::sync::mpsc::channel::<()>();
+        let gate = std::sync::Mutex::new(gate);
+        let loaded = testing::MockModel::default();
+        let model = loaded.clone();
+        let state = DaemonState::with_model_loader(
+            transcriber,
+            testing::silent_opener(),
+            Box::new(move |path| {
+                gate.lock().unwrap().recv()?;
+                if std::fs::metadata(path)?.len() == 0 {
+                    return Err("Failed to load model: invalid model file".into());
+                }
+                Ok(Box::new(model.clone()) as Box<dyn Model>)
+            }),
+        );
+        (state, go, loaded)
+    }
+
+    fn wait_for_reload(state: &DaemonState) -> Reload {
+        wait_until(|| !matches!(state.reload_status(), Reload::Loading(_)));
+        state.reload_status()
+    }
+
+    #[test]
+    fn test_reload_swaps_model_in_background() {
+        let (state, go, loaded) = gated_reload_state();
+        let path =
+            std::env::temp_dir().join(format!("yowl-test-{}-reload.bin", std::process::id()));
+        std::fs::write(&path, b"model").unwrap();
+
+        assert_eq!(testing::command(&state, "RELOAD"), "RELOAD:idle");
+        let command = format!("RELOAD {}", path.display());
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        assert_eq!(
+            testing::command(&state, &command),
+            format!("OK loading {name}")
+        );
+
+        // other commands are served while it loads, but recording waits
+        assert_eq!(testing::command(&state, "PING"), "PONG");
+        assert_eq!(
+            testing::command(&state, "RELOAD"),
+            format!("RELOAD:loading {name}")
+        );
+        assert_eq!(testing::command(&state, "START"), "LOADING");
+        assert_eq!(
+            testing::command(&state, &command),
+            "ERROR a reload is already in progress"
+        );
+
+        go.send(()).unwrap();
+        assert_eq!(wait_for_reload(&state), Reload::Done(name.clone()));
+        assert_eq!(
+            testing::command(&state, "RELOAD"),
+            format!("RELOAD:done {name}")
+        );
+
+        // the new model is the one transcribing
+        loaded.set_text("from the new model");
+        assert_eq!(testing::command(&state, "START"), "OK");
+        testing::transcribe(&state);
+        assert_eq!(
+            testing::command(&state, "POLL"),
+            "RECORDING:0:from the new model"
+        );
+
+        // reloading is rejected rather than queued while recording
+        assert_eq!(
+            testing::command(&state, &command),
+            "ERROR cannot reload model while recording"
+        );
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_failed_reload_keeps_old_model() {
+        let (state, go, loaded) = gated_reload_state();
+        let path =
+            std::env::temp_dir().join(format!("yowl-test-{}-corrupt.bin", std::process::id()));
+        std::fs::write(&path, b"").unwrap();
+
+        loaded.set_text("from the new model");
+        let command = format!("RELOAD {}", path.display());
+        assert!(testing::command(&state, &command).starts_with("OK loading"));
+        go.send(()).unwrap();
+        assert_eq!(
+            wait_for_reload(&state),
+            Reload::Failed("Failed to load model: invalid model file".to_string())
+        );
+        assert_eq!(
+            testing::command(&state, "RELOAD"),
+            "ERROR Failed to load model: invalid model file"
+        );
+
+        // the old (silent) model is still in use
+        assert_eq!(testing::command(&state, "START"), "OK");
+        testing::transcribe(&state);
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:");
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+
+        assert_eq!(
+            testing::command(&state, "RELOAD /nonexistent/ggml-tiny.en.bin"),
+            "ERROR model not found: /nonexistent/ggml-tiny.en.bin"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// State with no model yet, which loads `contents` as its model file
+    /// when first needed.
+    fn lazy_state(
+        name: &str,
+        contents: &[u8],
+    ) -> (
+        std::sync::Arc<DaemonState>,
+        std::sync::mpsc::Sender<()>,
+        std::path::PathBuf,
+    ) {
+        let (state, go, loaded) = gated_state(StreamingTranscriber::without_model(
+            std::time::Duration::from_secs(10),
+        ));
+        loaded.set_text("loaded at last");
+        let path = std::env::temp_dir().join(format!("yowl-test-{}-{name}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        *state.model_path.lock().unwrap() = Some(path.clone());
+        (state, go, path)
+    }
+
+    #[test]
+    fn test_model_loads_on_first_start() {
+        let (state, go, path) = lazy_state("lazy.bin", b"model");
+
+        // the daemon answers without a model
+        assert_eq!(testing::command(&state, "PING"), "PONG");
+        assert!(testing::command(&state, "STATUS").starts_with("state=idle "));
+        assert_eq!(testing::command(&state, "POLL"), "IDLE:");
+
+        assert_eq!(testing::command(&state, "START"), "LOADING");
+        assert_eq!(testing::command(&state, "START"), "LOADING");
+        assert!(!state.is_recording());
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        go.send(()).unwrap();
+        assert_eq!(wait_for_reload(&state), Reload::Done(model_name(&path)));
+        let load_ms = state
+            .metrics()
+            .into_iter()
+            .find(|(key, _)| *key == "model_load_ms")
+            .unwrap()
+            .1;
+        assert_ne!(load_ms, "0.0");
+
+        assert_eq!(testing::command(&state, "START"), "OK");
+        testing::transcribe(&state);
+        assert_eq!(
+            testing::command(&state, "POLL"),
+            "RECORDING:0:loaded at last"
+        );
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+        assert_eq!(testing::command(&state, "PRELOAD"), "OK");
+        assert_eq!(testing::command(&state, "SHUTDOWN"), "OK");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_preload_and_failed_first_load() {
+        let (state, go, path) = lazy_state("lazy-corrupt.bin", b"");
+        assert_eq!(testing::command(&state, "PRELOAD"), "LOADING");
+        assert_eq!(testing::command(&state, "START"), "LOADING");
+        go.send(()).unwrap();
+        assert!(matches!(wait_for_reload(&state), Reload::Failed(_)));
+
+        // the failure is reported once, then the next START tries again
+        assert_eq!(
+            testing::command(&state, "START"),
+            "ERROR model failed to load: Failed to load model: invalid model file"
+        );
+        std::fs::write(&path, b"model").unwrap();
+        assert_eq!(testing::command(&state, "START"), "LOADING");
+        go.send(()).unwrap();
+        assert!(matches!(wait_for_reload(&state), Reload::Done(_)));
+        assert_eq!(testing::command(&state, "START"), "OK");
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_model_in_background() {
+        let (state, go, _) = gated_reload_state();
+        let path = std::env::temp_dir().join(format!("yowl-test-{}-model.bin", std::process::id()));
+        std::fs::write(&path, b"model").unwrap();
+        let name = model_name(&path);
+
+        let command = format!("MODEL {}", path.display());
+        assert_eq!(
+            testing::command(&state, &command),
+            format!("OK loading {name}")
+        );
+        // clients are served while it loads
+        assert_eq!(testing::command(&state, "PING"), "PONG");
+        assert_eq!(testing::command(&state, "START"), "LOADING");
+
+        go.send(()).unwrap();
+        assert_eq!(wait_for_reload(&state), Reload::Done(name.clone()));
+        assert_eq!(state.model_name(), Some(name));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_model_missing_file() {
+        let (state, _) = testing::state();
+
+        let response = testing::command(&state, "MODEL /nonexistent/ggml-tiny.en.bin");
+        assert_eq!(
+            response,
+            "ERROR model not found: /nonexistent/ggml-tiny.en.bin"
+        );
+        assert_eq!(
+            testing::command(&state, "MODEL"),
+            "ERROR usage: MODEL <path>"
+        );
+    }
+
+    #[test]
+    fn test_status_idle_and_recording() {
+        let (state, model) = testing::state();
+
+        assert_eq!(
+            testing::command(&state, "STATUS"),
+            "state=idle elapsed_ms=0 samples=0 committed=0 provisional=0 \
+             language=en buffer_secs=10"
+        );
+
+        assert_eq!(testing::command(&state, "LANG de"), "OK");
+        assert_eq!(testing::command(&state, "BUFFER 5"), "OK");
+        *state.model_path.lock().unwrap() =
+            Some(std::path::PathBuf::from("/models/my ggml-base.bin"));
+        assert_eq!(testing::command(&state, "START"), "OK");
+        model.set_text("Hello world");
+        testing::transcribe(&state);
+        testing::command(&state, "POLL");
+
+        let status = state.status();
+        assert!(status.recording);
+        assert_eq!(status.buffered_samples, 160);
+        assert_eq!(status.committed_chars, 0);
+        assert_eq!(status.provisional_chars, 11);
+
+        assert_eq!(status.language, "de");
+        assert_eq!(status.buffer_secs, 5);
+        assert_eq!(status.model.as_deref(), Some("my ggml-base.bin"));
+
+        // changes to the config wait for the next START
+        assert_eq!(
+            testing::command(&state, "SET buffer_duration_secs 20"),
+            "OK"
+        );
+        let response = testing::command(&state, "STATUS");
+        assert!(response.starts_with("state=recording elapsed_ms="));
+        assert!(response.ends_with(
+            " samples=160 committed=0 provisional=11 \
+             language=de buffer_secs=5 model=my\\sggml-base.bin"
+        ));
+
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+        assert!(testing::command(&state, "STATUS").starts_with("state=idle elapsed_ms=0 "));
+    }
+
+    #[test]
+    fn test_named_models_resolve_in_models_dir() {
+        let (state, _) = testing::state();
+        let dir = std::env::temp_dir().join(format!("yowl-test-{}-modeldir", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let command = format!("SETMODELDIR {}", dir.display());
+        assert_eq!(testing::command(&state, &command), "OK");
+        assert_eq!(
+            testing::command(&state, "SETMODEL no-such-model"),
+            format!(
+                "ERROR model not found: {}",
+                dir.join("ggml-no-such-model.bin").display()
+            )
+        );
+        assert_eq!(
+            testing::command(&state, "SETMODEL ../base.en"),
+            "ERROR invalid model name: ../base.en"
+        );
+        assert!(testing::command(&state, "SETMODELDIR /nonexistent/models").starts_with("ERROR"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_transcript_survives_stop() {
+        let (state, model) = testing::state();
+        assert_eq!(testing::command(&state, "TRANSCRIPT"), "TRANSCRIPT:");
+        assert_eq!(testing::command(&state, "START"), "OK");
+
+        model.set_text("First line.\nSecond part of the sentence");
+        testing::transcribe(&state);
+        testing::command(&state, "POLL");
+
+        // the buffer moved on, so the first line is committed
+        model.set_text("Second part of the sentence continues");
+        testing::transcribe(&state);
+        testing::command(&state, "POLL");
+
+        let full = "TRANSCRIPT:First line.\\nSecond part of the sentence continues";
+        assert_eq!(testing::command(&state, "TRANSCRIPT"), full);
+        assert_eq!(
+            testing::command(&state, "TRANSCRIPT committed"),
+            "TRANSCRIPT:First line.\\n"
+        );
+
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+        assert_eq!(testing::command(&state, "TRANSCRIPT"), full);
+
+        assert_eq!(testing::command(&state, "START"), "OK");
+        assert_eq!(testing::command(&state, "TRANSCRIPT"), "TRANSCRIPT:");
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_set_and_get_config() {
+        let (state, model) = testing::state();
+        assert_eq!(
+            testing::command(&state, "GET transcribe_interval_ms"),
+            "transcribe_interval_ms=500"
+        );
+        assert_eq!(
+            testing::command(&state, "SET transcribe_interval_ms 99"),
+            "ERROR transcribe_interval_ms must be between 100 and 5000"
+        );
+        assert!(testing::command(&state, "GET colour").starts_with("ERROR unknown key: colour"));
+        assert_eq!(
+            testing::command(&state, "SET language"),
+            "ERROR usage: SET <key> <value>"
+        );
+
+        assert_eq!(testing::command(&state, "SET buffer_duration_secs 2"), "OK");
+        assert_eq!(testing::command(&state, "SET language de"), "OK");
+        assert_eq!(testing::command(&state, "GET language"), "language=de");
+
+        assert_eq!(testing::command(&state, "START"), "OK");
+        state
+            .transcriber()
+            .push_audio(&vec![0.0; 3 * whisper::SAMPLE_RATE]);
+        assert_eq!(state.status().buffered_samples, 2 * whisper::SAMPLE_RATE);
+        testing::transcribe(&state);
+        assert_eq!(model.last_language().as_deref(), Some("de"));
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_aging_match_command() {
+        let (state, _) = testing::state();
+        assert_eq!(testing::command(&state, "AGING_MATCH 8"), "OK");
+        assert_eq!(
+            testing::command(&state, "AGING_MATCH 2"),
+            "ERROR min match length must be at least 3"
+        );
+        assert_eq!(
+            testing::command(&state, "AGING_MATCH many"),
+            "ERROR usage: AGING_MATCH <chars>"
+        );
+    }
+
+    #[test]
+    fn test_grapheme_backspaces() {
+        let (state, model) = testing::state();
+        assert_eq!(
+            testing::command(&state, "BACKSPACE byte"),
+            "ERROR unknown backspace unit: byte"
+        );
+        assert_eq!(testing::command(&state, "BACKSPACE grapheme"), "OK");
+        assert_eq!(testing::command(&state, "START"), "OK");
+
+        model.set_text("nice 👍🏽");
+        testing::transcribe(&state);
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:nice 👍🏽");
+
+        model.set_text("nice one");
+        testing::transcribe(&state);
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:1:one");
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_post_process_saved_with_profile() {
+        let (state, model) = testing::state();
+        assert_eq!(
+            testing::command(&state, "POSTPROCESS shout"),
+            "ERROR unknown post-processing: shout"
+        );
+        assert_eq!(testing::command(&state, "POSTPROCESS capitalize"), "OK");
+        assert_eq!(testing::command(&state, "SAVEPROFILE prose"), "OK");
+        assert_eq!(testing::command(&state, "SETPROFILE default"), "OK");
+        assert_eq!(testing::command(&state, "SETPROFILE prose"), "OK");
+
+        assert_eq!(testing::command(&state, "START"), "OK");
+        model.set_text("hello. world");
+        testing::transcribe(&state);
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:Hello. World");
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_repeats_collapse_whisper_loops() {
+        let (state, model) = testing::state();
+        assert_eq!(
+            testing::command(&state, "REPEATS 1"),
+            "ERROR usage: REPEATS <words of 2 or more|off>"
+        );
+        assert_eq!(testing::command(&state, "REPEATS 4"), "OK");
+
+        assert_eq!(testing::command(&state, "START"), "OK");
+        model.set_text(" so so so so so so very very good");
+        testing::transcribe(&state);
+        assert_eq!(
+            testing::command(&state, "POLL"),
+            "RECORDING:0:so very very good"
+        );
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_stability_command() {
+        let (state, model) = testing::state();
+        assert_eq!(
+            testing::command(&state, "STABILITY lots"),
+            "ERROR usage: STABILITY <updates>"
+        );
+        assert_eq!(testing::command(&state, "STABILITY 2"), "OK");
+        assert_eq!(testing::command(&state, "START"), "OK");
+
+        model.set_text("time ther");
+        testing::transcribe(&state);
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:");
+        // polling again without a new transcription settles nothing
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:");
+        model.set_text("time there.");
+        testing::transcribe(&state);
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:time ther");
+
+        // the unsettled tail is still delivered when the daemon shuts down
+        assert_eq!(
+            testing::command(&state, "SHUTDOWN"),
+            "TRANSCRIPT:time there."
+        );
+    }
+
+    /// Apply POLL responses to a terminal until the daemon answers something
+    /// other than RECORDING, which is returned.
+    fn poll_into(state: &std::sync::Arc<DaemonState>, terminal: &mut String) -> String {
+        loop {
+            let response = testing::command(state, "POLL");
+            if !response.starts_with("RECORDING:") {
+                return response;
+            }
+            testing::apply_poll(terminal, &response);
+            if response == "RECORDING:0:" {
+                return response;
+            }
+        }
+    }
+
+    #[test]
+    fn test_stop_delivers_unsettled_tail() {
+        let (state, model) = testing::state();
+        assert_eq!(testing::command(&state, "STABILITY 3"), "OK");
+        assert_eq!(testing::command(&state, "START"), "OK");
+
+        let mut terminal = String::new();
+        for text in ["once upon", "once upon a time", "once upon a time there"] {
+            model.set_text(text);
+            testing::transcribe(&state);
+            poll_into(&state, &mut terminal);
+        }
+        assert_ne!(terminal, "once upon a time there");
+        // a pass the client never polled for
+        model.set_text("once upon a time there was");
+        testing::transcribe(&state);
+
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+        assert_eq!(poll_into(&state, &mut terminal), "IDLE:");
+        assert_eq!(terminal, "once upon a time there was");
+    }
+
+    #[test]
+    fn test_word_diff_mode() {
+        let (state, model) = testing::state();
+        assert_eq!(
+            testing::command(&state, "DIFF line"),
+            "ERROR unknown diff mode: line"
+        );
+        assert_eq!(testing::command(&state, "DIFF word"), "OK");
+        assert_eq!(testing::command(&state, "START"), "OK");
+
+        model.set_text("hello word");
+        testing::transcribe(&state);
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:hello word");
+
+        model.set_text("hello world");
+        testing::transcribe(&state);
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:4:world");
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_large_append_spread_over_polls() {
+        let (state, model) = testing::state();
+        assert_eq!(testing::command(&state, "CHUNK 16"), "OK");
+        assert_eq!(testing::command(&state, "START"), "OK");
+
+        let transcript = "After a long pause, a whole sentence arrives in one go.";
+        model.set_text(transcript);
+        testing::transcribe(&state);
+
+        let mut terminal = String::new();
+        let mut polls = 0;
+        while terminal != transcript && polls < 10 {
+            testing::apply_poll(&mut terminal, &testing::command(&state, "POLL"));
+            polls += 1;
+            assert_eq!(
+                testing::command(&state, "TRANSCRIPT"),
+                format!("TRANSCRIPT:{terminal}")
+            );
+        }
+
+        assert_eq!(terminal, transcript);
+        assert!(polls > 1, "append was delivered in one poll");
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_subscribed_worker_publishes_events() {
+        let model = testing::MockModel::default();
+        let transcriber = StreamingTranscriber::with_model(
+            Box::new(model.clone()),
+            std::time::Duration::from_secs(10),
+        );
+        let (senders, sources) = std::sync::mpsc::channel();
+        let state = DaemonState::with_parts(transcriber, testing::channel_opener(senders));
+        state.set_subscribed(true);
+        model.set_text("Hello world");
+        assert_eq!(testing::command(&state, "START"), "OK");
+
+        let audio = sources
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        let tone: Vec<f32> = (0..1600)
+            .map(|i| 0.3 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 16000.0).sin())
+            .collect();
+        audio.send(tone).unwrap();
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        let mut events = Vec::new();
+        while events.is_empty() && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            events = state.take_events();
+        }
+
+        match events.as_slice() {
+            [Poll::Recording(diff)] => assert_eq!(diff.new_text, "Hello world"),
+            other => panic!("unexpected events: {other:?}"),
+        }
+
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+        assert!(matches!(state.take_events().as_slice(), [Poll::Idle]));
+    }
+
+    #[test]
+    fn test_poll_and_subscribers_each_get_everything() {
+        let (state, model) = testing::state();
+        state.set_subscribed(true);
+        assert_eq!(testing::command(&state, "STABILITY 2"), "OK");
+        assert_eq!(testing::command(&state, "START"), "OK");
+
+        let mut polled = String::new();
+        let mut pushed = String::new();
+        let push = |pushed: &mut String, events: Vec<Poll>| {
+            for event in events {
+                match event {
+                    Poll::Recording(diff) => diff.apply(pushed),
+                    Poll::Idle => pushed.push('|'),
+                    other => panic!("unexpected event: {other:?}"),
+                }
+            }
+        };
+        for (i, text) in ["one", "one two", "one two three", "one two three four"]
+            .into_iter()
+            .enumerate()
+        {
+            model.set_text(text);
+            testing::transcribe(&state);
+            // the worker publishes and a client polls, either first
+            if i % 2 == 0 {
+                state.publish();
+                testing::apply_poll(&mut polled, &testing::command(&state, "POLL"));
+            } else {
+                testing::apply_poll(&mut polled, &testing::command(&state, "POLL"));
+                state.publish();
+            }
+            push(&mut pushed, state.take_events());
+        }
+        assert_eq!(polled, pushed);
+        assert_ne!(polled, "one two three four");
+
+        // both hear the held-back tail, and that recording ended
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+        push(&mut pushed, state.take_events());
+        assert_eq!(pushed, "one two three four|");
+        testing::apply_poll(&mut polled, &testing::command(&state, "POLL"));
+        assert_eq!(polled, "one two three four");
+        assert_eq!(testing::command(&state, "POLL"), "IDLE:");
+    }
+
+    #[test]
+    fn test_session_transcripts_saved() {
+        let (state, model) = testing::state();
+        assert_eq!(
+            testing::command(&state, "SESSIONS"),
+            "ERROR session_log_dir is not set"
+        );
+        let dir = std::env::temp_dir().join(format!("yowl-sessions-{}", std::process::id()));
+        state.config.lock().unwrap().session_log_dir = Some(dir.clone());
+        assert_eq!(testing::command(&state, "SESSIONS"), "SESSIONS:");
+
+        assert_eq!(testing::command(&state, "START"), "OK");
+        model.set_text("Hello world");
+        testing::transcribe(&state);
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:Hello world");
+        // a pass the client never polled for still reaches the file
+        model.set_text("Hello world again");
+        testing::transcribe(&state);
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+
+        let response = testing::command(&state, "SESSIONS");
+        let files: Vec<&str> = response
+            .strip_prefix("SESSIONS:")
+            .unwrap()
+            .split('|')
+            .collect();
+        assert_eq!(files.len(), 2, "{response}");
+        assert!(files[0].ends_with(".jsonl") && files[1].ends_with(".txt"));
+        assert_eq!(
+            std::fs::read_to_string(dir.join(files[1])).unwrap(),
+            "Hello world again"
+        );
+        let updates = std::fs::read_to_string(dir.join(files[0])).unwrap();
+        assert_eq!(updates.lines().count(), 2);
+        assert!(updates.contains(r#""new_text":"Hello world""#), "{updates}");
+
+        // RESET starts a new pair, leaving the old text where it was
+        assert_eq!(testing::command(&state, "START"), "OK");
+        testing::transcribe(&state);
+        testing::command(&state, "POLL");
+        assert_eq!(testing::command(&state, "RESET"), "OK");
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+        let response = testing::command(&state, "SESSIONS");
+        assert_eq!(response.split('|').count(), 6, "{response}");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_record_wav_saves_captured_audio() {
+        let transcriber = StreamingTranscriber::with_model(
+            Box::new(testing::MockModel::default()),
+            std::time::Duration::from_secs(10),
+        );
+        let (senders, sources) = std::sync::mpsc::channel();
+        let state = DaemonState::with_parts(transcriber, testing::channel_opener(senders));
+        let path = std::env::temp_dir().join(format!("yowl-record-{}.wav", std::process::id()));
+
+        assert_eq!(
+            testing::command(&state, "RECORD_WAV yowl.wav"),
+            "ERROR path must be absolute"
+        );
+        assert_eq!(
+            testing::command(&state, "RECORD_WAV /no/such/dir/yowl.wav"),
+            "ERROR no such directory: /no/such/dir/yowl.wav"
+        );
+        let command = format!("RECORD_WAV {}", path.display());
+        assert_eq!(testing::command(&state, &command), "OK");
+        assert_eq!(testing::command(&state, "START"), "OK");
+
+        let audio = sources
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        audio.send(vec![0.25; 1600]).unwrap();
+        audio.send(vec![-0.25; 800]).unwrap();
+        wait_until(|| state.status().buffered_samples == 2400);
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+
+        // finished by the time STOP answers
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().sample_rate, 16000);
+        assert_eq!(reader.len(), 2400);
+        assert_eq!(reader.samples::<i16>().next().unwrap().unwrap(), 8191);
+        std::fs::remove_file(&path).unwrap();
+
+        // a file that can't be created leaves the recording going
+        let command = format!("RECORD_WAV {}", std::env::temp_dir().display());
+        assert_eq!(testing::command(&state, &command), "OK");
+        assert_eq!(testing::command(&state, "START"), "OK");
+        let _ = sources.recv_timeout(std::time::Duration::from_secs(5));
+        assert!(state.is_recording());
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+
+        assert_eq!(testing::command(&state, "RECORD_WAV off"), "OK");
+        assert_eq!(*state.record_wav.lock().unwrap(), None);
+    }
+
+    #[test]
+    fn test_silence_stops_recording() {
+        let transcriber = StreamingTranscriber::with_model(
+            Box::new(testing::MockModel::default()),
+            std::time::Duration::from_secs(10),
+        );
+        let (senders, sources) = std::sync::mpsc::channel();
+        let state = DaemonState::with_parts(transcriber, testing::channel_opener(senders));
+        assert_eq!(testing::command(&state, "SILENCE_TIMEOUT 300"), "OK");
+        assert_eq!(
+            testing::command(&state, "SET transcribe_interval_ms 100"),
+            "OK"
+        );
+        assert_eq!(testing::command(&state, "START"), "OK");
+        let audio = sources
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+
+        // 100ms chunks of a 440 Hz tone, then of silence, in real time
+        let tone: Vec<f32> = (0..1600)
+            .map(|i| 0.3 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 16000.0).sin())
+            .collect();
+        let started = std::time::Instant::now();
+        for _ in 0..6 {
+            audio.send(tone.clone()).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        assert!(state.is_recording());
+        let silent_from = std::time::Instant::now();
+        while state.is_recording() && started.elapsed() < std::time::Duration::from_secs(5) {
+            audio.send(vec![0.0; 1600]).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        assert!(!state.is_recording());
+        assert!(silent_from.elapsed() >= std::time::Duration::from_millis(300));
+        assert_eq!(testing::command(&state, "POLL"), "STOPPED:");
+        assert_eq!(testing::command(&state, "POLL"), "IDLE:");
+        assert_eq!(testing::command(&state, "STOP"), "ERROR not recording");
+    }
+
+    #[test]
+    fn test_silence_stop_delivers_unsettled_tail() {
+        let model = testing::MockModel::default();
+        let transcriber = StreamingTranscriber::with_model(
+            Box::new(model.clone()),
+            std::time::Duration::from_secs(10),
+        );
+        let (senders, sources) = std::sync::mpsc::channel();
+        let state = DaemonState::with_parts(transcriber, testing::channel_opener(senders));
+        assert_eq!(testing::command(&state, "SILENCE_TIMEOUT 300"), "OK");
+        assert_eq!(testing::command(&state, "STABILITY 3"), "OK");
+        assert_eq!(
+            testing::command(&state, "SET transcribe_interval_ms 100"),
+            "OK"
+        );
+        assert_eq!(testing::command(&state, "START"), "OK");
+        let audio = sources
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+
+        let tone: Vec<f32> = (0..1600)
+            .map(|i| 0.3 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 16000.0).sin())
+            .collect();
+        let mut terminal = String::new();
+        for text in ["the last", "the last words", "the last words are"] {
+            model.set_text(text);
+            audio.send(tone.clone()).unwrap();
+            wait_until(|| state.transcriber.current_transcript() == text);
+            poll_into(&state, &mut terminal);
+        }
+        assert_ne!(terminal, "the last words are");
+
+        let started = std::time::Instant::now();
+        while state.is_recording() && started.elapsed() < std::time::Duration::from_secs(5) {
+            audio.send(vec![0.0; 1600]).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        assert!(!state.is_recording());
+        assert_eq!(poll_into(&state, &mut terminal), "STOPPED:");
+        assert_eq!(terminal, "the last words are");
+        assert_eq!(testing::command(&state, "POLL"), "IDLE:");
+
+        // the stopped worker is collected by the next START
+        assert_eq!(testing::command(&state, "START"), "OK");
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_silence_timeout_zero_keeps_recording() {
+        let transcriber = StreamingTranscriber::with_model(
+            Box::new(testing::MockModel::default()),
+            std::time::Duration::from_secs(10),
+        );
+        let (senders, sources) = std::sync::mpsc::channel();
+        let state = DaemonState::with_parts(transcriber, testing::channel_opener(senders));
+        assert_eq!(
+            testing::command(&state, "SILENCE_TIMEOUT soon"),
+            "ERROR usage: SILENCE_TIMEOUT <ms>"
+        );
+        assert_eq!(testing::command(&state, "SILENCE_TIMEOUT 0"), "OK");
+        assert_eq!(
+            testing::command(&state, "SET transcribe_interval_ms 100"),
+            "OK"
+        );
+        assert_eq!(testing::command(&state, "START"), "OK");
+        let audio = sources
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+
+        for _ in 0..5 {
+            audio.send(vec![0.0; 1600]).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        assert!(state.is_recording());
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+        assert_eq!(testing::command(&state, "POLL"), "IDLE:");
+    }
+
+    #[test]
+    fn test_stats_flag_slow_inference() {
+        let (state, model) = testing::state();
+        assert_eq!(
+            testing::command(&state, "STATS"),
+            "calls=0 min_ms=0.0 avg_ms=0.0 max_ms=0.0 interval_ms=500 behind=no"
+        );
+
+        model.set_delay(std::time::Duration::from_millis(150));
+        testing::transcribe(&state);
+        testing::transcribe(&state);
+        let stats = testing::command(&state, "STATS");
+        assert!(stats.starts_with("calls=2 "), "{stats}");
+        assert!(stats.ends_with(" interval_ms=500 behind=no"), "{stats}");
+
+        assert_eq!(
+            testing::command(&state, "SET transcribe_interval_ms 100"),
+            "OK"
+        );
+        assert!(testing::command(&state, "STATS").ends_with(" interval_ms=100 behind=yes"));
+    }
+
+    #[test]
+    fn test_warmup_suppresses_early_output() {
+        let (state, model) = testing::state();
+        assert_eq!(testing::command(&state, "WARMUP 2 ticks"), "OK");
+        assert_eq!(testing::command(&state, "START"), "OK");
+
+        for transcript in ["Hell", "Hello wor"] {
+            model.set_text(transcript);
+            state.transcriber.push_audio(&[0.0; 160]);
+            state.transcribe_tick();
+            assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:");
+        }
+
+        model.set_text("Hello world.");
+        state.transcriber.push_audio(&[0.0; 160]);
+        state.transcribe_tick();
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:Hello world.");
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_parse_warmup() {
+        assert_eq!("off".parse(), Ok(Warmup::Off));
+        assert_eq!("3 ticks".parse(), Ok(Warmup::Ticks(3)));
+        assert_eq!(
+            "1500 ms".parse(),
+            Ok(Warmup::Duration(std::time::Duration::from_millis(1500)))
+        );
+        assert!("3".parse::<Warmup>().is_err());
+        assert!("3 seconds".parse::<Warmup>().is_err());
+    }
+
+    #[test]
+    fn test_profiles_save_list_and_switch() {
+        let (state, model) = testing::state();
+        assert_eq!(
+            testing::command(&state, "LISTPROFILES"),
+            "active=default profiles=default"
+        );
+
+        assert_eq!(testing::command(&state, "SUBSTITUTE at=@"), "OK");
+        assert_eq!(testing::command(&state, "SUBSTITUTE dot com=.com"), "OK");
+        assert_eq!(testing::command(&state, "SAVEPROFILE email"), "OK");
+        assert_eq!(
+            testing::command(&state, "LISTPROFILES"),
+            "active=email profiles=default,email"
+        );
+
+        assert_eq!(testing::command(&state, "START"), "OK");
+        model.set_text("write to me at example dot com");
+        testing::transcribe(&state);
+
+        let mut terminal = String::new();
+        testing::apply_poll(&mut terminal, &testing::command(&state, "POLL"));
+        assert_eq!(terminal, "write to me @ example .com");
+
+        // switching mid-recording rewrites the provisional text
+        assert_eq!(testing::command(&state, "SETPROFILE default"), "OK");
+        testing::apply_poll(&mut terminal, &testing::command(&state, "POLL"));
+        assert_eq!(terminal, "write to me at example dot com");
+
+        assert_eq!(testing::command(&state, "SETPROFILE email"), "OK");
+        testing::apply_poll(&mut terminal, &testing::command(&state, "POLL"));
+        assert_eq!(terminal, "write to me @ example .com");
+
+        assert_eq!(
+            testing::command(&state, "SETPROFILE code"),
+            "ERROR unknown profile: code"
+        );
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_selected_device_reaches_opener() {
+        let opened = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = std::sync::Arc::clone(&opened);
+        let opener: AudioOpener = Box::new(move |device| {
+            seen.lock().unwrap().push(device.map(str::to_string));
+            Err("no audio in tests".into())
+        });
+        let transcriber = StreamingTranscriber::with_model(
+            Box::new(testing::MockModel::default()),
+            std::time::Duration::from_secs(10),
+        );
+        let state = DaemonState::with_parts(transcriber, opener);
+
+        // START waits for the worker to try the device, so it has been tried
+        // by the time the failure comes back
+        *state.device.lock().unwrap() = Some("USB Podcast Mic".to_string());
+        assert!(testing::command(&state, "START").starts_with("ERROR "));
+
+        assert_eq!(testing::command(&state, "DEVICE default"), "OK");
+        assert!(testing::command(&state, "START").starts_with("ERROR "));
+
+        assert_eq!(
+            *opened.lock().unwrap(),
+            vec![Some("USB Podcast Mic".to_string()), None]
+        );
+    }
+
+    /// An audio source whose device can be "unplugged" by the test.
+    struct UnpluggableSource {
+        lost: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl AudioSource for UnpluggableSource {
+        fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
+            Ok(())
+        }
+
+        fn stop(&self) -> Result<(), Box<dyn std::error::Error>> {
+            Ok(())
+        }
+
+        fn try_recv(&self) -> Option<Vec<f32>> {
+            None
+        }
+
+        fn device_lost(&self) -> bool {
+            self.lost.load(std::sync::atomic::Ordering::SeqCst)
+        }
+
+        fn device_name(&self) -> Option<String> {
+            Some("USB Mic".to_string())
+        }
+    }
+
+    struct Unpluggable {
+        state: std::sync::Arc<DaemonState>,
+        /// Set to simulate the stream's error callback firing
+        lost: std::sync::Arc<std::sync::atomic::AtomicBool>,
+        /// Device passed to each open
+        opened: std::sync::Arc<std::sync::Mutex<Vec<Option<String>>>>,
+    }
+
+    /// State whose audio device can be unplugged, and which reconnects
+    /// `reconnects` times before the device is gone for good.
+    fn unpluggable_state(reconnects: usize) -> Unpluggable {
+        let lost = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let opened = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let source_lost = std::sync::Arc::clone(&lost);
+        let seen = std::sync::Arc::clone(&opened);
+        let opener: AudioOpener = Box::new(move |device| {
+            let mut seen = seen.lock().unwrap();
+            seen.push(device.map(str::to_string));
+            if seen.len() > reconnects + 1 {
+                return Err("No input device matching \"USB Mic\"".into());
+            }
+            source_lost.store(false, std::sync::atomic::Ordering::SeqCst);
+            Ok(Box::new(UnpluggableSource {
+                lost: std::sync::Arc::clone(&source_lost),
+            }) as Box<dyn AudioSource>)
+        });
+
+        let transcriber = StreamingTranscriber::with_model(
+            Box::new(testing::MockModel::default()),
+            std::time::Duration::from_secs(10),
+        );
+        Unpluggable {
+            state: DaemonState::with_parts(transcriber, opener),
+            lost,
+            opened,
+        }
+    }
+
+    fn wait_until(condition: impl Fn() -> bool) {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while !condition() && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_capture_failure_is_reported() {
+        let transcriber = StreamingTranscriber::with_model(
+            Box::new(testing::MockModel::default()),
+            std::time::Duration::from_secs(10),
+        );
+        let state = DaemonState::with_parts(transcriber, testing::failing_opener("no microphone"));
+
+        // START waits to hear whether the device opened
+        assert_eq!(
+            testing::command(&state, "START"),
+            "ERROR ERR_AUDIO_DEVICE_UNAVAILABLE audio device unavailable: no microphone"
+        );
+        assert!(!state.status().recording);
+        assert_eq!(state.status().error, Some(ERR_AUDIO_DEVICE_UNAVAILABLE));
+        assert!(testing::command(&state, "STATUS").ends_with(" error=ERR_AUDIO_DEVICE_UNAVAILABLE"));
+        // the caller has been told, so POLL doesn't say it again
+        assert_eq!(testing::command(&state, "POLL"), "IDLE:");
+    }
+
+    #[test]
+    fn test_start_succeeds_once_device_appears() {
+        let transcriber = StreamingTranscriber::with_model(
+            Box::new(testing::MockModel::default()),
+            std::time::Duration::from_secs(10),
+        );
+        let plugged_in = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let (senders, _sources) = std::sync::mpsc::channel();
+        let channel_opener = testing::channel_opener(senders);
+        let state = DaemonState::with_parts(transcriber, {
+            let plugged_in = std::sync::Arc::clone(&plugged_in);
+            Box::new(move |device| {
+                if !plugged_in.load(std::sync::atomic::Ordering::SeqCst) {
+                    return Err("No input device available".into());
+                }
+                channel_opener(device)
+            })
+        });
+
+        assert!(
+            testing::command(&state, "START").starts_with("ERROR ERR_AUDIO_DEVICE_UNAVAILABLE ")
+        );
+        plugged_in.store(true, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(testing::command(&state, "START"), "OK");
+        assert!(state.status().recording);
+        assert_eq!(state.status().error, None);
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_worker_panic_recovers() {
+        let model = testing::MockModel::default();
+        model.set_panic(Some("corrupt model state"));
+        let transcriber = StreamingTranscriber::with_model(
+            Box::new(model.clone()),
+            std::time::Duration::from_secs(10),
+        );
+        let (senders, sources) = std::sync::mpsc::channel();
+        let state = DaemonState::with_parts(transcriber, testing::channel_opener(senders));
+        assert_eq!(
+            testing::command(&state, "SET transcribe_interval_ms 100"),
+            "OK"
+        );
+        state.set_watchdog(1, false);
+        let tone: Vec<f32> = (0..1600)
+            .map(|i| 0.3 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 16000.0).sin())
+            .collect();
+
+        assert_eq!(testing::command(&state, "START"), "OK");
+        let audio = sources
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        audio.send(tone.clone()).unwrap();
+        wait_until(|| !state.is_recording());
+        assert!(!state.is_recording(), "a panic should end the recording");
+        // the dead pass isn't taken for a stuck one
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert!(!state.status().degraded);
+
+        assert!(testing::command(&state, "STATUS").ends_with(" error=ERR_WORKER_PANICKED"));
+        assert_eq!(
+            testing::command(&state, "STOP"),
+            "ERROR ERR_WORKER_PANICKED recording worker panicked"
+        );
+        assert_eq!(testing::command(&state, "STOP"), "ERROR not recording");
+        assert!(testing::command(&state, "STATUS").ends_with(" error=ERR_WORKER_PANICKED"));
+        assert_eq!(
+            testing::command(&state, "POLL"),
+            "ERROR ERR_WORKER_PANICKED recording worker panicked: corrupt model state"
+        );
+
+        // the daemon isn't wedged: the next START records as usual
+        model.set_panic(None);
+        model.set_text("Hello world");
+        assert_eq!(testing::command(&state, "START"), "OK");
+        let audio = sources
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        audio.send(tone).unwrap();
+        wait_until(|| model.calls() >= 2);
+        assert!(state.is_recording());
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_device_lost_stops_recording() {
+        let Unpluggable {
+            state,
+            lost,
+            opened,
+        } = unpluggable_state(0);
+        assert_eq!(testing::command(&state, "START"), "OK");
+        wait_until(|| opened.lock().unwrap().len() == 1);
+
+        // the stream's error callback fires
+        lost.store(true, std::sync::atomic::Ordering::SeqCst);
+        wait_until(|| !state.status().recording);
+
+        assert!(!state.status().recording);
+        assert!(testing::command(&state, "POLL").starts_with("ERROR ERR_DEVICE_LOST device lost"));
+        assert_eq!(testing::command(&state, "POLL"), "IDLE:");
+        // one reconnect was attempted, to the same device
+        assert_eq!(
+            *opened.lock().unwrap(),
+            vec![None, Some("USB Mic".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_device_lost_reaches_poll_and_subscribers() {
+        let Unpluggable {
+            state,
+            lost,
+            opened,
+        } = unpluggable_state(0);
+        state.set_subscribed(true);
+        assert_eq!(testing::command(&state, "START"), "OK");
+        wait_until(|| opened.lock().unwrap().len() == 1);
+
+        lost.store(true, std::sync::atomic::Ordering::SeqCst);
+        wait_until(|| !state.status().recording);
+
+        let events = state.take_events();
+        assert!(
+            matches!(&events[..], [Poll::Fault(fault), Poll::Idle] if fault.code == ERR_DEVICE_LOST),
+            "{events:?}"
+        );
+        assert!(testing::command(&state, "POLL").starts_with("ERROR ERR_DEVICE_LOST device lost"));
+        assert_eq!(testing::command(&state, "POLL"), "IDLE:");
+    }
+
+    #[test]
+    fn test_device_lost_reconnects() {
+        let Unpluggable {
+            state,
+            lost,
+            opened,
+        } = unpluggable_state(1);
+        assert_eq!(testing::command(&state, "START"), "OK");
+        wait_until(|| opened.lock().unwrap().len() == 1);
+
+        lost.store(true, std::sync::atomic::Ordering::SeqCst);
+        wait_until(|| opened.lock().unwrap().len() == 2);
+        wait_until(|| !lost.load(std::sync::atomic::Ordering::SeqCst));
+
+        assert!(state.status().recording);
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:");
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let (state, _) = testing::state();
+        assert_eq!(testing::command(&state, "PAUSE"), "ERROR not recording");
+        assert_eq!(testing::command(&state, "RESUME"), "ERROR not recording");
+
+        assert_eq!(testing::command(&state, "START"), "OK");
+        assert_eq!(testing::command(&state, "RESUME"), "ERROR not paused");
+        assert_eq!(testing::command(&state, "PAUSE"), "OK");
+        assert!(testing::command(&state, "STATUS").starts_with("state=paused "));
+
+        assert_eq!(testing::command(&state, "RESUME"), "OK");
+        assert!(testing::command(&state, "STATUS").starts_with("state=recording "));
+
+        // stopping while paused ends the session
+        assert_eq!(testing::command(&state, "PAUSE"), "OK");
+        assert_eq!(testing::command(&state, "STOP"), "OK");
+        assert!(testing::command(&state, "STATUS").starts_with("state=idle "));
+    }
+
+    #[test]
+    fn test_pause_keeps_committed_text_and_buffer() {
+        let (state, model) = testing::state();
+        assert_eq!(testing::command(&state, "START"), "OK");
+
+        let mut terminal = String::new();
+        model.set_text("Thanks for joining us. So tell me about");
+        testing::transcribe(&state);
+        testing::apply_poll(&mut terminal, &testing::command(&state, "POLL"));
+
+        // the buffer moved on, committing the first sentence
+        model.set_text("So tell me about your work");
+        testing::transcribe(&state);
+        testing::apply_poll(&mut terminal, &testing::command(&state, "POLL"));
+        assert_eq!(
+            testing::command(&state, "TRANSCRIPT committed"),
+            "TRANSCRIPT:Thanks for joining us. "
+        );
+        let buffered = state.status().buffered_samples;
+
+        assert_eq!(testing::command(&state, "PAUSE"), "OK");
+        assert_eq!(testing::command(&state, "PAUSE"), "ERROR already paused");
+        assert_eq!(testing::command(&state, "POLL"), "RECORDING:0:");
+        assert_eq!(state.status().buffered_samples, buffered);
+        assert_eq!(testing::command(&state, "RESUME"), "OK");
+
+        // carries on from where it left off rather than retyping
+        model.set_text("So tell me about your work on the project.");
+        testing::transcribe(&state);
+        assert_eq!(
+            testing::command(&state, "POLL"),
+            "RECORDING:0: on the project."
+        );
+        assert_eq!(
+            testing::command(&state, "TRANSCRIPT"),
+            "TRANSCRIPT:Thanks for joining us. So tell me about your work on the project."
+        );
+        assert_eq!(testing::command(&state, "STOP"), "OK");
     }
 }
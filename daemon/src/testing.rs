@@ -0,0 +1,213 @@
+//! Test doubles for the audio and inference seams, so daemon state can be
+//! exercised without a microphone or a whisper model on disk.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::audio::{AudioOpener, AudioSource};
+use crate::ipc::{handle_command, Session};
+use crate::state::DaemonState;
+use crate::whisper::{Decode, Model, Segment, StreamingTranscriber};
+
+/// A model whose output is set by the test. Clones share the same output,
+/// so a test can keep one handle while the transcriber owns another.
+#[derive(Clone, Default)]
+pub struct MockModel {
+    segments: Arc<Mutex<Vec<Segment>>>,
+    error: Arc<Mutex<Option<String>>>,
+    decode: Arc<Mutex<Option<Decode>>>,
+    calls: Arc<std::sync::atomic::AtomicUsize>,
+    delay: Arc<Mutex<Duration>>,
+    panic: Arc<Mutex<Option<String>>>,
+}
+
+impl MockModel {
+    /// Make subsequent inference return `text` as a single segment.
+    pub fn set_text(&self, text: &str) {
+        *self.segments.lock().unwrap() = vec![Segment {
+            text: text.to_string(),
+            start_ms: 0,
+            end_ms: 0,
+            ..Segment::default()
+        }];
+    }
+
+    /// Make subsequent inference return `segments`, timed from the start of
+    /// the audio it is given.
+    pub fn set_segments(&self, segments: Vec<Segment>) {
+        *self.segments.lock().unwrap() = segments;
+    }
+
+    /// Make subsequent inference fail with `message`, or succeed again with `None`.
+    pub fn set_error(&self, message: Option<&str>) {
+        *self.error.lock().unwrap() = message.map(String::from);
+    }
+
+    /// The language requested by the most recent inference.
+    pub fn last_language(&self) -> Option<String> {
+        self.last_decode().map(|decode| decode.language)
+    }
+
+    /// Make subsequent inference take `delay`, like a slow model.
+    pub fn set_delay(&self, delay: Duration) {
+        *self.delay.lock().unwrap() = delay;
+    }
+
+    /// Make subsequent inference panic with `message`, or stop with `None`.
+    pub fn set_panic(&self, message: Option<&str>) {
+        *self.panic.lock().unwrap() = message.map(str::to_string);
+    }
+
+    /// How many times inference has run.
+    pub fn calls(&self) -> usize {
+        self.calls.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// How the most recent inference was asked to decode.
+    pub fn last_decode(&self) -> Option<Decode> {
+        self.decode.lock().unwrap().clone()
+    }
+}
+
+impl Model for MockModel {
+    fn transcribe(
+        &self,
+        _samples: &[f32],
+        decode: &Decode,
+        abort: &Arc<AtomicBool>,
+    ) -> Result<Vec<Segment>, Box<dyn std::error::Error>> {
+        self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        // wait out the delay in steps, as whisper checks its abort callback
+        let until = std::time::Instant::now() + *self.delay.lock().unwrap();
+        while std::time::Instant::now() < until {
+            if abort.load(std::sync::atomic::Ordering::SeqCst) {
+                return Err("aborted".into());
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let panic = self.panic.lock().unwrap().clone();
+        if let Some(message) = panic {
+            panic!("{message}");
+        }
+        *self.decode.lock().unwrap() = Some(decode.clone());
+        if let Some(message) = self.error.lock().unwrap().clone() {
+            return Err(message.into());
+        }
+        Ok(self.segments.lock().unwrap().clone())
+    }
+}
+
+/// An audio source fed from a channel held by the test.
+pub struct ChannelSource {
+    receiver: Mutex<Receiver<Vec<f32>>>,
+}
+
+impl AudioSource for ChannelSource {
+    fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    fn try_recv(&self) -> Option<Vec<f32>> {
+        self.receiver.lock().unwrap().try_recv().ok()
+    }
+}
+
+/// An opener that hands out channel sources and returns the sender for
+/// each one on `senders`.
+pub fn channel_opener(senders: Sender<Sender<Vec<f32>>>) -> AudioOpener {
+    let senders = Mutex::new(senders);
+    Box::new(move |_| {
+        let (sender, receiver) = mpsc::channel();
+        let _ = senders.lock().unwrap().send(sender);
+        Ok(Box::new(ChannelSource {
+            receiver: Mutex::new(receiver),
+        }) as Box<dyn AudioSource>)
+    })
+}
+
+/// An opener that always fails with `message`, like a missing microphone.
+pub fn failing_opener(message: &'static str) -> AudioOpener {
+    Box::new(move |_| Err(message.into()))
+}
+
+/// An opener whose sources never produce audio.
+pub fn silent_opener() -> AudioOpener {
+    let (senders, _) = mpsc::channel();
+    channel_opener(senders)
+}
+
+/// Daemon state backed by a mock model and a silent audio source.
+pub fn state() -> (Arc<DaemonState>, MockModel) {
+    let model = MockModel::default();
+    let transcriber =
+        StreamingTranscriber::with_model(Box::new(model.clone()), Duration::from_secs(10));
+    (DaemonState::with_parts(transcriber, silent_opener()), model)
+}
+
+/// Feed a little audio and run one transcription pass, as the worker would.
+pub fn transcribe(state: &DaemonState) {
+    state.transcriber().push_audio(&[0.0; 160]);
+    state
+        .transcriber()
+        .transcribe()
+        .expect("Transcription failed");
+}
+
+/// Held by tests that change the global log level, so they don't race.
+pub static LOG_LEVEL: Mutex<()> = Mutex::new(());
+
+/// Held by tests that install the signal handlers, so they don't race.
+pub static SIGNALS: Mutex<()> = Mutex::new(());
+
+static CAPTURED_LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Keeps each record as `<LEVEL> <message>`.
+struct CaptureLogger;
+
+impl log::Log for CaptureLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        CAPTURED_LOGS
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", record.level(), record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+/// Records logged by any test from now on, at whatever level is set. The
+/// first call installs the logger that captures them, for the whole run.
+pub fn captured_logs() -> &'static Mutex<Vec<String>> {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| {
+        let _ = log::set_logger(&CaptureLogger);
+    });
+    &CAPTURED_LOGS
+}
+
+/// Handle a command as a client that never sent HELLO would.
+pub fn command(state: &Arc<DaemonState>, cmd: &str) -> String {
+    handle_command(state, &mut Session::default(), cmd)
+}
+
+/// Apply a `RECORDING:<backspaces>:<text>` POLL response to simulated terminal text.
+pub fn apply_poll(terminal: &mut String, response: &str) {
+    let rest = response
+        .strip_prefix("RECORDING:")
+        .unwrap_or_else(|| panic!("unexpected POLL response: {response}"));
+    let (backspaces, text) = rest.split_once(':').expect("malformed POLL response");
+    for _ in 0..backspaces.parse::<usize>().expect("bad backspace count") {
+        terminal.pop();
+    }
+    terminal.push_str(text);
+}
@@ -0,0 +1,29 @@
+//! Describes this build, for VERSION and the startup log.
+
+/// Cargo features that change what the daemon can do, and whether each is on.
+const FEATURES: [(&str, bool); 3] = [
+    ("cuda", cfg!(feature = "cuda")),
+    ("metal", cfg!(feature = "metal")),
+    ("vulkan", cfg!(feature = "vulkan")),
+];
+
+/// e.g. `yowl 0.1.0 (commit 1a2b3c4, whisper-rs 0.15.1, features: none)`.
+pub fn describe() -> String {
+    let features: Vec<&str> = FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect();
+
+    format!(
+        "yowl {} (commit {}, whisper-rs {}, features: {})",
+        env!("CARGO_PKG_VERSION"),
+        env!("YOWL_GIT_COMMIT"),
+        env!("YOWL_WHISPER_RS_VERSION"),
+        if features.is_empty() {
+            "none".to_string()
+        } else {
+            features.join(",")
+        }
+    )
+}
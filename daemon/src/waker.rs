@@ -0,0 +1,48 @@
+//! Wakes the main loop from other threads.
+//!
+//! The main loop sleeps in poll(2) until a client has something for it. The
+//! recording worker has no socket of its own, so it writes a byte to one end
+//! of a socket pair that the loop polls alongside its clients.
+
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+
+pub struct Waker {
+    reader: UnixStream,
+    writer: UnixStream,
+}
+
+impl Waker {
+    pub fn new() -> std::io::Result<Self> {
+        let (reader, writer) = UnixStream::pair()?;
+        reader.set_nonblocking(true)?;
+        writer.set_nonblocking(true)?;
+        Ok(Self { reader, writer })
+    }
+
+    /// Make the main loop's next wait return at once.
+    pub fn wake(&self) {
+        // a full buffer already holds a wakeup, which is all we need
+        let _ = (&self.writer).write(&[1]);
+    }
+
+    /// The descriptor `wake` writes to, for a signal handler, which can't do
+    /// more than write(2) to it.
+    pub fn wake_fd(&self) -> RawFd {
+        self.writer.as_raw_fd()
+    }
+
+    /// Forget the wakeups so far. Call this before looking for the work they
+    /// announce, so none that arrive while it is done are lost.
+    pub fn clear(&self) {
+        let mut chunk = [0; 64];
+        while matches!((&self.reader).read(&mut chunk), Ok(n) if n > 0) {}
+    }
+}
+
+impl AsRawFd for Waker {
+    fn as_raw_fd(&self) -> RawFd {
+        self.reader.as_raw_fd()
+    }
+}
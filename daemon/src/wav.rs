@@ -0,0 +1,95 @@
+//! Saving captured audio to WAV files, for debugging poor transcriptions.
+//!
+//! The audio is written exactly as the transcriber receives it: 16 kHz mono,
+//! as 16-bit PCM.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::whisper::SAMPLE_RATE;
+
+pub struct WavRecorder {
+    writer: hound::WavWriter<BufWriter<File>>,
+}
+
+impl WavRecorder {
+    /// Create (or truncate) the WAV file at `path`.
+    pub fn create(path: &Path) -> Result<Self, hound::Error> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE as u32,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        Ok(Self {
+            writer: hound::WavWriter::create(path, spec)?,
+        })
+    }
+
+    /// Append `samples`, clipping any outside -1.0..=1.0.
+    pub fn write(&mut self, samples: &[f32]) -> Result<(), hound::Error> {
+        for &sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+            self.writer.write_sample(sample)?;
+        }
+        Ok(())
+    }
+
+    /// Fill in the header's lengths and close the file.
+    pub fn finish(self) -> Result<(), hound::Error> {
+        self.writer.finalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tone_reads_back() {
+        let path = std::env::temp_dir().join(format!("yowl-wav-test-{}.wav", std::process::id()));
+        // half a second of 440 Hz
+        let tone: Vec<f32> = (0..SAMPLE_RATE / 2)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                0.5 * (2.0 * std::f32::consts::PI * 440.0 * t).sin()
+            })
+            .collect();
+
+        let mut recorder = WavRecorder::create(&path).unwrap();
+        recorder.write(&tone[..1000]).unwrap();
+        recorder.write(&tone[1000..]).unwrap();
+        recorder.finish().unwrap();
+
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        let spec = reader.spec();
+        assert_eq!(spec.sample_rate, 16000);
+        assert_eq!(spec.channels, 1);
+        assert_eq!(spec.bits_per_sample, 16);
+        assert_eq!(reader.len() as usize, tone.len());
+        assert_eq!(reader.duration(), 8000);
+
+        let samples: Vec<i16> = reader.samples::<i16>().map(Result::unwrap).collect();
+        for (read, written) in samples.iter().zip(&tone) {
+            assert!((f32::from(*read) / f32::from(i16::MAX) - written).abs() < 1e-3);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_out_of_range_samples_clip() {
+        let path = std::env::temp_dir().join(format!("yowl-wav-clip-{}.wav", std::process::id()));
+        let mut recorder = WavRecorder::create(&path).unwrap();
+        recorder.write(&[2.0, -2.0, 0.0]).unwrap();
+        recorder.finish().unwrap();
+
+        let samples: Vec<i16> = hound::WavReader::open(&path)
+            .unwrap()
+            .samples::<i16>()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(samples, [i16::MAX, -i16::MAX, 0]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,16 +1,91 @@
+use std::ffi::c_void;
 use std::path::Path;
-use std::sync::Mutex;
-use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use whisper_rs::{
+    FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperSegment,
+    WhisperTokenId,
+};
 
-// TODO: allow model selection and download at runtime
-const MODEL_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/models/ggml-base.en.bin");
 pub const SAMPLE_RATE: usize = 16000;
 
+/// Language transcribed unless another is requested.
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+/// Longest initial prompt passed to whisper, in characters. Whisper only
+/// reads the last 224 tokens of a prompt, which is about this much English.
+pub const MAX_PROMPT_CHARS: usize = 800;
+
+/// Default RMS level above which audio may contain speech, tuned for a quiet room.
+pub const DEFAULT_VAD_RMS_THRESHOLD: f32 = 0.01;
+
+/// No-speech probability above which a segment whisper was unsure of is
+/// dropped as a likely hallucination, as in OpenAI's whisper.
+pub const DEFAULT_NO_SPEECH_THRESHOLD: f32 = 0.6;
+
+/// Mean token log probability below which whisper counts as unsure of a
+/// segment. Whisper only treats a window as silent when it is unsure of the
+/// text as well, since a confident transcript is speech however likely the
+/// no-speech estimate says otherwise.
+const NO_SPEECH_MAX_LOGPROB: f32 = -1.0;
+
+/// Zero-crossing rate (crossings per sample) above which audio is treated as
+/// broadband noise rather than speech.
+const VAD_MAX_ZERO_CROSSING_RATE: f32 = 0.45;
+
+/// How much of the end of the buffer is checked for speech before inference.
+const VAD_WINDOW_MS: usize = 1000;
+
+/// Inference calls whose times STATS summarizes.
+const RECENT_INFERENCE_CALLS: usize = 32;
+
+/// Wall-clock times of the most recent inference calls.
+#[derive(Debug, Default)]
+pub struct InferenceTimes {
+    recent: std::collections::VecDeque<std::time::Duration>,
+    /// Calls timed since the model was loaded
+    calls: u64,
+}
+
+/// Summary of recent inference times; all zero before the first call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InferenceStats {
+    pub calls: u64,
+    pub min: std::time::Duration,
+    pub avg: std::time::Duration,
+    pub max: std::time::Duration,
+}
+
+impl InferenceTimes {
+    pub fn record(&mut self, took: std::time::Duration) {
+        if self.recent.len() == RECENT_INFERENCE_CALLS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(took);
+        self.calls += 1;
+    }
+
+    /// Min, average and max over the recent calls, with the count of all calls.
+    pub fn stats(&self) -> InferenceStats {
+        let total: std::time::Duration = self.recent.iter().sum();
+        InferenceStats {
+            calls: self.calls,
+            min: self.recent.iter().min().copied().unwrap_or_default(),
+            avg: total
+                .checked_div(self.recent.len() as u32)
+                .unwrap_or_default(),
+            max: self.recent.iter().max().copied().unwrap_or_default(),
+        }
+    }
+}
+
 /// Rolling buffer for audio samples with a fixed capacity.
 /// New samples push out old ones when capacity is exceeded.
 pub struct RollingBuffer {
     samples: Vec<f32>,
     capacity: usize,
+    /// Samples pushed out of the front since the buffer was last cleared
+    dropped: usize,
 }
 
 impl RollingBuffer {
@@ -20,16 +95,34 @@ impl RollingBuffer {
         Self {
             samples: Vec::with_capacity(capacity),
             capacity,
+            dropped: 0,
         }
     }
 
+    /// Change the capacity to the given duration of audio, discarding the
+    /// oldest samples if the buffer no longer fits.
+    pub fn set_duration(&mut self, duration: std::time::Duration) {
+        self.capacity = (duration.as_secs() as usize) * SAMPLE_RATE;
+        self.trim();
+    }
+
+    /// How much audio the buffer holds when full.
+    pub fn duration(&self) -> std::time::Duration {
+        std::time::Duration::from_secs((self.capacity / SAMPLE_RATE) as u64)
+    }
+
     /// Append new samples, discarding old ones if we exceed capacity.
     pub fn push(&mut self, new_samples: &[f32]) {
         self.samples.extend_from_slice(new_samples);
+        self.trim();
+    }
 
+    /// Discard the oldest samples beyond capacity.
+    fn trim(&mut self) {
         if self.samples.len() > self.capacity {
             let excess = self.samples.len() - self.capacity;
             self.samples.drain(0..excess);
+            self.dropped += excess;
         }
     }
 
@@ -41,94 +134,479 @@ impl RollingBuffer {
     /// Clear the buffer.
     pub fn clear(&mut self) {
         self.samples.clear();
+        self.dropped = 0;
+    }
+
+    /// Discard every sample, counting them as dropped so later samples keep
+    /// their place in the recording.
+    pub fn discard(&mut self) {
+        self.dropped += self.samples.len();
+        self.samples.clear();
+    }
+
+    /// Number of samples discarded since the buffer was cleared, i.e. the
+    /// position of the oldest buffered sample in the recording.
+    pub fn dropped(&self) -> usize {
+        self.dropped
     }
 
     /// Returns the number of samples currently in the buffer.
-    #[allow(dead_code)]
     pub fn len(&self) -> usize {
         self.samples.len()
     }
+
+    /// Downsample the buffer into `bins` peak amplitudes, oldest first.
+    /// Bins that cover no samples (e.g. a short buffer) are reported as silence.
+    pub fn envelope(&self, bins: usize) -> Vec<f32> {
+        let len = self.samples.len();
+        (0..bins)
+            .map(|i| {
+                let start = i * len / bins;
+                let end = (i + 1) * len / bins;
+                self.samples[start..end]
+                    .iter()
+                    .fold(0.0f32, |peak, s| peak.max(s.abs()))
+            })
+            .collect()
+    }
 }
 
-/// Streaming transcriber optimized for real-time audio.
-/// Maintains a rolling buffer and tracks transcript changes.
-pub struct StreamingTranscriber {
+/// A transcribed segment. A [`Model`] times it from the start of the audio
+/// it was given; [`StreamingTranscriber::segments`] from the start of the
+/// recording.
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize)]
+pub struct Segment {
+    pub text: String,
+    pub start_ms: i64,
+    pub end_ms: i64,
+    /// Whisper's estimate that the audio holds no speech at all
+    #[serde(skip)]
+    pub no_speech_prob: f32,
+    /// Mean log probability of the segment's tokens; the lower, the less
+    /// sure whisper was of them
+    #[serde(skip)]
+    pub avg_logprob: f32,
+}
+
+/// How to handle a segment that starts before the previous segment ended.
+/// Whisper occasionally produces these near the edges of the rolling buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverlapPolicy {
+    /// Move the segment's start forward to the previous segment's end.
+    #[default]
+    Clamp,
+    /// Discard the overlapping segment entirely.
+    Drop,
+}
+
+impl std::str::FromStr for OverlapPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &*s.to_lowercase() {
+            "clamp" => Ok(Self::Clamp),
+            "drop" => Ok(Self::Drop),
+            other => Err(format!("unknown overlap policy: {other}")),
+        }
+    }
+}
+
+/// Make segment timings monotonic so each segment starts no earlier than
+/// the previous one ended. Returns the number of segments that were corrected.
+pub fn normalize_segments(segments: &mut Vec<Segment>, policy: OverlapPolicy) -> usize {
+    let mut corrected = 0;
+    let mut prev_end = i64::MIN;
+
+    segments.retain_mut(|segment| {
+        if segment.start_ms < prev_end {
+            corrected += 1;
+            match policy {
+                OverlapPolicy::Clamp => segment.start_ms = prev_end,
+                OverlapPolicy::Drop => return false,
+            }
+        }
+        segment.end_ms = segment.end_ms.max(segment.start_ms);
+        prev_end = segment.end_ms;
+        true
+    });
+
+    corrected
+}
+
+/// Drop segments whisper thinks more likely than `threshold` to hold no
+/// speech and was unsure of, such as the "Thank you." it can hallucinate
+/// over breath noise. Returns the number of segments dropped.
+pub fn drop_no_speech(segments: &mut Vec<Segment>, threshold: f32) -> usize {
+    let before = segments.len();
+    segments.retain(|segment| {
+        let speech =
+            segment.no_speech_prob <= threshold || segment.avg_logprob >= NO_SPEECH_MAX_LOGPROB;
+        if !speech {
+            log::debug!(
+                "dropped {:?}: no_speech_prob {:.2}, avg_logprob {:.2}",
+                segment.text,
+                segment.no_speech_prob,
+                segment.avg_logprob
+            );
+        }
+        speech
+    });
+    before - segments.len()
+}
+
+/// How an inference pass should decode the audio.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decode {
+    /// Whisper language code of the speech, or `auto` to detect it
+    pub language: String,
+    /// Translate the speech to English instead of transcribing it
+    pub translate: bool,
+    /// Text whisper takes as coming before the speech, to steer spelling
+    pub initial_prompt: String,
+}
+
+/// Speech-to-text backend run over a block of 16kHz mono samples.
+pub trait Model: Send + Sync {
+    /// Run inference as `decode` asks and return the transcribed segments
+    /// in order. Give up with an error as soon as `abort` is set.
+    fn transcribe(
+        &self,
+        samples: &[f32],
+        decode: &Decode,
+        abort: &Arc<AtomicBool>,
+    ) -> Result<Vec<Segment>, Box<dyn std::error::Error>>;
+}
+
+/// Loads a model from a file, e.g. [`WhisperModel::load`].
+pub type ModelLoader =
+    Box<dyn Fn(&Path) -> Result<Box<dyn Model>, Box<dyn std::error::Error>> + Send + Sync>;
+
+/// Returns true if whisper knows `language`, or it is `auto`.
+pub fn is_valid_language(language: &str) -> bool {
+    language == "auto" || whisper_rs::get_lang_id(language).is_some()
+}
+
+/// The language to pass to whisper, where `None` has it detect the language.
+fn whisper_language(language: &str) -> Option<&str> {
+    (language != "auto").then_some(language)
+}
+
+/// Inference parameters for a pass decoding as `decode` asks.
+fn full_params(decode: &Decode) -> FullParams<'_, '_> {
+    let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+    params.set_language(whisper_language(&decode.language));
+    params.set_translate(decode.translate);
+    params.set_print_special(false);
+    params.set_print_progress(false);
+    params.set_print_realtime(false);
+    params.set_print_timestamps(false);
+    params.set_suppress_nst(true);
+    params.set_no_context(true);
+    params
+}
+
+/// Cut `prompt` to its last [`MAX_PROMPT_CHARS`], between words if there is
+/// a space to cut at. The end is kept, as that is the context whisper reads.
+/// Returns None if it already fits.
+fn truncate_prompt(prompt: &str) -> Option<&str> {
+    let excess = prompt
+        .chars()
+        .count()
+        .checked_sub(MAX_PROMPT_CHARS)
+        .filter(|&excess| excess > 0)?;
+    let (start, _) = prompt.char_indices().nth(excess)?;
+    let cut = &prompt[start..];
+    if prompt[..start].ends_with(char::is_whitespace) {
+        return Some(cut.trim_start());
+    }
+    Some(match cut.find(char::is_whitespace) {
+        Some(space) => cut[space..].trim_start(),
+        None => cut,
+    })
+}
+
+/// Whether whisper.cpp was built with a GPU backend, by enabling the
+/// `cuda`, `metal` or `vulkan` feature.
+pub const GPU_BUILD: bool = cfg!(any(feature = "cuda", feature = "metal", feature = "vulkan"));
+
+/// Whether to run inference on the GPU, given the `gpu` setting, which is
+/// on by default when there is a GPU backend.
+pub fn use_gpu(setting: bool) -> bool {
+    if setting && !GPU_BUILD {
+        log::warn!(
+            "gpu is on, but yowl was built without a GPU backend \
+             (enable the cuda, metal or vulkan feature)"
+        );
+    }
+    setting && GPU_BUILD
+}
+
+/// A whisper.cpp model loaded from a ggml file.
+pub struct WhisperModel {
     ctx: WhisperContext,
-    buffer: Mutex<RollingBuffer>,
-    last_transcript: Mutex<String>,
 }
 
-impl StreamingTranscriber {
-    /// Create a new streaming transcriber with the given buffer duration.
-    pub fn new(buffer_duration: std::time::Duration) -> Result<Self, Box<dyn std::error::Error>> {
-        let path = Path::new(MODEL_PATH);
+impl WhisperModel {
+    /// Load the model at `path`, for inference on the GPU if `use_gpu`.
+    /// Falls back to the CPU if the GPU can't be set up.
+    pub fn load(path: &Path, use_gpu: bool) -> Result<Self, Box<dyn std::error::Error>> {
         if !path.exists() {
-            return Err(format!("Model not found: {MODEL_PATH}").into());
+            return Err(format!("Model not found: {}", path.display()).into());
         }
 
-        log::info!("Loading whisper model from {MODEL_PATH}");
-        let ctx = WhisperContext::new_with_params(MODEL_PATH, WhisperContextParameters::default())
-            .map_err(|e| format!("Failed to load model: {e}"))?;
-
         log::info!(
-            "Whisper streaming transcriber ready ({}s buffer)",
-            buffer_duration.as_secs()
+            "Loading whisper model from {} ({})",
+            path.display(),
+            if use_gpu { "GPU" } else { "CPU" }
         );
+        let load = |use_gpu| {
+            let mut params = WhisperContextParameters::default();
+            params.use_gpu(use_gpu);
+            WhisperContext::new_with_params(path, params)
+        };
+        let ctx = match load(use_gpu) {
+            Ok(ctx) => ctx,
+            Err(e) if use_gpu => {
+                log::warn!("GPU setup failed ({e}), falling back to the CPU");
+                load(false).map_err(|e| format!("Failed to load model: {e}"))?
+            }
+            Err(e) => return Err(format!("Failed to load model: {e}").into()),
+        };
 
-        Ok(Self {
-            ctx,
-            buffer: Mutex::new(RollingBuffer::new(buffer_duration)),
-            last_transcript: Mutex::new(String::new()),
-        })
+        Ok(Self { ctx })
     }
 
-    /// Push new audio samples into the buffer.
-    pub fn push_audio(&self, samples: &[f32]) {
-        self.buffer.lock().unwrap().push(samples);
+    /// A loader for models that [`load`](Self::load) for the GPU if `use_gpu`.
+    pub fn loader(use_gpu: bool) -> ModelLoader {
+        Box::new(move |path| Ok(Box::new(Self::load(path, use_gpu)?) as Box<dyn Model>))
     }
 
-    /// Run transcription on the current buffer contents.
-    /// Returns the new transcript if it changed, or None if unchanged.
-    pub fn transcribe(&self) -> Result<Option<String>, Box<dyn std::error::Error>> {
-        let samples = {
-            let buffer = self.buffer.lock().unwrap();
-            buffer.samples().to_vec()
-        };
-
-        if samples.is_empty() {
-            return Ok(None);
+    /// `prompt` in this model's tokens, or none if it is empty.
+    fn prompt_tokens(
+        &self,
+        prompt: &str,
+    ) -> Result<Vec<WhisperTokenId>, Box<dyn std::error::Error>> {
+        if prompt.is_empty() {
+            return Ok(Vec::new());
         }
+        // no text comes to more tokens than it has bytes
+        self.ctx
+            .tokenize(prompt, prompt.len())
+            .map_err(|e| format!("Failed to tokenize the initial prompt: {e}").into())
+    }
+}
 
+impl Model for WhisperModel {
+    fn transcribe(
+        &self,
+        samples: &[f32],
+        decode: &Decode,
+        abort: &Arc<AtomicBool>,
+    ) -> Result<Vec<Segment>, Box<dyn std::error::Error>> {
         let mut state = self
             .ctx
             .create_state()
             .map_err(|e| format!("Failed to create state: {e}"))?;
 
-        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
-        params.set_language(Some("en"));
-        params.set_print_special(false);
-        params.set_print_progress(false);
-        params.set_print_realtime(false);
-        params.set_print_timestamps(false);
-        params.set_suppress_nst(true);
-        params.set_no_context(true);
-
+        // whisper-rs never frees its copy of a prompt passed as text, so it
+        // goes in as tokens borrowed for the pass
+        let prompt = self.prompt_tokens(&decode.initial_prompt)?;
+        let mut params = full_params(decode);
+        if !prompt.is_empty() {
+            params.set_tokens(&prompt);
+        }
+        // SAFETY: `abort` is borrowed for the whole of `full`, so the pointer
+        // stays valid for every call whisper makes to `is_aborted`
+        unsafe {
+            params.set_abort_callback(Some(is_aborted));
+            params.set_abort_callback_user_data(Arc::as_ptr(abort) as *mut c_void);
+        }
         state
-            .full(params, &samples)
+            .full(params, samples)
             .map_err(|e| format!("Inference failed: {e}"))?;
 
         let num_segments = state.full_n_segments();
-        let mut result = String::new();
+        let mut segments = Vec::new();
         for i in 0..num_segments {
             if let Some(segment) = state.get_segment(i) {
                 if let Ok(text) = segment.to_str() {
-                    result.push_str(text);
+                    // whisper timestamps are in centiseconds
+                    segments.push(Segment {
+                        text: text.to_string(),
+                        start_ms: segment.start_timestamp() * 10,
+                        end_ms: segment.end_timestamp() * 10,
+                        no_speech_prob: segment.no_speech_probability(),
+                        avg_logprob: avg_logprob(&segment),
+                    });
                 }
             }
         }
 
+        Ok(segments)
+    }
+}
+
+/// Whisper's abort callback: whether the [`AtomicBool`] at `data` is set.
+unsafe extern "C" fn is_aborted(data: *mut c_void) -> bool {
+    (*(data as *const AtomicBool)).load(Ordering::SeqCst)
+}
+
+/// Mean log probability of `segment`'s tokens, or zero if it has none.
+fn avg_logprob(segment: &WhisperSegment) -> f32 {
+    let logprobs: Vec<f32> = (0..segment.n_tokens())
+        .filter_map(|i| segment.get_token(i))
+        .map(|token| token.token_probability().ln())
+        .collect();
+    if logprobs.is_empty() {
+        return 0.0;
+    }
+    logprobs.iter().sum::<f32>() / logprobs.len() as f32
+}
+
+/// Streaming transcriber optimized for real-time audio.
+/// Maintains a rolling buffer and tracks transcript changes.
+pub struct StreamingTranscriber {
+    /// None until the first model has loaded
+    model: RwLock<Option<Box<dyn Model>>>,
+    buffer: Mutex<RollingBuffer>,
+    last_transcript: Mutex<String>,
+    last_segments: Mutex<Vec<Segment>>,
+    /// Segments whose audio has left the buffer, so they can no longer change
+    final_segments: Mutex<Vec<Segment>>,
+    overlap_policy: Mutex<OverlapPolicy>,
+    language: Mutex<String>,
+    translate: Mutex<bool>,
+    initial_prompt: Mutex<String>,
+    /// RMS level below which audio counts as silence (0 disables the check)
+    vad_threshold: Mutex<f32>,
+    /// Segments more likely than this to hold no speech are dropped
+    no_speech_threshold: Mutex<f32>,
+    /// Successful transcription passes so far
+    passes: std::sync::atomic::AtomicU64,
+    /// How long recent passes took, failed ones included
+    inference_times: Mutex<InferenceTimes>,
+    /// Audio has arrived, or decoding changed, since the last pass; until
+    /// then another pass would only repeat it
+    dirty: std::sync::atomic::AtomicBool,
+    /// Bumped whenever the audio and transcript are thrown away, so a pass
+    /// already running then throws its result away too
+    generation: std::sync::atomic::AtomicU64,
+    /// Set to give up on the pass in progress
+    abort: Arc<AtomicBool>,
+}
+
+impl StreamingTranscriber {
+    /// Create a new streaming transcriber using the default model and the given buffer duration.
+    #[cfg(test)]
+    pub fn new(buffer_duration: std::time::Duration) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::new_with_params(
+            &crate::models::default_model_path(),
+            buffer_duration,
+            GPU_BUILD,
+        )
+    }
+
+    /// Create a new streaming transcriber using the whisper model at
+    /// `model_path`, run on the GPU if `use_gpu` (see [`WhisperModel::load`]).
+    #[cfg(test)]
+    pub fn new_with_params(
+        model_path: &Path,
+        buffer_duration: std::time::Duration,
+        use_gpu: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let model = WhisperModel::load(model_path, use_gpu)?;
+        Ok(Self::with_model(Box::new(model), buffer_duration))
+    }
+
+    /// Create a streaming transcriber backed by an already loaded model.
+    #[cfg(test)]
+    pub fn with_model(model: Box<dyn Model>, buffer_duration: std::time::Duration) -> Self {
+        let transcriber = Self::without_model(buffer_duration);
+        *transcriber.model.write().unwrap() = Some(model);
+        transcriber
+    }
+
+    /// Create a streaming transcriber that can't transcribe until it is
+    /// given a model with [`set_model`](Self::set_model).
+    pub fn without_model(buffer_duration: std::time::Duration) -> Self {
+        log::info!(
+            "Whisper streaming transcriber ready ({}s buffer)",
+            buffer_duration.as_secs()
+        );
+
+        Self {
+            model: RwLock::new(None),
+            buffer: Mutex::new(RollingBuffer::new(buffer_duration)),
+            last_transcript: Mutex::new(String::new()),
+            last_segments: Mutex::new(Vec::new()),
+            final_segments: Mutex::new(Vec::new()),
+            overlap_policy: Mutex::new(OverlapPolicy::default()),
+            language: Mutex::new(DEFAULT_LANGUAGE.to_string()),
+            translate: Mutex::new(false),
+            initial_prompt: Mutex::new(String::new()),
+            vad_threshold: Mutex::new(DEFAULT_VAD_RMS_THRESHOLD),
+            no_speech_threshold: Mutex::new(DEFAULT_NO_SPEECH_THRESHOLD),
+            passes: std::sync::atomic::AtomicU64::new(0),
+            inference_times: Mutex::new(InferenceTimes::default()),
+            dirty: std::sync::atomic::AtomicBool::new(false),
+            generation: std::sync::atomic::AtomicU64::new(0),
+            abort: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Push new audio samples into the buffer.
+    pub fn push_audio(&self, samples: &[f32]) {
+        self.buffer.lock().unwrap().push(samples);
+        if !samples.is_empty() {
+            self.mark_dirty();
+        }
+    }
+
+    fn mark_dirty(&self) {
+        self.dirty.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    /// Make the pass in progress, if any, give up with an error.
+    pub fn abort(&self) {
+        self.abort.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether a pass now could say anything the last one didn't.
+    pub fn has_new_audio(&self) -> bool {
+        self.dirty.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Run transcription on the current buffer contents.
+    /// Returns the new transcript if it changed, or None if unchanged,
+    /// including when nothing has arrived since the last pass, which skips
+    /// inference.
+    pub fn transcribe(&self) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        if !self.dirty.swap(false, std::sync::atomic::Ordering::SeqCst)
+            || self.buffer.lock().unwrap().samples().is_empty()
+        {
+            return Ok(None);
+        }
+
+        let generation = self.generation();
+        let started = std::time::Instant::now();
+        let segments = self.transcribe_segments();
+        self.inference_times
+            .lock()
+            .unwrap()
+            .record(started.elapsed());
+        let segments = segments.inspect_err(|_| {
+            // the same audio is worth another try
+            self.mark_dirty();
+        })?;
+        let result: String = segments.iter().map(|s| s.text.as_str()).collect();
+
         let transcript = result.trim().to_string();
         let mut last = self.last_transcript.lock().unwrap();
+        if self.generation() != generation {
+            return Ok(None);
+        }
+        self.passes
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
 
         if transcript != *last {
             *last = transcript.clone();
@@ -138,15 +616,240 @@ impl StreamingTranscriber {
         }
     }
 
+    /// Run transcription on the current buffer contents and return the
+    /// segments, timed from the start of the recording. They are also kept
+    /// for [`segments`](Self::segments). If the transcriber is reset while
+    /// the pass runs, there are none.
+    pub fn transcribe_segments(&self) -> Result<Vec<Segment>, Box<dyn std::error::Error>> {
+        let (samples, offset_ms, generation) = {
+            let buffer = self.buffer.lock().unwrap();
+            let offset_ms = (buffer.dropped() * 1000 / SAMPLE_RATE) as i64;
+            (buffer.samples().to_vec(), offset_ms, self.generation())
+        };
+
+        if samples.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let decode = Decode {
+            language: self.language.lock().unwrap().clone(),
+            translate: *self.translate.lock().unwrap(),
+            initial_prompt: self.initial_prompt.lock().unwrap().clone(),
+        };
+        self.abort.store(false, Ordering::SeqCst);
+        let mut segments = match &*self.model.read().unwrap() {
+            Some(model) => model.transcribe(&samples, &decode, &self.abort)?,
+            None => return Err("no model loaded".into()),
+        };
+
+        let dropped = drop_no_speech(&mut segments, *self.no_speech_threshold.lock().unwrap());
+        if dropped > 0 {
+            log::info!("dropped {dropped} segment(s) likely to hold no speech");
+        }
+
+        let policy = *self.overlap_policy.lock().unwrap();
+        let corrected = normalize_segments(&mut segments, policy);
+        if corrected > 0 {
+            log::info!("corrected {corrected} overlapping segment(s) ({policy:?})");
+        }
+
+        // time from the start of the recording rather than of the buffer
+        for segment in &mut segments {
+            segment.start_ms += offset_ms;
+            segment.end_ms += offset_ms;
+        }
+        let mut last_segments = self.last_segments.lock().unwrap();
+        if self.generation() != generation {
+            log::debug!("discarding a pass that outlived its audio");
+            // the buffer may hold new audio that came in meanwhile
+            self.mark_dirty();
+            return Ok(Vec::new());
+        }
+        let aged_out = last_segments
+            .iter()
+            .filter(|segment| segment.end_ms <= offset_ms)
+            .cloned();
+        self.final_segments.lock().unwrap().extend(aged_out);
+        *last_segments = segments.clone();
+
+        Ok(segments)
+    }
+
+    /// Replace the model and clear any buffered audio and transcript.
+    /// Waits for an in-flight transcription to finish first.
+    pub fn set_model(&self, model: Box<dyn Model>) {
+        *self.model.write().unwrap() = Some(model);
+        *self.inference_times.lock().unwrap() = InferenceTimes::default();
+        self.reset();
+    }
+
+    /// How long recent transcription passes took with the current model.
+    pub fn inference_stats(&self) -> InferenceStats {
+        self.inference_times.lock().unwrap().stats()
+    }
+
+    /// Whether a model has been loaded yet.
+    pub fn has_model(&self) -> bool {
+        self.model.read().unwrap().is_some()
+    }
+
+    /// Get the segments transcribed so far, timed from the start of the
+    /// recording. Those whose audio has aged out of the buffer are final; with
+    /// `final_only` false the most recent pass's segments follow them.
+    pub fn segments(&self, final_only: bool) -> Vec<Segment> {
+        // same lock order as `transcribe`
+        let last_segments = self.last_segments.lock().unwrap();
+        let mut segments = self.final_segments.lock().unwrap().clone();
+        if !final_only {
+            segments.extend(last_segments.iter().cloned());
+        }
+        segments
+    }
+
+    /// Resize the rolling buffer to hold `duration` of audio.
+    pub fn set_buffer_duration(&self, duration: std::time::Duration) {
+        self.buffer.lock().unwrap().set_duration(duration);
+    }
+
+    /// How much audio the rolling buffer holds when full.
+    pub fn buffer_duration(&self) -> std::time::Duration {
+        self.buffer.lock().unwrap().duration()
+    }
+
+    /// The language transcriptions are in, or `auto`.
+    pub fn language(&self) -> String {
+        self.language.lock().unwrap().clone()
+    }
+
+    /// Set the language for subsequent transcriptions (see [`is_valid_language`]).
+    pub fn set_language(&self, language: &str) {
+        *self.language.lock().unwrap() = language.to_string();
+        self.mark_dirty();
+    }
+
+    /// Translate subsequent transcriptions to English. Whisper does this
+    /// from whatever language is set, though English-only models can't.
+    pub fn set_translate(&self, translate: bool) {
+        *self.translate.lock().unwrap() = translate;
+        self.mark_dirty();
+    }
+
+    /// Prime subsequent transcriptions with `prompt`, such as names and jargon
+    /// the speaker uses, so whisper spells them the same way. An empty prompt
+    /// clears it. One longer than [`MAX_PROMPT_CHARS`] is cut short, keeping
+    /// its end. Returns whether it was.
+    pub fn set_initial_prompt(&self, prompt: &str) -> bool {
+        // whisper takes the prompt as a C string
+        let prompt = prompt.replace('\0', "");
+        let truncated = truncate_prompt(&prompt);
+        if truncated.is_some() {
+            log::warn!("initial prompt cut to {MAX_PROMPT_CHARS} characters");
+        }
+        *self.initial_prompt.lock().unwrap() = truncated.unwrap_or(&prompt).to_string();
+        self.mark_dirty();
+        truncated.is_some()
+    }
+
+    /// Set how overlapping segment timestamps are handled on the next transcription.
+    pub fn set_overlap_policy(&self, policy: OverlapPolicy) {
+        *self.overlap_policy.lock().unwrap() = policy;
+    }
+
+    /// Set the RMS level below which audio counts as silence (0 disables the check).
+    pub fn set_vad_threshold(&self, threshold: f32) {
+        *self.vad_threshold.lock().unwrap() = threshold;
+    }
+
+    /// Drop segments more likely than `threshold` to hold no speech, if
+    /// whisper was also unsure of their text (1 keeps them all).
+    pub fn set_no_speech_threshold(&self, threshold: f32) {
+        *self.no_speech_threshold.lock().unwrap() = threshold;
+        self.mark_dirty();
+    }
+
+    /// Energy and zero-crossing voice activity check: loud enough, and not so
+    /// noisy that it's more likely hiss than speech.
+    pub fn has_speech(&self, samples: &[f32]) -> bool {
+        let threshold = *self.vad_threshold.lock().unwrap();
+        if threshold <= 0.0 {
+            return true;
+        }
+        if samples.is_empty() {
+            return false;
+        }
+
+        let rms = (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt();
+        if rms < threshold {
+            return false;
+        }
+
+        let crossings = samples
+            .windows(2)
+            .filter(|pair| (pair[0] >= 0.0) != (pair[1] >= 0.0))
+            .count();
+        (crossings as f32 / samples.len() as f32) < VAD_MAX_ZERO_CROSSING_RATE
+    }
+
+    /// Returns true if the most recent audio in the buffer may contain speech,
+    /// so inference can be skipped during pauses.
+    pub fn tail_has_speech(&self) -> bool {
+        let buffer = self.buffer.lock().unwrap();
+        let samples = buffer.samples();
+        let window = SAMPLE_RATE * VAD_WINDOW_MS / 1000;
+        self.has_speech(&samples[samples.len().saturating_sub(window)..])
+    }
+
+    /// Number of samples currently held in the rolling buffer.
+    pub fn buffered_samples(&self) -> usize {
+        self.buffer.lock().unwrap().len()
+    }
+
+    /// Get a coarse peak envelope of the buffered audio for visualization.
+    pub fn waveform(&self, bins: usize) -> Vec<f32> {
+        self.buffer.lock().unwrap().envelope(bins)
+    }
+
+    /// Number of successful transcription passes, whether or not they
+    /// changed the transcript.
+    pub fn passes(&self) -> u64 {
+        self.passes.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Which lot of audio the buffer holds; see [`discard_audio`](Self::discard_audio).
+    fn generation(&self) -> u64 {
+        self.generation.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Empty the buffer with `empty`, and start a new generation, so a pass
+    /// over the old audio doesn't report on it after it is gone. Callers
+    /// clear the transcript after this, which a pass checks the generation
+    /// before writing.
+    fn discard_audio(&self, empty: fn(&mut RollingBuffer)) {
+        let mut buffer = self.buffer.lock().unwrap();
+        empty(&mut buffer);
+        self.generation
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+
     /// Get the current full transcript without running inference.
     pub fn current_transcript(&self) -> String {
         self.last_transcript.lock().unwrap().clone()
     }
 
+    /// Drop the buffered audio and the transcript in progress, keeping final
+    /// segments and the position in the recording.
+    pub fn cancel(&self) {
+        self.discard_audio(RollingBuffer::discard);
+        *self.last_transcript.lock().unwrap() = String::new();
+        self.last_segments.lock().unwrap().clear();
+    }
+
     /// Clear the buffer and transcript (call when stopping recording).
     pub fn reset(&self) {
-        self.buffer.lock().unwrap().clear();
+        self.discard_audio(RollingBuffer::clear);
         *self.last_transcript.lock().unwrap() = String::new();
+        self.last_segments.lock().unwrap().clear();
+        self.final_segments.lock().unwrap().clear();
     }
 }
 
@@ -155,6 +858,120 @@ mod tests {
     use super::*;
     use std::time::Duration;
 
+    #[test]
+    fn test_inference_stats() {
+        let mut times = InferenceTimes::default();
+        assert_eq!(times.stats(), InferenceStats::default());
+
+        for ms in [120, 80, 400] {
+            times.record(Duration::from_millis(ms));
+        }
+        assert_eq!(
+            times.stats(),
+            InferenceStats {
+                calls: 3,
+                min: Duration::from_millis(80),
+                avg: Duration::from_millis(200),
+                max: Duration::from_millis(400),
+            }
+        );
+
+        // only the recent calls count towards min, average and max
+        for _ in 0..RECENT_INFERENCE_CALLS {
+            times.record(Duration::from_millis(50));
+        }
+        let stats = times.stats();
+        assert_eq!(stats.calls, 3 + RECENT_INFERENCE_CALLS as u64);
+        assert_eq!(stats.max, Duration::from_millis(50));
+        assert_eq!(stats.avg, Duration::from_millis(50));
+    }
+
+    fn transcriber() -> StreamingTranscriber {
+        StreamingTranscriber::with_model(
+            Box::new(crate::testing::MockModel::default()),
+            Duration::from_secs(10),
+        )
+    }
+
+    fn tone(frequency: f32, amplitude: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                amplitude
+                    * (2.0 * std::f32::consts::PI * frequency * i as f32 / SAMPLE_RATE as f32).sin()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_whisper_language() {
+        assert_eq!(whisper_language("de"), Some("de"));
+        assert_eq!(whisper_language("auto"), None);
+    }
+
+    #[test]
+    fn test_truncate_prompt() {
+        assert_eq!(truncate_prompt("Kubernetes, etcd"), None);
+
+        // the start goes, to a word boundary
+        let words = format!("first {}last", "jargon ".repeat(200));
+        let cut = truncate_prompt(&words).unwrap();
+        assert!(cut.len() <= MAX_PROMPT_CHARS);
+        assert!(cut.starts_with("jargon "));
+        assert!(cut.ends_with(" last"));
+        assert!(!cut.contains("first"));
+
+        // a cut that falls just after a space keeps the word after it
+        let exact = format!("{} {}", "a".repeat(10), "b".repeat(MAX_PROMPT_CHARS));
+        assert_eq!(
+            truncate_prompt(&exact),
+            Some("b".repeat(MAX_PROMPT_CHARS).as_str())
+        );
+
+        // no space to cut at, and multibyte characters
+        let word = "é".repeat(MAX_PROMPT_CHARS + 1);
+        assert_eq!(
+            truncate_prompt(&word).unwrap().chars().count(),
+            MAX_PROMPT_CHARS
+        );
+    }
+
+    #[test]
+    fn test_use_gpu_setting() {
+        assert_eq!(use_gpu(true), GPU_BUILD);
+        assert!(!use_gpu(false));
+    }
+
+    #[test]
+    fn test_has_speech() {
+        let transcriber = transcriber();
+
+        assert!(!transcriber.has_speech(&vec![0.0; SAMPLE_RATE]));
+        assert!(!transcriber.has_speech(&tone(440.0, 0.001, SAMPLE_RATE)));
+        assert!(transcriber.has_speech(&tone(440.0, 0.3, SAMPLE_RATE)));
+
+        // loud but crossing zero on every sample: noise, not speech
+        let hiss: Vec<f32> = (0..SAMPLE_RATE)
+            .map(|i| if i % 2 == 0 { 0.3 } else { -0.3 })
+            .collect();
+        assert!(!transcriber.has_speech(&hiss));
+
+        transcriber.set_vad_threshold(0.0);
+        assert!(transcriber.has_speech(&vec![0.0; SAMPLE_RATE]));
+    }
+
+    #[test]
+    fn test_tail_has_speech() {
+        let transcriber = transcriber();
+        assert!(!transcriber.tail_has_speech());
+
+        transcriber.push_audio(&tone(440.0, 0.3, SAMPLE_RATE));
+        assert!(transcriber.tail_has_speech());
+
+        // speech followed by a pause
+        transcriber.push_audio(&vec![0.0; 2 * SAMPLE_RATE]);
+        assert!(!transcriber.tail_has_speech());
+    }
+
     #[test]
     fn test_rolling_buffer() {
         let mut buffer = RollingBuffer::new(Duration::from_secs(2)); // 2 seconds = 32000 samples
@@ -181,9 +998,263 @@ mod tests {
     }
 
     #[test]
-    fn test_streaming_transcriber() {
+    fn test_rolling_buffer_set_duration() {
+        let mut buffer = RollingBuffer::new(Duration::from_secs(2));
+        buffer.push(&vec![0.1; SAMPLE_RATE]);
+        buffer.push(&vec![0.2; SAMPLE_RATE]);
+
+        // shrinking keeps the newest audio
+        buffer.set_duration(Duration::from_secs(1));
+        assert_eq!(buffer.len(), SAMPLE_RATE);
+        assert!((buffer.samples()[0] - 0.2).abs() < 0.001);
+
+        buffer.set_duration(Duration::from_secs(3));
+        buffer.push(&vec![0.3; 2 * SAMPLE_RATE]);
+        assert_eq!(buffer.len(), 3 * SAMPLE_RATE);
+        assert_eq!(buffer.dropped(), SAMPLE_RATE);
+
+        buffer.clear();
+        assert_eq!(buffer.dropped(), 0);
+    }
+
+    #[test]
+    fn test_rolling_buffer_envelope() {
+        let mut buffer = RollingBuffer::new(Duration::from_secs(1));
+
+        // quiet first half, loud second half with alternating sign
+        let quiet: Vec<f32> = vec![0.1; SAMPLE_RATE / 2];
+        let loud: Vec<f32> = (0..SAMPLE_RATE / 2)
+            .map(|i| if i % 2 == 0 { 0.8 } else { -0.9 })
+            .collect();
+        buffer.push(&quiet);
+        buffer.push(&loud);
+
+        let envelope = buffer.envelope(4);
+        assert_eq!(envelope.len(), 4);
+        assert!((envelope[0] - 0.1).abs() < 0.001);
+        assert!((envelope[1] - 0.1).abs() < 0.001);
+        assert!((envelope[2] - 0.9).abs() < 0.001);
+        assert!((envelope[3] - 0.9).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_rolling_buffer_envelope_short_buffer() {
+        let mut buffer = RollingBuffer::new(Duration::from_secs(1));
+        buffer.push(&[0.5, -0.25]);
+
+        let envelope = buffer.envelope(4);
+        assert_eq!(envelope.len(), 4);
+        assert_eq!(envelope.iter().filter(|v| **v > 0.0).count(), 2);
+    }
+
+    fn segment(text: &str, start_ms: i64, end_ms: i64) -> Segment {
+        Segment {
+            text: text.to_string(),
+            start_ms,
+            end_ms,
+            ..Segment::default()
+        }
+    }
+
+    fn overlapping_segments() -> Vec<Segment> {
+        vec![
+            segment(" The three", 0, 1200),
+            segment(" billy goats", 1000, 2400), // starts before previous end
+            segment(" gruff.", 2400, 3000),
+            segment(" Once", 2800, 2700), // overlaps and ends before it starts
+            segment(" upon a time", 3500, 4800),
+        ]
+    }
+
+    #[test]
+    fn test_normalize_segments_clamp() {
+        let mut segments = overlapping_segments();
+        let corrected = normalize_segments(&mut segments, OverlapPolicy::Clamp);
+
+        assert_eq!(corrected, 2);
+        assert_eq!(segments.len(), 5);
+        for pair in segments.windows(2) {
+            assert!(
+                pair[1].start_ms >= pair[0].end_ms,
+                "segments not monotonic: {:?}",
+                pair
+            );
+        }
+        for s in &segments {
+            assert!(
+                s.end_ms >= s.start_ms,
+                "segment ends before it starts: {:?}",
+                s
+            );
+        }
+        assert_eq!(segments[1], segment(" billy goats", 1200, 2400));
+        assert_eq!(segments[3], segment(" Once", 3000, 3000));
+    }
+
+    #[test]
+    fn test_normalize_segments_drop() {
+        let mut segments = overlapping_segments();
+        let corrected = normalize_segments(&mut segments, OverlapPolicy::Drop);
+
+        assert_eq!(corrected, 2);
+        let texts: Vec<&str> = segments.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(texts, vec![" The three", " gruff.", " upon a time"]);
+        for pair in segments.windows(2) {
+            assert!(pair[1].start_ms >= pair[0].end_ms);
+        }
+    }
+
+    #[test]
+    fn test_normalize_segments_already_monotonic() {
+        let mut segments = vec![segment(" Hello", 0, 500), segment(" world", 500, 900)];
+        let expected = segments.clone();
+
+        assert_eq!(normalize_segments(&mut segments, OverlapPolicy::Clamp), 0);
+        assert_eq!(segments, expected);
+    }
+
+    #[test]
+    fn test_drop_no_speech() {
+        let scored = |text, no_speech_prob, avg_logprob| Segment {
+            no_speech_prob,
+            avg_logprob,
+            ..segment(text, 0, 0)
+        };
+        let mut segments = vec![
+            scored(" Pass the salt.", 0.05, -0.2),
+            scored(" Thank you.", 0.92, -1.4),
+            scored(" Please.", 0.6, -0.4),
+            // whisper.cpp gives every segment in a window the same
+            // no-speech estimate, so a confident one is kept regardless
+            scored(" Right away.", 0.95, -0.3),
+            scored(" Um.", 0.3, -1.6),
+        ];
+
+        assert_eq!(
+            drop_no_speech(&mut segments, DEFAULT_NO_SPEECH_THRESHOLD),
+            1
+        );
+        let texts: Vec<&str> = segments.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            [" Pass the salt.", " Please.", " Right away.", " Um."]
+        );
+
+        assert_eq!(drop_no_speech(&mut segments, 1.0), 0);
+        assert_eq!(drop_no_speech(&mut segments, 0.0), 1);
+    }
+
+    #[test]
+    fn test_transcript_leaves_out_no_speech_segments() {
+        let model = crate::testing::MockModel::default();
+        let transcriber =
+            StreamingTranscriber::with_model(Box::new(model.clone()), Duration::from_secs(1));
+        model.set_segments(vec![
+            segment(" Hello.", 0, 400),
+            Segment {
+                no_speech_prob: 0.8,
+                avg_logprob: -1.5,
+                ..segment(" Thank you.", 400, 900)
+            },
+        ]);
+
+        transcriber.push_audio(&vec![0.0; SAMPLE_RATE]);
+        assert_eq!(transcriber.transcribe().unwrap().as_deref(), Some("Hello."));
+
+        transcriber.set_no_speech_threshold(1.0);
+        assert_eq!(
+            transcriber.transcribe().unwrap().as_deref(),
+            Some("Hello. Thank you.")
+        );
+    }
+
+    #[test]
+    fn test_segments_timed_from_recording_start() {
+        let model = crate::testing::MockModel::default();
         let transcriber =
-            StreamingTranscriber::new(Duration::from_secs(8)).expect("Failed to create transcriber");
+            StreamingTranscriber::with_model(Box::new(model.clone()), Duration::from_secs(1));
+
+        transcriber.push_audio(&vec![0.0; SAMPLE_RATE]);
+        model.set_segments(vec![segment(" one", 0, 400), segment(" two", 400, 900)]);
+        transcriber.transcribe().unwrap();
+        assert_eq!(transcriber.segments(true), vec![]);
+        assert_eq!(transcriber.segments(false).len(), 2);
+
+        // half a second ages out: " one" is final, the rest is re-timed
+        transcriber.push_audio(&vec![0.0; SAMPLE_RATE / 2]);
+        model.set_segments(vec![segment(" two", 0, 400), segment(" three", 400, 500)]);
+        transcriber.transcribe().unwrap();
+        assert_eq!(transcriber.segments(true), vec![segment(" one", 0, 400)]);
+        assert_eq!(
+            transcriber.segments(false),
+            vec![
+                segment(" one", 0, 400),
+                segment(" two", 500, 900),
+                segment(" three", 900, 1000),
+            ]
+        );
+
+        // cancelling keeps final segments, and later ones are still timed
+        // from the start of the recording
+        transcriber.cancel();
+        assert_eq!(transcriber.segments(false), vec![segment(" one", 0, 400)]);
+        transcriber.push_audio(&vec![0.0; SAMPLE_RATE / 2]);
+        model.set_segments(vec![segment(" four", 0, 500)]);
+        transcriber.transcribe().unwrap();
+        assert_eq!(
+            transcriber.segments(false),
+            vec![segment(" one", 0, 400), segment(" four", 1500, 2000)]
+        );
+
+        transcriber.reset();
+        assert_eq!(transcriber.segments(false), vec![]);
+    }
+
+    #[test]
+    fn test_transcribe_skips_unchanged_audio() {
+        let model = crate::testing::MockModel::default();
+        let transcriber =
+            StreamingTranscriber::with_model(Box::new(model.clone()), Duration::from_secs(1));
+        model.set_text("Hello");
+
+        // nothing to hear yet
+        assert_eq!(transcriber.transcribe().unwrap(), None);
+        assert_eq!(model.calls(), 0);
+
+        transcriber.push_audio(&[0.0; 160]);
+        assert!(transcriber.has_new_audio());
+        assert_eq!(transcriber.transcribe().unwrap(), Some("Hello".to_string()));
+        assert!(!transcriber.has_new_audio());
+        assert_eq!(transcriber.transcribe().unwrap(), None);
+        assert_eq!(model.calls(), 1);
+
+        // a failed pass is retried on the same audio
+        transcriber.push_audio(&[0.0; 160]);
+        model.set_error(Some("busy"));
+        assert!(transcriber.transcribe().is_err());
+        model.set_error(None);
+        model.set_text("Hello there");
+        assert_eq!(
+            transcriber.transcribe().unwrap(),
+            Some("Hello there".to_string())
+        );
+        assert_eq!(model.calls(), 3);
+
+        // so is one decoding differently
+        transcriber.set_language("de");
+        transcriber.transcribe().unwrap();
+        assert_eq!(model.calls(), 4);
+    }
+
+    #[test]
+    fn test_streaming_transcriber() {
+        // the CPU path works whatever the build
+        let transcriber = StreamingTranscriber::new_with_params(
+            &crate::models::default_model_path(),
+            Duration::from_secs(8),
+            false,
+        )
+        .expect("Failed to create transcriber");
 
         println!("\n=== Streaming transcriber test ===");
 
@@ -202,6 +1273,15 @@ mod tests {
         let result2 = transcriber.transcribe().expect("Transcription failed");
         println!("After 2s silence: {:?}", result2);
 
+        // timed segments come out of the real model ordered and disjoint
+        let segments = transcriber
+            .transcribe_segments()
+            .expect("Transcription failed");
+        println!("Segments: {:?}", segments);
+        for pair in segments.windows(2) {
+            assert!(pair[0].end_ms <= pair[1].start_ms, "{segments:?}");
+        }
+
         // Reset and verify empty
         transcriber.reset();
         assert!(transcriber.current_transcript().is_empty());
@@ -11,6 +11,9 @@ target_window_id: int | None = None
 # Polling interval in seconds
 POLL_INTERVAL = 0.1
 
+# How often to retry START while the daemon loads its model, in seconds
+LOADING_RETRY_INTERVAL = 0.2
+
 
 def main(args: list[str]) -> None:
     """Not used when no_ui=True."""
@@ -59,8 +62,12 @@ def _start_recording(window_id: int) -> str:
 
     with Client() as client:
         response = client.start()
-        if response != "OK":
-            return f"Start failed: {response}"
+    if response == "LOADING":
+        # The daemon loads its model on first use; try again shortly
+        add_timer(lambda timer_id: _start_recording(window_id), LOADING_RETRY_INTERVAL, False)
+        return "Loading model"
+    if response != "OK":
+        return f"Start failed: {response}"
 
     polling_active = True
     target_window_id = window_id
@@ -4,14 +4,37 @@ import os
 import socket
 from pathlib import Path
 
+MAX_INSTANCE_LEN = 64
+
+
+def validate_instance(name: str) -> None:
+    """Raise ValueError unless the daemon would accept `name` as an instance name."""
+    if not 1 <= len(name) <= MAX_INSTANCE_LEN:
+        raise ValueError(f"instance name must be 1 to {MAX_INSTANCE_LEN} characters")
+    allowed = all(c.isascii() and (c.isalnum() or c in "-_.") for c in name)
+    if name.startswith(".") or not allowed:
+        raise ValueError(
+            f"invalid instance name {name!r}: use letters, digits, '-', '_' and '.'"
+        )
+
 
 def socket_path() -> Path:
     """Get the socket path, matching the daemon's logic."""
+    instance = os.environ.get("YOWL_INSTANCE")
+    if instance:
+        try:
+            validate_instance(instance)
+        except ValueError as e:
+            raise ValueError(f"YOWL_INSTANCE: {e}") from None
     env_path = os.environ.get("YOWL_SOCKET_PATH")
     if env_path:
         return Path(env_path)
+    suffix = f"-{instance}" if instance else ""
+    runtime_dir = os.environ.get("XDG_RUNTIME_DIR")
+    if runtime_dir:
+        return Path(runtime_dir) / "yowl" / f"yowl{suffix}.sock"
     import tempfile
-    return Path(tempfile.gettempdir()) / f"yowl-{os.getuid()}.sock"
+    return Path(tempfile.gettempdir()) / f"yowl-{os.getuid()}{suffix}.sock"
 
 
 class Client:
@@ -78,7 +101,8 @@ class Client:
                     pass
             # Fallback for malformed response
             return (True, 0, rest)
-        elif response.startswith("IDLE:"):
+        elif response.startswith(("IDLE:", "STOPPED:")):
+            # STOPPED: means recording ended itself after silence
             return (False, 0, "")
         else:
             # Unexpected response, treat as not recording